@@ -0,0 +1,140 @@
+//! Benchmarks of the command path: datasheet against tuned processing
+//! delays, retry policies on a noisy bus, and readings from several sensors
+//! one after another against overlapped ones.
+//!
+//! The chips are simulated, taking `SIMULATED_PROCESSING_MS` to make a
+//! reading. With `EZO_RTD_BENCH_HARDWARE=1`, the delay benchmarks run on
+//! the chip given by the `EZO_RTD_*` variables of the `env` module instead,
+//! to measure what tuning buys on real hardware.
+//!
+//! Run with `cargo bench`.
+
+#[macro_use]
+extern crate criterion;
+extern crate ezo_rtd;
+extern crate i2cdev;
+
+use std::env;
+use std::time::Duration;
+
+use criterion::Criterion;
+use ezo_rtd::RtdSensor;
+use ezo_rtd::common::{RetryPolicy, WaitStrategy};
+use ezo_rtd::env::EnvConfig;
+use ezo_rtd::sensor::read_temperatures;
+use ezo_rtd::simulator::{SimulatedChip, SimulatedDevice, VirtualBus};
+use ezo_rtd::timing::{self, Delays};
+use i2cdev::core::I2CDevice;
+
+/// Set to `1` to run the delay benchmarks on a real chip.
+const HARDWARE_VAR: &str = "EZO_RTD_BENCH_HARDWARE";
+
+/// How long a simulated chip takes to make a reading.
+const SIMULATED_PROCESSING_MS: u64 = 40;
+
+/// Samples taken by `timing::tune`, and the margin it adds, in percent.
+const TUNE_SAMPLES: u32 = 5;
+const TUNE_MARGIN: u64 = 20;
+
+/// Sensors read together by the overlapped benchmark.
+const SENSORS: u16 = 4;
+
+fn hardware() -> bool {
+    env::var(HARDWARE_VAR).map(|value| value == "1").unwrap_or(false)
+}
+
+/// A simulated chip at `address`, on `bus`, and a sensor talking to it.
+fn simulated(bus: &VirtualBus, address: u16) -> RtdSensor<SimulatedDevice> {
+    let mut chip = SimulatedChip::new(address);
+    chip.set_temperature(21.5)
+        .set_processing_time(Duration::from_millis(SIMULATED_PROCESSING_MS));
+    bus.attach(chip);
+    RtdSensor::new(bus.device(address))
+}
+
+/// Benchmarks readings with the datasheet delays, then with tuned ones.
+fn compare_delays<D: I2CDevice>(c: &mut Criterion, group: &str, sensor: &mut RtdSensor<D>) {
+    let tuned = timing::tune(sensor.device(), TUNE_SAMPLES, TUNE_MARGIN)
+        .expect("could not tune the delays");
+    let mut group = c.benchmark_group(group);
+    group.sample_size(10);
+    sensor.set_delays(Delays::default());
+    group.bench_function("datasheet", |b| b.iter(|| sensor.read_temperature()));
+    sensor.set_delays(tuned);
+    group.bench_function("tuned", |b| b.iter(|| sensor.read_temperature()));
+    group.finish();
+}
+
+fn delays(c: &mut Criterion) {
+    if hardware() {
+        let mut sensor = EnvConfig::from_env()
+            .and_then(|config| config.open())
+            .expect("could not open the chip given by the environment");
+        compare_delays(c, "delays/hardware", &mut sensor);
+    } else {
+        let bus = VirtualBus::new();
+        let mut sensor = simulated(&bus, 0x66);
+        compare_delays(c, "delays/simulated", &mut sensor);
+    }
+}
+
+/// Readings over a bus that flips a byte now and then, with the default
+/// retry policy and with quicker, spinning retries.
+fn retries(c: &mut Criterion) {
+    let policies = [("default", RetryPolicy::default()),
+                    ("spinning",
+                     RetryPolicy {
+                         attempts: 5,
+                         min_wait: Duration::from_millis(1),
+                         wait_strategy: WaitStrategy::SpinYield,
+                         ..RetryPolicy::default()
+                     })];
+    let mut group = c.benchmark_group("retries");
+    group.sample_size(10);
+    for &(name, policy) in &policies {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_processing_time(Duration::from_millis(SIMULATED_PROCESSING_MS))
+            .set_bit_flip_chaos(0.02, 7);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(policy);
+        sensor.set_delays(Delays {
+                              reading: SIMULATED_PROCESSING_MS,
+                              ..Delays::default()
+                          });
+        group.bench_function(name, |b| b.iter(|| sensor.read_temperature()));
+    }
+    group.finish();
+}
+
+/// Readings from several sensors, one after another, then overlapped with
+/// `read_temperatures`.
+fn multi_sensor(c: &mut Criterion) {
+    let bus = VirtualBus::new();
+    let mut sensors: Vec<_> = (0..SENSORS)
+        .map(|i| {
+                 let mut sensor = simulated(&bus, 0x66 + i);
+                 sensor.set_delays(Delays {
+                                       reading: SIMULATED_PROCESSING_MS,
+                                       ..Delays::default()
+                                   });
+                 sensor
+             })
+        .collect();
+    let mut group = c.benchmark_group("multi_sensor");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+                   sensors
+                       .iter_mut()
+                       .map(|sensor| sensor.read_temperature())
+                       .collect::<Vec<_>>()
+               })
+    });
+    group.bench_function("overlapped", |b| b.iter(|| read_temperatures(&mut sensors)));
+    group.finish();
+}
+
+criterion_group!(benches, delays, retries, multi_sensor);
+criterion_main!(benches);
@@ -3,12 +3,10 @@
 //!
 extern crate chrono;
 extern crate ezo_rtd;
-extern crate i2cdev;
 
 use chrono::{DateTime, Utc};
 use ezo_rtd::errors::*;
-use ezo_rtd::{CommandBuilder, I2cCommand, TemperatureCommand};
-use i2cdev::linux::LinuxI2CDevice;
+use ezo_rtd::RtdSensor;
 use std::thread;
 use std::time::Duration;
 
@@ -16,19 +14,13 @@ const I2C_BUS_ID: u8 = 1;
 const EZO_SENSOR_ADDR: u16 = 101; // could be specified as 0x65
 
 fn run() -> Result<()> {
-    let device_path = format!("/dev/i2c-{}", I2C_BUS_ID);
-    let mut dev = LinuxI2CDevice::new(&device_path, EZO_SENSOR_ADDR)
-        .chain_err(|| "Could not open I2C device")?;
+    let mut sensor = RtdSensor::open(I2C_BUS_ID, EZO_SENSOR_ADDR)?;
     loop {
-        let mut builder = TemperatureCommand::Reading.build();
-        builder.run(&mut dev)?;
-        let temp = builder.parse_response()?;
-        TemperatureCommand::Sleep.build().run(&mut dev)?;
+        let results = sensor.session().read().sleep().execute()?;
         let dt: DateTime<Utc> = Utc::now();
-        println!("{:?},{:.*},°C",
-                 dt,
-                 2,
-                 temp.parse::<f64>().chain_err(|| "unparsable temperature")?);
+        for temp in results.readings() {
+            println!("{:?},{:.*},{}", dt, 2, temp.value(), temp.scale().symbol());
+        }
         thread::sleep(Duration::from_millis(9400));
     }
 }
@@ -2,37 +2,19 @@
 //! An example that retrieves the current settings of the RTD EZO chip.
 //!
 extern crate ezo_rtd;
-extern crate i2cdev;
 
 use ezo_rtd::errors::*;
-use ezo_rtd::{CommandBuilder, I2cCommand, TemperatureCommand};
-use i2cdev::linux::LinuxI2CDevice;
+use ezo_rtd::RtdSensor;
 
 const I2C_BUS_ID: u8 = 1;
 const EZO_SENSOR_ADDR: u16 = 101; // could be specified as 0x65
 
 fn run() -> Result<()> {
-    let device_path = format!("/dev/i2c-{}", I2C_BUS_ID);
-    let mut dev = LinuxI2CDevice::new(&device_path, EZO_SENSOR_ADDR)
-        .chain_err(|| "Could not open I2C device")?;
-    let mut response = String::new();
-    let commands = [TemperatureCommand::Status,
-                    TemperatureCommand::CalibrationState,
-                    TemperatureCommand::DataloggerInterval,
-                    TemperatureCommand::LedState,
-                    TemperatureCommand::ExportInfo,
-                    TemperatureCommand::Export,
-                    TemperatureCommand::Export,
-                    TemperatureCommand::Export,
-                    TemperatureCommand::Sleep];
-    for cmd in commands.iter() {
-        let mut builder = cmd.build();
-        builder.run(&mut dev)?;
-        response += &builder.parse_response()?;
-        response += &"\n";
-    }
-    println!("responses:");
-    println!("{}", response);
+    let mut sensor = RtdSensor::open(I2C_BUS_ID, EZO_SENSOR_ADDR)?;
+    let snapshot = sensor.snapshot()?;
+    println!("state:");
+    println!("{:#?}", snapshot);
+    sensor.sleep()?;
     Ok(())
 }
 
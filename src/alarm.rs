@@ -0,0 +1,343 @@
+//! Alarms on readings, and output lines driven by them.
+//!
+//! An `Alarm` watches readings against high and low thresholds, with
+//! hysteresis so that a reading hovering at a threshold does not make the
+//! alarm flap. A `SupplyAlarm` does the same for the supply voltage the
+//! chip reports in its status: a marginal supply is a common cause of
+//! flaky chips, and is better reported as such. An `Interlock` drives an
+//! output line, such as the GPIO pin of a relay, from an alarm: on for a
+//! chiller while the temperature is high, or for a heater while it is low.
+//! When the sensor fails, the line is set to its fail-safe level, typically
+//! off.
+//!
+//! With the `gpio` feature, `GpioLine` is an output line on a Raspberry Pi
+//! GPIO pin.
+
+use errors::*;
+use response::{DeviceStatus, Temperature, TemperatureScale};
+
+/// The state of an alarm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmState {
+    /// The temperature is within the thresholds.
+    Normal,
+    /// The temperature went above the high threshold.
+    High,
+    /// The temperature went below the low threshold.
+    Low,
+    /// The last reading failed.
+    Fault,
+}
+
+/// Thresholds of an alarm, in `scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub scale: TemperatureScale,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    /// How far back past a threshold the temperature must come for the
+    /// alarm to clear.
+    pub hysteresis: f64,
+}
+
+/// An alarm on readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alarm {
+    thresholds: Thresholds,
+    state: AlarmState,
+}
+
+impl Alarm {
+    pub fn new(thresholds: Thresholds) -> Alarm {
+        Alarm {
+            thresholds: thresholds,
+            state: AlarmState::Normal,
+        }
+    }
+
+    pub fn state(&self) -> AlarmState {
+        self.state
+    }
+
+    /// Updates the state with the latest reading, or its failure, and
+    /// returns the new state.
+    pub fn update(&mut self, reading: &Result<Temperature>) -> AlarmState {
+        let value = match *reading {
+            Ok(temperature) => temperature.to_scale(self.thresholds.scale).value(),
+            Err(_) => {
+                self.state = AlarmState::Fault;
+                return self.state;
+            }
+        };
+        let t = &self.thresholds;
+        let above = |limit: Option<f64>, margin: f64| limit.map_or(false, |l| value > l - margin);
+        let below = |limit: Option<f64>, margin: f64| limit.map_or(false, |l| value < l + margin);
+        self.state = match self.state {
+            AlarmState::High if above(t.high, t.hysteresis) => AlarmState::High,
+            AlarmState::Low if below(t.low, t.hysteresis) => AlarmState::Low,
+            _ if above(t.high, 0.0) => AlarmState::High,
+            _ if below(t.low, 0.0) => AlarmState::Low,
+            _ => AlarmState::Normal,
+        };
+        self.state
+    }
+}
+
+/// The state of a supply voltage alarm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupplyState {
+    /// The supply voltage is above the threshold.
+    Normal,
+    /// The supply voltage went below the threshold; the chip may brown out.
+    Low,
+    /// The status could not be read.
+    Fault,
+}
+
+/// An alarm on the supply voltage reported by `Status`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SupplyAlarm {
+    /// Volts below which the supply is low.
+    low: f64,
+    /// How far above `low` the voltage must come back for the alarm to
+    /// clear, in volts.
+    hysteresis: f64,
+    state: SupplyState,
+    /// The state of the last voltage read, `Normal` or `Low`, kept across
+    /// faults.
+    voltage: SupplyState,
+}
+
+impl SupplyAlarm {
+    /// An alarm for supply voltages below `low` volts. The chips are rated
+    /// for 3.3 V to 5 V.
+    pub fn new(low: f64, hysteresis: f64) -> SupplyAlarm {
+        SupplyAlarm {
+            low: low,
+            hysteresis: hysteresis,
+            state: SupplyState::Normal,
+            voltage: SupplyState::Normal,
+        }
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.low
+    }
+
+    pub fn state(&self) -> SupplyState {
+        self.state
+    }
+
+    /// The state of the last supply voltage read, `Normal` or `Low`; unlike
+    /// `state`, this is not changed by failures to read the status.
+    pub fn voltage_state(&self) -> SupplyState {
+        self.voltage
+    }
+
+    /// Updates the state with the latest status, or its failure, and
+    /// returns the new state. A voltage read after a fault is compared with
+    /// the one read before it.
+    pub fn update(&mut self, status: &Result<DeviceStatus>) -> SupplyState {
+        let vcc = match *status {
+            Ok(ref status) => status.vcc_voltage,
+            Err(_) => {
+                self.state = SupplyState::Fault;
+                return self.state;
+            }
+        };
+        self.voltage = match self.voltage {
+            SupplyState::Low if vcc < self.low + self.hysteresis => SupplyState::Low,
+            _ if vcc < self.low => SupplyState::Low,
+            _ => SupplyState::Normal,
+        };
+        self.state = self.voltage;
+        self.state
+    }
+}
+
+/// An output line, such as a GPIO pin driving a relay.
+pub trait OutputLine {
+    /// Turns the line on or off.
+    fn set(&mut self, on: bool) -> Result<()>;
+}
+
+/// An output line driven by an alarm.
+pub struct Interlock<L: OutputLine> {
+    alarm: Alarm,
+    line: L,
+    /// The state in which the line is on.
+    on_in: AlarmState,
+    /// Whether the line is on while the sensor fails.
+    on_fault: bool,
+}
+
+impl<L: OutputLine> Interlock<L> {
+    /// Drives `line` on while `alarm` is in state `on_in`, and off
+    /// otherwise; while readings fail, the line is on if `on_fault`. For a
+    /// heater, `on_in` is `AlarmState::Low` and `on_fault` is `false`.
+    pub fn new(alarm: Alarm, line: L, on_in: AlarmState, on_fault: bool) -> Interlock<L> {
+        Interlock {
+            alarm: alarm,
+            line: line,
+            on_in: on_in,
+            on_fault: on_fault,
+        }
+    }
+
+    pub fn alarm(&self) -> &Alarm {
+        &self.alarm
+    }
+
+    /// Updates the alarm with the latest reading, or its failure, and sets
+    /// the line accordingly. Returns the alarm's new state.
+    pub fn update(&mut self, reading: &Result<Temperature>) -> Result<AlarmState> {
+        let state = self.alarm.update(reading);
+        let on = match state {
+            AlarmState::Fault => self.on_fault,
+            state => state == self.on_in,
+        };
+        self.line.set(on)?;
+        Ok(state)
+    }
+
+    pub fn into_line(self) -> L {
+        self.line
+    }
+}
+
+#[cfg(feature = "gpio")]
+pub use self::gpio::GpioLine;
+
+#[cfg(feature = "gpio")]
+mod gpio {
+    use rppal::gpio::{Gpio, OutputPin};
+
+    use super::*;
+
+    /// An output line on a Raspberry Pi GPIO pin.
+    pub struct GpioLine {
+        pin: OutputPin,
+        active_low: bool,
+    }
+
+    impl GpioLine {
+        /// Takes the GPIO pin with BCM number `pin` as an output, off to
+        /// begin with. An `active_low` line is on when the pin is low, as
+        /// with many relay boards.
+        pub fn open(pin: u8, active_low: bool) -> Result<GpioLine> {
+            let gpio = Gpio::new().chain_err(|| "Could not open the GPIO controller")?;
+            let pin = gpio.get(pin)
+                .chain_err(|| format!("Could not take GPIO pin {}", pin))?;
+            let mut line = GpioLine {
+                pin: pin.into_output(),
+                active_low: active_low,
+            };
+            line.set(false)?;
+            Ok(line)
+        }
+    }
+
+    impl OutputLine for GpioLine {
+        fn set(&mut self, on: bool) -> Result<()> {
+            if on != self.active_low {
+                self.pin.set_high();
+            } else {
+                self.pin.set_low();
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use response::RestartReason;
+
+    struct Line(Vec<bool>);
+
+    impl OutputLine for Line {
+        fn set(&mut self, on: bool) -> Result<()> {
+            self.0.push(on);
+            Ok(())
+        }
+    }
+
+    fn thresholds() -> Thresholds {
+        Thresholds {
+            scale: TemperatureScale::Celsius,
+            high: Some(30.0),
+            low: Some(20.0),
+            hysteresis: 0.5,
+        }
+    }
+
+    #[test]
+    fn alarms_have_hysteresis() {
+        let mut alarm = Alarm::new(thresholds());
+        let states: Vec<AlarmState> = [25.0, 30.1, 29.8, 29.4, 19.9, 20.3, 20.6]
+            .iter()
+            .map(|&c| alarm.update(&Ok(Temperature::Celsius(c))))
+            .collect();
+        assert_eq!(states,
+                   vec![AlarmState::Normal,
+                        AlarmState::High,
+                        AlarmState::High,
+                        AlarmState::Normal,
+                        AlarmState::Low,
+                        AlarmState::Low,
+                        AlarmState::Normal]);
+        assert_eq!(alarm.update(&Ok(Temperature::Fahrenheit(90.0))),
+                   AlarmState::High);
+    }
+
+    #[test]
+    fn supply_alarms_have_hysteresis() {
+        let mut alarm = SupplyAlarm::new(3.2, 0.1);
+        let states: Vec<SupplyState> = [5.0, 3.19, 3.25, 3.31]
+            .iter()
+            .map(|&vcc| {
+                     alarm.update(&Ok(DeviceStatus {
+                                          restart_reason: RestartReason::PoweredOff,
+                                          vcc_voltage: vcc,
+                                      }))
+                 })
+            .collect();
+        assert_eq!(states,
+                   vec![SupplyState::Normal,
+                        SupplyState::Low,
+                        SupplyState::Low,
+                        SupplyState::Normal]);
+        assert_eq!(alarm.update(&Err("no answer".into())), SupplyState::Fault);
+        assert_eq!(alarm.voltage_state(), SupplyState::Normal);
+    }
+
+    #[test]
+    fn supply_faults_keep_the_voltage_state() {
+        let mut alarm = SupplyAlarm::new(3.2, 0.1);
+        let status = |vcc| {
+            Ok(DeviceStatus {
+                   restart_reason: RestartReason::PoweredOff,
+                   vcc_voltage: vcc,
+               })
+        };
+        alarm.update(&status(3.1));
+        assert_eq!(alarm.update(&Err("no answer".into())), SupplyState::Fault);
+        assert_eq!(alarm.voltage_state(), SupplyState::Low);
+        assert_eq!(alarm.update(&status(3.25)), SupplyState::Low);
+    }
+
+    #[test]
+    fn interlocks_fail_safe() {
+        let mut heater = Interlock::new(Alarm::new(thresholds()),
+                                        Line(vec![]),
+                                        AlarmState::Low,
+                                        false);
+        heater.update(&Ok(Temperature::Celsius(19.0))).unwrap();
+        heater.update(&Err("no answer".into())).unwrap();
+        heater.update(&Ok(Temperature::Celsius(19.0))).unwrap();
+        heater.update(&Ok(Temperature::Celsius(25.0))).unwrap();
+        assert_eq!(heater.alarm().state(), AlarmState::Normal);
+        assert_eq!(heater.into_line().0, vec![true, false, true, false]);
+    }
+}
@@ -0,0 +1,144 @@
+//! Averaging several readings into a steadier one.
+//!
+//! `RtdSensor::read_averaged` takes readings a fixed time apart, drops the
+//! ones that stand out from the rest, and gives their mean and spread.
+//! Outliers are told apart by their distance to the median, in median
+//! absolute deviations, so that one wild reading does not drag the
+//! threshold along with it.
+
+use std::time::{Duration, Instant};
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+use response::Temperature;
+use sensor::RtdSensor;
+
+/// Readings further from the median than this many scaled median absolute
+/// deviations are dropped.
+pub const OUTLIER_DEVIATIONS: f64 = 3.0;
+
+/// Scales the median absolute deviation to a standard deviation, for
+/// normally distributed readings.
+const MAD_SCALE: f64 = 1.4826;
+
+/// Readings this close to the median, two steps of the chip's resolution,
+/// are never outliers.
+const NOISE_FLOOR: f64 = 0.002;
+
+/// The mean of several readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AveragedReading {
+    /// The mean of the readings kept.
+    pub temperature: Temperature,
+    /// Sample standard deviation of the readings kept, in the temperature's
+    /// scale; zero for a single reading.
+    pub spread: f64,
+    /// Readings kept.
+    pub samples: usize,
+    /// Readings dropped as outliers.
+    pub outliers: usize,
+}
+
+/// The mean and sample standard deviation of `values`, without their
+/// outliers, and how many values were kept. `values` must not be empty.
+pub fn average(values: &[f64]) -> (f64, f64, usize) {
+    let middle = median(values.to_vec());
+    let deviations: Vec<f64> = values.iter().map(|value| (value - middle).abs()).collect();
+    let threshold = (OUTLIER_DEVIATIONS * MAD_SCALE * median(deviations)).max(NOISE_FLOOR);
+    let kept: Vec<f64> = values
+        .iter()
+        .cloned()
+        .filter(|value| (value - middle).abs() <= threshold)
+        .collect();
+    let n = kept.len() as f64;
+    let mean = kept.iter().sum::<f64>() / n;
+    let spread = if kept.len() > 1 {
+        (kept.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+    } else {
+        0.0
+    };
+    (mean, spread, kept.len())
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+impl<D: I2CDevice> RtdSensor<D> {
+    /// Takes `n` readings, like `read_temperature`, starting `spacing`
+    /// apart, or back to back if a reading takes longer than that, and
+    /// averages them without their outliers. The wait is made with the
+    /// retry policy's wait strategy. Fails if any reading fails.
+    pub fn read_averaged(&mut self, n: usize, spacing: Duration) -> Result<AveragedReading> {
+        if n == 0 {
+            bail!("an average needs at least one reading");
+        }
+        let wait = self.retry_policy().wait_strategy;
+        let mut values = Vec::with_capacity(n);
+        let mut scale = None;
+        for i in 0..n {
+            let started = Instant::now();
+            let temperature = self.read_temperature()?;
+            // Readings are kept in the scale of the first one, should the
+            // chip's scale change in between.
+            let first = *scale.get_or_insert(temperature.scale());
+            values.push(temperature.to_scale(first).value());
+            let elapsed = started.elapsed();
+            if i + 1 < n && elapsed < spacing {
+                wait.wait(spacing - elapsed);
+            }
+        }
+        let (mean, spread, samples) = average(&values);
+        Ok(AveragedReading {
+               temperature: Temperature::new(scale.unwrap(), mean),
+               spread: spread,
+               samples: samples,
+               outliers: n - samples,
+           })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::RetryPolicy;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn drops_outliers_from_the_average() {
+        let (mean, spread, kept) = average(&[25.0, 25.2, 24.8, 25.1, 24.9, 80.0]);
+        assert!((mean - 25.0).abs() < 1e-9);
+        assert!((spread - 0.158_113_883).abs() < 1e-6);
+        assert_eq!(kept, 5);
+
+        assert_eq!(average(&[25.0, 25.0, 25.001]).2, 3);
+        assert_eq!(average(&[21.5]), (21.5, 0.0, 1));
+    }
+
+    #[test]
+    fn averages_spaced_readings() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(21.5);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy::no_wait());
+
+        let reading = sensor.read_averaged(4, Duration::from_secs(10)).unwrap();
+        assert_eq!(reading,
+                   AveragedReading {
+                       temperature: Temperature::Celsius(21.5),
+                       spread: 0.0,
+                       samples: 4,
+                       outliers: 0,
+                   });
+        assert_eq!(sensor.stats().commands()["R"].count, 4);
+        assert!(sensor.read_averaged(0, Duration::from_secs(1)).is_err());
+    }
+}
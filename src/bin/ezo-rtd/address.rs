@@ -0,0 +1,106 @@
+//! The `set-address` subcommand: moving a chip to another I2C address,
+//! with checks before and after.
+
+use std::thread;
+use std::time::Duration;
+
+use calibrate::confirm;
+use ezo_rtd::RtdSensor;
+use ezo_rtd::discovery;
+use ezo_rtd::errors::*;
+use ezo_rtd::sensor::ADDRESS_REBOOT_DELAY;
+use i2cdev::linux::LinuxI2CDevice;
+use parse_address;
+
+/// Attempts made to reach the chip at its new address.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Returns `true` if something on the bus acknowledges `address`.
+fn is_taken(bus: u8, address: u16) -> Result<bool> {
+    let device_path = format!("/dev/i2c-{}", bus);
+    let mut dev = LinuxI2CDevice::new(&device_path, address)
+        .chain_err(|| format!("Could not open I2C device {}", device_path))?;
+    discovery::acknowledges(&mut dev, address)
+}
+
+/// Runs `set-address [--from ADDR] --to ADDR [--yes]`, or the short form
+/// `set-address ADDR`. The chip is moved from `--from`, or the global
+/// `--address`, after checking that nothing answers at the new address.
+pub fn set_address(bus: u8, address: u16, args: &[String], json: bool) -> Result<()> {
+    let mut from = address;
+    let mut to = None;
+    let mut assume_yes = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => from = parse_address(args.next().ok_or("--from needs a value")?)?,
+            "--to" => to = Some(parse_address(args.next().ok_or("--to needs a value")?)?),
+            "--yes" => assume_yes = true,
+            other if to.is_none() && !other.starts_with("--") => {
+                to = Some(parse_address(other)?)
+            }
+            other => bail!("unknown set-address option `{}`", other),
+        }
+    }
+    let to = match to {
+        Some(to) => to,
+        None => bail!("set-address needs the new address"),
+    };
+    if from == to {
+        bail!("the chip is already at {:#04x}", to);
+    }
+
+    let mut sensor = RtdSensor::open(bus, from)?;
+    let info = sensor
+        .get_device_info()
+        .chain_err(|| format!("no EZO chip answers at {:#04x}", from))?;
+    if is_taken(bus, to)? {
+        bail!("address {:#04x} is already taken on /dev/i2c-{}", to, bus);
+    }
+
+    eprintln!("Moving the {} chip (firmware {}) on /dev/i2c-{} from {:#04x} to {:#04x}.",
+              info.device_type,
+              info.firmware,
+              bus,
+              from,
+              to);
+    eprintln!("The chip reboots, and only answers at the new address afterwards.");
+    if !assume_yes && !confirm("Change the address?")? {
+        bail!("address change cancelled");
+    }
+    sensor.change_address(from, to)?;
+
+    let mut moved = RtdSensor::open(bus, to)?;
+    let mut attempt = 1;
+    let moved_info = loop {
+        thread::sleep(Duration::from_millis(ADDRESS_REBOOT_DELAY));
+        match moved.get_device_info() {
+            Ok(moved_info) => break moved_info,
+            Err(e) => {
+                if attempt == MAX_ATTEMPTS {
+                    return Err(e)
+                               .chain_err(|| format!("the chip does not answer at {:#04x}", to));
+                }
+            }
+        }
+        attempt += 1;
+    };
+    if moved_info != info {
+        bail!("a {} chip answers at {:#04x}, expected {}",
+              moved_info.device_type,
+              to,
+              info.device_type);
+    }
+    if json {
+        println!("{}",
+                 json!({
+                           "from": from,
+                           "to": to,
+                           "device_type": moved_info.device_type,
+                           "firmware": moved_info.firmware,
+                       }));
+    } else {
+        println!("address changed from {:#04x} to {:#04x}", from, to);
+    }
+    Ok(())
+}
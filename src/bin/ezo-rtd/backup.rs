@@ -0,0 +1,125 @@
+//! The `export` and `import` subcommands: backing up a chip's calibration
+//! and cloning it onto another one.
+
+use std::fs::File;
+use std::io::{self, Read};
+
+use chrono::Utc;
+use ezo_rtd::RtdSensor;
+use ezo_rtd::errors::*;
+use ezo_rtd::response::CalibrationString;
+use i2cdev::linux::LinuxI2CDevice;
+use serde_json;
+
+/// A calibration backup, as written by `export --out FILE`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CalibrationFile {
+    device_type: String,
+    firmware: String,
+    exported_at: String,
+    lines: Vec<String>,
+}
+
+/// Runs `export [--out FILE]`. Without `--out`, the calibration strings are
+/// printed one per line, or as a JSON backup with `json`.
+pub fn export(sensor: &mut RtdSensor<LinuxI2CDevice>, args: &[String], json: bool) -> Result<()> {
+    let mut out_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_path = Some(args.next().ok_or("--out needs a value")?),
+            other => bail!("unknown export option `{}`", other),
+        }
+    }
+
+    let lines = sensor.export_calibration()?.lines;
+    if out_path.is_none() && !json {
+        for line in lines {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    let info = sensor.get_device_info()?;
+    let backup = CalibrationFile {
+        device_type: info.device_type,
+        firmware: info.firmware,
+        exported_at: Utc::now().to_rfc3339(),
+        lines: lines,
+    };
+    let path = match out_path {
+        Some(path) => path,
+        None => {
+            println!("{}",
+                     serde_json::to_string(&backup).chain_err(|| "could not encode the backup")?);
+            return Ok(());
+        }
+    };
+    let file = File::create(path).chain_err(|| format!("could not create {}", path))?;
+    serde_json::to_writer_pretty(file, &backup)
+        .chain_err(|| format!("could not write to {}", path))?;
+    if json {
+        println!("{}", json!({ "path": path, "strings": backup.lines.len() }));
+    } else {
+        println!("exported {} calibration strings to {}", backup.lines.len(), path);
+    }
+    Ok(())
+}
+
+/// Reads calibration strings, either from a JSON backup or as plain text
+/// with one string per line.
+fn read_backup<R: Read>(mut reader: R, source: &str) -> Result<(Option<CalibrationFile>, Vec<String>)> {
+    let mut text = String::new();
+    reader
+        .read_to_string(&mut text)
+        .chain_err(|| format!("could not read {}", source))?;
+    if text.trim_start().starts_with('{') {
+        let backup: CalibrationFile = serde_json::from_str(&text)
+            .chain_err(|| format!("{} is not a calibration backup", source))?;
+        let lines = backup.lines.clone();
+        return Ok((Some(backup), lines));
+    }
+    let lines = text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect();
+    Ok((None, lines))
+}
+
+/// Runs `import [FILE]`, reading from stdin when no file is given. The
+/// upload is verified by exporting the calibration back from the chip.
+pub fn import(sensor: &mut RtdSensor<LinuxI2CDevice>, args: &[String], json: bool) -> Result<()> {
+    let (backup, lines) = match args.first() {
+        Some(path) => {
+            let file = File::open(path).chain_err(|| format!("could not open {}", path))?;
+            read_backup(file, path)?
+        }
+        None => read_backup(io::stdin(), "stdin")?,
+    };
+    if lines.is_empty() {
+        bail!("no calibration strings to import");
+    }
+    if let Some(ref backup) = backup {
+        let info = sensor.get_device_info()?;
+        if !info.device_type.eq_ignore_ascii_case(&backup.device_type) {
+            bail!("the backup is from a {} chip, but this is a {} chip",
+                  backup.device_type,
+                  info.device_type);
+        }
+        if info.firmware != backup.firmware {
+            eprintln!("warning: the backup is from firmware {}, this chip runs {}",
+                      backup.firmware,
+                      info.firmware);
+        }
+    }
+    let calibration = CalibrationString::new(lines);
+    sensor
+        .restore_calibration(&calibration)
+        .chain_err(|| "the chip did not accept the calibration")?;
+    if json {
+        println!("{}", json!({ "strings": calibration.lines.len(), "verified": true }));
+    } else {
+        println!("imported and verified {} calibration strings", calibration.lines.len());
+    }
+    Ok(())
+}
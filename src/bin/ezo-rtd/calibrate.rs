@@ -0,0 +1,215 @@
+//! The `calibrate --interactive` subcommand: a guided single-point
+//! calibration against a reference bath.
+
+use std::io::{self, BufRead, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use ezo_rtd::RtdSensor;
+use ezo_rtd::errors::*;
+use ezo_rtd::response::{CalibrationState, Temperature};
+use i2cdev::linux::LinuxI2CDevice;
+
+/// Readings considered when deciding whether the probe has stabilized.
+const STABLE_WINDOW: usize = 5;
+
+/// Largest spread between the readings in the window for the probe to
+/// count as stable, in the chip's scale.
+const STABLE_SPREAD: f64 = 0.05;
+
+/// How long to wait for the probe to stabilize before asking the user
+/// whether to go ahead anyway.
+const STABILIZE_TIMEOUT_SECS: u64 = 300;
+
+/// Largest difference between the reference and a reading taken after
+/// calibrating for the calibration to count as verified.
+const VERIFY_TOLERANCE: f64 = 0.1;
+
+const POLL_INTERVAL_MS: u64 = 1000;
+
+/// Prints a line of the wizard's narration; on stderr with `--json`, where
+/// stdout only gets the final record.
+macro_rules! say {
+    ($json:expr) => {
+        if $json { eprintln!() } else { println!() }
+    };
+    ($json:expr, $($arg:tt)*) => {
+        if $json { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
+
+fn value(temperature: Temperature) -> (f64, &'static str) {
+    match temperature {
+        Temperature::Celsius(value) => (value, "°C"),
+        Temperature::Kelvin(value) => (value, "K"),
+        Temperature::Fahrenheit(value) => (value, "°F"),
+    }
+}
+
+fn spread(readings: &[f64]) -> f64 {
+    let min = readings.iter().cloned().fold(::std::f64::INFINITY, f64::min);
+    let max = readings.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max);
+    max - min
+}
+
+/// Prints `question` and reads one line from stdin. The question goes to
+/// stderr, so that it does not mix with `--json` output.
+fn prompt(question: &str) -> Result<String> {
+    eprint!("{} ", question);
+    io::stderr().flush().chain_err(|| "could not write to stderr")?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .chain_err(|| "could not read from stdin")?;
+    Ok(answer.trim().to_string())
+}
+
+pub fn confirm(question: &str) -> Result<bool> {
+    let answer = prompt(&format!("{} [y/N]", question))?;
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
+
+/// Shows live readings until the last `STABLE_WINDOW` of them agree to
+/// within `STABLE_SPREAD`, and returns the last one. Gives up after
+/// `STABILIZE_TIMEOUT_SECS`, returning `None`.
+fn stabilize(sensor: &mut RtdSensor<LinuxI2CDevice>, json: bool) -> Result<Option<f64>> {
+    let started = Instant::now();
+    let mut readings = Vec::new();
+    loop {
+        let (reading, unit) = value(sensor.read_temperature()?);
+        readings.push(reading);
+        if readings.len() > STABLE_WINDOW {
+            readings.remove(0);
+        }
+        let current = spread(&readings);
+        say!(json,
+             "  {:>9.3} {}   spread over last {} readings: {:.3}",
+             reading,
+             unit,
+             readings.len(),
+             current);
+        if readings.len() == STABLE_WINDOW && current <= STABLE_SPREAD {
+            return Ok(Some(reading));
+        }
+        if started.elapsed() >= Duration::from_secs(STABILIZE_TIMEOUT_SECS) {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
+
+/// Runs `calibrate --interactive [--reference TEMP]`. With `json`, the
+/// calibration record is printed as JSON.
+pub fn calibrate(sensor: &mut RtdSensor<LinuxI2CDevice>,
+                 bus: u8,
+                 address: u16,
+                 args: &[String],
+                 json: bool)
+                 -> Result<()> {
+    let mut interactive = false;
+    let mut reference = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interactive" => interactive = true,
+            "--reference" => {
+                let value = args.next().ok_or("--reference needs a value")?;
+                reference = Some(value
+                                     .parse::<f64>()
+                                     .chain_err(|| format!("invalid reference: {}", value))?);
+            }
+            other => bail!("unknown calibrate option `{}`", other),
+        }
+    }
+    if !interactive {
+        bail!("calibrate needs --interactive; use `cal <temp>` to calibrate directly");
+    }
+
+    let info = sensor.get_device_info()?;
+    let scale = sensor.get_scale()?;
+    say!(json,
+         "Calibrating the {} chip at {:#04x} on /dev/i2c-{} (firmware {}).",
+         info.device_type,
+         address,
+         bus,
+         info.firmware);
+    say!(json, "Readings are in {:?}.", scale);
+    say!(json);
+    say!(json, "Put the probe in a reference bath of known temperature, and stir it");
+    say!(json, "until the readings settle.");
+
+    let reference = match reference {
+        Some(reference) => reference,
+        None => {
+            let answer = prompt("Reference temperature:")?;
+            answer
+                .parse::<f64>()
+                .chain_err(|| format!("invalid reference: {}", answer))?
+        }
+    };
+
+    say!(json);
+    say!(json, "Waiting for the readings to stabilize...");
+    let before = match stabilize(sensor, json)? {
+        Some(reading) => {
+            say!(json, "The readings are stable.");
+            reading
+        }
+        None => {
+            say!(json,
+                 "The readings did not stabilize within {} seconds.",
+                 STABILIZE_TIMEOUT_SECS);
+            if !confirm("Calibrate anyway?")? {
+                bail!("calibration cancelled");
+            }
+            value(sensor.read_temperature()?).0
+        }
+    };
+
+    if !confirm(&format!("Calibrate at {}?", reference))? {
+        bail!("calibration cancelled");
+    }
+    sensor.calibrate(reference)?;
+
+    if let CalibrationState::Uncalibrated = sensor.get_calibration_state()? {
+        bail!("the chip does not report being calibrated");
+    }
+    let (after, unit) = value(sensor.read_temperature()?);
+    let error = (after - reference).abs();
+    let verified = error <= VERIFY_TOLERANCE;
+
+    if json {
+        println!("{}",
+                 json!({
+                           "date": Utc::now().to_rfc3339(),
+                           "bus": bus,
+                           "address": address,
+                           "device_type": info.device_type,
+                           "firmware": info.firmware,
+                           "unit": unit.trim_start_matches('°'),
+                           "reference": reference,
+                           "before": before,
+                           "after": after,
+                           "error": error,
+                           "verified": verified,
+                       }));
+    } else {
+        println!();
+        println!("Calibration record");
+        println!("  date:                {}", Utc::now().to_rfc3339());
+        println!("  device:              /dev/i2c-{} address {:#04x}", bus, address);
+        println!("  chip:                {} firmware {}", info.device_type, info.firmware);
+        println!("  reference:           {:.3} {}", reference, unit);
+        println!("  reading before:      {:.3} {}", before, unit);
+        println!("  reading after:       {:.3} {}", after, unit);
+        println!("  error after:         {:.3} {}", error, unit);
+        println!("  verified:            {}", if verified { "yes" } else { "no" });
+    }
+
+    if !verified {
+        bail!("the reading after calibrating is {:.3} away from the reference", error);
+    }
+    Ok(())
+}
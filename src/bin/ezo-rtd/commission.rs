@@ -0,0 +1,83 @@
+//! The `commission` subcommand: giving the chips of a board their
+//! addresses and names, one chip at a time.
+
+use std::thread;
+use std::time::Duration;
+
+use ezo_rtd::RtdSensor;
+use ezo_rtd::commission::{Commissioning, Step};
+use ezo_rtd::discovery::FIRST_ADDRESS;
+use ezo_rtd::errors::*;
+use i2cdev::linux::LinuxI2CDevice;
+use parse_address;
+
+/// How often the factory address is checked for the next chip.
+const POLL_INTERVAL_MS: u64 = 1000;
+
+/// Runs `commission --first ADDR --count N [--prefix NAME]`.
+pub fn commission(bus: u8, args: &[String], json: bool) -> Result<()> {
+    let mut first = None;
+    let mut count = None;
+    let mut prefix = "rtd".to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--first" => first = Some(parse_address(args.next().ok_or("--first needs a value")?)?),
+            "--count" => {
+                let value = args.next().ok_or("--count needs a value")?;
+                count = Some(value
+                                 .parse::<usize>()
+                                 .chain_err(|| format!("invalid count: {}", value))?);
+            }
+            "--prefix" => prefix = args.next().ok_or("--prefix needs a value")?.clone(),
+            other => bail!("unknown commission option `{}`", other),
+        }
+    }
+    let (first, count) = match (first, count) {
+        (Some(first), Some(count)) => (first, count),
+        _ => bail!("usage: ezo-rtd commission --first ADDR --count N [--prefix NAME]"),
+    };
+    let mut commissioning = Commissioning::sequential(&prefix, first, count)?;
+
+    let device_path = format!("/dev/i2c-{}", bus);
+    let dev = LinuxI2CDevice::new(&device_path, FIRST_ADDRESS)
+        .chain_err(|| format!("Could not open I2C device {}", device_path))?;
+    let mut sensor = RtdSensor::new(dev);
+    let mut waiting = false;
+    loop {
+        match commissioning.step(&mut sensor)? {
+            Step::WaitingForChip(next) => {
+                if !waiting {
+                    eprintln!("Connect chip {} of {}, alone at the factory address; it \
+                               becomes {} at {:#04x}.",
+                              commissioning.done() + 1,
+                              count,
+                              next.name,
+                              next.address);
+                    waiting = true;
+                }
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+            }
+            Step::Commissioned(assignment, info) => {
+                waiting = false;
+                if json {
+                    println!("{}",
+                             json!({
+                                       "name": assignment.name,
+                                       "address": assignment.address,
+                                       "device_type": info.device_type,
+                                       "firmware": info.firmware,
+                                   }));
+                } else {
+                    println!("{} at {:#04x}: done (firmware {})",
+                             assignment.name,
+                             assignment.address,
+                             info.firmware);
+                }
+            }
+            Step::Finished => break,
+        }
+    }
+    eprintln!("All {} chips are commissioned.", count);
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! The configuration file, `~/.config/ezo-rtd/config.toml`.
+//!
+//! ```toml
+//! bus = 1
+//! address = 0x66
+//! units = "C"
+//!
+//! [retry]
+//! attempts = 3
+//! delay_ms = 1000
+//!
+//! [sensors.sump]
+//! address = 0x66
+//!
+//! [sensors.mash-tun]
+//! bus = 0
+//! ```
+//!
+//! A sensor without an address is looked up by scanning its bus for a chip
+//! with the same name, as set with `Name,n`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+#[cfg(feature = "daemon")]
+use daemon::DaemonConfig;
+use ezo_rtd::discovery;
+use ezo_rtd::errors::*;
+use ezo_rtd::response::TemperatureScale;
+use toml;
+
+/// How often, and how patiently, a failed reading is tried again.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts made for one reading before it is given up on.
+    pub attempts: u32,
+    /// Time to wait between attempts, in milliseconds.
+    pub delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            delay_ms: 1000,
+        }
+    }
+}
+
+/// Where to find one named sensor.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    pub bus: Option<u8>,
+    pub address: Option<u16>,
+}
+
+/// The contents of the configuration file. Every field is optional.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub bus: Option<u8>,
+    pub address: Option<u16>,
+    /// Unit readings are shown in, whatever the chip's scale: `C`, `K` or
+    /// `F`.
+    pub units: Option<String>,
+    pub retry: RetryPolicy,
+    pub sensors: BTreeMap<String, SensorConfig>,
+    #[cfg(feature = "daemon")]
+    pub daemon: DaemonConfig,
+}
+
+/// The default location of the configuration file, honoring
+/// `XDG_CONFIG_HOME`.
+fn default_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("ezo-rtd").join("config.toml"))
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or from the default location.
+    /// A missing file at the default location yields the defaults.
+    pub fn load(path: Option<&str>) -> Result<Config> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                match default_path() {
+                    Some(ref path) if path.exists() => path.clone(),
+                    _ => return Ok(Config::default()),
+                }
+            }
+        };
+        let mut text = String::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .chain_err(|| format!("could not read {}", path.display()))?;
+        let config: Config = toml::from_str(&text)
+            .chain_err(|| format!("invalid configuration in {}", path.display()))?;
+        config.units()?;
+        Ok(config)
+    }
+
+    /// The unit readings are to be shown in, if one is configured.
+    pub fn units(&self) -> Result<Option<TemperatureScale>> {
+        match self.units {
+            Some(ref units) => {
+                Ok(Some(units.parse::<TemperatureScale>().chain_err(|| "invalid units")?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The entry for the sensor called `name`.
+    pub fn sensor(&self, name: &str) -> Result<&SensorConfig> {
+        match self.sensors.get(name) {
+            Some(sensor) => Ok(sensor),
+            None => bail!("no sensor named `{}` in the configuration", name),
+        }
+    }
+}
+
+/// Scans `bus` for the chip called `name`, and returns its address.
+pub fn find_by_name(bus: u8, name: &str) -> Result<u16> {
+    match discovery::scan_bus(bus)?
+              .into_iter()
+              .find(|device| device.name.as_ref().map(|n| n.as_str()) == Some(name)) {
+        Some(device) => Ok(device.address),
+        None => bail!("no chip named `{}` found on /dev/i2c-{}", name, bus),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    /// Writes `text` to a file for one test, and returns its path.
+    fn scratch(name: &str, text: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ezo-rtd-config-{}-{}", process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_a_configuration_file() {
+        let path = scratch("valid.toml",
+                           "bus = 2\n\
+                            address = 0x64\n\
+                            units = \"F\"\n\
+                            \n\
+                            [retry]\n\
+                            attempts = 5\n\
+                            \n\
+                            [sensors.sump]\n\
+                            address = 0x66\n\
+                            \n\
+                            [sensors.mash-tun]\n\
+                            bus = 0\n");
+        let config = Config::load(path.to_str()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.bus, Some(2));
+        assert_eq!(config.address, Some(0x64));
+        assert_eq!(config.units().unwrap(), Some(TemperatureScale::Fahrenheit));
+        assert_eq!(config.retry.attempts, 5);
+        assert_eq!(config.retry.delay_ms, 1000);
+        assert_eq!(config.sensor("sump").unwrap().address, Some(0x66));
+        assert_eq!(config.sensor("sump").unwrap().bus, None);
+        assert_eq!(config.sensor("mash-tun").unwrap().bus, Some(0));
+        assert_eq!(config.sensor("mash-tun").unwrap().address, None);
+        assert!(config.sensor("boiler").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_ignored() {
+        let path = scratch("unknown.toml",
+                           "bus = 1\n\
+                            colour = \"blue\"\n\
+                            \n\
+                            [sensors.sump]\n\
+                            address = 0x66\n\
+                            location = \"basement\"\n\
+                            \n\
+                            [plotting]\n\
+                            width = 80\n");
+        let config = Config::load(path.to_str()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.bus, Some(1));
+        assert_eq!(config.sensor("sump").unwrap().address, Some(0x66));
+    }
+
+    #[test]
+    fn bad_values_are_refused() {
+        let path = scratch("units.toml", "units = \"R\"\n");
+        assert!(Config::load(path.to_str()).is_err());
+        fs::write(&path, "address = \"sump\"\n").unwrap();
+        assert!(Config::load(path.to_str()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_missing_file_is_an_error_unless_it_is_the_default() {
+        let path = env::temp_dir().join(format!("ezo-rtd-config-{}-missing.toml", process::id()));
+        assert!(Config::load(path.to_str()).is_err());
+
+        let home = env::temp_dir().join(format!("ezo-rtd-config-{}-home", process::id()));
+        env::set_var("XDG_CONFIG_HOME", &home);
+        let config = Config::load(None).unwrap();
+        assert_eq!(config.bus, None);
+        assert_eq!(config.address, None);
+        assert!(config.sensors.is_empty());
+    }
+}
@@ -0,0 +1,945 @@
+//! The `daemon` subcommand: polling the configured sensors forever and
+//! publishing their readings to the configured sinks.
+//!
+//! Built with the `daemon` feature. The `[daemon]` section of the
+//! configuration file lists the sinks:
+//!
+//! ```toml
+//! [daemon]
+//! interval = "30s"
+//!
+//! [[daemon.sinks]]
+//! type = "csv"
+//! path = "/var/log/ezo-rtd.csv"
+//!
+//! [[daemon.sinks]]
+//! type = "mqtt"
+//! host = "localhost"
+//! topic = "home/temperature"
+//!
+//! [[daemon.sinks]]
+//! type = "sqlite"
+//! path = "/var/lib/ezo-rtd/readings.db"
+//! ```
+//!
+//! With the `redis-sink` feature, readings can also go to Redis, either as
+//! RedisTimeSeries samples (`TS.ADD`) or as stream entries (`XADD`), under
+//! `<key_prefix>:<sensor>`:
+//!
+//! ```toml
+//! [[daemon.sinks]]
+//! type = "redis"
+//! url = "redis://localhost/"
+//! key_prefix = "temperature"
+//! mode = "stream"
+//! ```
+//!
+//! With the `kafka-sink` feature, readings, and optionally the events of the
+//! sensors, are sent to Kafka topics as JSON, keyed by sensor name. They
+//! are sent in batches, once `batch_size` of them are waiting or the oldest
+//! has waited for `linger`, and at the end of each round of readings when
+//! `linger` is unset:
+//!
+//! ```toml
+//! [[daemon.sinks]]
+//! type = "kafka"
+//! brokers = ["kafka-1:9092", "kafka-2:9092"]
+//! topic = "plant.temperature"
+//! event_topic = "plant.sensor-events"
+//! batch_size = 500
+//! linger = "5m"
+//! ```
+//!
+//! Sending `SIGHUP` reloads the configuration file before the next round of
+//! readings.
+//!
+//! With `rescan = "5m"`, the EZO factory addresses and those of the
+//! configured sensors are scanned that often: every RTD chip found is
+//! polled, under its configured name if it has one, and chips that
+//! disappear are no longer polled.
+
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use config::{self, Config};
+use ezo_rtd::RtdSensor;
+use ezo_rtd::errors::*;
+use ezo_rtd::events::DeviceEvent;
+use ezo_rtd::hotplug::{BusWatcher, HotplugEvent};
+use ezo_rtd::response::{Temperature, TemperatureScale};
+use ezo_rtd::sink::{ReadingSink, Sinks, TimestampedReading};
+use i2cdev::linux::LinuxI2CDevice;
+#[cfg(feature = "kafka-sink")]
+use kafka;
+#[cfg(feature = "kafka-sink")]
+use kafka::producer::{Producer, Record, RequiredAcks};
+use rumqttc::{Client, ConnectionError, MqttOptions, QoS};
+use rusqlite::{self, Connection};
+use rusqlite::types::ToSql;
+use serde_json::Value;
+#[cfg(feature = "redis-sink")]
+use redis;
+use signal_hook;
+use temperature_json;
+use watch::{parse_interval, read_with_retries};
+use DEFAULT_BUS;
+
+/// Time between rounds of readings when `interval` is not configured.
+const DEFAULT_INTERVAL: &str = "10s";
+
+const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// Readings the MQTT client holds while the broker is unreachable; later
+/// ones are dropped.
+const MQTT_QUEUE: usize = 64;
+
+#[cfg(feature = "kafka-sink")]
+const DEFAULT_KAFKA_BATCH_SIZE: usize = 100;
+
+#[cfg(feature = "redis-sink")]
+const DEFAULT_REDIS_PREFIX: &str = "ezo-rtd";
+
+/// The `[daemon]` section of the configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Time between rounds of readings, as taken by `watch --interval`.
+    pub interval: Option<String>,
+    /// Time between scans of the buses for attached or removed chips; the
+    /// buses are not rescanned when unset.
+    pub rescan: Option<String>,
+    pub sinks: Vec<SinkConfig>,
+}
+
+/// Where readings are published.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    /// Appends `timestamp,sensor,temperature,unit` lines to a file.
+    Csv { path: String },
+    /// Publishes a JSON reading to `<topic>/<sensor>`.
+    Mqtt {
+        host: String,
+        port: Option<u16>,
+        topic: String,
+        client_id: Option<String>,
+    },
+    /// Inserts rows into a `readings` table, created if needed.
+    Sqlite { path: String },
+    /// Adds the reading to `<key_prefix>:<sensor>`, a time series by
+    /// default.
+    #[cfg(feature = "redis-sink")]
+    Redis {
+        url: String,
+        key_prefix: Option<String>,
+        mode: Option<RedisMode>,
+    },
+    /// Sends a JSON reading, keyed by sensor name, to `topic`, and the
+    /// sensors' events to `event_topic`, if set.
+    #[cfg(feature = "kafka-sink")]
+    Kafka {
+        brokers: Vec<String>,
+        topic: String,
+        event_topic: Option<String>,
+        batch_size: Option<usize>,
+        linger: Option<String>,
+    },
+}
+
+/// How readings are stored in Redis.
+#[cfg(feature = "redis-sink")]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisMode {
+    /// RedisTimeSeries samples, with `TS.ADD`; the series is created with
+    /// `sensor` and `unit` labels.
+    TimeSeries,
+    /// Stream entries with `timestamp`, `temperature` and `unit` fields,
+    /// with `XADD`.
+    Stream,
+}
+
+fn value_and_unit(temperature: Temperature) -> (f64, &'static str) {
+    match temperature {
+        Temperature::Celsius(value) => (value, "C"),
+        Temperature::Kelvin(value) => (value, "K"),
+        Temperature::Fahrenheit(value) => (value, "F"),
+    }
+}
+
+fn event_json(event: &DeviceEvent) -> Value {
+    match *event {
+        DeviceEvent::Woke => json!({ "event": "woke" }),
+        DeviceEvent::Slept => json!({ "event": "slept" }),
+        DeviceEvent::Calibrated(temperature) => {
+            json!({ "event": "calibrated", "temperature": temperature })
+        }
+        DeviceEvent::ScaleChanged(scale) => {
+            let unit = match scale {
+                TemperatureScale::Celsius => "C",
+                TemperatureScale::Kelvin => "K",
+                TemperatureScale::Fahrenheit => "F",
+            };
+            json!({ "event": "scale_changed", "unit": unit })
+        }
+        DeviceEvent::AddressChanged(address) => {
+            json!({ "event": "address_changed", "address": address })
+        }
+        DeviceEvent::RecoveredFromError(failures) => {
+            json!({ "event": "recovered_from_error", "failures": failures })
+        }
+    }
+}
+
+struct CsvSink {
+    path: String,
+    file: File,
+}
+
+impl CsvSink {
+    fn open(path: &str) -> Result<CsvSink> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .chain_err(|| format!("could not open {}", path))?;
+        let is_empty = file.metadata()
+            .map(|metadata| metadata.len() == 0)
+            .unwrap_or(false);
+        if is_empty {
+            writeln!(file, "timestamp,sensor,temperature,unit")
+                .chain_err(|| format!("could not write to {}", path))?;
+        }
+        Ok(CsvSink {
+               path: path.to_string(),
+               file: file,
+           })
+    }
+}
+
+impl ReadingSink for CsvSink {
+    fn accept(&mut self, reading: &TimestampedReading) -> Result<()> {
+        let sensor = reading.sensor.as_str();
+        let timestamp = DateTime::<Utc>::from(reading.time);
+        let temperature = reading.temperature;
+        let (value, unit) = value_and_unit(temperature);
+        writeln!(self.file,
+                 "{},{},{:.3},{}",
+                 timestamp.to_rfc3339(),
+                 sensor,
+                 value,
+                 unit)
+                .chain_err(|| format!("could not write to {}", self.path))
+    }
+}
+
+struct MqttSink {
+    client: Client,
+    topic: String,
+    /// Readings dropped because the client's queue was full.
+    dropped: u64,
+}
+
+impl MqttSink {
+    /// Connects in the background; up to `MQTT_QUEUE` readings published
+    /// while the broker is unreachable are queued by the client, and later
+    /// ones are dropped rather than hold up the polling.
+    fn connect(host: &str, port: u16, topic: &str, client_id: &str) -> MqttSink {
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut connection) = Client::new(options, MQTT_QUEUE);
+        let host = host.to_string();
+        thread::spawn(move || for notification in connection.iter() {
+                          match notification {
+                              Ok(_) => {}
+                              Err(ConnectionError::RequestsDone) => break,
+                              Err(e) => {
+                                  eprintln!("warning: MQTT connection to {}: {}", host, e);
+                                  thread::sleep(Duration::from_secs(5));
+                              }
+                          }
+                      });
+        MqttSink {
+            client: client,
+            topic: topic.trim_end_matches('/').to_string(),
+            dropped: 0,
+        }
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        let _ = self.client.try_disconnect();
+    }
+}
+
+impl ReadingSink for MqttSink {
+    fn accept(&mut self, reading: &TimestampedReading) -> Result<()> {
+        let sensor = reading.sensor.as_str();
+        let timestamp = DateTime::<Utc>::from(reading.time);
+        let temperature = reading.temperature;
+        let mut payload = temperature_json(temperature);
+        payload["timestamp"] = json!(timestamp.to_rfc3339());
+        let published = self.client
+            .try_publish(format!("{}/{}", self.topic, sensor),
+                         QoS::AtLeastOnce,
+                         false,
+                         payload.to_string());
+        if let Err(e) = published {
+            self.dropped += 1;
+            bail!("could not queue the reading for MQTT ({} dropped so far): {}",
+                  self.dropped,
+                  e);
+        }
+        Ok(())
+    }
+}
+
+struct SqliteSink {
+    connection: Connection,
+}
+
+impl SqliteSink {
+    fn open(path: &str) -> Result<SqliteSink> {
+        let connection = Connection::open(path)
+            .chain_err(|| format!("could not open {}", path))?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS readings (
+                          timestamp TEXT NOT NULL,
+                          sensor TEXT NOT NULL,
+                          temperature REAL NOT NULL,
+                          unit TEXT NOT NULL
+                      )",
+                     &[] as &[&dyn ToSql])
+            .chain_err(|| format!("could not create the readings table in {}", path))?;
+        Ok(SqliteSink { connection: connection })
+    }
+}
+
+impl ReadingSink for SqliteSink {
+    fn accept(&mut self, reading: &TimestampedReading) -> Result<()> {
+        let sensor = reading.sensor.as_str();
+        let timestamp = DateTime::<Utc>::from(reading.time);
+        let temperature = reading.temperature;
+        let (value, unit) = value_and_unit(temperature);
+        let timestamp = timestamp.to_rfc3339();
+        self.connection
+            .execute("INSERT INTO readings (timestamp, sensor, temperature, unit)
+                      VALUES (?1, ?2, ?3, ?4)",
+                     &[&timestamp as &dyn ToSql, &sensor, &value, &unit])
+            .map(|_| ())
+            .map_err(|e: rusqlite::Error| Error::from(e.to_string()))
+            .chain_err(|| "could not store the reading")
+    }
+}
+
+#[cfg(feature = "redis-sink")]
+struct RedisSink {
+    client: redis::Client,
+    /// Opened on the first reading, and again after an error, so that the
+    /// daemon outlives restarts of the server.
+    connection: Option<redis::Connection>,
+    key_prefix: String,
+    mode: RedisMode,
+}
+
+#[cfg(feature = "redis-sink")]
+impl RedisSink {
+    fn open(url: &str, key_prefix: &str, mode: RedisMode) -> Result<RedisSink> {
+        let client = redis::Client::open(url)
+            .map_err(|e: redis::RedisError| Error::from(e.to_string()))
+            .chain_err(|| format!("invalid Redis URL {}", url))?;
+        Ok(RedisSink {
+               client: client,
+               connection: None,
+               key_prefix: key_prefix.trim_end_matches(':').to_string(),
+               mode: mode,
+           })
+    }
+
+    fn command(&self,
+               key: &str,
+               sensor: &str,
+               timestamp: &DateTime<Utc>,
+               temperature: Temperature)
+               -> redis::Cmd {
+        let (value, unit) = value_and_unit(temperature);
+        match self.mode {
+            RedisMode::TimeSeries => {
+                let mut cmd = redis::cmd("TS.ADD");
+                cmd.arg(key)
+                    .arg(timestamp.timestamp_millis())
+                    .arg(value)
+                    .arg("LABELS")
+                    .arg("sensor")
+                    .arg(sensor)
+                    .arg("unit")
+                    .arg(unit);
+                cmd
+            }
+            RedisMode::Stream => {
+                let mut cmd = redis::cmd("XADD");
+                cmd.arg(key)
+                    .arg("*")
+                    .arg("timestamp")
+                    .arg(timestamp.to_rfc3339())
+                    .arg("temperature")
+                    .arg(value)
+                    .arg("unit")
+                    .arg(unit);
+                cmd
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis-sink")]
+impl ReadingSink for RedisSink {
+    fn accept(&mut self, reading: &TimestampedReading) -> Result<()> {
+        let sensor = reading.sensor.as_str();
+        let timestamp = DateTime::<Utc>::from(reading.time);
+        let temperature = reading.temperature;
+        let key = format!("{}:{}", self.key_prefix, sensor);
+        let cmd = self.command(&key, sensor, &timestamp, temperature);
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                self.client
+                    .get_connection()
+                    .map_err(|e: redis::RedisError| Error::from(e.to_string()))
+                    .chain_err(|| "could not connect to Redis")?
+            }
+        };
+        cmd.query::<()>(&mut connection)
+            .map_err(|e: redis::RedisError| Error::from(e.to_string()))
+            .chain_err(|| format!("could not add the reading to {}", key))?;
+        // Kept only on success: after an error, the next reading reconnects.
+        self.connection = Some(connection);
+        Ok(())
+    }
+}
+
+/// Whether `waiting` records, the oldest added `waited` ago, are to be sent
+/// to Kafka now. A full batch always is; otherwise, at the end of a round of
+/// readings, any waiting records are once they have lingered long enough.
+#[cfg(feature = "kafka-sink")]
+fn batch_due(waiting: usize,
+             batch_size: usize,
+             waited: Duration,
+             linger: Option<Duration>,
+             end_of_round: bool)
+             -> bool {
+    if waiting >= batch_size {
+        return true;
+    }
+    end_of_round && waiting > 0 && linger.map_or(true, |linger| waited >= linger)
+}
+
+#[cfg(feature = "kafka-sink")]
+struct KafkaSink {
+    brokers: Vec<String>,
+    /// Created when the first batch is sent, and again after an error.
+    producer: Option<Producer>,
+    topic: String,
+    event_topic: Option<String>,
+    batch_size: usize,
+    linger: Option<Duration>,
+    /// Topic, key and value of the records waiting to be sent.
+    batch: Vec<(String, String, String)>,
+    /// When the oldest waiting record was added.
+    oldest: Option<Instant>,
+}
+
+#[cfg(feature = "kafka-sink")]
+impl KafkaSink {
+    fn new(brokers: &[String],
+           topic: &str,
+           event_topic: Option<&str>,
+           batch_size: usize,
+           linger: Option<Duration>)
+           -> Result<KafkaSink> {
+        if brokers.is_empty() {
+            bail!("the Kafka sink needs at least one broker");
+        }
+        if batch_size == 0 {
+            bail!("the Kafka batch size must be at least 1");
+        }
+        Ok(KafkaSink {
+               brokers: brokers.to_vec(),
+               producer: None,
+               topic: topic.to_string(),
+               event_topic: event_topic.map(|topic| topic.to_string()),
+               batch_size: batch_size,
+               linger: linger,
+               batch: Vec::new(),
+               oldest: None,
+           })
+    }
+
+    fn add(&mut self, topic: String, key: &str, value: Value) -> Result<()> {
+        if self.batch.is_empty() {
+            self.oldest = Some(Instant::now());
+        }
+        self.batch.push((topic, key.to_string(), value.to_string()));
+        if self.due(false) { self.send() } else { Ok(()) }
+    }
+
+    fn due(&self, end_of_round: bool) -> bool {
+        let waited = self.oldest
+            .map(|oldest| oldest.elapsed())
+            .unwrap_or_default();
+        batch_due(self.batch.len(), self.batch_size, waited, self.linger, end_of_round)
+    }
+
+    /// Sends the waiting records. They are dropped if they cannot be sent,
+    /// rather than piling up while the brokers are unreachable.
+    fn send(&mut self) -> Result<()> {
+        self.oldest = None;
+        let batch = mem::replace(&mut self.batch, Vec::new());
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut producer = match self.producer.take() {
+            Some(producer) => producer,
+            None => {
+                Producer::from_hosts(self.brokers.clone())
+                    .with_ack_timeout(Duration::from_secs(5))
+                    .with_required_acks(RequiredAcks::One)
+                    .create()
+                    .map_err(|e: kafka::Error| Error::from(e.to_string()))
+                    .chain_err(|| {
+                                   format!("could not connect to Kafka at {}",
+                                           self.brokers.join(","))
+                               })?
+            }
+        };
+        let records: Vec<_> = batch
+            .iter()
+            .map(|&(ref topic, ref key, ref value)| {
+                     Record::from_key_value(topic.as_str(), key.as_bytes(), value.as_bytes())
+                 })
+            .collect();
+        producer
+            .send_all(&records)
+            .map_err(|e: kafka::Error| Error::from(e.to_string()))
+            .chain_err(|| format!("could not send {} records to Kafka", records.len()))?;
+        self.producer = Some(producer);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kafka-sink")]
+impl Drop for KafkaSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.send() {
+            eprintln!("warning: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "kafka-sink")]
+impl ReadingSink for KafkaSink {
+    fn accept(&mut self, reading: &TimestampedReading) -> Result<()> {
+        let sensor = reading.sensor.as_str();
+        let timestamp = DateTime::<Utc>::from(reading.time);
+        let temperature = reading.temperature;
+        let mut value = temperature_json(temperature);
+        value["sensor"] = json!(sensor);
+        value["timestamp"] = json!(timestamp.to_rfc3339());
+        let topic = self.topic.clone();
+        self.add(topic, sensor, value)
+    }
+
+    fn accept_event(&mut self, sensor: &str, time: SystemTime, event: &DeviceEvent) -> Result<()> {
+        let topic = match self.event_topic {
+            Some(ref topic) => topic.clone(),
+            None => return Ok(()),
+        };
+        let mut value = event_json(event);
+        value["sensor"] = json!(sensor);
+        value["timestamp"] = json!(DateTime::<Utc>::from(time).to_rfc3339());
+        self.add(topic, sensor, value)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.due(true) { self.send() } else { Ok(()) }
+    }
+}
+
+/// A sensor being polled.
+struct Polled {
+    name: String,
+    bus: u8,
+    address: u16,
+    sensor: RtdSensor<LinuxI2CDevice>,
+    events: Receiver<DeviceEvent>,
+}
+
+impl Polled {
+    fn open(name: String, bus: u8, address: u16) -> Result<Polled> {
+        let mut sensor = RtdSensor::open(bus, address)?;
+        let events = sensor.subscribe_channel();
+        Ok(Polled {
+               name: name,
+               bus: bus,
+               address: address,
+               sensor: sensor,
+               events: events,
+           })
+    }
+}
+
+/// Everything the daemon builds from the configuration.
+struct Daemon {
+    config: Config,
+    interval: Duration,
+    sensors: Vec<Polled>,
+    sinks: Sinks,
+    /// One per polled bus, when rescanning is configured.
+    watchers: Vec<BusWatcher>,
+}
+
+impl Daemon {
+    /// Opens the configured sensors, or the one at `bus` and `address` when
+    /// none are configured, and the configured sinks.
+    fn new(config: Config, bus: u8, address: u16) -> Result<Daemon> {
+        let interval = parse_interval(config
+                                          .daemon
+                                          .interval
+                                          .as_ref()
+                                          .map(|interval| interval.as_str())
+                                          .unwrap_or(DEFAULT_INTERVAL))?;
+
+        let mut sensors = Vec::new();
+        for (name, sensor) in &config.sensors {
+            let bus = sensor.bus.or(config.bus).unwrap_or(DEFAULT_BUS);
+            let address = match sensor.address {
+                Some(address) => address,
+                None => config::find_by_name(bus, name)?,
+            };
+            sensors.push(Polled::open(name.clone(), bus, address)?);
+        }
+        if sensors.is_empty() {
+            sensors.push(Polled::open(format!("{:#04x}", address), bus, address)?);
+        }
+
+        let watchers = match config.daemon.rescan {
+            Some(ref rescan) => {
+                let rescan = parse_interval(rescan)?;
+                let buses: BTreeSet<u8> = sensors.iter().map(|polled| polled.bus).collect();
+                buses
+                    .into_iter()
+                    .map(|bus| {
+                        let mut watcher = BusWatcher::new(bus, rescan);
+                        for polled in sensors.iter().filter(|polled| polled.bus == bus) {
+                            watcher.watch_address(polled.address);
+                        }
+                        watcher
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        let mut sinks = Sinks::new();
+        for sink in &config.daemon.sinks {
+            sinks.add_boxed(match *sink {
+                           SinkConfig::Csv { ref path } => Box::new(CsvSink::open(path)?),
+                           SinkConfig::Mqtt {
+                               ref host,
+                               port,
+                               ref topic,
+                               ref client_id,
+                           } => {
+                               let client_id = client_id
+                                   .clone()
+                                   .unwrap_or_else(|| "ezo-rtd".to_string());
+                               Box::new(MqttSink::connect(host,
+                                                          port.unwrap_or(DEFAULT_MQTT_PORT),
+                                                          topic,
+                                                          &client_id))
+                           }
+                           SinkConfig::Sqlite { ref path } => Box::new(SqliteSink::open(path)?),
+                           #[cfg(feature = "redis-sink")]
+                           SinkConfig::Redis {
+                               ref url,
+                               ref key_prefix,
+                               mode,
+                           } => {
+                               let key_prefix = key_prefix
+                                   .as_ref()
+                                   .map(|prefix| prefix.as_str())
+                                   .unwrap_or(DEFAULT_REDIS_PREFIX);
+                               Box::new(RedisSink::open(url,
+                                                        key_prefix,
+                                                        mode.unwrap_or(RedisMode::TimeSeries))?)
+                           }
+                           #[cfg(feature = "kafka-sink")]
+                           SinkConfig::Kafka {
+                               ref brokers,
+                               ref topic,
+                               ref event_topic,
+                               batch_size,
+                               ref linger,
+                           } => {
+                               let linger = match *linger {
+                                   Some(ref linger) => Some(parse_interval(linger)?),
+                                   None => None,
+                               };
+                               Box::new(KafkaSink::new(brokers,
+                                                       topic,
+                                                       event_topic.as_ref().map(|t| t.as_str()),
+                                                       batch_size
+                                                           .unwrap_or(DEFAULT_KAFKA_BATCH_SIZE),
+                                                       linger)?)
+                           }
+                       });
+        }
+        if sinks.is_empty() {
+            bail!("no sinks configured; add [[daemon.sinks]] to the configuration file");
+        }
+
+        Ok(Daemon {
+               config: config,
+               interval: interval,
+               sensors: sensors,
+               sinks: sinks,
+               watchers: watchers,
+           })
+    }
+
+    /// The name to poll a newly attached chip under: that of the configured
+    /// sensor it matches, else the chip's own name, else its address.
+    fn name_for(&self, bus: u8, address: u16, chip_name: Option<&str>) -> String {
+        let config = &self.config;
+        config
+            .sensors
+            .iter()
+            .find(|&(name, sensor)| {
+                      sensor.bus.or(config.bus).unwrap_or(DEFAULT_BUS) == bus &&
+                      match sensor.address {
+                          Some(configured) => configured == address,
+                          None => chip_name == Some(name.as_str()),
+                      }
+                  })
+            .map(|(name, _)| name.clone())
+            .or_else(|| chip_name.map(|name| name.to_string()))
+            .unwrap_or_else(|| format!("{:#04x}", address))
+    }
+
+    /// Rescans the buses that are due, and starts or stops polling the chips
+    /// that were attached or removed. Failed scans are reported and skipped.
+    fn rescan(&mut self) {
+        let mut events = Vec::new();
+        for watcher in &mut self.watchers {
+            match watcher.poll() {
+                Ok(found) => events.extend(found),
+                Err(e) => eprintln!("warning: could not rescan bus {}: {}", watcher.bus(), e),
+            }
+        }
+        for event in events {
+            let (bus, address) = (event.bus(), event.device().address);
+            let polled = self.sensors
+                .iter()
+                .position(|polled| polled.bus == bus && polled.address == address);
+            match event {
+                HotplugEvent::Detached { .. } => {
+                    if let Some(index) = polled {
+                        let polled = self.sensors.remove(index);
+                        eprintln!("note: {} at {:#04x} on bus {} was removed",
+                                  polled.name,
+                                  address,
+                                  bus);
+                    }
+                }
+                HotplugEvent::Attached { ref device, .. } => {
+                    if polled.is_some() || !device.is_rtd() {
+                        continue;
+                    }
+                    let chip_name = device.name.as_ref().map(|name| name.as_str());
+                    let name = self.name_for(bus, address, chip_name);
+                    match Polled::open(name, bus, address) {
+                        Ok(polled) => {
+                            eprintln!("note: polling {}, attached at {:#04x} on bus {}",
+                                      polled.name,
+                                      address,
+                                      bus);
+                            self.sensors.push(polled);
+                        }
+                        Err(e) => eprintln!("warning: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Takes one reading from every sensor and publishes it to every sink.
+    /// Failures are reported and skipped.
+    fn poll(&mut self) -> Result<()> {
+        let units = self.config.units()?;
+        for polled in &mut self.sensors {
+            let reading = read_with_retries(&mut polled.sensor, &self.config.retry);
+            let time = SystemTime::now();
+            for event in polled.events.try_iter() {
+                for e in self.sinks.accept_event(&polled.name, time, &event) {
+                    eprintln!("warning: {}", e);
+                }
+            }
+            let temperature = match reading {
+                Ok(temperature) => temperature,
+                Err(e) => {
+                    eprintln!("warning: skipping reading from {}: {}", polled.name, e);
+                    polled.sensor.forget_scale();
+                    continue;
+                }
+            };
+            let temperature = match units {
+                Some(units) => temperature.to_scale(units),
+                None => temperature,
+            };
+            let reading = TimestampedReading {
+                sensor: polled.name.clone(),
+                time: time,
+                temperature: temperature,
+            };
+            for e in self.sinks.accept(&reading) {
+                eprintln!("warning: {}", e);
+            }
+        }
+        for e in self.sinks.flush() {
+            eprintln!("warning: {}", e);
+        }
+        Ok(())
+    }
+}
+
+/// Runs `daemon`. The configuration is read from `config_path`, or the
+/// default location, and read again on `SIGHUP`.
+pub fn daemon(config: Config,
+              config_path: Option<&str>,
+              bus: u8,
+              address: u16,
+              args: &[String])
+              -> Result<()> {
+    if let Some(arg) = args.first() {
+        bail!("unknown daemon option `{}`", arg);
+    }
+    let reload = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload))
+        .chain_err(|| "could not install the SIGHUP handler")?;
+
+    let mut daemon = Daemon::new(config, bus, address)?;
+    loop {
+        if reload.swap(false, Ordering::SeqCst) {
+            match Config::load(config_path).and_then(|config| Daemon::new(config, bus, address)) {
+                Ok(reloaded) => {
+                    daemon = reloaded;
+                    eprintln!("note: configuration reloaded");
+                }
+                Err(e) => eprintln!("warning: keeping the old configuration: {}", e),
+            }
+        }
+        let started = Instant::now();
+        daemon.rescan();
+        daemon.poll()?;
+        if let Some(remaining) = daemon.interval.checked_sub(started.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::UNIX_EPOCH;
+
+    /// A path for one test's file, removed if left over from an earlier run.
+    fn scratch(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ezo-rtd-daemon-{}-{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn reading(sensor: &str, celsius: f64) -> TimestampedReading {
+        TimestampedReading {
+            sensor: sensor.to_string(),
+            time: UNIX_EPOCH + Duration::from_secs(1_500_000_000),
+            temperature: Temperature::Celsius(celsius),
+        }
+    }
+
+    #[test]
+    fn csv_sink_writes_the_header_once() {
+        let path = scratch("readings.csv");
+        let path = path.to_str().unwrap();
+        CsvSink::open(path).unwrap().accept(&reading("sump", 21.5)).unwrap();
+        CsvSink::open(path).unwrap().accept(&reading("mash-tun", 66.25)).unwrap();
+
+        assert_eq!(fs::read_to_string(path).unwrap(),
+                   "timestamp,sensor,temperature,unit\n\
+                    2017-07-14T02:40:00+00:00,sump,21.500,C\n\
+                    2017-07-14T02:40:00+00:00,mash-tun,66.250,C\n");
+    }
+
+    #[test]
+    fn sqlite_sink_stores_readings_across_reopening() {
+        let path = scratch("readings.db");
+        let path = path.to_str().unwrap();
+        SqliteSink::open(path).unwrap().accept(&reading("sump", 21.5)).unwrap();
+        let mut sink = SqliteSink::open(path).unwrap();
+        sink.accept(&reading("mash-tun", 66.25)).unwrap();
+
+        let mut statement = sink.connection
+            .prepare("SELECT timestamp, sensor, temperature, unit FROM readings ORDER BY rowid")
+            .unwrap();
+        let rows: Vec<(String, String, f64, String)> = statement
+            .query_map(&[] as &[&dyn ToSql],
+                       |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows,
+                   vec![("2017-07-14T02:40:00+00:00".to_string(),
+                         "sump".to_string(),
+                         21.5,
+                         "C".to_string()),
+                        ("2017-07-14T02:40:00+00:00".to_string(),
+                         "mash-tun".to_string(),
+                         66.25,
+                         "C".to_string())]);
+    }
+
+    #[cfg(feature = "kafka-sink")]
+    #[test]
+    fn kafka_batches_are_sent_when_full() {
+        let linger = Some(Duration::from_secs(300));
+        let none = Duration::from_secs(0);
+        assert!(!batch_due(499, 500, none, linger, false));
+        assert!(batch_due(500, 500, none, linger, false));
+        assert!(batch_due(500, 500, none, None, false));
+        assert!(!batch_due(499, 500, Duration::from_secs(600), linger, false));
+    }
+
+    #[cfg(feature = "kafka-sink")]
+    #[test]
+    fn kafka_batches_are_sent_after_lingering() {
+        let linger = Some(Duration::from_secs(300));
+        assert!(!batch_due(1, 500, Duration::from_secs(299), linger, true));
+        assert!(batch_due(1, 500, Duration::from_secs(300), linger, true));
+        assert!(batch_due(1, 500, Duration::from_secs(0), None, true));
+        assert!(!batch_due(0, 500, Duration::from_secs(600), linger, true));
+        assert!(!batch_due(0, 500, Duration::from_secs(0), None, true));
+    }
+}
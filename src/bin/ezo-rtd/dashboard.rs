@@ -0,0 +1,241 @@
+//! The `dashboard` subcommand: a live terminal view of one or more chips.
+//!
+//! Built with the `tui` feature.
+
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use crossterm::ExecutableCommand;
+use crossterm::event::{self, Event, KeyCode};
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode,
+                          enable_raw_mode};
+use ezo_rtd::RtdSensor;
+use ezo_rtd::errors::*;
+use ezo_rtd::response::{CalibrationState, Temperature};
+use i2cdev::linux::LinuxI2CDevice;
+use parse_address;
+use ratatui::{Frame, Terminal};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Sparkline};
+use watch::parse_interval;
+
+/// Time between readings when `--interval` is not given.
+const DEFAULT_INTERVAL_MS: u64 = 2000;
+
+/// Readings kept for the sparkline.
+const HISTORY_LEN: usize = 120;
+
+/// Errors kept for display, per chip.
+const ERROR_LEN: usize = 5;
+
+/// Status and calibration state are refreshed once every this many
+/// readings.
+const SLOW_POLL_EVERY: u32 = 15;
+
+/// One chip on the dashboard, and what is known about it.
+struct Panel {
+    address: u16,
+    sensor: RtdSensor<LinuxI2CDevice>,
+    last: Option<Temperature>,
+    history: VecDeque<f64>,
+    vcc: Option<f64>,
+    calibration: Option<CalibrationState>,
+    errors: VecDeque<String>,
+}
+
+impl Panel {
+    fn record_error(&mut self, e: &Error) {
+        if self.errors.len() == ERROR_LEN {
+            self.errors.pop_front();
+        }
+        self.errors
+            .push_back(format!("{} {}", Local::now().format("%H:%M:%S"), e));
+    }
+
+    fn poll(&mut self, slow: bool) {
+        match self.sensor.read_temperature() {
+            Ok(temperature) => {
+                let value = match temperature {
+                    Temperature::Celsius(value) |
+                    Temperature::Kelvin(value) |
+                    Temperature::Fahrenheit(value) => value,
+                };
+                if self.history.len() == HISTORY_LEN {
+                    self.history.pop_front();
+                }
+                self.history.push_back(value);
+                self.last = Some(temperature);
+            }
+            Err(e) => {
+                self.sensor.forget_scale();
+                self.record_error(&e);
+            }
+        }
+        if slow {
+            match self.sensor.get_status() {
+                Ok(status) => self.vcc = Some(status.vcc_voltage),
+                Err(e) => self.record_error(&e),
+            }
+            match self.sensor.get_calibration_state() {
+                Ok(state) => self.calibration = Some(state),
+                Err(e) => self.record_error(&e),
+            }
+        }
+    }
+
+    /// The history scaled for the sparkline, which only takes integers.
+    fn sparkline_data(&self) -> Vec<u64> {
+        let min = self.history.iter().cloned().fold(::std::f64::INFINITY, f64::min);
+        self.history
+            .iter()
+            .map(|value| ((value - min) * 100.0).round() as u64)
+            .collect()
+    }
+
+    fn draw(&self, frame: &mut Frame, area: Rect) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(28),
+                          Constraint::Min(20),
+                          Constraint::Percentage(40)])
+            .split(area);
+
+        let reading = match self.last {
+            Some(Temperature::Celsius(value)) => format!("{:.3} °C", value),
+            Some(Temperature::Kelvin(value)) => format!("{:.3} K", value),
+            Some(Temperature::Fahrenheit(value)) => format!("{:.3} °F", value),
+            None => "-".to_string(),
+        };
+        let vcc = match self.vcc {
+            Some(vcc) => format!("{:.3} V", vcc),
+            None => "-".to_string(),
+        };
+        let calibration = match self.calibration {
+            Some(CalibrationState::Calibrated) => "calibrated",
+            Some(CalibrationState::Uncalibrated) => "not calibrated",
+            None => "-",
+        };
+        let summary = Paragraph::new(vec![Line::from(format!("temperature: {}", reading)),
+                                          Line::from(format!("vcc:         {}", vcc)),
+                                          Line::from(format!("calibration: {}", calibration))])
+                .block(Block::default()
+                           .borders(Borders::ALL)
+                           .title(format!("{:#04x}", self.address)));
+        frame.render_widget(summary, columns[0]);
+
+        let data = self.sparkline_data();
+        let sparkline = Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title("history"))
+            .data(&data);
+        frame.render_widget(sparkline, columns[1]);
+
+        let errors: Vec<ListItem> = self.errors
+            .iter()
+            .rev()
+            .map(|e| ListItem::new(e.clone()))
+            .collect();
+        let errors = List::new(errors).block(Block::default()
+                                                 .borders(Borders::ALL)
+                                                 .title("recent errors"));
+        frame.render_widget(errors, columns[2]);
+    }
+}
+
+fn draw(frame: &mut Frame, panels: &[Panel]) {
+    let constraints: Vec<Constraint> = panels
+        .iter()
+        .map(|_| Constraint::Ratio(1, panels.len() as u32))
+        .collect();
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(frame.size());
+    for (panel, row) in panels.iter().zip(rows.iter()) {
+        panel.draw(frame, *row);
+    }
+}
+
+/// Polls the chips and redraws until `q` or Esc is pressed.
+fn run_loop(terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+            panels: &mut [Panel],
+            interval: Duration)
+            -> Result<()> {
+    let mut polls = 0;
+    loop {
+        let started = Instant::now();
+        for panel in panels.iter_mut() {
+            panel.poll(polls % SLOW_POLL_EVERY == 0);
+        }
+        polls += 1;
+        terminal
+            .draw(|frame| draw(frame, panels))
+            .chain_err(|| "could not draw the dashboard")?;
+        while let Some(remaining) = interval.checked_sub(started.elapsed()) {
+            if !event::poll(remaining).chain_err(|| "could not read the keyboard")? {
+                break;
+            }
+            if let Event::Key(key) = event::read().chain_err(|| "could not read the keyboard")? {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Runs `dashboard [--interval DURATION] [--sensors ADDR,ADDR...]`. Without
+/// `--sensors`, only the chip at the global `--address` is shown.
+pub fn dashboard(bus: u8, address: u16, args: &[String]) -> Result<()> {
+    let mut interval = Duration::from_millis(DEFAULT_INTERVAL_MS);
+    let mut addresses = vec![address];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => {
+                interval = parse_interval(args.next().ok_or("--interval needs a value")?)?;
+            }
+            "--sensors" => {
+                let value = args.next().ok_or("--sensors needs a value")?;
+                addresses = value
+                    .split(',')
+                    .map(parse_address)
+                    .collect::<Result<Vec<u16>>>()?;
+            }
+            other => bail!("unknown dashboard option `{}`", other),
+        }
+    }
+
+    let mut panels = Vec::new();
+    for address in addresses {
+        panels.push(Panel {
+                        address: address,
+                        sensor: RtdSensor::open(bus, address)?,
+                        last: None,
+                        history: VecDeque::with_capacity(HISTORY_LEN),
+                        vcc: None,
+                        calibration: None,
+                        errors: VecDeque::with_capacity(ERROR_LEN),
+                    });
+    }
+
+    enable_raw_mode().chain_err(|| "could not set up the terminal")?;
+    let mut stdout = io::stdout();
+    stdout
+        .execute(EnterAlternateScreen)
+        .chain_err(|| "could not set up the terminal")?;
+    let result = Terminal::new(CrosstermBackend::new(stdout))
+        .chain_err(|| "could not set up the terminal")
+        .and_then(|mut terminal| {
+                      let result = run_loop(&mut terminal, &mut panels, interval);
+                      let _ = terminal.show_cursor();
+                      result
+                  });
+    let _ = disable_raw_mode();
+    let _ = io::stdout().execute(LeaveAlternateScreen);
+    result
+}
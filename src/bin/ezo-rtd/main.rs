@@ -0,0 +1,540 @@
+//! Command-line tool for operating an RTD EZO chip from the shell.
+//!
+//! Built with `cargo build --features cli`.
+extern crate chrono;
+#[cfg(feature = "tui")]
+extern crate crossterm;
+#[macro_use]
+extern crate error_chain;
+extern crate ezo_rtd;
+extern crate i2cdev;
+#[cfg(feature = "tui")]
+extern crate ratatui;
+#[cfg(feature = "kafka-sink")]
+extern crate kafka;
+#[cfg(feature = "redis-sink")]
+extern crate redis;
+#[cfg(feature = "daemon")]
+extern crate rumqttc;
+#[cfg(feature = "daemon")]
+extern crate rusqlite;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "daemon")]
+extern crate signal_hook;
+extern crate toml;
+
+mod address;
+mod backup;
+mod calibrate;
+mod commission;
+mod config;
+#[cfg(feature = "daemon")]
+mod daemon;
+#[cfg(feature = "tui")]
+mod dashboard;
+mod watch;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use ezo_rtd::{DEFAULT_ADDRESS, DEFAULT_BUS, RtdSensor};
+use ezo_rtd::discovery;
+use ezo_rtd::dryrun::DryRun;
+use ezo_rtd::errors::*;
+use ezo_rtd::fleet::Fleet;
+use config::Config;
+use ezo_rtd::response::{CalibrationState, Temperature};
+use i2cdev::linux::LinuxI2CDevice;
+use serde_json::Value;
+
+const USAGE: &str = "\
+usage: ezo-rtd [--bus N] [--address ADDR | --sensor NAME] [--config FILE] [--json]
+               <command> [args]
+
+Options may be given before or after the command.
+
+options:
+  --bus N              I2C bus number, for /dev/i2c-N (default: 1)
+  --address ADDR       chip address, decimal or 0x-prefixed hex (default: 0x66)
+  --sensor NAME        use the sensor called NAME in the configuration file
+  --config FILE        configuration file to use instead of
+                       ~/.config/ezo-rtd/config.toml
+  --json               print results as JSON, see the README for the schemas
+
+commands:
+  scan [--all]         list the EZO chips found at the EZO factory
+                       addresses, or with --all at every address
+  read                 take a temperature reading
+  watch [--interval DURATION] [--out FILE]
+                       log timestamped readings as CSV, every 10s by
+                       default, appending to FILE or printing to stdout
+  dashboard [--interval DURATION] [--sensors ADDR,ADDR...]
+                       live view of one or more chips; needs the `tui`
+                       feature, press q to quit
+  daemon               poll the configured sensors and publish readings to
+                       the configured sinks; needs the `daemon` feature,
+                       SIGHUP reloads the configuration
+  status               describe the chip: model, firmware, settings, supply
+                       voltage and a reading; with --json, only the restart
+                       reason and supply voltage
+  cal <temp>           calibrate against a reference at <temp>
+  cal clear            delete the calibration data
+  cal state            show whether the chip is calibrated
+  calibrate --interactive [--reference TEMP]
+                       walk through calibrating against a reference bath
+  led on|off           turn the LED on or off
+  sleep                put the chip to sleep
+  export [--out FILE]  print the calibration strings, one per line, or
+                       save them to FILE as a JSON backup
+  import [FILE]        upload and verify calibration strings from FILE
+                       (a JSON backup or plain text), or stdin
+  set-address [--from ADDR] --to ADDR [--yes]
+                       move the chip to a free I2C address, checking
+                       that it answers there afterwards; --from defaults
+                       to --address
+  commission --first ADDR --count N [--prefix NAME]
+                       give chips connected one at a time at the factory
+                       address consecutive addresses from ADDR and the
+                       names NAME-1 to NAME-N (default prefix: rtd)
+  provision [--dry-run] <FILE>
+                       name and configure every sensor of the fleet
+                       described in FILE, then check each one; with
+                       --dry-run, print what would be sent to the chips
+                       instead, against simulated ones";
+
+/// Command-line options common to every subcommand.
+struct Options {
+    bus: Option<u8>,
+    address: Option<u16>,
+    sensor: Option<String>,
+    config: Option<String>,
+    json: bool,
+    command: String,
+    args: Vec<String>,
+}
+
+/// Parses an I2C address, in decimal or `0x`-prefixed hex.
+fn parse_address(addr: &str) -> Result<u16> {
+    let parsed = if addr.starts_with("0x") {
+        u16::from_str_radix(&addr[2..], 16)
+    } else {
+        addr.parse::<u16>()
+    };
+    match parsed {
+        Ok(address) if (1..=127).contains(&address) => Ok(address),
+        _ => Err(format!("invalid I2C address: {}", addr).into()),
+    }
+}
+
+fn parse_options<I: Iterator<Item = String>>(mut args: I) -> Result<Options> {
+    let mut bus = None;
+    let mut address = None;
+    let mut sensor = None;
+    let mut config = None;
+    let mut json = false;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bus" => {
+                let value = args.next().ok_or("--bus needs a value")?;
+                bus = Some(value
+                               .parse()
+                               .chain_err(|| format!("invalid bus number: {}", value))?);
+            }
+            "--address" => {
+                let value = args.next().ok_or("--address needs a value")?;
+                address = Some(parse_address(&value)?);
+            }
+            "--sensor" => sensor = Some(args.next().ok_or("--sensor needs a value")?),
+            "--config" => config = Some(args.next().ok_or("--config needs a value")?),
+            "--json" => json = true,
+            _ => positional.push(arg),
+        }
+    }
+    if positional.is_empty() {
+        bail!("no command given");
+    }
+    if sensor.is_some() && address.is_some() {
+        bail!("--sensor and --address cannot be used together");
+    }
+    let command = positional.remove(0);
+    Ok(Options {
+           bus: bus,
+           address: address,
+           sensor: sensor,
+           config: config,
+           json: json,
+           command: command,
+           args: positional,
+       })
+}
+
+fn format_temperature(temperature: Temperature) -> String {
+    match temperature {
+        Temperature::Celsius(value) => format!("{:.3} °C", value),
+        Temperature::Kelvin(value) => format!("{:.3} K", value),
+        Temperature::Fahrenheit(value) => format!("{:.3} °F", value),
+    }
+}
+
+/// The value and unit symbol of a temperature, as used in JSON output.
+fn temperature_json(temperature: Temperature) -> Value {
+    let (value, unit) = match temperature {
+        Temperature::Celsius(value) => (value, "C"),
+        Temperature::Kelvin(value) => (value, "K"),
+        Temperature::Fahrenheit(value) => (value, "F"),
+    };
+    json!({ "temperature": value, "unit": unit })
+}
+
+/// Prints a JSON document on a single line.
+fn print_json(value: &Value) {
+    println!("{}", value);
+}
+
+fn scan(bus: u8, args: &[String], json: bool) -> Result<()> {
+    let found = if args.iter().any(|arg| arg == "--all") {
+        discovery::scan_bus_addresses(bus, discovery::FIRST_ADDRESS..discovery::LAST_ADDRESS + 1)?
+    } else {
+        discovery::scan_bus(bus)?
+    };
+    if json {
+        let devices: Vec<Value> = found
+            .into_iter()
+            .map(|device| {
+                     json!({
+                               "address": device.address,
+                               "device_type": device.device_type,
+                               "firmware": device.firmware,
+                               "name": device.name,
+                           })
+                 })
+            .collect();
+        print_json(&Value::Array(devices));
+        return Ok(());
+    }
+    if found.is_empty() {
+        println!("no EZO chips found on /dev/i2c-{}", bus);
+        return Ok(());
+    }
+    println!("{:<8} {:<8} {:<9} {}", "ADDRESS", "TYPE", "FIRMWARE", "NAME");
+    for device in found {
+        println!("{:<8} {:<8} {:<9} {}",
+                 format!("{:#04x}", device.address),
+                 device.device_type,
+                 device.firmware,
+                 device.name.unwrap_or_default());
+    }
+    Ok(())
+}
+
+fn provision(args: &[String], json: bool) -> Result<()> {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let path = match args.iter().find(|arg| *arg != "--dry-run") {
+        Some(path) => path,
+        None => bail!("usage: ezo-rtd provision [--dry-run] <FILE>"),
+    };
+    if dry_run && json {
+        bail!("provision --dry-run has no JSON output");
+    }
+    let text = fs::read_to_string(path)
+        .chain_err(|| format!("could not read fleet file {}", path))?;
+    let fleet = Fleet::from_toml(&text)
+        .chain_err(|| format!("could not load fleet file {}", path))?;
+    let results = if dry_run {
+        let run = DryRun::new();
+        let results =
+            fleet.apply_all_with_policy(run.retry_policy(), |_, address| Ok(run.device(address)));
+        print!("{}", run.plan());
+        println!();
+        results
+    } else {
+        fleet.apply_all()
+    };
+    let failures = results.iter().filter(|result| result.outcome.is_err()).count();
+    if json {
+        let devices: Vec<Value> = results
+            .iter()
+            .map(|result| match result.outcome {
+                     Ok(ref changes) => {
+                         let changes: Vec<String> =
+                             changes.iter().map(|change| change.to_string()).collect();
+                         json!({ "name": result.name, "ok": true, "changes": changes })
+                     }
+                     Err(ref e) => {
+                         json!({ "name": result.name, "ok": false, "error": e.to_string() })
+                     }
+                 })
+            .collect();
+        print_json(&Value::Array(devices));
+    } else {
+        for result in &results {
+            match result.outcome {
+                Ok(ref changes) if changes.is_empty() => {
+                    println!("{}: already up to date", result.name)
+                }
+                Ok(ref changes) => {
+                    println!("{}: provisioned", result.name);
+                    for change in changes {
+                        println!("  {}", change);
+                    }
+                }
+                Err(ref e) => println!("{}: FAILED: {}", result.name, e),
+            }
+        }
+    }
+    if failures > 0 {
+        bail!("{} of {} sensors could not be provisioned", failures, results.len());
+    }
+    Ok(())
+}
+
+fn cal(sensor: &mut RtdSensor<LinuxI2CDevice>, args: &[String], json: bool) -> Result<()> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("clear") => {
+            sensor.clear_calibration()?;
+            if json {
+                print_json(&json!({ "calibrated": false }));
+            } else {
+                println!("calibration cleared");
+            }
+        }
+        Some("state") => {
+            let calibrated = sensor.get_calibration_state()? == CalibrationState::Calibrated;
+            if json {
+                print_json(&json!({ "calibrated": calibrated }));
+            } else if calibrated {
+                println!("calibrated");
+            } else {
+                println!("not calibrated");
+            }
+        }
+        Some(temp) => {
+            let temp = temp.parse::<f64>()
+                .chain_err(|| format!("invalid calibration temperature: {}", temp))?;
+            sensor.calibrate(temp)?;
+            if json {
+                print_json(&json!({ "calibrated": true, "reference": temp }));
+            } else {
+                println!("calibrated at {}", temp);
+            }
+        }
+        None => bail!("cal needs a temperature, `clear` or `state`"),
+    }
+    Ok(())
+}
+
+fn led(sensor: &mut RtdSensor<LinuxI2CDevice>, args: &[String], json: bool) -> Result<()> {
+    let on = match args.first().map(|arg| arg.as_str()) {
+        Some("on") => {
+            sensor.led_on()?;
+            true
+        }
+        Some("off") => {
+            sensor.led_off()?;
+            false
+        }
+        _ => bail!("led needs `on` or `off`"),
+    };
+    if json {
+        print_json(&json!({ "led": on }));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn dashboard(bus: u8, address: u16, args: &[String]) -> Result<()> {
+    dashboard::dashboard(bus, address, args)
+}
+
+#[cfg(not(feature = "tui"))]
+fn dashboard(_bus: u8, _address: u16, _args: &[String]) -> Result<()> {
+    bail!("ezo-rtd was built without the dashboard; rebuild it with --features tui")
+}
+
+#[cfg(feature = "daemon")]
+fn daemon(config: Config,
+          config_path: Option<&str>,
+          bus: u8,
+          address: u16,
+          args: &[String])
+          -> Result<()> {
+    daemon::daemon(config, config_path, bus, address, args)
+}
+
+#[cfg(not(feature = "daemon"))]
+fn daemon(_config: Config,
+          _config_path: Option<&str>,
+          _bus: u8,
+          _address: u16,
+          _args: &[String])
+          -> Result<()> {
+    bail!("ezo-rtd was built without the daemon; rebuild it with --features daemon")
+}
+
+fn run() -> Result<()> {
+    let options = parse_options(env::args().skip(1))?;
+    if options.command == "help" || options.command == "--help" {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let json = options.json;
+    let config = Config::load(options.config.as_ref().map(|path| path.as_str()))?;
+    let units = config.units()?;
+    if options.command == "scan" {
+        return scan(options.bus.or(config.bus).unwrap_or(DEFAULT_BUS), &options.args, json);
+    }
+    if options.command == "provision" {
+        return provision(&options.args, json);
+    }
+    if options.command == "commission" {
+        let bus = options.bus.or(config.bus).unwrap_or(DEFAULT_BUS);
+        return commission::commission(bus, &options.args, json);
+    }
+    let (bus, address) = match options.sensor {
+        Some(ref name) => {
+            let sensor = config.sensor(name)?;
+            let bus = options.bus.or(sensor.bus).or(config.bus).unwrap_or(DEFAULT_BUS);
+            match sensor.address {
+                Some(address) => (bus, address),
+                None => (bus, config::find_by_name(bus, name)?),
+            }
+        }
+        None => {
+            (options.bus.or(config.bus).unwrap_or(DEFAULT_BUS),
+             options.address.or(config.address).unwrap_or(DEFAULT_ADDRESS))
+        }
+    };
+    if options.command == "dashboard" {
+        if json {
+            bail!("dashboard has no JSON output");
+        }
+        return dashboard(bus, address, &options.args);
+    }
+    if options.command == "set-address" {
+        return address::set_address(bus, address, &options.args, json);
+    }
+    if options.command == "daemon" {
+        if json {
+            bail!("daemon has no JSON output");
+        }
+        let config_path = options.config.as_ref().map(|path| path.as_str());
+        return daemon(config, config_path, bus, address, &options.args);
+    }
+    let mut sensor = RtdSensor::open(bus, address)?;
+    let args = &options.args;
+    match options.command.as_str() {
+        "read" => {
+            let mut temperature = watch::read_with_retries(&mut sensor, &config.retry)?;
+            if let Some(units) = units {
+                temperature = temperature.to_scale(units);
+            }
+            if json {
+                print_json(&temperature_json(temperature));
+            } else {
+                println!("{}", format_temperature(temperature));
+            }
+        }
+        "watch" => watch::watch(&mut sensor, args, json, &config)?,
+        "status" => {
+            if json {
+                let status = sensor.get_status()?;
+                print_json(&json!({
+                                      "restart_reason": status.restart_reason.description(),
+                                      "vcc": status.vcc_voltage,
+                                  }));
+            } else {
+                print!("{}", sensor.describe()?);
+            }
+        }
+        "cal" => cal(&mut sensor, args, json)?,
+        "calibrate" => {
+            calibrate::calibrate(&mut sensor, bus, address, args, json)?
+        }
+        "led" => led(&mut sensor, args, json)?,
+        "sleep" => {
+            sensor.sleep()?;
+            if json {
+                print_json(&json!({ "asleep": true }));
+            }
+        }
+        "export" => backup::export(&mut sensor, args, json)?,
+        "import" => backup::import(&mut sensor, args, json)?,
+        other => bail!("unknown command `{}`\n\n{}", other, USAGE),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(ref e) = run() {
+        eprintln!("error: {}", e);
+
+        for e in e.iter().skip(1) {
+            eprintln!("caused by: {}", e);
+        }
+
+        if let Some(backtrace) = e.backtrace() {
+            eprintln!("backtrace: {:?}", backtrace);
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Options> {
+        parse_options(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn addresses_are_decimal_or_hex() {
+        assert_eq!(parse_address("102").unwrap(), 102);
+        assert_eq!(parse_address("0x66").unwrap(), 0x66);
+        assert_eq!(parse_address("0x7f").unwrap(), 127);
+        assert_eq!(parse_address("1").unwrap(), 1);
+    }
+
+    #[test]
+    fn addresses_outside_the_7_bit_range_are_refused() {
+        for addr in &["0", "0x0", "128", "0x80", "65536", "-1", "0x", "66h", ""] {
+            assert!(parse_address(addr).is_err(), "{} was accepted", addr);
+        }
+    }
+
+    #[test]
+    fn options_go_before_or_after_the_command() {
+        let parsed = options(&["--bus", "2", "read", "--address", "0x64", "--json"]).unwrap();
+        assert_eq!(parsed.bus, Some(2));
+        assert_eq!(parsed.address, Some(0x64));
+        assert_eq!(parsed.sensor, None);
+        assert_eq!(parsed.config, None);
+        assert!(parsed.json);
+        assert_eq!(parsed.command, "read");
+        assert!(parsed.args.is_empty());
+
+        let parsed = options(&["watch", "--interval", "5s", "--config", "fleet.toml"]).unwrap();
+        assert_eq!(parsed.bus, None);
+        assert!(!parsed.json);
+        assert_eq!(parsed.config, Some("fleet.toml".to_string()));
+        assert_eq!(parsed.command, "watch");
+        assert_eq!(parsed.args, vec!["--interval", "5s"]);
+    }
+
+    #[test]
+    fn bad_options_are_refused() {
+        assert!(options(&[]).is_err());
+        assert!(options(&["--json"]).is_err());
+        assert!(options(&["read", "--bus"]).is_err());
+        assert!(options(&["read", "--bus", "one"]).is_err());
+        assert!(options(&["read", "--address", "0x80"]).is_err());
+        assert!(options(&["read", "--sensor"]).is_err());
+        assert!(options(&["read", "--sensor", "tank", "--address", "0x66"]).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! The `watch` subcommand: continuous logging of timestamped readings.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use config::{Config, RetryPolicy};
+use ezo_rtd::RtdSensor;
+use ezo_rtd::errors::*;
+use ezo_rtd::response::{RestartReason, Temperature};
+use i2cdev::linux::LinuxI2CDevice;
+use serde_json::Value;
+use temperature_json;
+
+/// Time between readings when `--interval` is not given.
+const DEFAULT_INTERVAL_MS: u64 = 10_000;
+
+const CSV_HEADER: &str = "timestamp,temperature,unit";
+
+/// Parses an interval such as `10s`, `500ms`, `2m` or `1h`. A bare number is
+/// taken as seconds.
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (value, unit) = interval.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .chain_err(|| format!("invalid interval: {}", interval))?;
+    let scale = match unit {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => bail!("invalid interval unit in {}; use ms, s, m or h", interval),
+    };
+    let millis = value
+        .checked_mul(scale)
+        .ok_or_else(|| format!("interval too long: {}", interval))?;
+    if millis == 0 {
+        bail!("the interval must be longer than zero");
+    }
+    Ok(Duration::from_millis(millis))
+}
+
+fn csv_line(temperature: Temperature) -> String {
+    let (value, unit) = match temperature {
+        Temperature::Celsius(value) => (value, "C"),
+        Temperature::Kelvin(value) => (value, "K"),
+        Temperature::Fahrenheit(value) => (value, "F"),
+    };
+    format!("{},{:.3},{}", Utc::now().to_rfc3339(), value, unit)
+}
+
+/// One reading as a JSON object, for `--json` output.
+fn json_line(temperature: Temperature) -> String {
+    let mut line = temperature_json(temperature);
+    if let Value::Object(ref mut fields) = line {
+        fields.insert("timestamp".to_string(),
+                      Value::String(Utc::now().to_rfc3339()));
+    }
+    line.to_string()
+}
+
+/// Takes a reading, trying again as the retry policy allows if the chip
+/// does not answer.
+pub fn read_with_retries(sensor: &mut RtdSensor<LinuxI2CDevice>,
+                         retry: &RetryPolicy)
+                         -> Result<Temperature> {
+    let mut attempt = 1;
+    loop {
+        match sensor.read_temperature() {
+            Ok(temperature) => return Ok(temperature),
+            Err(e) => {
+                if attempt >= retry.attempts {
+                    return Err(e);
+                }
+                eprintln!("warning: reading failed (attempt {} of {}): {}",
+                          attempt,
+                          retry.attempts,
+                          e);
+            }
+        }
+        attempt += 1;
+        thread::sleep(Duration::from_millis(retry.delay_ms));
+    }
+}
+
+/// Called once the chip answers again after failed readings. A chip that
+/// lost power may have come back with other settings, so the cached scale
+/// is dropped and the restart reason reported.
+fn recover(sensor: &mut RtdSensor<LinuxI2CDevice>) -> Result<()> {
+    let status = sensor.get_status()?;
+    sensor.forget_scale();
+    match status.restart_reason {
+        RestartReason::BrownOut => eprintln!("note: the chip restarted after a brown-out"),
+        RestartReason::PoweredOff => eprintln!("note: the chip restarted after losing power"),
+        _ => eprintln!("note: the chip is answering again"),
+    }
+    Ok(())
+}
+
+/// Runs `watch [--interval DURATION] [--out FILE]`. Readings are written as
+/// CSV, or as one JSON object per line with `json`, appended to `FILE` or
+/// printed to stdout. Failed readings are reported and skipped; the loop only stops on
+/// errors writing the output. Readings are converted to the configured
+/// units, if any.
+pub fn watch(sensor: &mut RtdSensor<LinuxI2CDevice>,
+             args: &[String],
+             json: bool,
+             config: &Config)
+             -> Result<()> {
+    let units = config.units()?;
+    let mut interval = Duration::from_millis(DEFAULT_INTERVAL_MS);
+    let mut out_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => {
+                interval = parse_interval(args.next().ok_or("--interval needs a value")?)?;
+            }
+            "--out" => out_path = Some(args.next().ok_or("--out needs a value")?),
+            other => bail!("unknown watch option `{}`", other),
+        }
+    }
+
+    let mut out: Box<dyn Write> = match out_path {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .chain_err(|| format!("could not open {}", path))?;
+            let is_empty = file.metadata()
+                .map(|metadata| metadata.len() == 0)
+                .unwrap_or(false);
+            let mut file: Box<dyn Write> = Box::new(file);
+            if is_empty && !json {
+                writeln!(file, "{}", CSV_HEADER)
+                    .chain_err(|| format!("could not write to {}", path))?;
+            }
+            file
+        }
+        None => {
+            if !json {
+                println!("{}", CSV_HEADER);
+            }
+            Box::new(io::stdout())
+        }
+    };
+
+    let mut failing = false;
+    loop {
+        let started = Instant::now();
+        if failing {
+            match recover(sensor) {
+                Ok(()) => failing = false,
+                Err(e) => eprintln!("warning: the chip is still not answering: {}", e),
+            }
+        }
+        if !failing {
+            match read_with_retries(sensor, &config.retry) {
+                Ok(temperature) => {
+                    let temperature = match units {
+                        Some(units) => temperature.to_scale(units),
+                        None => temperature,
+                    };
+                    let line = if json {
+                        json_line(temperature)
+                    } else {
+                        csv_line(temperature)
+                    };
+                    writeln!(out, "{}", line)
+                        .and_then(|_| out.flush())
+                        .chain_err(|| "could not write the reading")?;
+                }
+                Err(e) => {
+                    eprintln!("warning: skipping reading: {}", e);
+                    failing = true;
+                }
+            }
+        }
+        if let Some(remaining) = interval.checked_sub(started.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_take_a_unit() {
+        assert_eq!(parse_interval("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_interval("10").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_interval("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_interval("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_interval("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("10d").is_err());
+        assert!(parse_interval("s").is_err());
+    }
+
+    #[test]
+    fn intervals_too_long_for_a_duration_are_refused() {
+        assert!(parse_interval("5124095576031h").is_err());
+        assert!(parse_interval("18446744073709552s").is_err());
+        assert_eq!(parse_interval("5124095576030h").unwrap(),
+                   Duration::from_secs(5124095576030 * 3600));
+    }
+}
@@ -0,0 +1,233 @@
+//! Hardware-in-the-loop conformance check for an attached RTD EZO chip.
+//!
+//! Set `EZO_RTD_HIL` to `<bus>:<address>` (e.g. `1:0x66` or `1:102`) to run a
+//! matrix of non-destructive commands against the chip and print a
+//! conformance report. Settings touched along the way (LED, scale) are
+//! restored before exiting. Without the variable the binary does nothing.
+extern crate ezo_rtd;
+extern crate i2cdev;
+
+use std::env;
+use std::process;
+
+use ezo_rtd::buffer::MAX_RESPONSE;
+use ezo_rtd::errors::*;
+use ezo_rtd::parse;
+use ezo_rtd::response::{DataLoggerStorageIntervalSeconds, DeviceInfo, DeviceStatus,
+                        ResponseFormat, Temperature, TemperatureScale};
+use ezo_rtd::{CommandBuilder, I2cCommand, TemperatureCommand};
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::LinuxI2CDevice;
+
+const HIL_VAR: &str = "EZO_RTD_HIL";
+
+/// Parses `<bus>:<address>`, where the address may be decimal or `0x` hex.
+fn parse_target(target: &str) -> Result<(u8, u16)> {
+    let mut split = target.splitn(2, ':');
+    let bus = split
+        .next()
+        .and_then(|bus| bus.parse::<u8>().ok())
+        .ok_or_else(|| Error::from(format!("invalid bus in {}={}", HIL_VAR, target)))?;
+    let address = split
+        .next()
+        .and_then(|addr| if addr.starts_with("0x") {
+                      u16::from_str_radix(&addr[2..], 16).ok()
+                  } else {
+                      addr.parse::<u16>().ok()
+                  })
+        .ok_or_else(|| Error::from(format!("invalid address in {}={}", HIL_VAR, target)))?;
+    Ok((bus, address))
+}
+
+/// One line of the conformance report.
+struct CheckResult {
+    name: String,
+    command: String,
+    response: String,
+    passed: bool,
+}
+
+/// Runs commands against the chip and collects their outcome.
+struct Report<'a, D: I2CDevice + 'a> {
+    dev: &'a mut D,
+    results: Vec<CheckResult>,
+}
+
+impl<'a, D: I2CDevice + 'a> Report<'a, D> {
+    /// Runs `command`, records whether `verify` accepts the response, and
+    /// returns the response if the chip answered with success. Responses
+    /// are read into a buffer that fits the longest one.
+    fn check<F>(&mut self, name: &str, command: TemperatureCommand, verify: F) -> Option<String>
+        where F: FnOnce(&str) -> bool
+    {
+        let mut cmd = command.build();
+        let mut buf = [0u8; MAX_RESPONSE];
+        let command_str = cmd.command.as_str().trim_end_matches('\0').to_string();
+        let outcome = cmd.run_with_buffer(self.dev, &mut buf)
+            .and_then(|_| match cmd.response {
+                          Some(_) => parse::payload(&buf),
+                          None => Ok(String::new()),
+                      });
+        let (response, passed) = match outcome {
+            Ok(response) => {
+                let passed = !cmd.flipping && verify(&response);
+                let shown = if cmd.flipping {
+                    format!("{} (bit 7 corrected)", response)
+                } else {
+                    response.clone()
+                };
+                (shown, passed)
+            }
+            Err(e) => (format!("error: {}", e), false),
+        };
+        self.results
+            .push(CheckResult {
+                      name: name.to_string(),
+                      command: command_str,
+                      response: response.clone(),
+                      passed: passed,
+                  });
+        if passed { Some(response) } else { None }
+    }
+
+    fn failures(&self) -> usize {
+        self.results.iter().filter(|r| !r.passed).count()
+    }
+
+    fn print(&self) {
+        for r in &self.results {
+            println!("{}  {:<24} {:<10} -> {}",
+                     if r.passed { "PASS" } else { "FAIL" },
+                     r.name,
+                     r.command,
+                     r.response);
+        }
+        println!("{} checks, {} passed, {} failed",
+                 self.results.len(),
+                 self.results.len() - self.failures(),
+                 self.failures());
+    }
+}
+
+fn is_flag(response: &str, prefix: &str) -> bool {
+    response == format!("{}0", prefix) || response == format!("{}1", prefix)
+}
+
+fn is_unsigned_pair(response: &str) -> bool {
+    let fields: Vec<&str> = response.split(',').collect();
+    fields.len() == 2 && fields.iter().all(|f| f.parse::<u32>().is_ok())
+}
+
+fn scale_command(scale: TemperatureScale) -> TemperatureCommand {
+    match scale {
+        TemperatureScale::Celsius => TemperatureCommand::ScaleCelsius,
+        TemperatureScale::Kelvin => TemperatureCommand::ScaleKelvin,
+        TemperatureScale::Fahrenheit => TemperatureCommand::ScaleFahrenheit,
+    }
+}
+
+fn run_matrix<D: I2CDevice>(report: &mut Report<D>) {
+    let format = report
+        .check("device information",
+               TemperatureCommand::DeviceInformation,
+               |r| r.starts_with("?I,RTD,"))
+        .and_then(|r| DeviceInfo::parse(&r).ok())
+        .map_or(ResponseFormat::Current,
+                |info| ResponseFormat::for_firmware(&info));
+    report.check("status",
+                 TemperatureCommand::Status,
+                 |r| DeviceStatus::parse_with_format(r, format).is_ok());
+    report.check("calibration state",
+                 TemperatureCommand::CalibrationState,
+                 |r| r.len() == 6 && r.to_lowercase().starts_with("?cal,"));
+    report.check("datalogger interval",
+                 TemperatureCommand::DataloggerInterval,
+                 |r| DataLoggerStorageIntervalSeconds::parse(r).is_ok());
+    report.check("protocol lock state",
+                 TemperatureCommand::ProtocolLockState,
+                 |r| is_flag(r, "?Plock,"));
+    report.check("memory last location",
+                 TemperatureCommand::MemoryRecallLastLocation,
+                 |r| r.starts_with("?M,"));
+    report.check("export info", TemperatureCommand::ExportInfo, is_unsigned_pair);
+
+    // LED: toggle both ways, then put it back as it was.
+    let led = report.check("led state",
+                           TemperatureCommand::LedState,
+                           |r| is_flag(r, "?L,"));
+    if let Some(led) = led {
+        report.check("led off", TemperatureCommand::LedOff, |_| true);
+        report.check("led off applied", TemperatureCommand::LedState, |r| r == "?L,0");
+        report.check("led on", TemperatureCommand::LedOn, |_| true);
+        report.check("led on applied", TemperatureCommand::LedState, |r| r == "?L,1");
+        if led == "?L,0" {
+            report.check("led restored", TemperatureCommand::LedOff, |_| true);
+        }
+    }
+
+    // Scale: take a reading in every scale, then restore the original one.
+    let original = report
+        .check("scale state",
+               TemperatureCommand::ScaleState,
+               |r| TemperatureScale::parse(r).is_ok())
+        .and_then(|r| TemperatureScale::parse(&r).ok());
+    if let Some(original) = original {
+        for &scale in &[TemperatureScale::Celsius,
+                        TemperatureScale::Kelvin,
+                        TemperatureScale::Fahrenheit] {
+            report.check(&format!("set scale {:?}", scale),
+                         scale_command(scale),
+                         |_| true);
+            report.check(&format!("scale {:?} applied", scale),
+                         TemperatureCommand::ScaleState,
+                         |r| TemperatureScale::parse(r).ok() == Some(scale));
+            report.check(&format!("reading in {:?}", scale),
+                         TemperatureCommand::Reading,
+                         |r| Temperature::parse(r, scale).is_ok());
+        }
+        report.check("scale restored", scale_command(original), |_| true);
+    }
+}
+
+fn run() -> Result<i32> {
+    let target = match env::var(HIL_VAR) {
+        Ok(target) => target,
+        Err(_) => {
+            println!("{} is not set; skipping hardware-in-the-loop tests.", HIL_VAR);
+            println!("Set it to <bus>:<address>, e.g. {}=1:0x66", HIL_VAR);
+            return Ok(0);
+        }
+    };
+    let (bus, address) = parse_target(&target)?;
+    let device_path = format!("/dev/i2c-{}", bus);
+    let mut dev = LinuxI2CDevice::new(&device_path, address)
+        .chain_err(|| "Could not open I2C device")?;
+
+    println!("EZO RTD conformance report");
+    println!("device: {} address {:#04x}", device_path, address);
+    let mut report = Report {
+        dev: &mut dev,
+        results: Vec::new(),
+    };
+    run_matrix(&mut report);
+    report.print();
+    Ok(if report.failures() == 0 { 0 } else { 1 })
+}
+
+fn main() {
+    match run() {
+        Ok(code) => process::exit(code),
+        Err(ref e) => {
+            println!("error: {}", e);
+
+            for e in e.iter().skip(1) {
+                println!("caused by: {}", e);
+            }
+
+            if let Some(backtrace) = e.backtrace() {
+                println!("backtrace: {:?}", backtrace);
+            }
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Async methods over the blocking sensor, for tokio applications.
+//!
+//! An `AsyncSensor` runs each call of the blocking `RtdSensor` on tokio's
+//! blocking thread pool, with `spawn_blocking`, and hands back a future of
+//! its result, so that the I2C transactions and the chip's processing
+//! delays never stall the async runtime. The sensor is a `SharedSensor`
+//! underneath, so a blocking part of the application can keep using it,
+//! maintenance leases included.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::task::{self, JoinHandle};
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+use response::{CalibrationState, CalibrationString, DeviceStatus, Temperature, TemperatureScale};
+use sensor::{RtdSensor, Snapshot};
+use shared::SharedSensor;
+
+/// The result of a blocking call run on tokio's blocking thread pool.
+pub struct Blocking<T> {
+    handle: JoinHandle<Result<T>>,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
+        match Pin::new(&mut self.handle).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(e)) => {
+                Poll::Ready(Err(format!("the blocking sensor call failed: {}", e).into()))
+            }
+        }
+    }
+}
+
+/// A sensor with async methods.
+///
+/// Cloning an `AsyncSensor` yields another handle to the same sensor.
+pub struct AsyncSensor<D: I2CDevice> {
+    shared: SharedSensor<D>,
+}
+
+impl<D: I2CDevice> Clone for AsyncSensor<D> {
+    fn clone(&self) -> AsyncSensor<D> {
+        AsyncSensor { shared: self.shared.clone() }
+    }
+}
+
+impl<D: I2CDevice + Send + 'static> AsyncSensor<D> {
+    pub fn new(sensor: RtdSensor<D>) -> AsyncSensor<D> {
+        AsyncSensor::from_shared(SharedSensor::new(sensor))
+    }
+
+    pub fn from_shared(shared: SharedSensor<D>) -> AsyncSensor<D> {
+        AsyncSensor { shared: shared }
+    }
+
+    /// The blocking handle to the same sensor.
+    pub fn shared(&self) -> &SharedSensor<D> {
+        &self.shared
+    }
+
+    /// Runs `f` on the sensor on the blocking thread pool, like
+    /// `SharedSensor::with`. Must be called within a tokio runtime.
+    pub fn run<T, F>(&self, f: F) -> Blocking<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut RtdSensor<D>) -> Result<T> + Send + 'static
+    {
+        let shared = self.shared.clone();
+        Blocking { handle: task::spawn_blocking(move || shared.with(f)) }
+    }
+
+    pub fn read_temperature(&self) -> Blocking<Temperature> {
+        self.run(|sensor| sensor.read_temperature())
+    }
+
+    pub fn get_scale(&self) -> Blocking<TemperatureScale> {
+        self.run(|sensor| sensor.get_scale())
+    }
+
+    pub fn set_scale(&self, scale: TemperatureScale) -> Blocking<()> {
+        self.run(move |sensor| sensor.set_scale(scale))
+    }
+
+    pub fn get_status(&self) -> Blocking<DeviceStatus> {
+        self.run(|sensor| sensor.get_status())
+    }
+
+    pub fn get_calibration_state(&self) -> Blocking<CalibrationState> {
+        self.run(|sensor| sensor.get_calibration_state())
+    }
+
+    pub fn snapshot(&self) -> Blocking<Snapshot> {
+        self.run(|sensor| sensor.snapshot())
+    }
+
+    pub fn sleep(&self) -> Blocking<()> {
+        self.run(|sensor| sensor.sleep())
+    }
+
+    /// Calibrates the probe under the maintenance lease, like
+    /// `SharedSensor::calibrate`.
+    pub fn calibrate(&self, temperature: f64) -> Blocking<()> {
+        let shared = self.shared.clone();
+        Blocking { handle: task::spawn_blocking(move || shared.calibrate(temperature)) }
+    }
+
+    pub fn export_calibration(&self) -> Blocking<CalibrationString> {
+        self.run(|sensor| sensor.export_calibration())
+    }
+
+    /// Uploads calibration data under the maintenance lease, like
+    /// `SharedSensor::import_calibration`.
+    pub fn import_calibration(&self, calibration: CalibrationString) -> Blocking<()> {
+        let shared = self.shared.clone();
+        Blocking {
+            handle: task::spawn_blocking(move || shared.import_calibration(&calibration)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::runtime::Builder;
+
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn runs_sensor_calls_off_the_runtime() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(19.25);
+        bus.attach(chip);
+        let sensor = AsyncSensor::new(RtdSensor::new(bus.device(0x66)));
+        let runtime = Builder::new_current_thread().build().unwrap();
+
+        assert_eq!(runtime.block_on(sensor.read_temperature()).unwrap(),
+                   Temperature::Celsius(19.25));
+        runtime.block_on(sensor.calibrate(19.25)).unwrap();
+        assert_eq!(runtime.block_on(sensor.get_calibration_state()).unwrap(),
+                   CalibrationState::Calibrated);
+
+        let _lease = sensor.shared().lease("calibration").unwrap();
+        assert!(runtime.block_on(sensor.read_temperature()).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Fixed-capacity buffers, so that building and issuing a command does not
+//! touch the heap.
+
+use std::fmt;
+use std::str;
+
+use errors::*;
+
+/// Longest command string the buffer holds, NUL terminator included. The
+/// longest commands are `Name,n` with a 16-character name and `Import,n`.
+pub const MAX_COMMAND: usize = 32;
+
+/// Room left in every response buffer for a status string the chip may
+/// append to the payload, `\r*OK`.
+pub const STATUS_SUFFIX: usize = 4;
+
+/// Length of the buffer for responses to commands that only acknowledge:
+/// the response code and a NUL terminator.
+pub const ACK_RESPONSE: usize = 2 + STATUS_SUFFIX;
+
+/// Length of the buffer for readings and short queries such as `S,?`.
+pub const QUERY_RESPONSE: usize = 16 + STATUS_SUFFIX;
+
+/// Length of the buffer for the `Status` response, `?Status,P,5.038`.
+pub const STATUS_RESPONSE: usize = 18 + STATUS_SUFFIX;
+
+/// Length of the buffer for the `Name,?` response, with a 16-character
+/// name.
+pub const NAME_RESPONSE: usize = 24 + STATUS_SUFFIX;
+
+/// Length of the buffer for one `Export` string.
+pub const EXPORT_RESPONSE: usize = 16 + STATUS_SUFFIX;
+
+/// Length of the buffer that fits any response.
+pub const MAX_RESPONSE: usize = NAME_RESPONSE;
+
+/// A command string, stored in place.
+///
+/// Fixed commands such as `Status` are copied in from string literals and
+/// parameterized ones are formatted straight into the buffer, so neither
+/// allocates; there is no need to tell the two apart the way a
+/// `Cow<'static, str>` would.
+///
+/// Text that does not fit is not stored; the buffer then reports having
+/// overflowed, and the command is refused when written to the device.
+#[derive(Clone, Copy)]
+pub struct CommandBuf {
+    bytes: [u8; MAX_COMMAND],
+    len: usize,
+    overflow: bool,
+}
+
+impl CommandBuf {
+    /// Creates an empty buffer.
+    pub fn new() -> CommandBuf {
+        CommandBuf {
+            bytes: [0; MAX_COMMAND],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Empties the buffer and clears the overflow flag.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.overflow = false;
+    }
+
+    /// Appends `s`, or sets the overflow flag if it does not fit.
+    pub fn push_str(&mut self, s: &str) {
+        let end = self.len + s.len();
+        if end > MAX_COMMAND {
+            self.overflow = true;
+            return;
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+    }
+
+    /// Replaces the contents with formatted text.
+    pub fn set_fmt(&mut self, args: fmt::Arguments) {
+        self.clear();
+        let _ = fmt::Write::write_fmt(self, args);
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole `&str`s are ever appended, so this cannot fail.
+        str::from_utf8(self.as_bytes()).unwrap_or("")
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if some text did not fit in the buffer.
+    pub fn overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    /// Checks that the buffer holds a command the chip can take: one that
+    /// fit in the buffer, and is printable ASCII ending in a single NUL.
+    /// Parameters such as names come from users, and a stray byte can
+    /// leave the chip waiting for the rest of a command.
+    pub fn validate(&self) -> Result<()> {
+        if self.overflow {
+            return Err(ErrorKind::CommandTooLong.into());
+        }
+        let text = match self.as_bytes().split_last() {
+            Some((&0, text)) if !text.is_empty() => text,
+            _ => return Err(ErrorKind::InvalidCommand("not a NUL-terminated command").into()),
+        };
+        if text.contains(&0) {
+            return Err(ErrorKind::InvalidCommand("NUL inside the command").into());
+        }
+        if !text.iter().all(|b| (b' '..=b'~').contains(b)) {
+            return Err(ErrorKind::InvalidCommand("non-ASCII or control character").into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for CommandBuf {
+    fn default() -> CommandBuf {
+        CommandBuf::new()
+    }
+}
+
+impl fmt::Write for CommandBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        if self.overflow { Err(fmt::Error) } else { Ok(()) }
+    }
+}
+
+impl fmt::Debug for CommandBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)?;
+        if self.overflow {
+            f.write_str(" (overflowed)")?;
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for CommandBuf {
+    fn eq(&self, other: &CommandBuf) -> bool {
+        self.as_bytes() == other.as_bytes() && self.overflow == other.overflow
+    }
+}
+
+impl Eq for CommandBuf {}
+
+impl PartialEq<str> for CommandBuf {
+    fn eq(&self, other: &str) -> bool {
+        !self.overflow && self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for CommandBuf {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_formatted_commands() {
+        let mut buf = CommandBuf::new();
+        buf.set_fmt(format_args!("Cal,{:.*}\0", 2, -12.5));
+        assert_eq!(buf, "Cal,-12.50\0");
+        assert_eq!(buf.as_bytes(), b"Cal,-12.50\0");
+        assert!(!buf.overflowed());
+    }
+
+    #[test]
+    fn reports_overflow() {
+        let mut buf = CommandBuf::new();
+        buf.set_fmt(format_args!("Name,{}\0", "a name that is far too long"));
+        assert!(buf.overflowed());
+        assert!(buf != "Name,\0");
+
+        buf.set_fmt(format_args!("R\0"));
+        assert!(!buf.overflowed());
+        assert_eq!(buf, "R\0");
+    }
+
+    #[test]
+    fn validates_commands() {
+        let mut buf = CommandBuf::new();
+        for bad in &["", "\0", "R", "Name,a\0b\0", "Name,caf\u{e9}\0", "Name,\n\0"] {
+            buf.set_fmt(format_args!("{}", bad));
+            assert!(buf.validate().is_err(), "{:?} was accepted", bad);
+        }
+        buf.set_fmt(format_args!("Name,{}\0", "a name that is far too long"));
+        assert!(buf.validate().is_err());
+
+        buf.set_fmt(format_args!("Cal,{:.*}\0", 2, 100.0));
+        assert!(buf.validate().is_ok());
+    }
+}
@@ -0,0 +1,245 @@
+//! Framing of the chip's UART output into typed lines.
+//!
+//! In UART mode the chip sends text lines ended by a carriage return:
+//! readings, responses to queries, and status strings such as `*OK`, which
+//! may come between the readings of continuous mode. `LineCodec` splits the
+//! bytes, as they arrive in chunks of any size, into `UartLine`s. With the
+//! `tokio-codec` feature, it is also a `tokio_util` codec, for use with a
+//! `Framed` serial port.
+
+use std::io::Read;
+use std::str;
+
+use errors::*;
+use response::{StatusToken, Temperature, TemperatureScale};
+
+/// Longest line kept while waiting for its carriage return. Longer runs of
+/// bytes are noise on the line, and are dropped.
+pub const MAX_LINE: usize = 64;
+
+/// One line of the chip's UART output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UartLine {
+    /// A temperature reading.
+    Reading(Temperature),
+    /// A status string.
+    Status(StatusToken),
+    /// Any other line, such as the response to a query.
+    Response(String),
+}
+
+impl UartLine {
+    /// Tells what a line, without its carriage return, holds. Readings are
+    /// taken to be in `scale`.
+    pub fn parse(line: &str, scale: TemperatureScale) -> UartLine {
+        if let Some(token) = StatusToken::parse(line) {
+            return UartLine::Status(token);
+        }
+        match Temperature::parse(line, scale) {
+            Ok(temperature) => UartLine::Reading(temperature),
+            Err(_) => UartLine::Response(line.to_string()),
+        }
+    }
+}
+
+/// Decodes a line without its carriage return; `None` for empty lines.
+fn decode_line(line: &[u8], scale: TemperatureScale) -> Option<Result<UartLine>> {
+    // Some adapters turn the carriage return into CR LF.
+    let line = match line.split_first() {
+        Some((&b'\n', rest)) => rest,
+        _ => line,
+    };
+    if line.is_empty() {
+        return None;
+    }
+    Some(str::from_utf8(line)
+             .chain_err(|| ErrorKind::ResponseParse)
+             .map(|line| UartLine::parse(line, scale)))
+}
+
+/// Splits the chip's UART output into lines.
+#[derive(Debug, Clone)]
+pub struct LineCodec {
+    scale: TemperatureScale,
+    /// Bytes of a line whose carriage return has not arrived yet.
+    partial: Vec<u8>,
+}
+
+impl LineCodec {
+    /// Creates a codec for a chip whose readings are in `scale`.
+    pub fn new(scale: TemperatureScale) -> LineCodec {
+        LineCodec {
+            scale: scale,
+            partial: Vec::new(),
+        }
+    }
+
+    /// The scale readings are taken to be in.
+    pub fn scale(&self) -> TemperatureScale {
+        self.scale
+    }
+
+    /// Changes the scale readings are taken to be in, after an `S` command.
+    pub fn set_scale(&mut self, scale: TemperatureScale) {
+        self.scale = scale;
+    }
+
+    /// Adds bytes received from the chip.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.partial.extend_from_slice(bytes);
+    }
+
+    /// Takes the next complete line out of the bytes received so far, or
+    /// returns `None` until one arrives. A line that overruns `MAX_LINE` is
+    /// dropped, and reported as a parse error.
+    pub fn next_line(&mut self) -> Option<Result<UartLine>> {
+        loop {
+            let end = match self.partial.iter().position(|&b| b == b'\r') {
+                Some(end) => end,
+                None if self.partial.len() > MAX_LINE => {
+                    self.partial.clear();
+                    return Some(Err(ErrorKind::ResponseParse.into()));
+                }
+                None => return None,
+            };
+            let line: Vec<u8> = self.partial.drain(..end + 1).collect();
+            if let Some(line) = decode_line(&line[..end], self.scale) {
+                return Some(line);
+            }
+        }
+    }
+}
+
+/// The lines read from a serial port; see `LineCodec`.
+pub struct UartLines<R: Read> {
+    port: R,
+    codec: LineCodec,
+}
+
+impl<R: Read> UartLines<R> {
+    /// Reads lines from `port`, taking readings to be in `scale`.
+    pub fn new(port: R, scale: TemperatureScale) -> UartLines<R> {
+        UartLines {
+            port: port,
+            codec: LineCodec::new(scale),
+        }
+    }
+
+    /// The codec, to change its scale.
+    pub fn codec_mut(&mut self) -> &mut LineCodec {
+        &mut self.codec
+    }
+
+    pub fn into_inner(self) -> R {
+        self.port
+    }
+}
+
+impl<R: Read> Iterator for UartLines<R> {
+    type Item = Result<UartLine>;
+
+    /// Blocks until a whole line arrives; ends when the port has nothing
+    /// more to read.
+    fn next(&mut self) -> Option<Result<UartLine>> {
+        let mut chunk = [0u8; MAX_LINE];
+        loop {
+            if let Some(line) = self.codec.next_line() {
+                return Some(line);
+            }
+            match self.port.read(&mut chunk) {
+                Ok(0) => return None,
+                Ok(len) => self.codec.push(&chunk[..len]),
+                Err(e) => return Some(Err(e).chain_err(|| "Could not read from the serial port")),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+mod framed {
+    use bytes::{BufMut, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+
+    impl Decoder for LineCodec {
+        type Item = UartLine;
+        type Error = Error;
+
+        fn decode(&mut self, src: &mut BytesMut) -> Result<Option<UartLine>> {
+            loop {
+                let end = match src.iter().position(|&b| b == b'\r') {
+                    Some(end) => end,
+                    None if src.len() > MAX_LINE => {
+                        src.clear();
+                        return Err(ErrorKind::ResponseParse.into());
+                    }
+                    None => return Ok(None),
+                };
+                let line = src.split_to(end + 1);
+                if let Some(line) = decode_line(&line[..end], self.scale) {
+                    return line.map(Some);
+                }
+            }
+        }
+    }
+
+    /// Sends commands, given without their terminator.
+    impl<'a> Encoder<&'a str> for LineCodec {
+        type Error = Error;
+
+        fn encode(&mut self, command: &'a str, dst: &mut BytesMut) -> Result<()> {
+            dst.reserve(command.len() + 1);
+            dst.put_slice(command.as_bytes());
+            dst.put_u8(b'\r');
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lines_across_chunks() {
+        let mut codec = LineCodec::new(TemperatureScale::Celsius);
+        codec.push(b"25.1");
+        assert!(codec.next_line().is_none());
+        codec.push(b"04\r*O");
+        assert_eq!(codec.next_line().unwrap().unwrap(),
+                   UartLine::Reading(Temperature::Celsius(25.104)));
+        assert!(codec.next_line().is_none());
+        codec.push(b"K\r\r\n?S,c\r*DONE\r");
+        assert_eq!(codec.next_line().unwrap().unwrap(),
+                   UartLine::Status(StatusToken::Ok));
+        assert_eq!(codec.next_line().unwrap().unwrap(),
+                   UartLine::Response("?S,c".to_string()));
+        assert_eq!(codec.next_line().unwrap().unwrap(),
+                   UartLine::Response("*DONE".to_string()));
+        assert!(codec.next_line().is_none());
+    }
+
+    #[test]
+    fn drops_overlong_lines() {
+        let mut codec = LineCodec::new(TemperatureScale::Celsius);
+        codec.push(&[b'7'; MAX_LINE + 1]);
+        assert!(codec.next_line().unwrap().is_err());
+        codec.push(b"\xff\r-4.250\r");
+        assert!(codec.next_line().unwrap().is_err());
+        assert_eq!(codec.next_line().unwrap().unwrap(),
+                   UartLine::Reading(Temperature::Celsius(-4.25)));
+    }
+
+    #[test]
+    fn reads_lines_from_a_port() {
+        let port: &[u8] = b"*OK\r77.012\r*WA\r77.030";
+        let lines: Vec<_> = UartLines::new(port, TemperatureScale::Fahrenheit)
+            .map(|line| line.unwrap())
+            .collect();
+        assert_eq!(lines,
+                   vec![UartLine::Status(StatusToken::Ok),
+                        UartLine::Reading(Temperature::Fahrenheit(77.012)),
+                        UartLine::Status(StatusToken::Wake)]);
+    }
+}
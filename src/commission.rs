@@ -0,0 +1,208 @@
+//! Commissioning chips one at a time.
+//!
+//! Every chip leaves the factory at the same address, so the chips of a
+//! multi-chip board are commissioned one by one: each is connected alone,
+//! found at the factory address, moved to the next free address, named,
+//! and checked, before the next one is connected. `Commissioning` walks
+//! through that procedure; its caller connects the chips, and calls
+//! `step` until every assignment is done.
+
+use std::time::Duration;
+
+use discovery::{self, Addressable};
+use errors::*;
+use response::DeviceInfo;
+use sensor::{RtdSensor, ADDRESS_REBOOT_DELAY};
+use DEFAULT_ADDRESS;
+
+/// Times the chip is asked for its device information after moving, a
+/// second apart, before giving up on it.
+const VERIFY_ATTEMPTS: u32 = 5;
+
+/// The address and name a chip is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub address: u16,
+}
+
+/// Where the procedure is, after a `step`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Nothing answers at the factory address yet; connect the next chip.
+    WaitingForChip(Assignment),
+    /// A chip was moved, named and checked.
+    Commissioned(Assignment, DeviceInfo),
+    /// Every chip is commissioned.
+    Finished,
+}
+
+/// The commissioning of a board's chips, in order.
+#[derive(Debug, Clone)]
+pub struct Commissioning {
+    assignments: Vec<Assignment>,
+    done: usize,
+}
+
+impl Commissioning {
+    /// Chips named `<prefix>-1` to `<prefix>-<count>`, at consecutive
+    /// addresses from `first`.
+    pub fn sequential(prefix: &str, first: u16, count: usize) -> Result<Commissioning> {
+        Commissioning::new((0..count)
+                               .map(|i| {
+                                        Assignment {
+                                            name: format!("{}-{}", prefix, i + 1),
+                                            address: first + i as u16,
+                                        }
+                                    })
+                               .collect())
+    }
+
+    /// Chips given `assignments`, in order. Addresses must be valid,
+    /// distinct, and other than the factory address, where the next chips
+    /// are looked for.
+    pub fn new(assignments: Vec<Assignment>) -> Result<Commissioning> {
+        for (i, assignment) in assignments.iter().enumerate() {
+            if !(discovery::FIRST_ADDRESS..=discovery::LAST_ADDRESS).contains(&assignment.address) {
+                bail!("{:#04x} is not a valid I2C address", assignment.address);
+            }
+            if assignment.address == DEFAULT_ADDRESS {
+                bail!("chips cannot be left at the factory address {:#04x}", DEFAULT_ADDRESS);
+            }
+            if assignments[..i].iter().any(|other| other.address == assignment.address) {
+                bail!("address {:#04x} is assigned twice", assignment.address);
+            }
+        }
+        Ok(Commissioning {
+               assignments: assignments,
+               done: 0,
+           })
+    }
+
+    pub fn assignments(&self) -> &[Assignment] {
+        &self.assignments
+    }
+
+    /// How many chips are commissioned.
+    pub fn done(&self) -> usize {
+        self.done
+    }
+
+    /// The assignment of the next chip, if any.
+    pub fn next_assignment(&self) -> Option<&Assignment> {
+        self.assignments.get(self.done)
+    }
+
+    /// Commissions the chip at the factory address, if there is one, with
+    /// the next assignment. `sensor` is the bus, pointed at any address.
+    ///
+    /// Errors leave the assignment to be done again: a chip that failed
+    /// partway may have moved already, and has to be put back at the
+    /// factory address, or reset, before retrying.
+    pub fn step<D: Addressable>(&mut self, sensor: &mut RtdSensor<D>) -> Result<Step> {
+        let assignment = match self.next_assignment() {
+            Some(assignment) => assignment.clone(),
+            None => return Ok(Step::Finished),
+        };
+        if !discovery::acknowledges(sensor.device(), DEFAULT_ADDRESS)? {
+            return Ok(Step::WaitingForChip(assignment));
+        }
+        sensor.forget_scale();
+        sensor.set_known_address(DEFAULT_ADDRESS);
+        let info = sensor
+            .get_device_info()
+            .chain_err(|| format!("no EZO chip answers at {:#04x}", DEFAULT_ADDRESS))?;
+        if !info.device_type.eq_ignore_ascii_case("RTD") {
+            bail!("the chip at {:#04x} is a {} chip, not an RTD one",
+                  DEFAULT_ADDRESS,
+                  info.device_type);
+        }
+
+        sensor.change_address(DEFAULT_ADDRESS, assignment.address)?;
+        let wait = sensor.retry_policy().wait_strategy;
+        let mut attempt = 1;
+        let moved = loop {
+            wait.wait(Duration::from_millis(ADDRESS_REBOOT_DELAY));
+            match sensor.get_device_info() {
+                Ok(moved) => break moved,
+                Err(e) => {
+                    if attempt == VERIFY_ATTEMPTS {
+                        return Err(e).chain_err(|| {
+                            format!("the chip does not answer at {:#04x}", assignment.address)
+                        });
+                    }
+                }
+            }
+            attempt += 1;
+        };
+        if moved != info {
+            bail!("a {} chip answers at {:#04x}, expected {}",
+                  moved.device_type,
+                  assignment.address,
+                  info.device_type);
+        }
+
+        sensor.set_name(&assignment.name)?;
+        if sensor.get_name()? != assignment.name {
+            return Err(ErrorKind::VerificationFailed("name").into());
+        }
+        self.done += 1;
+        Ok(Step::Commissioned(assignment, moved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::RetryPolicy;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn commissions_chips_connected_one_at_a_time() {
+        let bus = VirtualBus::new();
+        let mut sensor = RtdSensor::new(bus.device(0x01));
+        sensor.set_retry_policy(RetryPolicy {
+                                    attempts: 1,
+                                    ..RetryPolicy::no_wait()
+                                });
+        let mut commissioning = Commissioning::sequential("tank", 0x70, 2).unwrap();
+        let first = commissioning.assignments()[0].clone();
+        let second = commissioning.assignments()[1].clone();
+        assert_eq!(first,
+                   Assignment {
+                       name: "tank-1".to_string(),
+                       address: 0x70,
+                   });
+
+        assert_eq!(commissioning.step(&mut sensor).unwrap(),
+                   Step::WaitingForChip(first.clone()));
+        let one = bus.attach(SimulatedChip::new(0x66));
+        match commissioning.step(&mut sensor).unwrap() {
+            Step::Commissioned(assignment, _) => assert_eq!(assignment, first),
+            other => panic!("unexpected step {:?}", other),
+        }
+        assert_eq!(commissioning.step(&mut sensor).unwrap(),
+                   Step::WaitingForChip(second.clone()));
+        bus.attach(SimulatedChip::new(0x66));
+        match commissioning.step(&mut sensor).unwrap() {
+            Step::Commissioned(assignment, _) => assert_eq!(assignment, second),
+            other => panic!("unexpected step {:?}", other),
+        }
+        assert_eq!(commissioning.step(&mut sensor).unwrap(), Step::Finished);
+
+        assert_eq!(bus.with_chip(one, |chip| (chip.address(), chip.name().to_string())),
+                   Some((0x70, "tank-1".to_string())));
+        assert!(!bus.acks(0x66));
+    }
+
+    #[test]
+    fn refuses_invalid_assignments() {
+        assert!(Commissioning::sequential("tank", 0x65, 3).is_err());
+        assert!(Commissioning::sequential("tank", 0x7f, 2).is_err());
+        let twice = Assignment {
+            name: "a".to_string(),
+            address: 0x10,
+        };
+        assert!(Commissioning::new(vec![twice.clone(), twice]).is_err());
+    }
+}
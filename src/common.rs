@@ -0,0 +1,351 @@
+//! Byte-level helpers for talking to an EZO chip over any `I2CDevice`.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+
+/// Processing time assumed by `write_to_ezo` and `read_into`, which do not
+/// know the command being sent.
+const DEFAULT_PROCESSING_MS: u64 = 300;
+
+/// Time a chip needs after the transaction that wakes it, before it takes a
+/// command, in milliseconds.
+pub const WAKE_SETTLE_MS: u64 = 300;
+
+/// How the crate waits out the chip's processing time and the pauses
+/// between retries.
+#[derive(Clone, Copy, Debug)]
+pub enum WaitStrategy {
+    /// Puts the thread to sleep; the default.
+    Sleep,
+    /// Busy-waits, yielding to other threads in between, for control loops
+    /// that cannot afford the scheduler's wakeup latency.
+    SpinYield,
+    /// Calls a function provided by the caller.
+    Custom(fn(Duration)),
+}
+
+impl Default for WaitStrategy {
+    fn default() -> WaitStrategy {
+        WaitStrategy::Sleep
+    }
+}
+
+impl PartialEq for WaitStrategy {
+    fn eq(&self, other: &WaitStrategy) -> bool {
+        use self::WaitStrategy::*;
+        match (*self, *other) {
+            (Sleep, Sleep) | (SpinYield, SpinYield) => true,
+            (Custom(a), Custom(b)) => a as usize == b as usize,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for WaitStrategy {}
+
+impl WaitStrategy {
+    /// Waits for `duration`.
+    pub fn wait(&self, duration: Duration) {
+        match *self {
+            WaitStrategy::Sleep => thread::sleep(duration),
+            WaitStrategy::SpinYield => {
+                let end = Instant::now() + duration;
+                while Instant::now() < end {
+                    thread::yield_now();
+                }
+            }
+            WaitStrategy::Custom(waiter) => waiter(duration),
+        }
+    }
+}
+
+/// How failed writes and reads are retried.
+///
+/// The wait before the first retry is a tenth of the command's processing
+/// time, and doubles with every further retry, within `min_wait` and
+/// `max_wait`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts at each write or read, the first one included.
+    pub attempts: u32,
+    /// Shortest wait before a retry.
+    pub min_wait: Duration,
+    /// Longest wait before a retry.
+    pub max_wait: Duration,
+    /// How waits are made, both before retries and for the processing time
+    /// of commands run with this policy.
+    pub wait_strategy: WaitStrategy,
+    /// Further reads made while the chip answers `Pending`, before the
+    /// response is given up on with `PendingResponse`; none by default.
+    pub pending_polls: u32,
+    /// Time between those reads.
+    pub poll_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            min_wait: Duration::from_millis(10),
+            max_wait: Duration::from_millis(1000),
+            wait_strategy: WaitStrategy::Sleep,
+            pending_polls: 0,
+            poll_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy, without the waits, for tests.
+    #[cfg(test)]
+    pub fn no_wait() -> RetryPolicy {
+        RetryPolicy {
+            wait_strategy: WaitStrategy::Custom(|_| {}),
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// The wait before retry number `retry`, counting from 1, of a command
+    /// that takes `processing` to complete.
+    pub fn wait(&self, processing: Duration, retry: u32) -> Duration {
+        let factor = 2u32.checked_pow(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        (processing / 10)
+            .checked_mul(factor)
+            .unwrap_or(self.max_wait)
+            .max(self.min_wait)
+            .min(self.max_wait)
+    }
+
+    /// Runs `f` until it succeeds or the attempts run out, waiting between
+    /// attempts, and adding the number of retries to `retries`. No wait goes
+    /// past `deadline`: the last one is cut short, and once it has passed
+    /// no further attempt is made. The last error is returned.
+    fn retry<T, E, F>(&self,
+                      processing: Duration,
+                      deadline: Option<Instant>,
+                      retries: &mut u32,
+                      mut f: F)
+                      -> ::std::result::Result<T, E>
+        where F: FnMut() -> ::std::result::Result<T, E>
+    {
+        let mut retry = 0;
+        loop {
+            match f() {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    retry += 1;
+                    if retry >= self.attempts {
+                        return Err(e);
+                    }
+                    let wait = match remaining(deadline) {
+                        Some(left) if left == Duration::from_millis(0) => return Err(e),
+                        Some(left) => self.wait(processing, retry).min(left),
+                        None => self.wait(processing, retry),
+                    };
+                    *retries += 1;
+                    self.wait_strategy.wait(wait);
+                }
+            }
+        }
+    }
+}
+
+/// The time left until `deadline`, zero once it has passed; `None` without
+/// a deadline.
+pub fn remaining(deadline: Option<Instant>) -> Option<Duration> {
+    deadline.map(|deadline| {
+                     let now = Instant::now();
+                     if now < deadline {
+                         deadline - now
+                     } else {
+                         Duration::from_millis(0)
+                     }
+                 })
+}
+
+/// Writes the command bytes to the device, retrying as `policy` says if the
+/// chip does not ACK. `processing` is the command's processing time.
+pub fn write_with_retry<D: I2CDevice>(dev: &mut D,
+                                      cmd_str: &[u8],
+                                      policy: &RetryPolicy,
+                                      processing: Duration)
+                                      -> Result<()> {
+    write_counting_retries(dev, cmd_str, policy, processing, None, &mut 0)
+}
+
+/// Like `write_with_retry`, adding the number of retries made to
+/// `retries`. No retry waits past `deadline`.
+pub fn write_counting_retries<D: I2CDevice>(dev: &mut D,
+                                            cmd_str: &[u8],
+                                            policy: &RetryPolicy,
+                                            processing: Duration,
+                                            deadline: Option<Instant>,
+                                            retries: &mut u32)
+                                            -> Result<()> {
+    policy
+        .retry(processing, deadline, retries, || dev.write(cmd_str))
+        .map_err(|e| ErrorKind::I2CWrite(e.to_string()).into())
+}
+
+/// Fills `buf` with bytes read from the device, retrying as `policy` says
+/// if the read fails. `processing` is the command's processing time.
+pub fn read_with_retry<D: I2CDevice>(dev: &mut D,
+                                     buf: &mut [u8],
+                                     policy: &RetryPolicy,
+                                     processing: Duration)
+                                     -> Result<()> {
+    read_counting_retries(dev, buf, policy, processing, None, &mut 0)
+}
+
+/// Like `read_with_retry`, adding the number of retries made to `retries`.
+/// No retry waits past `deadline`.
+pub fn read_counting_retries<D: I2CDevice>(dev: &mut D,
+                                           buf: &mut [u8],
+                                           policy: &RetryPolicy,
+                                           processing: Duration,
+                                           deadline: Option<Instant>,
+                                           retries: &mut u32)
+                                           -> Result<()> {
+    policy
+        .retry(processing, deadline, retries, || dev.read(buf))
+        .map_err(|e| ErrorKind::I2CRead(e.to_string()).into())
+}
+
+/// Wakes a sleeping chip: the chip ignores the first transaction after
+/// sleeping, so a lone NUL, which an awake chip would take as an empty
+/// command, is written first, and the chip is given time to settle.
+pub fn wake<D: I2CDevice>(dev: &mut D, strategy: &WaitStrategy) {
+    // A sleeping chip does not acknowledge the write, which is expected.
+    let _ = dev.write(b"\0");
+    strategy.wait(Duration::from_millis(WAKE_SETTLE_MS));
+}
+
+/// Writes the command bytes to the device, retrying with the default
+/// policy if the chip does not ACK.
+pub fn write_to_ezo<D: I2CDevice>(dev: &mut D, cmd_str: &[u8]) -> Result<()> {
+    write_with_retry(dev,
+                     cmd_str,
+                     &RetryPolicy::default(),
+                     Duration::from_millis(DEFAULT_PROCESSING_MS))
+}
+
+/// Fills `buf` with bytes read from the device, retrying with the default
+/// policy if the read fails.
+pub fn read_into<D: I2CDevice>(dev: &mut D, buf: &mut [u8]) -> Result<()> {
+    read_with_retry(dev,
+                    buf,
+                    &RetryPolicy::default(),
+                    Duration::from_millis(DEFAULT_PROCESSING_MS))
+}
+
+/// Reads `max_data` bytes from the device, retrying with the default policy
+/// if the read fails.
+pub fn read_raw_buffer<D: I2CDevice>(dev: &mut D, max_data: usize) -> Result<Vec<u8>> {
+    let mut data_buffer = vec![0u8; max_data];
+    read_into(dev, &mut data_buffer)?;
+    Ok(data_buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_waits_scale_with_processing_time_and_back_off() {
+        let policy = RetryPolicy::default();
+        let reading = Duration::from_millis(600);
+        assert_eq!(policy.wait(reading, 1), Duration::from_millis(60));
+        assert_eq!(policy.wait(reading, 2), Duration::from_millis(120));
+        assert_eq!(policy.wait(reading, 3), Duration::from_millis(240));
+        assert_eq!(policy.wait(reading, 40), policy.max_wait);
+        assert_eq!(policy.wait(Duration::from_millis(0), 1), policy.min_wait);
+    }
+
+    #[test]
+    fn gives_up_after_the_configured_attempts() {
+        let policy = RetryPolicy {
+            attempts: 4,
+            min_wait: Duration::from_millis(0),
+            max_wait: Duration::from_millis(0),
+            ..RetryPolicy::default()
+        };
+        let (mut calls, mut retries) = (0, 0);
+        let result: ::std::result::Result<(), ()> =
+            policy.retry(Duration::from_millis(0), None, &mut retries, || {
+                calls += 1;
+                Err(())
+            });
+        assert!(result.is_err());
+        assert_eq!(calls, 4);
+        assert_eq!(retries, 3);
+    }
+
+    #[test]
+    fn retries_stop_at_the_deadline() {
+        let policy = RetryPolicy {
+            attempts: 10,
+            min_wait: Duration::from_millis(50),
+            ..RetryPolicy::default()
+        };
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(120);
+        let mut calls = 0;
+        let result: ::std::result::Result<(), ()> =
+            policy.retry(Duration::from_millis(0), Some(deadline), &mut 0, || {
+                calls += 1;
+                Err(())
+            });
+        assert!(result.is_err());
+        assert!(calls < 10);
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn wakes_a_sleeping_chip_before_the_command() {
+        use simulator::{SimulatedChip, VirtualBus};
+
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_asleep(true);
+        let id = bus.attach(chip);
+        let mut dev = bus.device(0x66);
+        let once = RetryPolicy {
+            attempts: 1,
+            ..RetryPolicy::default()
+        };
+
+        assert!(write_with_retry(&mut dev, b"R\0", &once, Duration::from_millis(0)).is_err());
+        bus.with_chip(id, |chip| { chip.set_asleep(true); });
+        wake(&mut dev, &RetryPolicy::no_wait().wait_strategy);
+        assert_eq!(bus.with_chip(id, |chip| chip.is_asleep()), Some(false));
+        assert!(write_with_retry(&mut dev, b"R\0", &once, Duration::from_millis(0)).is_ok());
+    }
+
+    #[test]
+    fn waits_with_the_chosen_strategy() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static WAITS: AtomicUsize = AtomicUsize::new(0);
+        fn count(_: Duration) {
+            WAITS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let policy = RetryPolicy {
+            attempts: 3,
+            wait_strategy: WaitStrategy::Custom(count),
+            ..RetryPolicy::default()
+        };
+        let result: ::std::result::Result<(), ()> =
+            policy.retry(Duration::from_millis(0), None, &mut 0, || Err(()));
+        assert!(result.is_err());
+        assert_eq!(WAITS.load(Ordering::SeqCst), 2);
+
+        let start = Instant::now();
+        WaitStrategy::SpinYield.wait(Duration::from_millis(5));
+        assert!(start.elapsed() >= Duration::from_millis(5));
+    }
+}
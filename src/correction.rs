@@ -0,0 +1,96 @@
+//! Corrections applied to readings in software.
+//!
+//! When the probe cannot be taken out for a bath calibration, its readings
+//! can still be corrected against a reference thermometer: by a constant
+//! offset, or by the straight line through two points. The correction is
+//! applied on top of the chip's readings, and leaves the chip's own
+//! calibration untouched.
+
+use errors::*;
+use response::{Temperature, TemperatureScale};
+
+/// A linear correction, `corrected = slope * reading + offset`, with
+/// temperatures in `scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Correction {
+    pub scale: TemperatureScale,
+    pub slope: f64,
+    pub offset: f64,
+}
+
+impl Correction {
+    /// Adds `offset`, in `scale`, to every reading.
+    pub fn offset(scale: TemperatureScale, offset: f64) -> Correction {
+        Correction {
+            scale: scale,
+            slope: 1.0,
+            offset: offset,
+        }
+    }
+
+    /// The correction that maps two readings to the reference
+    /// temperatures taken at the same time, each point being a
+    /// `(reading, reference)` pair in `scale`. The readings must be apart.
+    pub fn two_point(scale: TemperatureScale,
+                     low: (f64, f64),
+                     high: (f64, f64))
+                     -> Result<Correction> {
+        let (reading_low, reference_low) = low;
+        let (reading_high, reference_high) = high;
+        let span = reading_high - reading_low;
+        if !span.is_finite() || span.abs() < 1e-6 {
+            bail!("the two readings of a two-point correction must differ");
+        }
+        let slope = (reference_high - reference_low) / span;
+        if !slope.is_finite() {
+            bail!("the two-point correction is not a finite line");
+        }
+        Ok(Correction {
+               scale: scale,
+               slope: slope,
+               offset: reference_low - slope * reading_low,
+           })
+    }
+
+    /// Corrects a reading; the result is in the reading's scale.
+    pub fn apply(&self, temperature: Temperature) -> Temperature {
+        let value = temperature.to_scale(self.scale).value();
+        Temperature::new(self.scale, self.slope * value + self.offset)
+            .to_scale(temperature.scale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Temperature, b: Temperature) -> bool {
+        a.scale() == b.scale() && (a.value() - b.value()).abs() < 1e-9
+    }
+
+    #[test]
+    fn offsets_apply_in_their_own_scale() {
+        let correction = Correction::offset(TemperatureScale::Celsius, -0.5);
+        assert!(close(correction.apply(Temperature::Celsius(25.0)),
+                      Temperature::Celsius(24.5)));
+        assert!(close(correction.apply(Temperature::Fahrenheit(77.0)),
+                      Temperature::Fahrenheit(76.1)));
+    }
+
+    #[test]
+    fn two_points_make_a_line() {
+        let correction = Correction::two_point(TemperatureScale::Celsius,
+                                               (0.4, 0.0),
+                                               (100.2, 100.0))
+                .unwrap();
+        assert!(close(correction.apply(Temperature::Celsius(0.4)),
+                      Temperature::Celsius(0.0)));
+        assert!(close(correction.apply(Temperature::Celsius(100.2)),
+                      Temperature::Celsius(100.0)));
+        assert!(close(correction.apply(Temperature::Celsius(50.3)),
+                      Temperature::Celsius(50.0)));
+
+        assert!(Correction::two_point(TemperatureScale::Celsius, (20.0, 19.0), (20.0, 21.0))
+                    .is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Bundles of what a sensor knows, for bug reports.
+//!
+//! A sensor keeps its last `RECENT_COMMANDS` commands, and its last
+//! `RECENT_ERRORS` failed ones, with what the chip answered.
+//! `RtdSensor::debug_bundle` gathers those, a snapshot of the chip and its
+//! firmware, the transaction statistics and the handle's settings into one
+//! `DebugBundle`, which is saved as text, so that a single file can be
+//! attached to a bug report.
+//!
+//! The bundle is made even when the chip does not answer: the snapshot's
+//! error is recorded in its place.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::RetryPolicy;
+use ezo_common::{ResponseCode, response_code};
+use hooks::CommandEvent;
+use response::CalibrationState;
+use restarts::RestartRecord;
+use sensor::Snapshot;
+use stats::TransactionStats;
+use timing::Delays;
+
+/// Commands a sensor remembers.
+pub const RECENT_COMMANDS: usize = 32;
+
+/// Failed commands a sensor remembers, beyond the recent ones.
+pub const RECENT_ERRORS: usize = 16;
+
+/// A command that is over, as remembered by a sensor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandRecord {
+    /// When the command ended.
+    pub time: SystemTime,
+    /// The command string, without its terminating NUL.
+    pub command: String,
+    /// The response code byte and the payload, for commands whose response
+    /// was read.
+    pub response: Option<(u8, String)>,
+    pub elapsed: Duration,
+    /// Set if the command failed on the bus.
+    pub error: Option<String>,
+}
+
+impl CommandRecord {
+    pub fn from_event(event: &CommandEvent) -> CommandRecord {
+        CommandRecord {
+            time: SystemTime::now(),
+            command: event.command.to_string(),
+            response: event
+                .response
+                .and_then(|data| data.split_first())
+                .map(|(&code, payload)| {
+                    let end = payload.iter().position(|&b| b == 0).unwrap_or(payload.len());
+                    (code, String::from_utf8_lossy(&payload[..end]).into_owned())
+                }),
+            elapsed: event.elapsed,
+            error: event.error.map(|e| e.to_string()),
+        }
+    }
+
+    /// Whether the command failed on the bus, or the chip answered with
+    /// anything but success or `Pending`.
+    pub fn failed(&self) -> bool {
+        match self.response {
+            Some((code, _)) => {
+                match response_code(code) {
+                    ResponseCode::Success | ResponseCode::Pending => false,
+                    _ => true,
+                }
+            }
+            None => self.error.is_some(),
+        }
+    }
+}
+
+impl fmt::Display for CommandRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} {} ({} ms): ",
+               seconds(self.time),
+               self.command,
+               self.elapsed.as_millis())?;
+        match (&self.response, &self.error) {
+            (_, &Some(ref error)) => write!(f, "error: {}", error),
+            (&Some((code, ref payload)), _) if payload.is_empty() => write!(f, "code {}", code),
+            (&Some((code, ref payload)), _) => write!(f, "code {} {}", code, payload),
+            (&None, &None) => write!(f, "no response"),
+        }
+    }
+}
+
+/// The commands a sensor remembers.
+#[derive(Debug, Clone, Default)]
+pub struct RecentCommands {
+    commands: VecDeque<CommandRecord>,
+    errors: VecDeque<CommandRecord>,
+}
+
+impl RecentCommands {
+    pub fn new() -> RecentCommands {
+        RecentCommands::default()
+    }
+
+    pub fn record(&mut self, event: &CommandEvent) {
+        let record = CommandRecord::from_event(event);
+        if record.failed() {
+            if self.errors.len() == RECENT_ERRORS {
+                self.errors.pop_front();
+            }
+            self.errors.push_back(record.clone());
+        }
+        if self.commands.len() == RECENT_COMMANDS {
+            self.commands.pop_front();
+        }
+        self.commands.push_back(record);
+    }
+
+    /// The last commands, oldest first.
+    pub fn commands(&self) -> Vec<CommandRecord> {
+        self.commands.iter().cloned().collect()
+    }
+
+    /// The last failed commands, oldest first.
+    pub fn errors(&self) -> Vec<CommandRecord> {
+        self.errors.iter().cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+        self.errors.clear();
+    }
+}
+
+/// Everything a sensor knows about its chip, from
+/// `RtdSensor::debug_bundle`.
+#[derive(Debug, Clone)]
+pub struct DebugBundle {
+    pub created: SystemTime,
+    pub address: Option<u16>,
+    /// The chip's state, or why it could not be had.
+    pub snapshot: ::std::result::Result<Snapshot, String>,
+    pub delays: Delays,
+    /// How writes are retried.
+    pub retry: RetryPolicy,
+    pub read_retry: RetryPolicy,
+    pub stats: TransactionStats,
+    /// The restarts of the chip noticed by the sensor, oldest first.
+    pub restarts: Vec<RestartRecord>,
+    /// The last commands before the bundle was made, oldest first.
+    pub commands: Vec<CommandRecord>,
+    /// The last failed commands, oldest first.
+    pub errors: Vec<CommandRecord>,
+}
+
+/// Seconds since the Unix epoch, to the millisecond.
+fn seconds(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    format!("{}.{:03}", since.as_secs(), since.subsec_millis())
+}
+
+impl fmt::Display for DebugBundle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# ezo_rtd {} debug bundle", env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "created: {}", seconds(self.created))?;
+        match self.address {
+            Some(address) => writeln!(f, "address: {:#04x}", address)?,
+            None => writeln!(f, "address: unknown")?,
+        }
+
+        writeln!(f, "\n[chip]")?;
+        match self.snapshot {
+            Ok(ref snapshot) => {
+                writeln!(f, "model: EZO {}", snapshot.info.device_type)?;
+                writeln!(f, "firmware: {}", snapshot.info.firmware)?;
+                match snapshot.name {
+                    Some(ref name) => writeln!(f, "name: {}", name)?,
+                    None => writeln!(f, "name: (not supported by the firmware)")?,
+                }
+                writeln!(f, "scale: {}", snapshot.scale.symbol())?;
+                writeln!(f,
+                         "calibration: {}",
+                         match snapshot.calibration {
+                             CalibrationState::Calibrated => "calibrated",
+                             CalibrationState::Uncalibrated => "uncalibrated",
+                         })?;
+                writeln!(f, "data logger interval: {} s", snapshot.datalogger_interval.0)?;
+                writeln!(f, "led: {:?}", snapshot.led)?;
+                writeln!(f, "vcc: {:.3} V", snapshot.status.vcc_voltage)?;
+                writeln!(f,
+                         "restart reason: {}",
+                         snapshot.status.restart_reason.description())?;
+            }
+            Err(ref error) => writeln!(f, "error: {}", error)?,
+        }
+
+        writeln!(f, "\n[settings]")?;
+        writeln!(f,
+                 "delays: reading {} ms, calibration {} ms, command {} ms",
+                 self.delays.reading,
+                 self.delays.calibration,
+                 self.delays.command)?;
+        for &(kind, retry) in &[("write", self.retry), ("read", self.read_retry)] {
+            writeln!(f,
+                     "{} retries: {} attempts, waits of {} to {} ms, {:?}, {} pending polls \
+                      every {} ms",
+                     kind,
+                     retry.attempts,
+                     retry.min_wait.as_millis(),
+                     retry.max_wait.as_millis(),
+                     retry.wait_strategy,
+                     retry.pending_polls,
+                     retry.poll_interval.as_millis())?;
+        }
+
+        writeln!(f, "\n[statistics]")?;
+        writeln!(f,
+                 "transactions: {}, retries: {}",
+                 self.stats.transactions(),
+                 self.stats.retries())?;
+        for &(code, count) in self.stats.codes() {
+            writeln!(f, "code {:?}: {}", code, count)?;
+        }
+        for (command, stats) in self.stats.commands() {
+            writeln!(f,
+                     "{}: {} run, {} retries, {} failed",
+                     command,
+                     stats.count,
+                     stats.retries,
+                     stats.failures)?;
+        }
+        for &percentile in &[50.0, 99.0] {
+            if let Some(latency) = self.stats.latency_percentile(percentile) {
+                writeln!(f, "p{} latency: {} ms", percentile, latency.as_millis())?;
+            }
+        }
+
+        writeln!(f, "\n[restarts]")?;
+        for restart in &self.restarts {
+            writeln!(f, "{} {}", seconds(restart.time), restart.reason.description())?;
+        }
+
+        writeln!(f, "\n[recent commands]")?;
+        for record in &self.commands {
+            writeln!(f, "{}", record)?;
+        }
+        writeln!(f, "\n[recent errors]")?;
+        for record in &self.errors {
+            writeln!(f, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sensor::RtdSensor;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn bundles_recent_commands_errors_and_the_chip_state() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy::no_wait());
+        sensor.read_temperature().unwrap();
+        bus.with_chip(chip, |chip| { chip.set_response_code(Some(2)); });
+        assert!(sensor.get_status().is_err());
+        bus.with_chip(chip, |chip| { chip.set_response_code(None); });
+
+        let bundle = sensor.debug_bundle();
+        assert_eq!(bundle.snapshot.as_ref().unwrap().info.device_type, "RTD");
+        let commands: Vec<&str> = bundle
+            .commands
+            .iter()
+            .map(|record| record.command.as_str())
+            .collect();
+        assert_eq!(commands, vec!["S,?", "R", "Status"]);
+        assert_eq!(bundle.errors.len(), 1);
+        assert_eq!(bundle.errors[0].response.as_ref().map(|&(code, _)| code), Some(2));
+
+        let text = bundle.to_string();
+        assert!(text.contains("model: EZO RTD"));
+        assert!(text.contains("[recent errors]\n"));
+        assert!(text.contains(" Status ("));
+
+        bus.detach(chip);
+        assert!(sensor.debug_bundle().snapshot.is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Finding EZO chips on an I2C bus.
+//!
+//! Every address in a range that acknowledges a probe is sent the `I`
+//! command; the chips that answer with a well-formed device information
+//! string are then asked for their name. Addresses that do not acknowledge,
+//! or that answer with anything else, are skipped.
+//!
+//! Other devices on the bus, such as EEPROMs or clocks, may not take kindly
+//! to being sent `I`, so `scan_bus` only looks at the addresses EZO chips
+//! ship with. Scanning the whole bus with `scan_bus_addresses` is up to the
+//! caller.
+
+use std::thread;
+use std::time::Duration;
+
+use buffer::MAX_RESPONSE;
+use errors::*;
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::LinuxI2CDevice;
+use parse;
+use response::{DeviceInfo, DeviceName};
+use {I2cCommand, TemperatureCommand};
+
+/// Lowest valid 7-bit I2C address.
+pub const FIRST_ADDRESS: u16 = 1;
+
+/// Highest valid 7-bit I2C address.
+pub const LAST_ADDRESS: u16 = 127;
+
+/// Lowest factory address of an EZO chip.
+pub const FIRST_EZO_ADDRESS: u16 = 0x61;
+
+/// Highest factory address of an EZO chip.
+pub const LAST_EZO_ADDRESS: u16 = 0x70;
+
+/// An I2C device handle that can be pointed at another slave address.
+pub trait Addressable: I2CDevice {
+    /// Makes subsequent reads and writes go to `address`.
+    fn select_address(&mut self, address: u16) -> Result<()>;
+}
+
+impl Addressable for LinuxI2CDevice {
+    fn select_address(&mut self, address: u16) -> Result<()> {
+        self.set_slave_address(address)
+            .chain_err(|| format!("Could not select I2C address {:#04x}", address))
+    }
+}
+
+/// An EZO chip found while scanning the bus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredDevice {
+    pub address: u16,
+    pub device_type: String,
+    pub firmware: String,
+    /// The name set with `Name,n`, if the chip answered the query and has
+    /// one.
+    pub name: Option<String>,
+}
+
+impl DiscoveredDevice {
+    /// Whether the chip is an RTD chip, rather than another kind of EZO
+    /// chip sharing the bus.
+    pub fn is_rtd(&self) -> bool {
+        self.device_type.eq_ignore_ascii_case("RTD")
+    }
+}
+
+/// Returns `true` if something on the bus acknowledges `address`, leaving
+/// `dev` pointed at it. The probe is tried twice, since a sleeping chip does
+/// not acknowledge the transaction that wakes it up.
+pub fn acknowledges<D: Addressable>(dev: &mut D, address: u16) -> Result<bool> {
+    dev.select_address(address)?;
+    Ok(dev.smbus_write_quick(false).is_ok() || dev.smbus_write_quick(false).is_ok())
+}
+
+/// Sends `command` to the currently selected address and returns the
+/// response payload, or `None` if nothing answered with a success code.
+///
+/// The write is tried twice, since a sleeping chip does not acknowledge the
+/// byte that wakes it up.
+fn query<D: I2CDevice>(dev: &mut D, command: TemperatureCommand) -> Option<String> {
+    let cmd = command.build();
+    if dev.write(cmd.command.as_bytes()).is_err() && dev.write(cmd.command.as_bytes()).is_err() {
+        return None;
+    }
+    if let Some(delay) = cmd.delay {
+        thread::sleep(Duration::from_millis(delay));
+    }
+    let mut data = [0u8; MAX_RESPONSE];
+    if dev.read(&mut data).is_err() {
+        return None;
+    }
+    parse::payload(&data).ok()
+}
+
+/// Asks every address in `addresses` that acknowledges for its device
+/// information, and returns the EZO chips that answered, in scanning order.
+pub fn scan<D, I>(dev: &mut D, addresses: I) -> Result<Vec<DiscoveredDevice>>
+    where D: Addressable,
+          I: IntoIterator<Item = u16>
+{
+    let mut found = Vec::new();
+    for address in addresses {
+        if !acknowledges(dev, address)? {
+            continue;
+        }
+        let info = match query(dev, TemperatureCommand::DeviceInformation)
+                  .and_then(|response| DeviceInfo::parse(&response).ok()) {
+            Some(info) => info,
+            None => continue,
+        };
+        let name = query(dev, TemperatureCommand::NameState)
+            .and_then(|response| DeviceName::parse(&response).ok())
+            .and_then(|DeviceName(name)| if name.is_empty() { None } else { Some(name) });
+        found.push(DiscoveredDevice {
+                       address: address,
+                       device_type: info.device_type,
+                       firmware: info.firmware,
+                       name: name,
+                   });
+    }
+    Ok(found)
+}
+
+/// Scans the EZO factory addresses on `/dev/i2c-<bus>`.
+pub fn scan_bus(bus: u8) -> Result<Vec<DiscoveredDevice>> {
+    scan_bus_addresses(bus, FIRST_EZO_ADDRESS..LAST_EZO_ADDRESS + 1)
+}
+
+/// Scans `addresses` on `/dev/i2c-<bus>`.
+pub fn scan_bus_addresses<I>(bus: u8, addresses: I) -> Result<Vec<DiscoveredDevice>>
+    where I: IntoIterator<Item = u16>
+{
+    let device_path = format!("/dev/i2c-{}", bus);
+    let mut dev = LinuxI2CDevice::new(&device_path, FIRST_ADDRESS)
+        .chain_err(|| format!("Could not open I2C device {}", device_path))?;
+    scan(&mut dev, addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn finds_every_ezo_chip_on_the_bus() {
+        let bus = VirtualBus::new();
+        let mut rtd = SimulatedChip::new(0x66);
+        rtd.set_name("sump");
+        bus.attach(rtd);
+        let mut ph = SimulatedChip::new(0x63);
+        ph.set_identity("pH", "1.96");
+        bus.attach(ph);
+        let mut sleeper = SimulatedChip::new(0x10);
+        sleeper.set_asleep(true);
+        bus.attach(sleeper);
+
+        let mut dev = bus.device(FIRST_ADDRESS);
+        let found = scan(&mut dev, FIRST_ADDRESS..LAST_ADDRESS + 1).unwrap();
+        assert_eq!(found,
+                   vec![DiscoveredDevice {
+                            address: 0x10,
+                            device_type: "RTD".to_string(),
+                            firmware: "2.01".to_string(),
+                            name: None,
+                        },
+                        DiscoveredDevice {
+                            address: 0x63,
+                            device_type: "pH".to_string(),
+                            firmware: "1.96".to_string(),
+                            name: None,
+                        },
+                        DiscoveredDevice {
+                            address: 0x66,
+                            device_type: "RTD".to_string(),
+                            firmware: "2.01".to_string(),
+                            name: Some("sump".to_string()),
+                        }]);
+        let rtds: Vec<u16> = found
+            .iter()
+            .filter(|device| device.is_rtd())
+            .map(|device| device.address)
+            .collect();
+        assert_eq!(rtds, vec![0x10, 0x66]);
+    }
+
+    #[test]
+    fn only_ezo_addresses_are_sent_commands_by_default() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut sleeper = SimulatedChip::new(0x10);
+        sleeper.set_asleep(true);
+        let sleeper = bus.attach(sleeper);
+
+        let mut dev = bus.device(FIRST_ADDRESS);
+        let found = scan(&mut dev, FIRST_EZO_ADDRESS..LAST_EZO_ADDRESS + 1).unwrap();
+        let addresses: Vec<u16> = found.iter().map(|device| device.address).collect();
+        assert_eq!(addresses, vec![0x66]);
+        assert_eq!(bus.with_chip(sleeper, |chip| chip.is_asleep()), Some(true));
+    }
+
+    #[test]
+    fn empty_bus_yields_no_devices() {
+        let bus = VirtualBus::new();
+        let mut dev = bus.device(FIRST_ADDRESS);
+        assert!(scan(&mut dev, FIRST_ADDRESS..LAST_ADDRESS + 1)
+                    .unwrap()
+                    .is_empty());
+    }
+}
@@ -0,0 +1,285 @@
+//! A transport that previews the transactions with a chip.
+//!
+//! `DryRun` devices talk to no hardware. They log every transaction a
+//! sensor makes through them, with the time waited since the one before,
+//! and answer like a chip that takes every command: the answers come from
+//! a simulated chip at the address each device was opened at, so that the
+//! sensor goes on as it would with a real one. Other addresses, such as
+//! that of a multiplexer, acknowledge every transaction and read back
+//! zeros. Since the sensor code is the real one, the bytes and waits logged
+//! are those a provisioning run or a calibration would send to the chip.
+//!
+//! The waits are not made but recorded, by the wait strategy of
+//! `DryRun::retry_policy`, which the sensors must use; the dry run is then
+//! immediate, and the waits logged are those the sensor asked for. They
+//! are recorded for the thread they are asked for on, so a dry run is best
+//! kept to one thread.
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use common::{RetryPolicy, WaitStrategy};
+use discovery::Addressable;
+use errors::*;
+use i2cdev::core::I2CDevice;
+use replay::Operation;
+use simulator::{SimError, SimulatedChip, SimulatedDevice, VirtualBus};
+
+/// One transaction a sensor would have made.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedTransaction {
+    /// The address the device pointed at.
+    pub address: u16,
+    /// Time since the transaction before, on any device of the dry run.
+    pub after: Duration,
+    /// What was done; reads carry the simulated answer.
+    pub operation: Operation,
+}
+
+impl fmt::Display for PlannedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#04x} +{:>4} ms  ", self.address, self.after.as_millis())?;
+        match self.operation {
+            Operation::Address(address) => write!(f, "select {:#04x}", address),
+            Operation::Quick(bit) => write!(f, "quick {}", bit as u8),
+            Operation::Write(ref bytes) => write!(f, "write \"{}\"", escaped(bytes)),
+            Operation::Read(ref bytes) => write!(f, "read  \"{}\"", escaped(bytes)),
+        }
+    }
+}
+
+/// `bytes` without their trailing NULs, with other unprintable bytes
+/// escaped, and a final NUL as `\0`.
+fn escaped(bytes: &[u8]) -> String {
+    let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |last| last + 1);
+    let mut text: String = bytes[..end]
+        .iter()
+        .flat_map(|&b| ::std::ascii::escape_default(b))
+        .map(char::from)
+        .collect();
+    if end < bytes.len() {
+        text.push_str("\\0");
+    }
+    text
+}
+
+thread_local! {
+    /// Time waited on this thread since the last transaction was logged.
+    static WAITED: Cell<Duration> = Cell::new(Duration::from_millis(0));
+}
+
+/// Records a wait instead of making it.
+fn record_wait(duration: Duration) {
+    WAITED.with(|waited| waited.set(waited.get() + duration));
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    transactions: Vec<PlannedTransaction>,
+    /// Addresses a simulated chip answers at.
+    attached: HashSet<u16>,
+}
+
+/// A dry run, shared by the devices it opens.
+///
+/// Cloning a `DryRun` yields another handle to the same log.
+#[derive(Debug, Clone, Default)]
+pub struct DryRun {
+    log: Arc<Mutex<Log>>,
+    bus: VirtualBus,
+}
+
+impl DryRun {
+    pub fn new() -> DryRun {
+        DryRun::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// A device pointing at `address`, to be used where a real one would
+    /// be opened. A simulated chip answers at `address` from now on.
+    pub fn device(&self, address: u16) -> DryRunDevice {
+        if self.lock().attached.insert(address) {
+            self.bus.attach(SimulatedChip::new(address));
+        }
+        DryRunDevice {
+            run: self.clone(),
+            dev: self.bus.device(address),
+            address: address,
+        }
+    }
+
+    /// The default retry policy, with a wait strategy that records the
+    /// waits for the log instead of making them; for the sensors of the
+    /// dry run.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            wait_strategy: WaitStrategy::Custom(record_wait),
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// The transactions made so far, in order.
+    pub fn transactions(&self) -> Vec<PlannedTransaction> {
+        self.lock().transactions.clone()
+    }
+
+    /// The transactions made so far, one per line.
+    pub fn plan(&self) -> String {
+        self.lock()
+            .transactions
+            .iter()
+            .map(|transaction| format!("{}\n", transaction))
+            .collect()
+    }
+}
+
+/// A device of a `DryRun`.
+pub struct DryRunDevice {
+    run: DryRun,
+    dev: SimulatedDevice,
+    address: u16,
+}
+
+impl DryRunDevice {
+    /// Whether a simulated chip answers at the device's address.
+    fn simulated(&self) -> bool {
+        self.run.lock().attached.contains(&self.address)
+    }
+
+    fn log(&mut self, operation: Operation) {
+        let after = WAITED.with(|waited| waited.replace(Duration::from_millis(0)));
+        self.run.lock().transactions.push(PlannedTransaction {
+                                  address: self.address,
+                                  after: after,
+                                  operation: operation,
+                              });
+    }
+
+    fn sim<T>(&self, result: ::std::result::Result<T, SimError>) -> Result<T> {
+        let address = self.address;
+        result.map_err(|e| {
+                           format!("the simulated chip at {:#04x} failed: {:?}", address, e).into()
+                       })
+    }
+}
+
+impl Addressable for DryRunDevice {
+    fn select_address(&mut self, address: u16) -> Result<()> {
+        self.log(Operation::Address(address));
+        self.address = address;
+        self.dev.select_address(address)
+    }
+}
+
+impl I2CDevice for DryRunDevice {
+    type Error = Error;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<()> {
+        if self.simulated() {
+            let result = self.dev.read(data);
+            self.sim(result)?;
+        } else {
+            for byte in data.iter_mut() {
+                *byte = 0;
+            }
+        }
+        self.log(Operation::Read(data.to_vec()));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.log(Operation::Write(data.to_vec()));
+        if !self.simulated() {
+            return Ok(());
+        }
+        let result = self.dev.write(data);
+        self.sim(result)
+    }
+
+    fn smbus_write_quick(&mut self, bit: bool) -> Result<()> {
+        self.log(Operation::Quick(bit));
+        if !self.simulated() {
+            return Ok(());
+        }
+        let result = self.dev.smbus_write_quick(bit);
+        self.sim(result)
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>> {
+        bail!("SMBus block reads are not previewed")
+    }
+
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> Result<()> {
+        bail!("SMBus block writes are not previewed")
+    }
+
+    fn smbus_process_block(&mut self, _register: u8, _values: &[u8]) -> Result<()> {
+        bail!("SMBus block calls are not previewed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sensor::RtdSensor;
+
+    #[test]
+    fn logs_what_a_calibration_would_send() {
+        let run = DryRun::new();
+        let mut sensor = RtdSensor::new(run.device(0x66));
+        sensor.set_retry_policy(run.retry_policy());
+        sensor.calibrate(100.0).unwrap();
+
+        let writes: Vec<Operation> = run.transactions()
+            .into_iter()
+            .map(|transaction| transaction.operation)
+            .filter(|operation| match *operation {
+                        Operation::Write(_) => true,
+                        _ => false,
+                    })
+            .collect();
+        assert_eq!(writes,
+                   vec![Operation::Write(b"S,?\0".to_vec()),
+                        Operation::Write(b"Cal,100.00\0".to_vec())]);
+        assert!(run.plan().contains("0x66 +   0 ms  write \"S,?\\0\"\n"));
+    }
+
+    #[test]
+    fn logs_the_planned_delays_without_waiting() {
+        let run = DryRun::new();
+        let mut sensor = RtdSensor::new(run.device(0x66));
+        sensor.set_retry_policy(run.retry_policy());
+        let started = ::std::time::Instant::now();
+        sensor.led_off().unwrap();
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        let transactions = run.transactions();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].operation, Operation::Write(b"L,0\0".to_vec()));
+        assert_eq!(transactions[1].after, Duration::from_millis(300));
+        match transactions[1].operation {
+            Operation::Read(ref answer) => assert_eq!(answer[0], 1),
+            ref other => panic!("unexpected operation {:?}", other),
+        }
+    }
+
+    #[test]
+    fn selecting_a_multiplexer_attaches_no_chip() {
+        let run = DryRun::new();
+        let mut dev = run.device(0x66);
+        dev.select_address(0x70).unwrap();
+        dev.write(&[1 << 2]).unwrap();
+        let mut buf = [0xffu8; 4];
+        dev.read(&mut buf).unwrap();
+        assert_eq!(buf, [0; 4]);
+        assert!(!run.bus.acks(0x70));
+        dev.select_address(0x66).unwrap();
+        assert!(run.bus.acks(0x66));
+    }
+}
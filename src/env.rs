@@ -0,0 +1,180 @@
+//! Configuring a sensor from environment variables.
+//!
+//! `RtdSensor::from_env` opens the chip given by `EZO_RTD_BUS` and
+//! `EZO_RTD_ADDR`, and applies `EZO_RTD_SCALE` and `EZO_RTD_ATTEMPTS`, so
+//! that containers and examples can be pointed at another chip without
+//! recompiling, or passing flags through every layer in between. Unset
+//! variables keep their defaults; invalid ones are errors.
+
+use std::env;
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::LinuxI2CDevice;
+use response::TemperatureScale;
+use sensor::RtdSensor;
+use {DEFAULT_ADDRESS, DEFAULT_BUS};
+
+/// I2C bus number, for `/dev/i2c-N`.
+pub const BUS_VAR: &str = "EZO_RTD_BUS";
+/// Chip address, decimal or `0x`-prefixed hex.
+pub const ADDRESS_VAR: &str = "EZO_RTD_ADDR";
+/// Temperature scale to set the chip to: `C`, `K` or `F`, or spelled out.
+pub const SCALE_VAR: &str = "EZO_RTD_SCALE";
+/// Attempts at each write or read, the first one included.
+pub const ATTEMPTS_VAR: &str = "EZO_RTD_ATTEMPTS";
+
+/// A sensor's configuration, as given by the environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvConfig {
+    pub bus: u8,
+    pub address: u16,
+    pub scale: Option<TemperatureScale>,
+    pub attempts: Option<u32>,
+}
+
+impl EnvConfig {
+    /// Reads the configuration from the process environment.
+    pub fn from_env() -> Result<EnvConfig> {
+        EnvConfig::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// Reads the configuration from the variables given by `lookup`.
+    pub fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Result<EnvConfig> {
+        let var = |name| lookup(name).map(|value| value.trim().to_string());
+        let bus = match var(BUS_VAR) {
+            Some(value) => {
+                value
+                    .parse()
+                    .chain_err(|| format!("{}: invalid bus number `{}`", BUS_VAR, value))?
+            }
+            None => DEFAULT_BUS,
+        };
+        let address = match var(ADDRESS_VAR) {
+            Some(value) => parse_address(&value)?,
+            None => DEFAULT_ADDRESS,
+        };
+        let scale = match var(SCALE_VAR) {
+            Some(value) => {
+                Some(value
+                         .parse::<TemperatureScale>()
+                         .chain_err(|| format!("{}: invalid scale", SCALE_VAR))?)
+            }
+            None => None,
+        };
+        let attempts = match var(ATTEMPTS_VAR) {
+            Some(value) => {
+                match value.parse() {
+                    Ok(attempts) if attempts > 0 => Some(attempts),
+                    _ => bail!("{}: invalid number of attempts `{}`", ATTEMPTS_VAR, value),
+                }
+            }
+            None => None,
+        };
+        Ok(EnvConfig {
+               bus: bus,
+               address: address,
+               scale: scale,
+               attempts: attempts,
+           })
+    }
+
+    /// Opens the configured chip, and applies the configuration to it.
+    pub fn open(&self) -> Result<RtdSensor<LinuxI2CDevice>> {
+        let mut sensor = RtdSensor::open(self.bus, self.address)?;
+        self.apply(&mut sensor)?;
+        Ok(sensor)
+    }
+
+    /// Applies the retry attempts to `sensor`'s writes and reads, and sets
+    /// the chip's scale.
+    pub fn apply<D: I2CDevice>(&self, sensor: &mut RtdSensor<D>) -> Result<()> {
+        if let Some(attempts) = self.attempts {
+            let mut write = sensor.retry_policy();
+            write.attempts = attempts;
+            sensor.set_write_retry_policy(write);
+            let mut read = sensor.read_retry_policy();
+            read.attempts = attempts;
+            sensor.set_read_retry_policy(read);
+        }
+        if let Some(scale) = self.scale {
+            sensor.set_scale(scale)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_address(value: &str) -> Result<u16> {
+    let parsed = if value.starts_with("0x") {
+        u16::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse::<u16>()
+    };
+    match parsed {
+        Ok(address) if (1..=127).contains(&address) => Ok(address),
+        _ => bail!("{}: invalid I2C address `{}`", ADDRESS_VAR, value),
+    }
+}
+
+impl RtdSensor<LinuxI2CDevice> {
+    /// Opens the chip given by the environment; see the `env` module.
+    pub fn from_env() -> Result<RtdSensor<LinuxI2CDevice>> {
+        EnvConfig::from_env()?.open()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    fn config(vars: &[(&str, &str)]) -> Result<EnvConfig> {
+        let vars: HashMap<String, String> = vars.iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        EnvConfig::from_lookup(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn reads_the_configuration_from_variables() {
+        assert_eq!(config(&[]).unwrap(),
+                   EnvConfig {
+                       bus: DEFAULT_BUS,
+                       address: DEFAULT_ADDRESS,
+                       scale: None,
+                       attempts: None,
+                   });
+
+        let env = config(&[(BUS_VAR, "3"), (ADDRESS_VAR, "0x67"), (SCALE_VAR, "f"),
+                           (ATTEMPTS_VAR, "5")])
+                .unwrap();
+        assert_eq!(env,
+                   EnvConfig {
+                       bus: 3,
+                       address: 0x67,
+                       scale: Some(TemperatureScale::Fahrenheit),
+                       attempts: Some(5),
+                   });
+
+        assert!(config(&[(ADDRESS_VAR, "0x80")]).is_err());
+        assert!(config(&[(SCALE_VAR, "R")]).is_err());
+        assert!(config(&[(ATTEMPTS_VAR, "0")]).is_err());
+    }
+
+    #[test]
+    fn applies_the_configuration_to_a_sensor() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        config(&[(SCALE_VAR, "K"), (ATTEMPTS_VAR, "2")])
+            .unwrap()
+            .apply(&mut sensor)
+            .unwrap();
+        assert_eq!(sensor.retry_policy().attempts, 2);
+        assert_eq!(sensor.read_retry_policy().attempts, 2);
+        assert_eq!(bus.with_chip(chip, |chip| chip.scale()), Some(TemperatureScale::Kelvin));
+    }
+}
@@ -6,8 +6,131 @@ error_chain! {
         ResponseParse {
             description ("could not parse response")
         }
+        // The chip read -1023, its reading when no probe is connected
+        ProbeDisconnected {
+            description ("no probe is connected to the chip")
+        }
+        // The chip answered with a response code other than success
+        UnsuccessfulResponse(code: u8) {
+            description ("the chip did not answer with success")
+            display ("the chip answered with response code {} ({:#04x})", code, code)
+        }
+        // The chip does not hold the calibration that was uploaded to it
+        CalibrationMismatch {
+            description ("the chip did not take the uploaded calibration")
+        }
+        // The calibration data exported is not what `Export,?` announced
+        IncompleteExport(strings: u32, bytes: u32) {
+            description ("the calibration data exported is incomplete")
+            display ("the chip did not export the {} strings and {} bytes of calibration \
+                      data it announced",
+                     strings, bytes)
+        }
+        // The chip was still processing the command when it should have
+        // been done
+        PendingResponse {
+            description ("the chip did not finish processing the command")
+        }
+        // The chip answered with the device error code, as it does for
+        // commands it does not understand or cannot carry out
+        DeviceError {
+            description ("the chip answered with a device error")
+        }
+        // The calibration temperature is outside the probe's range
+        CalibrationOutOfRange(temperature: f64, min: f64, max: f64) {
+            description ("calibration temperature out of range")
+            display ("calibration temperature {} is outside the probe's range, {} to {}",
+                     temperature, min, max)
+        }
+        // The command string does not fit in the command buffer
+        CommandTooLong {
+            description ("the command is too long")
+        }
+        // The command string is not one the chip can take
+        InvalidCommand(reason: &'static str) {
+            description ("invalid command")
+            display ("invalid command: {}", reason)
+        }
+        // Querying a setting after changing it gave another value
+        VerificationFailed(setting: &'static str) {
+            description ("the chip did not apply the setting")
+            display ("the chip did not apply the new {}", setting)
+        }
+        // A composite operation would have run past its time budget
+        BudgetExhausted(millis: u64) {
+            description ("the operation ran out of time")
+            display ("the operation ran out of its {} ms budget", millis)
+        }
+        // Another component holds the sensor's maintenance lease
+        SensorLeased(purpose: String) {
+            description ("the sensor is leased for maintenance")
+            display ("the sensor is leased for {}", purpose)
+        }
+        // Something already acknowledges the address a chip was to be moved to
+        AddressInUse(address: u16) {
+            description ("the address is already in use")
+            display ("address {:#04x} is already in use", address)
+        }
+        // The command bytes could not be written to the I2C device
+        I2CWrite(reason: String) {
+            description ("could not write to the I2C device")
+            display ("could not write to the I2C device: {}", reason)
+        }
+        // The buffer given for a response is shorter than the response can be
+        BufferTooSmall(needed: usize, len: usize) {
+            description ("the response buffer is too small")
+            display ("the response needs a {}-byte buffer, not a {}-byte one", needed, len)
+        }
+        // The response did not fit in the buffer it was read into
+        TruncatedResponse(len: usize) {
+            description ("the response was cut off")
+            display ("the response did not fit in the {}-byte buffer", len)
+        }
+        // An I2C transaction did not complete within the timeout
+        I2CTimeout(millis: u64) {
+            description ("the I2C transaction timed out")
+            display ("the I2C transaction did not complete within {} ms", millis)
+        }
+        // A replayed transaction is not the one recorded next
+        ReplayMismatch(index: usize, what: String) {
+            description ("the replay diverged from the transcript")
+            display ("transaction {} of the transcript: {}", index, what)
+        }
+        // A baud rate the chip cannot be set to
+        UnsupportedBaudRate(bps: u32) {
+            description ("unsupported baud rate")
+            display ("the chip does not support {} bps", bps)
+        }
+        // A command hook refused to let a command through
+        CommandVetoed(command: String, reason: String) {
+            description ("a command hook vetoed the command")
+            display ("command {} was vetoed: {}", command, reason)
+        }
+        // The response bytes could not be read from the I2C device
+        I2CRead(reason: String) {
+            description ("could not read from the I2C device")
+            display ("could not read from the I2C device: {}", reason)
+        }
     }
     links {
         Ezo(ezo_common::errors::Error, ezo_common::errors::ErrorKind);
     }
+    foreign_links {
+        Io(::std::io::Error);
+    }
+}
+
+/// Errors are logged with their `Display` text.
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Display2Format(self))
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ErrorKind {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Display2Format(self))
+    }
 }
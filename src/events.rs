@@ -0,0 +1,156 @@
+//! Events for what a sensor did to its chip.
+//!
+//! A sensor tells its subscribers when it woke the chip up or put it to
+//! sleep, calibrated it, changed its scale or its address, and when a
+//! command went through after failed ones, so that user interfaces and
+//! audit logs can follow the chip without wrapping every call. Subscribers
+//! are either functions, run on the thread that drives the sensor, or
+//! channels.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use response::TemperatureScale;
+use TemperatureCommand;
+
+/// Something a sensor did to its chip.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceEvent {
+    /// The chip was woken up.
+    Woke,
+    /// The chip was put to sleep.
+    Slept,
+    /// The chip was calibrated at this temperature, in its scale.
+    Calibrated(f64),
+    /// The chip was set to give readings in this scale.
+    ScaleChanged(TemperatureScale),
+    /// The chip was told to move to this address; it answers there once it
+    /// has rebooted.
+    AddressChanged(u16),
+    /// A command went through after this many failed ones.
+    RecoveredFromError(u32),
+}
+
+impl DeviceEvent {
+    /// The event for `command`, once the chip has taken it, if any.
+    pub fn for_command(command: &TemperatureCommand) -> Option<DeviceEvent> {
+        match *command {
+            TemperatureCommand::Sleep => Some(DeviceEvent::Slept),
+            TemperatureCommand::CalibrationTemperature(t) => Some(DeviceEvent::Calibrated(t)),
+            TemperatureCommand::ScaleCelsius => {
+                Some(DeviceEvent::ScaleChanged(TemperatureScale::Celsius))
+            }
+            TemperatureCommand::ScaleKelvin => {
+                Some(DeviceEvent::ScaleChanged(TemperatureScale::Kelvin))
+            }
+            TemperatureCommand::ScaleFahrenheit => {
+                Some(DeviceEvent::ScaleChanged(TemperatureScale::Fahrenheit))
+            }
+            TemperatureCommand::DeviceAddress(address) => {
+                Some(DeviceEvent::AddressChanged(address))
+            }
+            _ => None,
+        }
+    }
+}
+
+enum Subscriber {
+    Callback(Box<dyn FnMut(&DeviceEvent) + Send>),
+    Channel(Sender<DeviceEvent>),
+}
+
+/// The subscribers to the events of one sensor.
+#[derive(Default)]
+pub struct Subscribers {
+    subscribers: Vec<Subscriber>,
+}
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers::default()
+    }
+
+    /// Passes every event to `callback` from now on.
+    pub fn subscribe<F: FnMut(&DeviceEvent) + Send + 'static>(&mut self, callback: F) {
+        self.subscribers.push(Subscriber::Callback(Box::new(callback)));
+    }
+
+    /// Sends every event to the returned channel from now on, until the
+    /// receiver is dropped.
+    pub fn channel(&mut self) -> Receiver<DeviceEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(Subscriber::Channel(sender));
+        receiver
+    }
+
+    /// Removes every subscriber.
+    pub fn clear(&mut self) {
+        self.subscribers.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /// Passes `event` to every subscriber, in the order they subscribed,
+    /// and forgets the channels whose receiver is gone.
+    pub fn emit(&mut self, event: DeviceEvent) {
+        self.subscribers.retain(|subscriber| match *subscriber {
+                                    Subscriber::Channel(ref sender) => {
+                                        sender.send(event.clone()).is_ok()
+                                    }
+                                    Subscriber::Callback(_) => true,
+                                });
+        for subscriber in &mut self.subscribers {
+            if let Subscriber::Callback(ref mut callback) = *subscriber {
+                callback(&event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use common::RetryPolicy;
+    use sensor::RtdSensor;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn sensors_emit_events_to_callbacks_and_channels() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy {
+                                    attempts: 1,
+                                    ..RetryPolicy::no_wait()
+                                });
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        sensor.subscribe(move |event: &DeviceEvent| log.lock().unwrap().push(event.clone()));
+        let events = sensor.subscribe_channel();
+
+        sensor.set_scale(TemperatureScale::Kelvin).unwrap();
+        sensor.calibrate(373.15).unwrap();
+        sensor.sleep().unwrap();
+        sensor.read_temperature().unwrap();
+        bus.with_chip(chip, |chip| { chip.set_response_code(Some(2)); });
+        assert!(sensor.get_calibration_state().is_err());
+        assert!(sensor.get_calibration_state().is_err());
+        bus.with_chip(chip, |chip| { chip.set_response_code(None); });
+        sensor.get_calibration_state().unwrap();
+
+        let expected = vec![DeviceEvent::ScaleChanged(TemperatureScale::Kelvin),
+                            DeviceEvent::Calibrated(373.15),
+                            DeviceEvent::Slept,
+                            DeviceEvent::Woke,
+                            DeviceEvent::RecoveredFromError(2)];
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), expected);
+
+        drop(events);
+        sensor.sleep().unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 6);
+    }
+}
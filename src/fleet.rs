@@ -0,0 +1,335 @@
+//! Provisioning many sensors from one fleet description.
+//!
+//! A fleet lists every sensor of a rig, where to find it, and the profile
+//! it should have. `Fleet::apply_all` reconciles each chip with its profile,
+//! naming it as well, and reports the result of each chip separately, so
+//! that one missing chip does not stop the others from being set up.
+//!
+//! With the `fleet` feature, a fleet can be read from TOML:
+//!
+//! ```toml
+//! [[sensor]]
+//! name = "sump"
+//! bus = 1
+//! address = 0x66
+//!
+//! [sensor.profile]
+//! scale = "C"
+//! led = false
+//!
+//! [[sensor]]
+//! name = "mash-tun"
+//! address = 0x67
+//! mux = { address = 0x70, channel = 2 }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use common::RetryPolicy;
+use discovery::Addressable;
+use errors::*;
+use i2cdev::linux::LinuxI2CDevice;
+use response::{LedStatus, ProtocolLockStatus, TemperatureScale};
+use sensor::RtdSensor;
+use settings::{Profile, SettingChange};
+use DEFAULT_BUS;
+
+/// A channel of a TCA9548A-style I2C multiplexer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "fleet", derive(Deserialize))]
+pub struct MuxChannel {
+    pub address: u16,
+    pub channel: u8,
+}
+
+/// A profile as written in a fleet file.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "fleet", derive(Deserialize))]
+#[cfg_attr(feature = "fleet", serde(default, deny_unknown_fields))]
+pub struct ProfileConfig {
+    /// `C`, `K` or `F`, or spelled out.
+    pub scale: Option<String>,
+    pub led: Option<bool>,
+    pub datalogger_interval: Option<u16>,
+    pub protocol_lock: Option<bool>,
+}
+
+impl ProfileConfig {
+    pub fn to_profile(&self) -> Result<Profile> {
+        let scale = match self.scale {
+            Some(ref scale) => Some(scale.parse::<TemperatureScale>()?),
+            None => None,
+        };
+        Ok(Profile {
+               scale: scale,
+               led: self.led
+                   .map(|on| if on { LedStatus::On } else { LedStatus::Off }),
+               datalogger_interval: self.datalogger_interval,
+               protocol_lock: self.protocol_lock
+                   .map(|locked| if locked {
+                            ProtocolLockStatus::Locked
+                        } else {
+                            ProtocolLockStatus::Unlocked
+                        }),
+               name: None,
+           })
+    }
+}
+
+/// One sensor of a fleet.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "fleet", derive(Deserialize))]
+#[cfg_attr(feature = "fleet", serde(deny_unknown_fields))]
+pub struct FleetSensor {
+    /// Also given to the chip with `Name,n`.
+    pub name: String,
+    #[cfg_attr(feature = "fleet", serde(default))]
+    pub bus: Option<u8>,
+    pub address: u16,
+    #[cfg_attr(feature = "fleet", serde(default))]
+    pub mux: Option<MuxChannel>,
+    #[cfg_attr(feature = "fleet", serde(default))]
+    pub profile: ProfileConfig,
+}
+
+impl FleetSensor {
+    pub fn bus(&self) -> u8 {
+        self.bus.unwrap_or(DEFAULT_BUS)
+    }
+
+    /// The sensor's profile, with its name.
+    pub fn profile(&self) -> Result<Profile> {
+        let mut profile = self.profile.to_profile()?;
+        profile.name = Some(self.name.clone());
+        Ok(profile)
+    }
+}
+
+/// The result of provisioning one sensor.
+#[derive(Debug)]
+pub struct DeviceResult {
+    pub name: String,
+    /// The changes made, none if the chip already matched its profile.
+    pub outcome: Result<Vec<SettingChange>>,
+}
+
+/// All the sensors of a rig.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "fleet", derive(Deserialize))]
+#[cfg_attr(feature = "fleet", serde(deny_unknown_fields))]
+pub struct Fleet {
+    #[cfg_attr(feature = "fleet", serde(default, rename = "sensor"))]
+    pub sensors: Vec<FleetSensor>,
+}
+
+impl Fleet {
+    /// Reads a fleet from TOML, and checks that every profile is valid.
+    #[cfg(feature = "fleet")]
+    pub fn from_toml(text: &str) -> Result<Fleet> {
+        let fleet: Fleet = ::toml::from_str(text).chain_err(|| "invalid fleet description")?;
+        for sensor in &fleet.sensors {
+            sensor
+                .profile()
+                .chain_err(|| format!("invalid profile for sensor `{}`", sensor.name))?;
+        }
+        Ok(fleet)
+    }
+
+    /// Provisions every sensor through `/dev/i2c-*`.
+    pub fn apply_all(&self) -> Vec<DeviceResult> {
+        self.apply_all_with(|bus, address| {
+            let device_path = format!("/dev/i2c-{}", bus);
+            LinuxI2CDevice::new(&device_path, address)
+                .chain_err(|| format!("Could not open I2C device {}", device_path))
+        })
+    }
+
+    /// Provisions every sensor, in order, with devices opened by `open`
+    /// for a bus and an address: the chip is reconciled with its profile,
+    /// which is then verified. The results are in the order of `sensors`.
+    pub fn apply_all_with<D, F>(&self, open: F) -> Vec<DeviceResult>
+        where D: Addressable,
+              F: FnMut(u8, u16) -> Result<D>
+    {
+        self.provision(None, RetryPolicy::default(), open)
+    }
+
+    /// Provisions every sensor like `apply_all_with`, retrying and waiting
+    /// as `retry` says; for a dry run, with `DryRun::retry_policy`.
+    pub fn apply_all_with_policy<D, F>(&self, retry: RetryPolicy, open: F) -> Vec<DeviceResult>
+        where D: Addressable,
+              F: FnMut(u8, u16) -> Result<D>
+    {
+        self.provision(None, retry, open)
+    }
+
+    /// Provisions every sensor like `apply_all_with`, within `budget` for
+    /// the whole fleet: once it has run out, the sensors left fail with
+    /// `BudgetExhausted`, without being opened.
+    pub fn apply_all_within<D, F>(&self, budget: Duration, open: F) -> Vec<DeviceResult>
+        where D: Addressable,
+              F: FnMut(u8, u16) -> Result<D>
+    {
+        self.provision(Some((Instant::now() + budget, budget)), RetryPolicy::default(), open)
+    }
+
+    fn provision<D, F>(&self,
+                       deadline: Option<(Instant, Duration)>,
+                       retry: RetryPolicy,
+                       mut open: F)
+                       -> Vec<DeviceResult>
+        where D: Addressable,
+              F: FnMut(u8, u16) -> Result<D>
+    {
+        self.sensors
+            .iter()
+            .map(|sensor| {
+                let outcome = sensor
+                    .profile()
+                    .and_then(|profile| {
+                        let left = match deadline {
+                            Some((end, budget)) => {
+                                let now = Instant::now();
+                                if now >= end {
+                                    let millis = budget.as_millis() as u64;
+                                    return Err(ErrorKind::BudgetExhausted(millis).into());
+                                }
+                                Some(end - now)
+                            }
+                            None => None,
+                        };
+                        let mut dev = open(sensor.bus(), sensor.address)?;
+                        if let Some(mux) = sensor.mux {
+                            select_channel(&mut dev, mux, sensor.address)?;
+                        }
+                        let mut chip = RtdSensor::new(dev);
+                        chip.set_retry_policy(retry);
+                        chip.set_known_address(sensor.address);
+                        match left {
+                            Some(left) => chip.with_budget(left, |chip| chip.reconcile(&profile)),
+                            None => chip.reconcile(&profile),
+                        }
+                    });
+                DeviceResult {
+                    name: sensor.name.clone(),
+                    outcome: outcome,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Selects `mux`'s channel alone, then points `dev` back at `address`.
+fn select_channel<D: Addressable>(dev: &mut D, mux: MuxChannel, address: u16) -> Result<()> {
+    if mux.channel > 7 {
+        bail!("multiplexer channel {} does not exist", mux.channel);
+    }
+    dev.select_address(mux.address)?;
+    dev.write(&[1 << mux.channel])
+        .map_err(|e| ErrorKind::I2CWrite(e.to_string()))?;
+    dev.select_address(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    fn rig() -> Fleet {
+        Fleet {
+            sensors: vec![FleetSensor {
+                              name: "sump".to_string(),
+                              bus: None,
+                              address: 0x66,
+                              mux: None,
+                              profile: ProfileConfig {
+                                  scale: Some("F".to_string()),
+                                  ..ProfileConfig::default()
+                              },
+                          },
+                          FleetSensor {
+                              name: "mash-tun".to_string(),
+                              bus: None,
+                              address: 0x67,
+                              mux: Some(MuxChannel {
+                                            address: 0x70,
+                                            channel: 2,
+                                        }),
+                              profile: ProfileConfig {
+                                  led: Some(false),
+                                  ..ProfileConfig::default()
+                              },
+                          },
+                          FleetSensor {
+                              name: "missing".to_string(),
+                              bus: None,
+                              address: 0x68,
+                              mux: None,
+                              profile: ProfileConfig::default(),
+                          }],
+        }
+    }
+
+    #[test]
+    fn provisions_every_sensor() {
+        let bus = VirtualBus::new();
+        let sump = bus.attach(SimulatedChip::new(0x66));
+        bus.add_mux(0x70);
+        let tun = bus.attach_behind_mux(0x70, 2, SimulatedChip::new(0x67));
+
+        let results = rig().apply_all_with(|_, address| Ok(bus.device(address)));
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].outcome.as_ref().unwrap().len(), 2);
+        assert_eq!(bus.with_chip(sump, |chip| chip.scale()),
+                   Some(TemperatureScale::Fahrenheit));
+        assert!(results[1].outcome.is_ok());
+        assert_eq!(bus.with_chip(tun, |chip| chip.led()), Some(false));
+        assert!(results[2].outcome.is_err());
+
+        let again = rig().apply_all_with(|_, address| Ok(bus.device(address)));
+        assert!(again[0].outcome.as_ref().unwrap().is_empty());
+        assert!(again[1].outcome.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stops_provisioning_when_the_budget_runs_out() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+
+        let results = rig().apply_all_within(Duration::from_millis(0),
+                                             |_, address| Ok(bus.device(address)));
+        for result in results {
+            match *result.outcome.unwrap_err().kind() {
+                ErrorKind::BudgetExhausted(0) => {}
+                ref other => panic!("unexpected error {:?}", other),
+            }
+        }
+    }
+
+    #[cfg(feature = "fleet")]
+    #[test]
+    fn reads_fleets_from_toml() {
+        let fleet = Fleet::from_toml(r#"
+            [[sensor]]
+            name = "sump"
+            address = 0x66
+            [sensor.profile]
+            scale = "F"
+
+            [[sensor]]
+            name = "mash-tun"
+            address = 0x67
+            mux = { address = 0x70, channel = 2 }
+            [sensor.profile]
+            led = false
+
+            [[sensor]]
+            name = "missing"
+            address = 0x68
+        "#)
+                .unwrap();
+        assert_eq!(fleet, rig());
+        let bad_scale = "[[sensor]]\nname = \"x\"\naddress = 1\nprofile = { scale = \"X\" }";
+        assert!(Fleet::from_toml(bad_scale).is_err());
+    }
+}
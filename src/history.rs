@@ -0,0 +1,481 @@
+//! A reading history on disk, which survives restarts.
+//!
+//! Readings are appended to a file as length-prefixed records, so that a
+//! record cut short by a power cut only loses itself: it is cut off the
+//! file when the history is next opened, before anything is appended, and
+//! records that cannot be decoded are skipped. When the file grows
+//! past its size limit, or its first reading gets too old, it is rotated:
+//! `history` becomes `history.1`, `history.1` becomes `history.2`, and so
+//! on, and the oldest file beyond the count to keep is deleted.
+//!
+//! Each record is a length byte followed by the reading's time, in
+//! milliseconds since the Unix epoch, as a little-endian `u64`, its value
+//! as a little-endian `f64`, and its scale as `C`, `K` or `F`. Readers skip
+//! anything after the fields they know, so records can grow.
+//!
+//! A long history is too much to plot: `downsample` reduces it to a given
+//! number of points with the largest-triangle-three-buckets algorithm,
+//! which keeps the peaks and dips that averaging would flatten.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind as IoErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use errors::*;
+use response::{Temperature, TemperatureScale};
+
+/// Length of the record fields written by this version.
+const RECORD_LEN: usize = 17;
+
+/// When a history file is rotated, and how many rotated files are kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rotation {
+    /// Size past which the file is rotated.
+    pub max_bytes: u64,
+    /// Age of the file's first reading past which it is rotated, if any.
+    pub max_age: Option<Duration>,
+    /// Rotated files kept; zero discards the readings on rotation.
+    pub keep: usize,
+}
+
+impl Default for Rotation {
+    /// A megabyte per file, about 60000 readings, and four rotated files.
+    fn default() -> Rotation {
+        Rotation {
+            max_bytes: 1024 * 1024,
+            max_age: None,
+            keep: 4,
+        }
+    }
+}
+
+/// A reading and the time it was taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    pub time: SystemTime,
+    pub temperature: Temperature,
+}
+
+impl HistoryEntry {
+    fn encode(&self) -> Result<[u8; RECORD_LEN + 1]> {
+        let millis = self.time
+            .duration_since(UNIX_EPOCH)
+            .chain_err(|| "readings from before 1970 cannot be recorded")?
+            .as_millis() as u64;
+        let scale = match self.temperature.scale() {
+            TemperatureScale::Celsius => b'C',
+            TemperatureScale::Kelvin => b'K',
+            TemperatureScale::Fahrenheit => b'F',
+        };
+        let mut record = [0u8; RECORD_LEN + 1];
+        record[0] = RECORD_LEN as u8;
+        record[1..9].copy_from_slice(&millis.to_le_bytes());
+        record[9..17].copy_from_slice(&self.temperature.value().to_le_bytes());
+        record[17] = scale;
+        Ok(record)
+    }
+
+    fn decode(fields: &[u8]) -> Result<HistoryEntry> {
+        if fields.len() < RECORD_LEN {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let mut millis = [0u8; 8];
+        millis.copy_from_slice(&fields[0..8]);
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&fields[8..16]);
+        let scale = match fields[16] {
+            b'C' => TemperatureScale::Celsius,
+            b'K' => TemperatureScale::Kelvin,
+            b'F' => TemperatureScale::Fahrenheit,
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+        Ok(HistoryEntry {
+               time: UNIX_EPOCH + Duration::from_millis(u64::from_le_bytes(millis)),
+               temperature: Temperature::new(scale, f64::from_le_bytes(value)),
+           })
+    }
+}
+
+/// The path of rotated file number `index`; zero is the current file.
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// The bytes of one file; none if it does not exist.
+fn read_bytes(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut bytes)
+                .chain_err(|| format!("Could not read history file {}", path.display()))?;
+        }
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).chain_err(|| format!("Could not open history file {}", path.display()))
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decodes the records in `bytes`, oldest first, skipping those that cannot
+/// be decoded. Returns the entries and the length of the complete records;
+/// what follows them is a record cut short.
+fn decode_records(bytes: &[u8]) -> (Vec<HistoryEntry>, usize) {
+    let mut entries = Vec::new();
+    let mut complete = 0;
+    let mut rest = bytes;
+    while let Some((&len, tail)) = rest.split_first() {
+        if tail.len() < len as usize {
+            break;
+        }
+        let (fields, tail) = tail.split_at(len as usize);
+        if let Ok(entry) = HistoryEntry::decode(fields) {
+            entries.push(entry);
+        }
+        complete += 1 + len as usize;
+        rest = tail;
+    }
+    (entries, complete)
+}
+
+/// Reads the entries of one file, oldest first. A record cut short at the
+/// end of the file, and records that cannot be decoded, are ignored.
+fn read_file(path: &Path) -> Result<Vec<HistoryEntry>> {
+    Ok(decode_records(&read_bytes(path)?).0)
+}
+
+/// Reads the history at `path`, its rotated files included, oldest entry
+/// first.
+pub fn read_history<P: AsRef<Path>>(path: P) -> Result<Vec<HistoryEntry>> {
+    let path = path.as_ref();
+    let mut count = 0;
+    while rotated(path, count + 1).exists() {
+        count += 1;
+    }
+    let mut entries = Vec::new();
+    for index in (0..count + 1).rev() {
+        entries.extend(read_file(&rotated(path, index))?);
+    }
+    Ok(entries)
+}
+
+/// Reduces `entries`, oldest first, to `points` of them, with the
+/// largest-triangle-three-buckets algorithm: the first and last entries are
+/// kept, and of each bucket in between, the entry that makes the largest
+/// triangle with the one kept before it and the average of the next bucket.
+/// Readings in different scales are compared in Celsius.
+///
+/// Two `points` keep the first and last entries, and one keeps the last.
+pub fn downsample(entries: &[HistoryEntry], points: usize) -> Vec<HistoryEntry> {
+    let len = entries.len();
+    if points >= len {
+        return entries.to_vec();
+    }
+    match points {
+        0 => return Vec::new(),
+        1 => return vec![entries[len - 1]],
+        2 => return vec![entries[0], entries[len - 1]],
+        _ => {}
+    }
+
+    let start = entries[0].time;
+    let x = |entry: &HistoryEntry| {
+        entry
+            .time
+            .duration_since(start)
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or(0.0)
+    };
+    let y = |entry: &HistoryEntry| entry.temperature.to_scale(TemperatureScale::Celsius).value();
+    // Bucket `i` spans entries `bucket(i)` to `bucket(i + 1)`, the first and
+    // last entries being buckets of their own.
+    let every = (len - 2) as f64 / (points - 2) as f64;
+    let bucket = |i: usize| ((i as f64 * every) as usize + 1).min(len - 1);
+
+    let mut kept = Vec::with_capacity(points);
+    kept.push(entries[0]);
+    let mut previous = 0;
+    for i in 0..points - 2 {
+        let next = if i + 1 == points - 2 {
+            &entries[len - 1..]
+        } else {
+            &entries[bucket(i + 1)..bucket(i + 2)]
+        };
+        let next_x = next.iter().map(&x).sum::<f64>() / next.len() as f64;
+        let next_y = next.iter().map(&y).sum::<f64>() / next.len() as f64;
+        let (previous_x, previous_y) = (x(&entries[previous]), y(&entries[previous]));
+
+        let mut largest = (bucket(i), -1.0);
+        for index in bucket(i)..bucket(i + 1) {
+            let entry = &entries[index];
+            let area = ((previous_x - next_x) * (y(entry) - previous_y) -
+                        (previous_x - x(entry)) * (next_y - previous_y))
+                    .abs();
+            if area > largest.1 {
+                largest = (index, area);
+            }
+        }
+        kept.push(entries[largest.0]);
+        previous = largest.0;
+    }
+    kept.push(entries[len - 1]);
+    kept
+}
+
+/// An open history file, to which readings are appended.
+pub struct History {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    /// Size of the current file.
+    len: u64,
+    /// Time of the current file's first entry.
+    started: Option<SystemTime>,
+}
+
+impl History {
+    /// Opens the history at `path`, creating it if need be, for appending.
+    /// A record cut short at the end of the file is cut off, so that new
+    /// records do not land behind it.
+    pub fn open<P: AsRef<Path>>(path: P, rotation: Rotation) -> Result<History> {
+        let path = path.as_ref().to_path_buf();
+        let (entries, complete) = decode_records(&read_bytes(&path)?);
+        let started = entries.first().map(|entry| entry.time);
+        let file = History::open_file(&path)?;
+        let mut len = file.metadata()
+            .chain_err(|| format!("Could not read history file {}", path.display()))?
+            .len();
+        if len > complete as u64 {
+            len = complete as u64;
+            file.set_len(len)
+                .chain_err(|| format!("Could not repair history file {}", path.display()))?;
+        }
+        Ok(History {
+               path: path,
+               rotation: rotation,
+               file: file,
+               len: len,
+               started: started,
+           })
+    }
+
+    fn open_file(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .chain_err(|| format!("Could not open history file {}", path.display()))
+    }
+
+    /// Whether the current file is due for rotation before `entry` is
+    /// appended.
+    fn due(&self, entry: &HistoryEntry) -> bool {
+        if self.len + (RECORD_LEN as u64 + 1) > self.rotation.max_bytes && self.len > 0 {
+            return true;
+        }
+        match (self.rotation.max_age, self.started) {
+            (Some(max_age), Some(started)) => {
+                entry
+                    .time
+                    .duration_since(started)
+                    .map_or(false, |age| age >= max_age)
+            }
+            _ => false,
+        }
+    }
+
+    /// Rotates the files and starts a new, empty current file.
+    pub fn rotate(&mut self) -> Result<()> {
+        let keep = self.rotation.keep;
+        // With nothing to keep, this is the current file.
+        let oldest = rotated(&self.path, keep);
+        match fs::remove_file(&oldest) {
+            Err(ref e) if e.kind() == IoErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).chain_err(|| {
+                                            format!("Could not remove history file {}",
+                                                    oldest.display())
+                                        })
+            }
+            Ok(()) => {}
+        }
+        for index in (0..keep).rev() {
+            let from = rotated(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated(&self.path, index + 1))
+                    .chain_err(|| format!("Could not rotate history file {}", from.display()))?;
+            }
+        }
+        self.file = History::open_file(&self.path)?;
+        self.len = 0;
+        self.started = None;
+        Ok(())
+    }
+
+    /// Appends an entry, rotating the files first if it is due.
+    pub fn append(&mut self, entry: &HistoryEntry) -> Result<()> {
+        let record = entry.encode()?;
+        if self.due(entry) {
+            self.rotate()?;
+        }
+        self.file
+            .write_all(&record)
+            .and_then(|_| self.file.flush())
+            .chain_err(|| format!("Could not write history file {}", self.path.display()))?;
+        self.len += record.len() as u64;
+        if self.started.is_none() {
+            self.started = Some(entry.time);
+        }
+        Ok(())
+    }
+
+    /// Appends a reading taken now.
+    pub fn record(&mut self, temperature: Temperature) -> Result<()> {
+        self.append(&HistoryEntry {
+                         time: SystemTime::now(),
+                         temperature: temperature,
+                     })
+    }
+
+    /// Reads back the whole history, rotated files included, oldest entry
+    /// first.
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        read_history(&self.path)
+    }
+
+    /// Reads back the entries from `since` on, reduced to `points` of them
+    /// with `downsample`.
+    pub fn downsampled(&self, since: SystemTime, points: usize) -> Result<Vec<HistoryEntry>> {
+        let entries: Vec<HistoryEntry> = self.entries()?
+            .into_iter()
+            .filter(|entry| entry.time >= since)
+            .collect();
+        Ok(downsample(&entries, points))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// A fresh directory for one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ezo-rtd-history-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(seconds: u64, celsius: f64) -> HistoryEntry {
+        HistoryEntry {
+            time: UNIX_EPOCH + Duration::from_secs(1_500_000_000 + seconds),
+            temperature: Temperature::Celsius(celsius),
+        }
+    }
+
+    #[test]
+    fn entries_survive_reopening() {
+        let path = scratch("reopen").join("history");
+        {
+            let mut history = History::open(&path, Rotation::default()).unwrap();
+            history.append(&entry(0, 25.0)).unwrap();
+            history.append(&entry(1, 25.5)).unwrap();
+        }
+        let mut history = History::open(&path, Rotation::default()).unwrap();
+        history
+            .append(&HistoryEntry {
+                         time: UNIX_EPOCH + Duration::from_secs(1_500_000_002),
+                         temperature: Temperature::Kelvin(298.65),
+                     })
+            .unwrap();
+        let entries = history.entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], entry(0, 25.0));
+        assert_eq!(entries[2].temperature, Temperature::Kelvin(298.65));
+    }
+
+    #[test]
+    fn cut_off_records_are_ignored() {
+        let path = scratch("cut-off").join("history");
+        let mut history = History::open(&path, Rotation::default()).unwrap();
+        history.append(&entry(0, 25.0)).unwrap();
+        history.file.write_all(&[RECORD_LEN as u8, 1, 2, 3]).unwrap();
+        assert_eq!(read_history(&path).unwrap(), vec![entry(0, 25.0)]);
+    }
+
+    #[test]
+    fn appends_after_torn_and_corrupt_records() {
+        let path = scratch("torn").join("history");
+        {
+            let mut history = History::open(&path, Rotation::default()).unwrap();
+            history.append(&entry(0, 25.0)).unwrap();
+            let mut corrupt = entry(1, 25.5).encode().unwrap();
+            corrupt[RECORD_LEN] = b'X';
+            history.file.write_all(&corrupt).unwrap();
+            history.append(&entry(2, 26.0)).unwrap();
+            history.file.write_all(&[RECORD_LEN as u8, 1, 2, 3]).unwrap();
+        }
+        let mut history = History::open(&path, Rotation::default()).unwrap();
+        history.append(&entry(3, 26.5)).unwrap();
+        assert_eq!(read_history(&path).unwrap(),
+                   vec![entry(0, 25.0), entry(2, 26.0), entry(3, 26.5)]);
+        assert_eq!(fs::metadata(&path).unwrap().len(),
+                   4 * (RECORD_LEN as u64 + 1));
+    }
+
+    #[test]
+    fn rotates_by_size_and_age() {
+        let path = scratch("rotation").join("history");
+        let rotation = Rotation {
+            max_bytes: 3 * (RECORD_LEN as u64 + 1),
+            max_age: Some(Duration::from_secs(60)),
+            keep: 2,
+        };
+        let mut history = History::open(&path, rotation).unwrap();
+        for second in 0..7 {
+            history.append(&entry(second, second as f64)).unwrap();
+        }
+        assert!(rotated(&path, 2).exists());
+        assert!(!rotated(&path, 3).exists());
+        let values: Vec<f64> = read_history(&path)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.temperature.value())
+            .collect();
+        assert_eq!(values, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        history.append(&entry(120, 7.0)).unwrap();
+        assert_eq!(read_file(&path).unwrap(), vec![entry(120, 7.0)]);
+        assert_eq!(read_history(&path).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn downsampling_keeps_the_ends_and_the_peaks() {
+        let mut entries: Vec<HistoryEntry> = (0..1000).map(|second| entry(second, 20.0)).collect();
+        entries[377] = entry(377, 35.0);
+        entries[612] = entry(612, 5.0);
+
+        let kept = downsample(&entries, 50);
+        assert_eq!(kept.len(), 50);
+        assert_eq!(kept[0], entries[0]);
+        assert_eq!(kept[49], entries[999]);
+        assert!(kept.contains(&entries[377]));
+        assert!(kept.contains(&entries[612]));
+        assert!(kept.windows(2).all(|pair| pair[0].time < pair[1].time));
+
+        assert_eq!(downsample(&entries[..10], 50), &entries[..10]);
+        assert_eq!(downsample(&entries, 2), vec![entries[0], entries[999]]);
+        assert_eq!(downsample(&entries, 1), vec![entries[999]]);
+        assert!(downsample(&entries, 0).is_empty());
+        assert_eq!(downsample(&entries[..2], 1), vec![entries[1]]);
+        assert!(downsample(&[], 0).is_empty());
+    }
+}
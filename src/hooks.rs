@@ -0,0 +1,119 @@
+//! Hooks run around every command of a sensor.
+//!
+//! A `CommandHook` added with `RtdSensor::add_hook` sees each command
+//! before it is written, and may veto it, and sees each command again once
+//! it is over, with its raw response, how long it took, and its error if it
+//! failed. Logging, auditing and rate limiting can be layered on a sensor
+//! this way, without changing how commands are run.
+
+use std::time::Duration;
+
+use errors::*;
+
+/// Whether a command may be written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Proceed,
+    /// The command is not written, and fails with `CommandVetoed` and this
+    /// reason.
+    Veto(String),
+}
+
+/// A command that is over.
+#[derive(Debug)]
+pub struct CommandEvent<'a> {
+    /// The command string, without its terminating NUL.
+    pub command: &'a str,
+    /// The raw response, response code included, for commands that have
+    /// one and whose response was read.
+    pub response: Option<&'a [u8]>,
+    /// Time from the write to the end of the read.
+    pub elapsed: Duration,
+    /// Set if the command failed on the bus.
+    pub error: Option<&'a Error>,
+}
+
+/// Runs around every command of a sensor.
+pub trait CommandHook {
+    /// Called before `command` is written, without its terminating NUL.
+    fn before(&mut self, _command: &str) -> Verdict {
+        Verdict::Proceed
+    }
+
+    /// Called once a command is over, whether it succeeded or not. Vetoed
+    /// commands are not reported.
+    fn after(&mut self, _event: &CommandEvent) {}
+}
+
+/// A hook that passes every finished command to a function, for logging.
+pub struct Observer<F>(pub F);
+
+impl<F: FnMut(&CommandEvent)> CommandHook for Observer<F> {
+    fn after(&mut self, event: &CommandEvent) {
+        (self.0)(event)
+    }
+}
+
+/// Runs the `before` hooks in order, and stops at the first veto.
+pub fn check(hooks: &mut [Box<dyn CommandHook + Send>], command: &str) -> Result<()> {
+    for hook in hooks.iter_mut() {
+        if let Verdict::Veto(reason) = hook.before(command) {
+            return Err(ErrorKind::CommandVetoed(command.to_string(), reason).into());
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `after` hooks in order.
+pub fn report(hooks: &mut [Box<dyn CommandHook + Send>], event: &CommandEvent) {
+    for hook in hooks.iter_mut() {
+        hook.after(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use sensor::RtdSensor;
+    use simulator::{SimulatedChip, VirtualBus};
+    use TemperatureCommand;
+
+    struct NoCalibration;
+
+    impl CommandHook for NoCalibration {
+        fn before(&mut self, command: &str) -> Verdict {
+            if command.starts_with("Cal,") && command != "Cal,?" {
+                Verdict::Veto("calibration is locked".to_string())
+            } else {
+                Verdict::Proceed
+            }
+        }
+    }
+
+    #[test]
+    fn hooks_observe_and_veto_commands() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let observed = log.clone();
+        sensor.add_hook(Observer(move |event: &CommandEvent| {
+            let code = event.response.and_then(|response| response.first().cloned());
+            observed.lock().unwrap().push((event.command.to_string(), code));
+        }));
+        sensor.add_hook(NoCalibration);
+
+        sensor.send(TemperatureCommand::CalibrationState).unwrap();
+        match *sensor.calibrate(100.0).unwrap_err().kind() {
+            ErrorKind::CommandVetoed(ref command, _) => assert_eq!(command, "Cal,100.00"),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        sensor.send(TemperatureCommand::Sleep).unwrap();
+        assert_eq!(*log.lock().unwrap(),
+                   vec![("Cal,?".to_string(), Some(1)),
+                        ("S,?".to_string(), Some(1)),
+                        ("Sleep".to_string(), None)]);
+    }
+}
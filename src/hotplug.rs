@@ -0,0 +1,211 @@
+//! Noticing chips that are attached to, or removed from, a running bus.
+//!
+//! A `BusWatcher` remembers what the last scan of its bus found. Scanning
+//! again yields a `Detached` event for every chip that is gone and an
+//! `Attached` event for every new one. A chip that answers at a known
+//! address with another identity, as when a carrier board is swapped, is
+//! reported as detached and then attached.
+
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use discovery::{self, Addressable, DiscoveredDevice, FIRST_EZO_ADDRESS, LAST_EZO_ADDRESS};
+use errors::*;
+
+/// A change on a bus, found by rescanning it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HotplugEvent {
+    Attached { bus: u8, device: DiscoveredDevice },
+    Detached { bus: u8, device: DiscoveredDevice },
+}
+
+impl HotplugEvent {
+    pub fn bus(&self) -> u8 {
+        match *self {
+            HotplugEvent::Attached { bus, .. } |
+            HotplugEvent::Detached { bus, .. } => bus,
+        }
+    }
+
+    pub fn device(&self) -> &DiscoveredDevice {
+        match *self {
+            HotplugEvent::Attached { ref device, .. } |
+            HotplugEvent::Detached { ref device, .. } => device,
+        }
+    }
+}
+
+/// Rescans a bus every `interval`, reporting the chips that came and went.
+#[derive(Debug, Clone)]
+pub struct BusWatcher {
+    bus: u8,
+    interval: Duration,
+    last_scan: Option<Instant>,
+    known: Vec<DiscoveredDevice>,
+    /// The addresses scanned: the EZO factory addresses, and any added
+    /// with `watch_address`.
+    addresses: BTreeSet<u16>,
+}
+
+impl BusWatcher {
+    /// A watcher for `/dev/i2c-<bus>` that knows of no chips yet, so that
+    /// its first scan reports every chip as attached.
+    pub fn new(bus: u8, interval: Duration) -> BusWatcher {
+        BusWatcher {
+            bus: bus,
+            interval: interval,
+            last_scan: None,
+            known: Vec::new(),
+            addresses: (FIRST_EZO_ADDRESS..LAST_EZO_ADDRESS + 1).collect(),
+        }
+    }
+
+    /// Scans `address` as well, such as that of a chip moved away from its
+    /// factory address.
+    pub fn watch_address(&mut self, address: u16) {
+        self.addresses.insert(address);
+    }
+
+    pub fn bus(&self) -> u8 {
+        self.bus
+    }
+
+    /// The chips found by the last successful scan, by address.
+    pub fn known(&self) -> &[DiscoveredDevice] {
+        &self.known
+    }
+
+    /// Returns `true` if the bus has never been scanned, or was last
+    /// scanned at least `interval` before `now`.
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.last_scan {
+            None => true,
+            Some(last) => now.duration_since(last) >= self.interval,
+        }
+    }
+
+    /// Scans `/dev/i2c-<bus>` if a scan is due; otherwise nothing happens.
+    pub fn poll(&mut self) -> Result<Vec<HotplugEvent>> {
+        let now = Instant::now();
+        if !self.is_due(now) {
+            return Ok(Vec::new());
+        }
+        self.last_scan = Some(now);
+        let found = discovery::scan_bus_addresses(self.bus, self.addresses.iter().cloned())?;
+        Ok(self.update(found))
+    }
+
+    /// Scans the bus through `dev` right away. A failed scan leaves the
+    /// known chips as they were.
+    pub fn rescan<D: Addressable>(&mut self, dev: &mut D) -> Result<Vec<HotplugEvent>> {
+        self.last_scan = Some(Instant::now());
+        let found = discovery::scan(dev, self.addresses.iter().cloned())?;
+        Ok(self.update(found))
+    }
+
+    fn update(&mut self, found: Vec<DiscoveredDevice>) -> Vec<HotplugEvent> {
+        let bus = self.bus;
+        let mut events: Vec<HotplugEvent> = self.known
+            .iter()
+            .filter(|device| !found.contains(device))
+            .map(|device| {
+                     HotplugEvent::Detached {
+                         bus: bus,
+                         device: device.clone(),
+                     }
+                 })
+            .collect();
+        events.extend(found
+                          .iter()
+                          .filter(|device| !self.known.contains(device))
+                          .map(|device| {
+                                   HotplugEvent::Attached {
+                                       bus: bus,
+                                       device: device.clone(),
+                                   }
+                               }));
+        self.known = found;
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discovery::FIRST_ADDRESS;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    fn rtd(address: u16, firmware: &str) -> DiscoveredDevice {
+        DiscoveredDevice {
+            address: address,
+            device_type: "RTD".to_string(),
+            firmware: firmware.to_string(),
+            name: None,
+        }
+    }
+
+    #[test]
+    fn reports_chips_that_come_and_go() {
+        let bus = VirtualBus::new();
+        let first = bus.attach(SimulatedChip::new(0x66));
+        let mut dev = bus.device(FIRST_ADDRESS);
+        let mut watcher = BusWatcher::new(1, Duration::from_secs(60));
+        assert!(watcher.is_due(Instant::now()));
+
+        assert_eq!(watcher.rescan(&mut dev).unwrap(),
+                   vec![HotplugEvent::Attached {
+                            bus: 1,
+                            device: rtd(0x66, "2.01"),
+                        }]);
+        assert!(!watcher.is_due(Instant::now()));
+        assert!(watcher.rescan(&mut dev).unwrap().is_empty());
+
+        bus.attach(SimulatedChip::new(0x67));
+        bus.detach(first);
+        assert_eq!(watcher.rescan(&mut dev).unwrap(),
+                   vec![HotplugEvent::Detached {
+                            bus: 1,
+                            device: rtd(0x66, "2.01"),
+                        },
+                        HotplugEvent::Attached {
+                            bus: 1,
+                            device: rtd(0x67, "2.01"),
+                        }]);
+        assert_eq!(watcher.known(), &[rtd(0x67, "2.01")][..]);
+    }
+
+    #[test]
+    fn a_swapped_board_is_detached_then_attached() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let mut dev = bus.device(FIRST_ADDRESS);
+        let mut watcher = BusWatcher::new(1, Duration::from_secs(60));
+        watcher.rescan(&mut dev).unwrap();
+
+        bus.with_chip(chip, |chip| { chip.set_identity("RTD", "2.10"); });
+        let events = watcher.rescan(&mut dev).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0],
+                   HotplugEvent::Detached {
+                       bus: 1,
+                       device: rtd(0x66, "2.01"),
+                   });
+        assert_eq!(events[1].device(), &rtd(0x66, "2.10"));
+    }
+
+    #[test]
+    fn only_watched_addresses_are_scanned() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x20));
+        let mut dev = bus.device(FIRST_ADDRESS);
+        let mut watcher = BusWatcher::new(1, Duration::from_secs(60));
+        assert!(watcher.rescan(&mut dev).unwrap().is_empty());
+
+        watcher.watch_address(0x20);
+        assert_eq!(watcher.rescan(&mut dev).unwrap(),
+                   vec![HotplugEvent::Attached {
+                            bus: 1,
+                            device: rtd(0x20, "2.01"),
+                        }]);
+    }
+}
@@ -10,23 +10,172 @@
 extern crate error_chain;
 extern crate ezo_common;
 extern crate i2cdev;
+#[cfg(feature = "tokio-codec")]
+extern crate bytes;
+#[cfg(feature = "defmt")]
+extern crate defmt;
+#[cfg(feature = "gpio")]
+extern crate rppal;
+#[cfg(feature = "serialize")]
+extern crate serde;
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "webhook")]
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "tokio-blocking")]
+extern crate tokio;
+#[cfg(feature = "tokio-codec")]
+extern crate tokio_util;
+#[cfg(feature = "fleet")]
+extern crate toml;
+#[cfg(feature = "uom")]
+extern crate uom;
+#[cfg(feature = "webhook")]
+extern crate ureq;
 
 // Use error-chain.
 pub mod errors;
 
+/// Alarms on readings, and the output lines they drive.
+pub mod alarm;
+
+/// Averaging several readings into a steadier one.
+pub mod average;
+
+/// Async methods over the blocking sensor, for tokio applications.
+#[cfg(feature = "tokio-blocking")]
+pub mod bridge;
+
+/// Fixed-capacity command buffers.
+pub mod buffer;
+
+/// Framing of the chip's UART output.
+pub mod codec;
+
+/// Commissioning chips one at a time.
+pub mod commission;
+
+/// Byte-level I2C helpers.
+pub mod common;
+
+/// Software corrections applied to readings.
+pub mod correction;
+
+/// Bundles of what a sensor knows, for bug reports.
+pub mod debug;
+
+/// Scanning a bus for EZO chips.
+pub mod discovery;
+
+/// Previewing the transactions with a chip, without the chip.
+pub mod dryrun;
+
+/// Configuring a sensor from environment variables.
+pub mod env;
+
+/// Events for what a sensor did to its chip.
+pub mod events;
+
+/// Provisioning many sensors from one fleet description.
+pub mod fleet;
+
+/// A reading history on disk, with rotation.
+pub mod history;
+
+/// Hooks run around every command of a sensor.
+pub mod hooks;
+
+/// Noticing chips that are attached to, or removed from, a running bus.
+pub mod hotplug;
+
+/// Readings stored by the chip's data logger.
+pub mod memory;
+
+/// Scripted devices, for tests without hardware.
+pub mod mock;
+
+/// Notifications of alarms and device faults.
+pub mod notify;
+
+/// I/O-free parsing of raw response bytes.
+pub mod parse;
+
+/// Lazily opened bus handles shared by many sensors.
+pub mod pool;
+
+/// Recording the I2C transactions with a chip, and replaying them.
+pub mod replay;
+
 /// Parseable responses from the EZO RTD Chip.
 pub mod response;
 
-use std::thread;
-use std::time::Duration;
+/// Keeping track of the chip's restarts over time.
+pub mod restarts;
+
+/// High-level interface to the chip.
+pub mod sensor;
+
+/// Chained commands, run in one go.
+pub mod session;
+
+/// Settings applied as a whole, or rolled back.
+pub mod settings;
+
+/// A sensor handle shared between components, with maintenance leases.
+pub mod shared;
+
+/// Destinations for readings.
+pub mod sink;
+
+/// Simulated EZO chips on a virtual I2C bus, for testing without hardware.
+pub mod simulator;
+
+/// Per-device transaction statistics.
+pub mod stats;
 
+/// Clock-stretching workarounds.
+pub mod stretch;
+
+/// A hard timeout on I2C transactions.
+pub mod timeout;
+
+/// Measuring command latency and tuning processing delays.
+pub mod timing;
+
+/// Talking to a chip in UART mode, over a serial port.
+pub mod uart;
+
+/// Estimated uncertainty of readings.
+pub mod uncertainty;
+
+/// Recovering sensors that stop giving good readings.
+pub mod watchdog;
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use buffer::{ACK_RESPONSE, CommandBuf, EXPORT_RESPONSE, MAX_RESPONSE, NAME_RESPONSE,
+             QUERY_RESPONSE, STATUS_RESPONSE};
+use common::{self, RetryPolicy, read_counting_retries, write_counting_retries};
 use errors::*;
-use ezo_common::{BpsRate, ResponseCode, string_from_response_data, response_code, write_to_ezo,
-                 read_raw_buffer};
-use i2cdev::linux::LinuxI2CDevice;
+use ezo_common::{BpsRate, ResponseCode, response_code};
+use i2cdev::core::I2CDevice;
+use parse::has_flipped_bits;
+use response::{Response, ResponseFormat, TemperatureScale};
+
+pub use sensor::RtdSensor;
+
+/// Length of the `data` kept by `CommandOptions`, which fits any response.
+/// Only the `buffer_len` of the response a command expects is read into it.
+pub const MAX_DATA: usize = MAX_RESPONSE;
+
+/// I2C bus used when none is given, for `/dev/i2c-1`.
+pub const DEFAULT_BUS: u8 = 1;
 
-/// Maximum ascii-character response size + 2
-pub const MAX_DATA: usize = 16;
+/// The chip's factory address, used when none is given.
+pub const DEFAULT_ADDRESS: u16 = 0x66;
 
 /// Commands for interacting with the RTD EZO chip.
 #[derive(Debug)]
@@ -71,6 +220,10 @@ pub enum TemperatureCommand {
     MemoryRecall,
     /// 'M,?' command
     MemoryRecallLastLocation,
+    /// 'Name,n' command
+    Name(String),
+    /// 'Name,?' command
+    NameState,
     /// 'Plock,1' command
     ProtocolLockEnable,
     /// 'Plock,0' command
@@ -93,206 +246,283 @@ pub enum TemperatureCommand {
     Status,
 }
 
+/// The baud rate in bits per second, for printing it or sending it in a
+/// `Baud` command.
+///
+/// `BpsRate` belongs to `ezo_common`, so its conversions to and from
+/// numbers, and its display, are functions here rather than `From`,
+/// `TryFrom` and `Display` implementations.
+pub fn bps_rate_value(rate: &BpsRate) -> u32 {
+    match *rate {
+        BpsRate::Bps300 => 300,
+        BpsRate::Bps1200 => 1200,
+        BpsRate::Bps2400 => 2400,
+        BpsRate::Bps9600 => 9600,
+        BpsRate::Bps19200 => 19200,
+        BpsRate::Bps38400 => 38400,
+        BpsRate::Bps57600 => 57600,
+        BpsRate::Bps115200 => 115200,
+    }
+}
+
+/// The baud rate for `bps` bits per second, as read from a configuration
+/// file. Fails for rates the chip does not support.
+pub fn bps_rate_from_value(bps: u32) -> Result<BpsRate> {
+    Ok(match bps {
+           300 => BpsRate::Bps300,
+           1200 => BpsRate::Bps1200,
+           2400 => BpsRate::Bps2400,
+           9600 => BpsRate::Bps9600,
+           19200 => BpsRate::Bps19200,
+           38400 => BpsRate::Bps38400,
+           57600 => BpsRate::Bps57600,
+           115200 => BpsRate::Bps115200,
+           _ => return Err(ErrorKind::UnsupportedBaudRate(bps).into()),
+       })
+}
+
+/// Time the chip takes to take a reading, in milliseconds.
+pub const READING_DELAY: u64 = 600;
+
+/// Time the chip takes to calibrate, in milliseconds.
+pub const CALIBRATION_DELAY: u64 = 1000;
+
+/// Time the chip takes to process any other command that answers, in
+/// milliseconds.
+pub const COMMAND_DELAY: u64 = 300;
+
+impl TemperatureCommand {
+    /// The processing time given by the datasheet, in milliseconds: how long
+    /// to wait after writing the command before reading the response.
+    /// `None` for commands after which the chip does not answer at all.
+    pub fn processing_delay(&self) -> Option<u64> {
+        use self::TemperatureCommand::*;
+        match *self {
+            Reading => Some(READING_DELAY),
+            CalibrationTemperature(_) => Some(CALIBRATION_DELAY),
+            Baud(_) | Factory | Sleep => None,
+            _ => Some(COMMAND_DELAY),
+        }
+    }
+
+    /// The command's keyword, with its argument for the commands that are
+    /// told apart by it, e.g. `Cal,?` and `Cal,clear` but `Cal` for `Cal,t`.
+    pub fn name(&self) -> &'static str {
+        use self::TemperatureCommand::*;
+        match *self {
+            Baud(_) => "Baud",
+            CalibrationTemperature(_) => "Cal",
+            CalibrationClear => "Cal,clear",
+            CalibrationState => "Cal,?",
+            Export => "Export",
+            ExportInfo => "Export,?",
+            Import(_) => "Import",
+            DataloggerPeriod(_) => "D",
+            DataloggerDisable => "D,0",
+            DataloggerInterval => "D,?",
+            Factory => "Factory",
+            Find => "F",
+            DeviceAddress(_) => "I2C",
+            DeviceInformation => "I",
+            LedOn => "L,1",
+            LedOff => "L,0",
+            LedState => "L,?",
+            MemoryClear => "M,clear",
+            MemoryRecall => "M",
+            MemoryRecallLastLocation => "M,?",
+            Name(_) => "Name",
+            NameState => "Name,?",
+            ProtocolLockEnable => "Plock,1",
+            ProtocolLockDisable => "Plock,0",
+            ProtocolLockState => "Plock,?",
+            Reading => "R",
+            ScaleCelsius => "S,c",
+            ScaleKelvin => "S,k",
+            ScaleFahrenheit => "S,f",
+            ScaleState => "S,?",
+            Sleep => "Sleep",
+            Status => "Status",
+        }
+    }
+}
+
+/// Logs the command string, as written to the chip.
+#[cfg(feature = "defmt")]
+impl defmt::Format for TemperatureCommand {
+    fn format(&self, f: defmt::Formatter) {
+        let cmd = self.build();
+        defmt::write!(f, "{=str}", cmd.command.as_str().trim_end_matches('\0'))
+    }
+}
+
 /// Useful for properly building I2C parameters from a command.
 pub trait I2cCommand {
     fn build(&self) -> CommandOptions;
+
+    /// Builds the command and runs it on `dev`, returning the options with
+    /// the response read back, ready for `parse_response`.
+    fn execute<D: I2CDevice>(&self, dev: &mut D) -> Result<CommandOptions> {
+        let mut cmd = self.build();
+        cmd.run(dev)?;
+        Ok(cmd)
+    }
 }
 
 impl I2cCommand for TemperatureCommand {
     fn build(&self) -> CommandOptions {
         use self::TemperatureCommand::*;
         let mut opts = CommandOptions::default();
+        if let Some(delay) = self.processing_delay() {
+            opts.set_delay(delay);
+        }
         match *self {
             CalibrationTemperature(temp) => {
-                opts.set_command(format!("Cal,{:.*}\0", 2, temp))
-                    .set_delay(1000)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                // Rust formats numbers the same in every locale, always
+                // with a '.' separator and never in exponent notation,
+                // which is what the chip reads. Values that round to zero
+                // would be sent as "-0.00".
+                let temp = if temp.abs() < 0.005 { 0.0 } else { temp };
+                opts.set_command_fmt(format_args!("Cal,{:.*}\0", 2, temp))
+                    .set_response(CommandResponse::Ack);
             }
             CalibrationClear => {
-                opts.set_command("Cal,clear\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("Cal,clear\0").set_response(CommandResponse::Ack);
             }
             CalibrationState => {
-                opts.set_command("Cal,?\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::CalibrationState)
-                    .finish()
+                opts.set_command("Cal,?\0").set_response(CommandResponse::CalibrationState);
             }
             DataloggerPeriod(n) => {
-                opts.set_command(format!("D,{}\0", n))
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command_fmt(format_args!("D,{}\0", n)).set_response(CommandResponse::Ack);
             }
             DataloggerDisable => {
-                opts.set_command("D,0\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("D,0\0").set_response(CommandResponse::Ack);
             }
             DataloggerInterval => {
-                opts.set_command("D,?\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::DataloggerInterval)
-                    .finish()
+                opts.set_command("D,?\0").set_response(CommandResponse::DataloggerInterval);
             }
             DeviceAddress(addr) => {
-                opts.set_command(format!("I2C,{}\0", addr))
-                    .set_delay(300)
-                    .finish()
+                opts.set_command_fmt(format_args!("I2C,{}\0", addr));
             }
             DeviceInformation => {
-                opts.set_command("I\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::DeviceInformation)
-                    .finish()
+                opts.set_command("I\0").set_response(CommandResponse::DeviceInformation);
             }
             Export => {
-                opts.set_command("Export\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Export)
-                    .finish()
+                opts.set_command("Export\0").set_response(CommandResponse::Export);
             }
             ExportInfo => {
-                opts.set_command("Export,?\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::ExportInfo)
-                    .finish()
+                opts.set_command("Export,?\0").set_response(CommandResponse::ExportInfo);
             }
             Import(ref calib) => {
-                opts.set_command(format!("Import,{}\0", calib))
-                    .set_delay(300)
-                    .finish()
+                opts.set_command_fmt(format_args!("Import,{}\0", calib));
+            }
+            Factory => {
+                opts.set_command("Factory\0");
             }
-            Factory => opts.set_command("Factory\0".to_string()).finish(),
             Find => {
-                opts.set_command("F\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("F\0").set_response(CommandResponse::Ack);
             }
             LedOn => {
-                opts.set_command("L,1\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("L,1\0").set_response(CommandResponse::Ack);
             }
             LedOff => {
-                opts.set_command("L,0\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("L,0\0").set_response(CommandResponse::Ack);
             }
             LedState => {
-                opts.set_command("L,?\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::LedState)
-                    .finish()
+                opts.set_command("L,?\0").set_response(CommandResponse::LedState);
             }
             MemoryClear => {
-                opts.set_command("M,clear\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("M,clear\0").set_response(CommandResponse::Ack);
             }
             MemoryRecall => {
-                opts.set_command("M\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::MemoryRecall)
-                    .finish()
+                opts.set_command("M\0").set_response(CommandResponse::MemoryRecall);
             }
             MemoryRecallLastLocation => {
-                opts.set_command("M,?\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::MemoryRecallLastLocation)
-                    .finish()
+                opts.set_command("M,?\0").set_response(CommandResponse::MemoryRecallLastLocation);
+            }
+            Name(ref name) => {
+                opts.set_command_fmt(format_args!("Name,{}\0", name))
+                    .set_response(CommandResponse::Ack);
+            }
+            NameState => {
+                opts.set_command("Name,?\0").set_response(CommandResponse::NameState);
             }
             ProtocolLockEnable => {
-                opts.set_command("Plock,1\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("Plock,1\0").set_response(CommandResponse::Ack);
             }
             ProtocolLockDisable => {
-                opts.set_command("Plock,0\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("Plock,0\0").set_response(CommandResponse::Ack);
             }
             ProtocolLockState => {
-                opts.set_command("Plock,?\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::ProtocolLockState)
-                    .finish()
+                opts.set_command("Plock,?\0").set_response(CommandResponse::ProtocolLockState);
             }
             Reading => {
-                opts.set_command("R\0".to_string())
-                    .set_delay(600)
-                    .set_response(CommandResponse::Reading)
-                    .finish()
+                opts.set_command("R\0").set_response(CommandResponse::Reading);
             }
             ScaleCelsius => {
-                opts.set_command("S,c\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("S,c\0").set_response(CommandResponse::Ack);
             }
             ScaleKelvin => {
-                opts.set_command("S,k\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("S,k\0").set_response(CommandResponse::Ack);
             }
             ScaleFahrenheit => {
-                opts.set_command("S,f\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Ack)
-                    .finish()
+                opts.set_command("S,f\0").set_response(CommandResponse::Ack);
             }
             ScaleState => {
-                opts.set_command("S,?\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::ScaleState)
-                    .finish()
+                opts.set_command("S,?\0").set_response(CommandResponse::ScaleState);
             }
             Baud(ref baud) => {
-                let rate = match *baud {
-                    BpsRate::Bps300 => BpsRate::Bps300 as u32,
-                    BpsRate::Bps1200 => BpsRate::Bps1200 as u32,
-                    BpsRate::Bps2400 => BpsRate::Bps2400 as u32,
-                    BpsRate::Bps9600 => BpsRate::Bps9600 as u32,
-                    BpsRate::Bps19200 => BpsRate::Bps19200 as u32,
-                    BpsRate::Bps38400 => BpsRate::Bps38400 as u32,
-                    BpsRate::Bps57600 => BpsRate::Bps57600 as u32,
-                    BpsRate::Bps115200 => BpsRate::Bps115200 as u32,
-                };
-                opts.set_command(format!("Baud,{}\0", rate)).finish()
+                opts.set_command_fmt(format_args!("Baud,{}\0", bps_rate_value(baud)));
+            }
+            Sleep => {
+                opts.set_command("Sleep\0");
             }
-            Sleep => opts.set_command("Sleep\0".to_string()).finish(),
             Status => {
-                opts.set_command("Status\0".to_string())
-                    .set_delay(300)
-                    .set_response(CommandResponse::Status)
-                    .finish()
+                opts.set_command("Status\0").set_response(CommandResponse::Status);
             }
         }
+        opts
     }
 }
 
 /// Command-related parameters used to build I2C write/read interactions.
-#[derive(Clone,Debug,Default,PartialEq,Eq)]
+///
+/// Everything is stored in place, so building, copying and running a
+/// command does not allocate.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
 pub struct CommandOptions {
-    pub command: String,
+    pub command: CommandBuf,
     pub delay: Option<u64>,
     pub response: Option<CommandResponse>,
-    pub data: Option<Vec<u8>>,
+    pub data: Option<[u8; MAX_DATA]>,
+    /// Response code byte of the last response, exactly as read, even for
+    /// codes that `ezo_common` does not recognize.
+    pub code: Option<u8>,
+    /// How failed writes are retried, and failed reads too, unless
+    /// `read_retry` is set. Its wait strategy makes the processing waits.
+    pub retry: RetryPolicy,
+    /// How failed reads are retried, when not like writes: a write fails
+    /// when the chip does not ACK its address, a read more often when the
+    /// chip is still busy, and the two call for different backoffs.
+    pub read_retry: Option<RetryPolicy>,
+    /// Set when the last response had bytes with bit 7 set, which were
+    /// corrected while parsing.
+    pub flipping: bool,
+    /// Set when the last response did not fit in the buffer it was read
+    /// into. The chip does not continue a cut-off response on the next
+    /// read, which starts again at the response code.
+    pub truncated: bool,
+    /// Retries made by the last write, and by the read of its response.
+    pub retries: u32,
+    /// `Pending` codes read and polled over before the last response, which
+    /// `code` no longer shows.
+    pub pending_reads: u32,
+    /// When set, no retry or pending poll waits past it, and none is made
+    /// once it has passed.
+    pub deadline: Option<Instant>,
 }
 
 /// Allowed responses from I2C read interactions.
-#[derive(Clone,Debug,PartialEq,Eq)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
 pub enum CommandResponse {
     Ack,
     CalibrationState,
@@ -303,69 +533,192 @@ pub enum CommandResponse {
     LedState,
     MemoryRecall,
     MemoryRecallLastLocation,
+    NameState,
     ProtocolLockState,
     Reading,
     ScaleState,
     Status,
 }
 
+impl CommandResponse {
+    /// The length of buffer that fits this response.
+    pub fn buffer_len(&self) -> usize {
+        use self::CommandResponse::*;
+        match *self {
+            Ack => ACK_RESPONSE,
+            Status => STATUS_RESPONSE,
+            NameState => NAME_RESPONSE,
+            Export => EXPORT_RESPONSE,
+            _ => QUERY_RESPONSE,
+        }
+    }
+}
+
 /// Builds commands.
 pub trait CommandBuilder {
     fn delay(&self) -> Result<()>;
-    fn finish(&self) -> Self;
     fn parse_response(&self) -> Result<String>;
-    fn read_response(&mut self, dev: &mut LinuxI2CDevice) -> Result<()>;
-    fn run(&mut self, dev: &mut LinuxI2CDevice) -> Result<()>;
-    fn set_command(&mut self, command_str: String) -> &mut Self;
+    fn read_response<D: I2CDevice>(&mut self, dev: &mut D) -> Result<()>;
+    fn read_response_into<D: I2CDevice>(&mut self, dev: &mut D, buf: &mut [u8]) -> Result<()>;
+    fn run<D: I2CDevice>(&mut self, dev: &mut D) -> Result<()>;
+    fn run_with_buffer<D: I2CDevice>(&mut self, dev: &mut D, buf: &mut [u8]) -> Result<()>;
+    fn set_command(&mut self, command_str: &str) -> &mut Self;
+    fn set_command_fmt(&mut self, args: fmt::Arguments) -> &mut Self;
     fn set_delay(&mut self, delay: u64) -> &mut Self;
     fn set_response(&mut self, response: CommandResponse) -> &mut Self;
-    fn write(&mut self, dev: &mut LinuxI2CDevice) -> Result<()>;
+    fn write<D: I2CDevice>(&mut self, dev: &mut D) -> Result<()>;
+}
+
+impl CommandOptions {
+    /// The time the chip takes to process the command.
+    fn processing_time(&self) -> Duration {
+        Duration::from_millis(self.delay.unwrap_or(0))
+    }
+
+    /// How failed reads are retried.
+    pub fn read_retry_policy(&self) -> RetryPolicy {
+        self.read_retry.unwrap_or(self.retry)
+    }
+
+    /// The bytes of `data` that the last response was read into: the
+    /// `buffer_len` of the response the command expects.
+    pub fn response_data(&self) -> Option<&[u8]> {
+        match (self.response, self.data.as_ref()) {
+            (Some(kind), Some(data)) => Some(&data[..kind.buffer_len().min(MAX_DATA)]),
+            _ => None,
+        }
+    }
+
+    /// The last response, parsed by the kind of response the command
+    /// expects, instead of as text like `parse_response`; readings are
+    /// taken in the given `scale`, and `Status` as formatted by firmware with
+    /// the given response `format`. Commands without a response yield `Ack`.
+    pub fn typed_response(&self,
+                          scale: TemperatureScale,
+                          format: ResponseFormat)
+                          -> Result<Response> {
+        match (self.response, self.response_data()) {
+            (None, _) => Ok(Response::Ack),
+            (Some(kind), Some(data)) => parse::response(data, kind, scale, format),
+            (Some(_), None) => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
 }
 
 impl CommandBuilder for CommandOptions {
     fn delay(&self) -> Result<()> {
         if let Some(delay) = self.delay {
-            thread::sleep(Duration::from_millis(delay));
+            self.retry.wait_strategy.wait(Duration::from_millis(delay));
         };
         Ok(())
     }
-    fn finish(&self) -> CommandOptions {
-        self.clone()
-    }
-    fn run(&mut self, dev: &mut LinuxI2CDevice) -> Result<()> {
+    /// Writes the command, waits for it to be processed and reads the
+    /// response. Fails with `PendingResponse`, `DeviceError` or
+    /// `UnsuccessfulResponse` unless the chip answered with success.
+    fn run<D: I2CDevice>(&mut self, dev: &mut D) -> Result<()> {
         self.write(dev)?;
         self.delay()?;
         self.read_response(dev)?;
-        Ok(())
+        match self.response_data() {
+            Some(data) => parse::check_code(data),
+            None => Ok(()),
+        }
     }
-    fn write(&mut self, dev: &mut LinuxI2CDevice) -> Result<()> {
-        write_to_ezo(dev, self.command.as_bytes()).chain_err(|| "Error writing to EZO device.")
+    /// Like `run`, but reads the response into `buf`; see
+    /// `read_response_into`. Which response a command expects is only known
+    /// at run time, so the length of `buf` cannot be checked at compile
+    /// time: a buffer shorter than the response's `buffer_len` is refused
+    /// with `BufferTooSmall` before anything is written.
+    fn run_with_buffer<D: I2CDevice>(&mut self, dev: &mut D, buf: &mut [u8]) -> Result<()> {
+        if let Some(response) = self.response {
+            if buf.len() < response.buffer_len() {
+                return Err(ErrorKind::BufferTooSmall(response.buffer_len(), buf.len()).into());
+            }
+        }
+        self.write(dev)?;
+        self.delay()?;
+        self.read_response_into(dev, buf)?;
+        match self.response {
+            Some(_) => parse::check_code(buf),
+            None => Ok(()),
+        }
+    }
+    fn write<D: I2CDevice>(&mut self, dev: &mut D) -> Result<()> {
+        self.command.validate()?;
+        self.retries = 0;
+        write_counting_retries(dev,
+                               self.command.as_bytes(),
+                               &self.retry,
+                               self.processing_time(),
+                               self.deadline,
+                               &mut self.retries)
+                .chain_err(|| "Error writing to EZO device.")
     }
-    fn read_response(&mut self, dev: &mut LinuxI2CDevice) -> Result<()> {
-        if let Some(_) = self.response {
-            let data = read_raw_buffer(dev, MAX_DATA)?;
+    fn read_response<D: I2CDevice>(&mut self, dev: &mut D) -> Result<()> {
+        if let Some(response) = self.response {
+            let mut data = [0u8; MAX_DATA];
+            let len = response.buffer_len().min(MAX_DATA);
+            self.read_response_into(dev, &mut data[..len])?;
             self.data = Some(data);
         };
         Ok(())
     }
-    fn parse_response(&self) -> Result<String> {
-        match self.data {
-            Some(ref data) => {
-                match response_code(data[0]) {
-                    ResponseCode::Success => {
-                        string_from_response_data(&data[1..])
-                            .chain_err(|| "Data is not parsable")
-                    }
-                    _ => Ok(String::new()),
+    /// Reads the response into a caller-owned buffer instead of `data`,
+    /// which is left untouched. Does nothing for commands without a
+    /// response. While the chip answers `Pending`, reads again as many
+    /// times as the read retry policy's `pending_polls`.
+    fn read_response_into<D: I2CDevice>(&mut self, dev: &mut D, buf: &mut [u8]) -> Result<()> {
+        if self.response.is_some() {
+            let policy = self.read_retry_policy();
+            self.pending_reads = 0;
+            read_counting_retries(dev,
+                                  buf,
+                                  &policy,
+                                  self.processing_time(),
+                                  self.deadline,
+                                  &mut self.retries)?;
+            for _ in 0..policy.pending_polls {
+                if buf.first().map(|&b| response_code(b)) != Some(ResponseCode::Pending) {
+                    break;
                 }
+                let wait = match common::remaining(self.deadline) {
+                    Some(left) if left == Duration::from_millis(0) => break,
+                    Some(left) => policy.poll_interval.min(left),
+                    None => policy.poll_interval,
+                };
+                policy.wait_strategy.wait(wait);
+                self.pending_reads += 1;
+                read_counting_retries(dev,
+                                      buf,
+                                      &policy,
+                                      self.processing_time(),
+                                      self.deadline,
+                                      &mut self.retries)?;
             }
-            _ => Ok(String::new()),
+            self.code = buf.first().cloned();
+            self.flipping = buf.get(1..).map_or(false, has_flipped_bits);
+            self.truncated = parse::is_truncated(buf);
+        };
+        Ok(())
+    }
+    /// The payload of the last response; empty for commands without one.
+    /// Fails as `run` does unless the chip answered with success.
+    fn parse_response(&self) -> Result<String> {
+        match self.response_data() {
+            Some(data) => parse::payload(data),
+            None => Ok(String::new()),
         }
     }
 
     /// Sets the ASCII string for the command to be sent
-    fn set_command(&mut self, command_str: String) -> &mut CommandOptions {
-        self.command = command_str;
+    fn set_command(&mut self, command_str: &str) -> &mut CommandOptions {
+        self.command.clear();
+        self.command.push_str(command_str);
+        self
+    }
+    /// Sets the command string from formatted text, without allocating.
+    fn set_command_fmt(&mut self, args: fmt::Arguments) -> &mut CommandOptions {
+        self.command.set_fmt(args);
         self
     }
     fn set_delay(&mut self, delay: u64) -> &mut CommandOptions {
@@ -383,6 +736,32 @@ mod tests {
     use super::*;
     use super::TemperatureCommand::*;
 
+    #[test]
+    fn converts_baud_rates_to_and_from_numbers() {
+        for &bps in &[300, 1200, 2400, 9600, 19200, 38400, 57600, 115200] {
+            assert_eq!(bps_rate_value(&bps_rate_from_value(bps).unwrap()), bps);
+        }
+        match *bps_rate_from_value(4800).unwrap_err().kind() {
+            ErrorKind::UnsupportedBaudRate(4800) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_typed_responses_of_commands() {
+        let (celsius, current) = (TemperatureScale::Celsius, ResponseFormat::Current);
+        let mut cmd = LedState.build();
+        let mut data = [0u8; MAX_DATA];
+        data[..6].copy_from_slice(b"\x01?L,1\0");
+        cmd.data = Some(data);
+        assert_eq!(cmd.typed_response(celsius, current).unwrap(),
+                   Response::LedState(response::LedStatus::On));
+
+        let cmd = Baud(BpsRate::Bps9600).build();
+        assert_eq!(cmd.typed_response(celsius, current).unwrap(), Response::Ack);
+        assert!(Reading.build().typed_response(celsius, current).is_err());
+    }
+
     #[test]
     fn build_command_uart_300() {
         let cmd = Baud(BpsRate::Bps300).build();
@@ -455,6 +834,33 @@ mod tests {
         assert_eq!(cmd.response, Some(CommandResponse::Ack));
     }
 
+    #[test]
+    fn build_command_calibration_negative_temperature() {
+        let cmd = CalibrationTemperature(-12.53).build();
+        assert_eq!(cmd.command, "Cal,-12.53\0");
+        let cmd = CalibrationTemperature(-126.0).build();
+        assert_eq!(cmd.command, "Cal,-126.00\0");
+        let cmd = CalibrationTemperature(-0.001).build();
+        assert_eq!(cmd.command, "Cal,0.00\0");
+    }
+
+    #[test]
+    fn calibration_temperatures_are_plain_decimals() {
+        for &temp in &[0.0, 1e-9, 0.125, -37.5, 100.0, 1254.0, 1e6, -1e6] {
+            let cmd = CalibrationTemperature(temp).build();
+            let value = cmd.command
+                .as_str()
+                .trim_start_matches("Cal,")
+                .trim_end_matches('\0');
+            let (whole, fraction) = value.split_at(value.find('.').unwrap());
+            assert!(whole.trim_start_matches('-').chars().all(|c| c.is_ascii_digit()),
+                    "{:?}",
+                    value);
+            assert_eq!(fraction.len(), 3, "{:?}", value);
+            assert!(fraction[1..].chars().all(|c| c.is_ascii_digit()), "{:?}", value);
+        }
+    }
+
     #[test]
     fn build_command_calibration_clear() {
         let cmd = CalibrationClear.build();
@@ -601,6 +1007,22 @@ mod tests {
                    Some(CommandResponse::MemoryRecallLastLocation));
     }
 
+    #[test]
+    fn build_command_name() {
+        let cmd = Name("sump".to_string()).build();
+        assert_eq!(cmd.command, "Name,sump\0");
+        assert_eq!(cmd.delay, Some(300));
+        assert_eq!(cmd.response, Some(CommandResponse::Ack));
+    }
+
+    #[test]
+    fn build_command_name_state() {
+        let cmd = NameState.build();
+        assert_eq!(cmd.command, "Name,?\0");
+        assert_eq!(cmd.delay, Some(300));
+        assert_eq!(cmd.response, Some(CommandResponse::NameState));
+    }
+
     #[test]
     fn build_command_plock_enable() {
         let cmd = ProtocolLockEnable.build();
@@ -680,4 +1102,117 @@ mod tests {
         assert_eq!(cmd.delay, Some(300));
         assert_eq!(cmd.response, Some(CommandResponse::Status));
     }
+
+    #[test]
+    fn processing_delays_follow_the_datasheet() {
+        assert_eq!(Reading.processing_delay(), Some(READING_DELAY));
+        assert_eq!(CalibrationTemperature(25.0).processing_delay(),
+                   Some(CALIBRATION_DELAY));
+        assert_eq!(LedState.processing_delay(), Some(COMMAND_DELAY));
+        assert_eq!(Sleep.processing_delay(), None);
+        assert_eq!(Reading.build().delay, Reading.processing_delay());
+    }
+
+    #[test]
+    fn sized_buffers_fit_long_responses() {
+        use simulator::{SimulatedChip, VirtualBus};
+
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_name("sixteen-chars-ok");
+        bus.attach(chip);
+        let mut dev = bus.device(0x66);
+
+        let mut cmd = NameState.build();
+        cmd.delay = None;
+        assert!(cmd.response.unwrap().buffer_len() <= MAX_RESPONSE);
+        let mut short = [0u8; QUERY_RESPONSE];
+        match *cmd.run_with_buffer(&mut dev, &mut short).unwrap_err().kind() {
+            ErrorKind::BufferTooSmall(NAME_RESPONSE, QUERY_RESPONSE) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        let mut buf = [0u8; NAME_RESPONSE];
+        cmd.run_with_buffer(&mut dev, &mut buf).unwrap();
+        assert_eq!(parse::payload(&buf).unwrap(), "?Name,sixteen-chars-ok");
+    }
+
+    #[test]
+    fn unsuccessful_response_codes_are_errors() {
+        use mock::{MockDevice, Reply};
+
+        let run = |reply: Reply| {
+            let mut dev = MockDevice::new(0x66);
+            dev.script("L,?", vec![reply]);
+            let mut cmd = LedState.build();
+            cmd.delay = None;
+            cmd.retry = RetryPolicy::no_wait();
+            let ran = cmd.run(&mut dev);
+            (ran, cmd.parse_response())
+        };
+
+        let (ran, parsed) = run(Reply::success("?L,1"));
+        assert!(ran.is_ok());
+        assert_eq!(parsed.unwrap(), "?L,1");
+
+        for &(ref reply, code) in &[(Reply::Pending, ResponseCode::Pending),
+                                    (Reply::Code(2), ResponseCode::DeviceError),
+                                    (Reply::Code(255), ResponseCode::NoDataExpected)] {
+            let (ran, parsed) = run(reply.clone());
+            for error in &[ran.unwrap_err(), parsed.unwrap_err()] {
+                match (code, error.kind()) {
+                    (ResponseCode::Pending, &ErrorKind::PendingResponse) |
+                    (ResponseCode::DeviceError, &ErrorKind::DeviceError) |
+                    (ResponseCode::NoDataExpected, &ErrorKind::UnsuccessfulResponse(255)) => {}
+                    (_, other) => panic!("unexpected error {:?} for {:?}", other, reply),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn runs_read_whole_long_responses() {
+        use simulator::{SimulatedChip, VirtualBus};
+
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_name("sixteen-chars-ok");
+        bus.attach(chip);
+        let mut dev = bus.device(0x66);
+
+        let mut cmd = Status.build();
+        cmd.delay = None;
+        cmd.run(&mut dev).unwrap();
+        assert_eq!(cmd.parse_response().unwrap(), "?Status,P,5.038");
+        assert_eq!(cmd.response_data().map(|data| data.len()), Some(STATUS_RESPONSE));
+
+        let mut cmd = NameState.build();
+        cmd.delay = None;
+        cmd.run(&mut dev).unwrap();
+        assert_eq!(cmd.parse_response().unwrap(), "?Name,sixteen-chars-ok");
+    }
+
+    #[test]
+    fn overlong_command_is_refused() {
+        use simulator::VirtualBus;
+
+        let mut cmd = Name("a name that is much too long for the chip".to_string()).build();
+        assert!(cmd.command.overflowed());
+        let bus = VirtualBus::new();
+        assert!(cmd.write(&mut bus.device(0x66)).is_err());
+    }
+
+    #[test]
+    fn malformed_command_never_reaches_the_bus() {
+        use simulator::{SimulatedChip, VirtualBus};
+
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut cmd = Name("tank\0Factory".to_string()).build();
+        match *cmd.write(&mut bus.device(0x66)).unwrap_err().kind() {
+            ErrorKind::InvalidCommand(_) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(bus.with_chip(id, |chip| chip.name().to_string()),
+                   Some(String::new()));
+    }
 }
@@ -0,0 +1,238 @@
+//! Readings stored by the chip's data logger.
+//!
+//! The chip only recalls its stored readings in order, one per `M`
+//! command, and tells where the last one is with `M,?`; it cannot be asked
+//! for a given location, nor to start over. A `MemoryReader` walks the log
+//! that way, from wherever the chip's recall pointer is up to the last
+//! location, so that a sensor left to log unattended can have its log
+//! pulled back with `RtdSensor::read_memory`. Since the pointer is left
+//! after the last reading recalled, a second `read_memory`, or one after
+//! `recall_memory` or `memory_tail`, only yields what the chip logged in
+//! between; the `location` of each entry tells where it was stored.
+//! `RtdSensor::memory_tail` reads the log the same way, but keeps only as
+//! many readings as were asked for, and hands them back newest first. It
+//! still recalls every reading on the way: without a way to recall a given
+//! location, the log cannot be walked back from its end.
+
+use std::collections::VecDeque;
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+use response::{MemoryEntry, MemoryLocation};
+use sensor::RtdSensor;
+use TemperatureCommand;
+
+/// The last readings stored by the data logger, newest first.
+#[derive(Debug, Clone)]
+pub struct MemoryTail {
+    entries: VecDeque<MemoryEntry>,
+}
+
+impl Iterator for MemoryTail {
+    type Item = MemoryEntry;
+
+    fn next(&mut self) -> Option<MemoryEntry> {
+        self.entries.pop_back()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.entries.len(), Some(self.entries.len()))
+    }
+}
+
+impl DoubleEndedIterator for MemoryTail {
+    fn next_back(&mut self) -> Option<MemoryEntry> {
+        self.entries.pop_front()
+    }
+}
+
+impl ExactSizeIterator for MemoryTail {}
+
+/// The readings stored by the data logger, oldest first, recalled one at a
+/// time; from `RtdSensor::memory_reader`.
+///
+/// The reader starts at the chip's recall pointer, which is only at the
+/// first location if nothing was recalled since the log was started or
+/// cleared. It stops after the last location the chip reported when it was
+/// made, or when the chip says it has nothing more; after an error, it
+/// stops too.
+pub struct MemoryReader<'a, D: I2CDevice + 'a> {
+    sensor: &'a mut RtdSensor<D>,
+    first: Option<u32>,
+    last: u32,
+    done: bool,
+}
+
+impl<'a, D: I2CDevice> MemoryReader<'a, D> {
+    /// The location of the first reading yielded, which is where the chip's
+    /// recall pointer was; `None` until a reading is yielded.
+    pub fn first_location(&self) -> Option<MemoryLocation> {
+        self.first.map(MemoryLocation)
+    }
+
+    /// The location of the last reading the reader yields.
+    pub fn last_location(&self) -> MemoryLocation {
+        MemoryLocation(self.last)
+    }
+}
+
+impl<'a, D: I2CDevice> Iterator for MemoryReader<'a, D> {
+    type Item = Result<MemoryEntry>;
+
+    fn next(&mut self) -> Option<Result<MemoryEntry>> {
+        if self.done {
+            return None;
+        }
+        match self.sensor.recall_memory() {
+            Ok(Some(entry)) => {
+                self.first = self.first.or(Some(entry.location));
+                self.done = entry.location >= self.last;
+                Some(Ok(entry))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<D: I2CDevice> RtdSensor<D> {
+    /// The location of the last stored reading; zero if there is none.
+    pub fn get_last_memory_location(&mut self) -> Result<MemoryLocation> {
+        MemoryLocation::parse(&self.send(TemperatureCommand::MemoryRecallLastLocation)?)
+    }
+
+    /// Recalls the next stored reading, or `None` once every reading has
+    /// been recalled.
+    pub fn recall_memory(&mut self) -> Result<Option<MemoryEntry>> {
+        let response = self.send(TemperatureCommand::MemoryRecall)?;
+        if response == "*DONE" {
+            return Ok(None);
+        }
+        MemoryEntry::parse(&response).map(Some)
+    }
+
+    /// Walks the stored readings not recalled yet, from the chip's recall
+    /// pointer to the last location reported by `M,?` now.
+    pub fn memory_reader(&mut self) -> Result<MemoryReader<'_, D>> {
+        let MemoryLocation(last) = self.get_last_memory_location()?;
+        Ok(MemoryReader {
+               sensor: self,
+               first: None,
+               last: last,
+               done: last == 0,
+           })
+    }
+
+    /// Every stored reading not recalled yet, oldest first. The first call
+    /// after the log is started or cleared yields the whole log; later ones
+    /// only what was logged since.
+    pub fn read_memory(&mut self) -> Result<Vec<MemoryEntry>> {
+        self.memory_reader()?.collect()
+    }
+
+    /// The last `count` readings stored and not recalled yet, or fewer if
+    /// there are not as many, newest first. Every reading not recalled yet
+    /// is read, as by `read_memory`, and all but the last `count` dropped.
+    pub fn memory_tail(&mut self, count: usize) -> Result<MemoryTail> {
+        let mut entries = VecDeque::with_capacity(count);
+        if count == 0 {
+            return Ok(MemoryTail { entries: entries });
+        }
+        for entry in self.memory_reader()? {
+            if entries.len() == count {
+                entries.pop_front();
+            }
+            entries.push_back(entry?);
+        }
+        Ok(MemoryTail { entries: entries })
+    }
+
+    /// Deletes every stored reading.
+    pub fn clear_memory(&mut self) -> Result<()> {
+        self.send(TemperatureCommand::MemoryClear)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn keeps_the_last_readings_newest_first() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_memory(&[20.0, 21.0, 22.0, 23.0, 24.0]);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert_eq!(sensor.get_last_memory_location().unwrap(), MemoryLocation(5));
+        let tail: Vec<(u32, f64)> = sensor
+            .memory_tail(3)
+            .unwrap()
+            .map(|entry| (entry.location, entry.value))
+            .collect();
+        assert_eq!(tail, vec![(5, 24.0), (4, 23.0), (3, 22.0)]);
+    }
+
+    #[test]
+    fn an_empty_log_yields_nothing() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert_eq!(sensor.memory_tail(24).unwrap().len(), 0);
+        assert_eq!(sensor.recall_memory().unwrap(), None);
+    }
+
+    #[test]
+    fn reads_the_whole_log_and_clears_it() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_memory(&[20.0, 21.0, 22.0]);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        let log: Vec<(u32, f64)> = sensor
+            .read_memory()
+            .unwrap()
+            .iter()
+            .map(|entry| (entry.location, entry.value))
+            .collect();
+        assert_eq!(log, vec![(1, 20.0), (2, 21.0), (3, 22.0)]);
+
+        sensor.clear_memory().unwrap();
+        assert_eq!(sensor.get_last_memory_location().unwrap(), MemoryLocation(0));
+        assert!(sensor.read_memory().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_second_read_picks_up_where_the_first_stopped() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_memory(&[20.0, 21.0, 22.0]);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert_eq!(sensor.recall_memory().unwrap().map(|entry| entry.location), Some(1));
+        {
+            let mut reader = sensor.memory_reader().unwrap();
+            assert_eq!(reader.first_location(), None);
+            let locations: Vec<u32> = reader
+                .by_ref()
+                .map(|entry| entry.unwrap().location)
+                .collect();
+            assert_eq!(locations, vec![2, 3]);
+            assert_eq!(reader.first_location(), Some(MemoryLocation(2)));
+        }
+        assert_eq!(sensor.read_memory().unwrap().len(), 0);
+        assert_eq!(sensor.read_memory().unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,236 @@
+//! A scripted EZO device, for tests that need exact answers.
+//!
+//! Where a `SimulatedChip` behaves like a chip, a `MockDevice` only answers
+//! the way it is told to: each command is given the replies to its reads,
+//! in order, so that a test can make a reading come back `Pending` twice
+//! and then with a flipped bit, and check what the sensor made of it. The
+//! last reply scripted for a command is repeated once the others are used
+//! up. Commands without a script are answered with a syntax error, and
+//! reads before any write with "no data".
+//!
+//! Cloning a `MockDevice` yields another handle to the same script, so a
+//! test can keep one to look at the writes made through the other.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use discovery::Addressable;
+use ezo_common::ResponseCode;
+use i2cdev::core::I2CDevice;
+use simulator::SimError;
+
+/// What a `MockDevice` answers to one read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// A successful response with the given payload.
+    Success(String),
+    /// A bare response code, such as 2 for a syntax error.
+    Code(u8),
+    /// The command is still being processed.
+    Pending,
+    /// A successful response whose payload bytes all arrive with bit 7
+    /// set, as if flipped on the bus.
+    Flipped(String),
+    /// Exactly these bytes, followed by zeros.
+    Raw(Vec<u8>),
+    /// The read is not acknowledged.
+    Nack,
+}
+
+impl Reply {
+    pub fn success(payload: &str) -> Reply {
+        Reply::Success(payload.to_string())
+    }
+
+    /// The bytes of the reply, or `None` for `Nack`.
+    fn bytes(&self) -> Option<Vec<u8>> {
+        let success = ResponseCode::Success as u8;
+        let (code, payload, flip): (u8, &[u8], u8) = match *self {
+            Reply::Success(ref payload) => (success, payload.as_bytes(), 0),
+            Reply::Code(code) => (code, b"", 0),
+            Reply::Pending => (ResponseCode::Pending as u8, b"", 0),
+            Reply::Flipped(ref payload) => (success, payload.as_bytes(), 0x80),
+            Reply::Raw(ref bytes) => return Some(bytes.clone()),
+            Reply::Nack => return None,
+        };
+        let mut bytes = vec![code];
+        bytes.extend(payload.iter().map(|&b| b | flip));
+        bytes.push(0);
+        Some(bytes)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    replies: HashMap<String, VecDeque<Reply>>,
+    /// The last command written, if it was acknowledged.
+    command: Option<String>,
+    writes: Vec<String>,
+    reads: usize,
+    /// Writes still to be refused.
+    nacked_writes: u32,
+}
+
+/// A device answering with scripted replies.
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    script: Arc<Mutex<Script>>,
+    address: u16,
+}
+
+impl MockDevice {
+    /// A device at `address`, with nothing scripted yet.
+    pub fn new(address: u16) -> MockDevice {
+        MockDevice {
+            script: Arc::new(Mutex::new(Script::default())),
+            address: address,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Script> {
+        self.script.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Scripts the replies to the reads after `command`, which is given
+    /// without its terminating NUL. Replaces any earlier script for it.
+    pub fn script(&self, command: &str, replies: Vec<Reply>) -> &MockDevice {
+        self.lock().replies.insert(command.to_string(), replies.into_iter().collect());
+        self
+    }
+
+    /// Makes the next `count` writes go unacknowledged, like those to a
+    /// busy or sleeping chip.
+    pub fn nack_writes(&self, count: u32) -> &MockDevice {
+        self.lock().nacked_writes = count;
+        self
+    }
+
+    /// The commands written so far, acknowledged or not, without their
+    /// terminating NULs.
+    pub fn writes(&self) -> Vec<String> {
+        self.lock().writes.clone()
+    }
+
+    /// The reads made so far.
+    pub fn reads(&self) -> usize {
+        self.lock().reads
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+}
+
+impl Addressable for MockDevice {
+    fn select_address(&mut self, address: u16) -> ::errors::Result<()> {
+        self.address = address;
+        Ok(())
+    }
+}
+
+impl I2CDevice for MockDevice {
+    type Error = SimError;
+
+    fn read(&mut self, data: &mut [u8]) -> ::std::result::Result<(), SimError> {
+        let mut script = self.lock();
+        script.reads += 1;
+        let no_data = Reply::Code(ResponseCode::NoDataExpected as u8);
+        let reply = match script.command.clone() {
+            None => no_data,
+            Some(command) => {
+                match script.replies.get_mut(&command) {
+                    Some(replies) if replies.len() > 1 => replies.pop_front().unwrap(),
+                    Some(replies) => replies.front().cloned().unwrap_or(no_data),
+                    None => Reply::Code(ResponseCode::DeviceError as u8),
+                }
+            }
+        };
+        let bytes = reply.bytes().ok_or(SimError::Nack(self.address))?;
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = bytes.get(i).cloned().unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> ::std::result::Result<(), SimError> {
+        let mut script = self.lock();
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        let command = String::from_utf8_lossy(&data[..end]).into_owned();
+        script.writes.push(command.clone());
+        if script.nacked_writes > 0 {
+            script.nacked_writes -= 1;
+            return Err(SimError::Nack(self.address));
+        }
+        script.command = Some(command);
+        Ok(())
+    }
+
+    fn smbus_write_quick(&mut self, _bit: bool) -> ::std::result::Result<(), SimError> {
+        Ok(())
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> ::std::result::Result<Vec<u8>, SimError> {
+        Err(SimError::Unsupported("SMBus block read"))
+    }
+
+    fn smbus_write_block_data(&mut self,
+                              _register: u8,
+                              _values: &[u8])
+                              -> ::std::result::Result<(), SimError> {
+        Err(SimError::Unsupported("SMBus block write"))
+    }
+
+    fn smbus_process_block(&mut self,
+                           _register: u8,
+                           _values: &[u8])
+                           -> ::std::result::Result<(), SimError> {
+        Err(SimError::Unsupported("SMBus block process call"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {CommandBuilder, I2cCommand, TemperatureCommand};
+    use common::RetryPolicy;
+    use response::Temperature;
+    use sensor::RtdSensor;
+
+    #[test]
+    fn commands_get_their_scripted_replies() {
+        let mut dev = MockDevice::new(0x66);
+        dev.script("R", vec![Reply::Pending, Reply::Flipped("25.104".to_string())]);
+
+        let mut cmd = TemperatureCommand::Reading.build();
+        cmd.retry = RetryPolicy::no_wait();
+        assert!(cmd.run(&mut dev).is_err());
+        assert_eq!(cmd.code, Some(ResponseCode::Pending as u8));
+        cmd.read_response(&mut dev).unwrap();
+        assert_eq!(cmd.code, Some(ResponseCode::Success as u8));
+        assert!(cmd.flipping);
+        assert_eq!(cmd.parse_response().unwrap(), "25.104");
+
+        let mut cmd = TemperatureCommand::LedState.build();
+        cmd.retry = RetryPolicy::no_wait();
+        assert!(cmd.run(&mut dev).is_err());
+        assert_eq!(cmd.code, Some(ResponseCode::DeviceError as u8));
+        assert_eq!(dev.writes(), vec!["R", "L,?"]);
+        assert_eq!(dev.reads(), 3);
+    }
+
+    #[test]
+    fn sensors_run_on_mock_devices() {
+        let dev = MockDevice::new(0x66);
+        dev.script("S,?", vec![Reply::success("?S,c")])
+            .script("R", vec![Reply::Flipped("21.500".to_string())])
+            .nack_writes(2);
+        let mut sensor = RtdSensor::new(dev.clone());
+        sensor.set_retry_policy(RetryPolicy::no_wait());
+
+        assert_eq!(sensor.read_temperature().unwrap(), Temperature::Celsius(21.5));
+        assert_eq!(dev.writes(), vec!["S,?", "S,?", "S,?", "R"]);
+
+        dev.script("R", vec![Reply::Nack]);
+        assert!(sensor.read_temperature().is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! Notifications of alarms and device faults.
+//!
+//! A `Notifier` is told about each alarm that changes state, each sensor
+//! whose supply voltage goes low or recovers, and each sensor that starts
+//! failing; how it passes the news on is up to it. With
+//! the `webhook` feature, `Webhook` posts notifications to an HTTP
+//! endpoint, in a format that suits services such as Slack or ntfy.
+
+use alarm::{Alarm, AlarmState, SupplyAlarm, SupplyState};
+use errors::*;
+use response::{DeviceStatus, Temperature};
+
+/// Something worth telling someone about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    /// An alarm changed state, other than to `Fault`.
+    Alarm {
+        address: Option<u16>,
+        state: AlarmState,
+        temperature: Temperature,
+    },
+    /// A sensor's supply voltage went below the threshold.
+    LowVoltage {
+        address: Option<u16>,
+        vcc: f64,
+        threshold: f64,
+    },
+    /// A sensor's supply voltage came back above the threshold.
+    VoltageRestored { address: Option<u16>, vcc: f64 },
+    /// A sensor started failing.
+    Fault {
+        address: Option<u16>,
+        error: String,
+    },
+}
+
+impl Notification {
+    /// A one-line description, for people.
+    pub fn message(&self) -> String {
+        let sensor = |address: Option<u16>| match address {
+            Some(address) => format!("sensor {:#04x}", address),
+            None => "sensor".to_string(),
+        };
+        match *self {
+            Notification::Alarm {
+                address,
+                state,
+                temperature,
+            } => {
+                let what = match state {
+                    AlarmState::High => "above its high threshold",
+                    AlarmState::Low => "below its low threshold",
+                    _ => "back within its thresholds",
+                };
+                format!("{} is {}: {:.3} {}",
+                        sensor(address),
+                        what,
+                        temperature.value(),
+                        temperature.scale().symbol())
+            }
+            Notification::LowVoltage {
+                address,
+                vcc,
+                threshold,
+            } => {
+                format!("{} has a low supply voltage: {:.2} V, below {:.2} V",
+                        sensor(address),
+                        vcc,
+                        threshold)
+            }
+            Notification::VoltageRestored { address, vcc } => {
+                format!("{} has its supply voltage back: {:.2} V",
+                        sensor(address),
+                        vcc)
+            }
+            Notification::Fault { address, ref error } => {
+                format!("{} is failing: {}", sensor(address), error)
+            }
+        }
+    }
+}
+
+/// Passes notifications on.
+pub trait Notifier {
+    fn notify(&mut self, notification: &Notification) -> Result<()>;
+}
+
+/// Notifies all the notifiers, even if some fail, and returns the first
+/// failure.
+impl Notifier for Vec<Box<dyn Notifier + Send>> {
+    fn notify(&mut self, notification: &Notification) -> Result<()> {
+        let mut result = Ok(());
+        for notifier in self.iter_mut() {
+            let sent = notifier.notify(notification);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+}
+
+/// An alarm that notifies when its state changes.
+pub struct AlarmNotifier<N: Notifier> {
+    alarm: Alarm,
+    notifier: N,
+    address: Option<u16>,
+}
+
+impl<N: Notifier> AlarmNotifier<N> {
+    /// Watches the sensor at `address`, if known, with `alarm`.
+    pub fn new(alarm: Alarm, notifier: N, address: Option<u16>) -> AlarmNotifier<N> {
+        AlarmNotifier {
+            alarm: alarm,
+            notifier: notifier,
+            address: address,
+        }
+    }
+
+    pub fn alarm(&self) -> &Alarm {
+        &self.alarm
+    }
+
+    /// Updates the alarm with the latest reading, or its failure, and
+    /// notifies if its state changed. Returns the alarm's new state.
+    pub fn update(&mut self, reading: &Result<Temperature>) -> Result<AlarmState> {
+        let before = self.alarm.state();
+        let state = self.alarm.update(reading);
+        if state == before {
+            return Ok(state);
+        }
+        let notification = match *reading {
+            Ok(temperature) => {
+                Notification::Alarm {
+                    address: self.address,
+                    state: state,
+                    temperature: temperature,
+                }
+            }
+            Err(ref e) => {
+                Notification::Fault {
+                    address: self.address,
+                    error: e.to_string(),
+                }
+            }
+        };
+        self.notifier.notify(&notification)?;
+        Ok(state)
+    }
+}
+
+/// A supply voltage alarm that notifies when its state changes. Failures
+/// to read the status are left to the reading alarms.
+pub struct SupplyNotifier<N: Notifier> {
+    alarm: SupplyAlarm,
+    notifier: N,
+    address: Option<u16>,
+}
+
+impl<N: Notifier> SupplyNotifier<N> {
+    /// Watches the supply of the sensor at `address`, if known, with
+    /// `alarm`.
+    pub fn new(alarm: SupplyAlarm, notifier: N, address: Option<u16>) -> SupplyNotifier<N> {
+        SupplyNotifier {
+            alarm: alarm,
+            notifier: notifier,
+            address: address,
+        }
+    }
+
+    pub fn alarm(&self) -> &SupplyAlarm {
+        &self.alarm
+    }
+
+    /// Updates the alarm with the latest status, or its failure, and
+    /// notifies if the supply went low or recovered, even across failures
+    /// to read the status. Returns the alarm's new state.
+    pub fn update(&mut self, status: &Result<DeviceStatus>) -> Result<SupplyState> {
+        let before = self.alarm.voltage_state();
+        let state = self.alarm.update(status);
+        let vcc = match *status {
+            Ok(ref status) if self.alarm.voltage_state() != before => status.vcc_voltage,
+            _ => return Ok(state),
+        };
+        let notification = match state {
+            SupplyState::Low => {
+                Notification::LowVoltage {
+                    address: self.address,
+                    vcc: vcc,
+                    threshold: self.alarm.threshold(),
+                }
+            }
+            _ => {
+                Notification::VoltageRestored {
+                    address: self.address,
+                    vcc: vcc,
+                }
+            }
+        };
+        self.notifier.notify(&notification)?;
+        Ok(state)
+    }
+}
+
+#[cfg(feature = "webhook")]
+pub use self::webhook::{Webhook, WebhookFormat};
+
+#[cfg(feature = "webhook")]
+mod webhook {
+    use std::time::Duration;
+
+    use serde_json;
+    use ureq;
+
+    use super::*;
+
+    /// Time allowed for a webhook request.
+    const TIMEOUT_SECS: u64 = 10;
+
+    /// What a webhook posts.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum WebhookFormat {
+        /// The notification's fields, and its message, as a JSON object.
+        Json,
+        /// `{"text": message}`, as Slack incoming webhooks expect.
+        SlackText,
+        /// The message as plain text, as ntfy expects.
+        PlainText,
+    }
+
+    /// Posts notifications to an HTTP endpoint.
+    pub struct Webhook {
+        url: String,
+        format: WebhookFormat,
+        agent: ureq::Agent,
+    }
+
+    impl Webhook {
+        pub fn new(url: &str, format: WebhookFormat) -> Webhook {
+            Webhook {
+                url: url.to_string(),
+                format: format,
+                agent: ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(TIMEOUT_SECS))
+                    .build(),
+            }
+        }
+
+        /// The content type and body posted for `notification`.
+        pub fn body(&self, notification: &Notification) -> (&'static str, String) {
+            let message = notification.message();
+            match self.format {
+                WebhookFormat::PlainText => ("text/plain", message),
+                WebhookFormat::SlackText => {
+                    ("application/json", json!({ "text": message }).to_string())
+                }
+                WebhookFormat::Json => {
+                    let value = match *notification {
+                        Notification::Alarm {
+                            address,
+                            state,
+                            temperature,
+                        } => {
+                            json!({
+                                "event": "alarm",
+                                "address": address,
+                                "state": format!("{:?}", state).to_lowercase(),
+                                "temperature": temperature.value(),
+                                "unit": temperature.scale().symbol(),
+                                "message": message,
+                            })
+                        }
+                        Notification::LowVoltage {
+                            address,
+                            vcc,
+                            threshold,
+                        } => {
+                            json!({
+                                "event": "low_voltage",
+                                "address": address,
+                                "vcc": vcc,
+                                "threshold": threshold,
+                                "message": message,
+                            })
+                        }
+                        Notification::VoltageRestored { address, vcc } => {
+                            json!({
+                                "event": "voltage_restored",
+                                "address": address,
+                                "vcc": vcc,
+                                "message": message,
+                            })
+                        }
+                        Notification::Fault { address, ref error } => {
+                            json!({
+                                "event": "fault",
+                                "address": address,
+                                "error": error,
+                                "message": message,
+                            })
+                        }
+                    };
+                    ("application/json", serde_json::to_string(&value).unwrap_or_default())
+                }
+            }
+        }
+    }
+
+    impl Notifier for Webhook {
+        fn notify(&mut self, notification: &Notification) -> Result<()> {
+            let (content_type, body) = self.body(notification);
+            self.agent
+                .post(&self.url)
+                .set("Content-Type", content_type)
+                .send_string(&body)
+                .map(|_| ())
+                .chain_err(|| format!("Could not post to webhook {}", self.url))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn formats_bodies() {
+            let notification = Notification::Fault {
+                address: Some(0x66),
+                error: "no answer".to_string(),
+            };
+            let slack = Webhook::new("http://localhost/", WebhookFormat::SlackText);
+            assert_eq!(slack.body(&notification),
+                       ("application/json",
+                        r#"{"text":"sensor 0x66 is failing: no answer"}"#.to_string()));
+            let json = Webhook::new("http://localhost/", WebhookFormat::Json);
+            let body: serde_json::Value = serde_json::from_str(&json.body(&notification).1)
+                .unwrap();
+            assert_eq!(body["event"], "fault");
+            assert_eq!(body["address"], 0x66);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alarm::Thresholds;
+    use response::{RestartReason, TemperatureScale};
+
+    struct Recorder(Vec<String>);
+
+    impl Notifier for Recorder {
+        fn notify(&mut self, notification: &Notification) -> Result<()> {
+            self.0.push(notification.message());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn notifies_state_changes_only() {
+        let alarm = Alarm::new(Thresholds {
+                                   scale: TemperatureScale::Celsius,
+                                   high: Some(30.0),
+                                   low: None,
+                                   hysteresis: 0.5,
+                               });
+        let mut watcher = AlarmNotifier::new(alarm, Recorder(vec![]), Some(0x66));
+        for reading in vec![Ok(Temperature::Celsius(25.0)),
+                            Ok(Temperature::Celsius(31.0)),
+                            Ok(Temperature::Celsius(31.5)),
+                            Err("no answer".into()),
+                            Err("no answer".into()),
+                            Ok(Temperature::Celsius(25.0))] {
+            watcher.update(&reading).unwrap();
+        }
+        assert_eq!(watcher.notifier.0,
+                   vec!["sensor 0x66 is above its high threshold: 31.000 °C",
+                        "sensor 0x66 is failing: no answer",
+                        "sensor 0x66 is back within its thresholds: 25.000 °C"]);
+    }
+
+    #[test]
+    fn notifies_low_supply_and_its_recovery() {
+        let mut watcher = SupplyNotifier::new(SupplyAlarm::new(3.2, 0.1),
+                                              Recorder(vec![]),
+                                              Some(0x66));
+        for vcc in vec![5.0, 3.1, 3.0, 3.4] {
+            watcher
+                .update(&Ok(DeviceStatus {
+                                restart_reason: RestartReason::BrownOut,
+                                vcc_voltage: vcc,
+                            }))
+                .unwrap();
+        }
+        watcher.update(&Err("no answer".into())).unwrap();
+        assert_eq!(watcher.notifier.0,
+                   vec!["sensor 0x66 has a low supply voltage: 3.10 V, below 3.20 V",
+                        "sensor 0x66 has its supply voltage back: 3.40 V"]);
+    }
+
+    #[test]
+    fn status_failures_do_not_hide_supply_changes() {
+        let mut watcher = SupplyNotifier::new(SupplyAlarm::new(3.2, 0.1),
+                                              Recorder(vec![]),
+                                              Some(0x66));
+        let vcc = |vcc| {
+            Ok(DeviceStatus {
+                   restart_reason: RestartReason::BrownOut,
+                   vcc_voltage: vcc,
+               })
+        };
+        for status in vec![vcc(3.1), Err("no answer".into()), vcc(3.0)] {
+            watcher.update(&status).unwrap();
+        }
+        for status in vec![Err("no answer".into()), vcc(3.4)] {
+            watcher.update(&status).unwrap();
+        }
+        assert_eq!(watcher.notifier.0,
+                   vec!["sensor 0x66 has a low supply voltage: 3.10 V, below 3.20 V",
+                        "sensor 0x66 has its supply voltage back: 3.40 V"]);
+    }
+}
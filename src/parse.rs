@@ -0,0 +1,457 @@
+//! I/O-free parsing of the raw bytes read from the chip.
+//!
+//! The buffer-level functions take the bytes exactly as read from the
+//! device, response code first. The payload is borrowed from the buffer
+//! where possible; only bytes that need correcting are copied. None of the
+//! functions in this module panic, whatever their input, so they can be
+//! driven directly by fuzzers and property tests.
+//!
+//! UART-style status strings such as `*OK`, which some firmware leaves in
+//! I2C responses, are taken out of the payload before it is parsed; `*ER`
+//! makes the response unsuccessful.
+
+use std::borrow::Cow;
+use std::str;
+
+use CommandResponse;
+use errors::*;
+use ezo_common::{ResponseCode, response_code};
+use response::{CalibrationState, DataLoggerStorageIntervalSeconds, DeviceInfo, DeviceName,
+               DeviceStatus, ExportInfo, LedStatus, MemoryEntry, MemoryLocation,
+               ProtocolLockStatus, Response, ResponseFormat, StatusToken, Temperature,
+               TemperatureScale};
+
+/// Length of a response payload, up to its NUL terminator. A terminator that
+/// arrived with bit 7 set (`0x80`) still counts as one, and so does `0xFF`,
+/// which some adapters return once the chip has stopped sending; neither is
+/// ever part of the chip's 7-bit ASCII.
+fn payload_len(payload: &[u8]) -> usize {
+    payload
+        .iter()
+        .position(|&b| b & 0x7f == 0 || b == 0xff)
+        .unwrap_or(payload.len())
+}
+
+/// Returns `true` if any byte of the payload arrived with bit 7 set. The chip
+/// only sends 7-bit ASCII, so such bytes were flipped on the bus.
+pub fn has_flipped_bits(payload: &[u8]) -> bool {
+    let len = payload_len(payload);
+    // The terminator itself may be the flipped byte.
+    payload[..len].iter().any(|&b| b & 0x80 != 0) || payload.get(len) == Some(&0x80)
+}
+
+/// What follows the payload in a response buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Padding {
+    /// A NUL terminator followed by zeros, if anything: the usual case.
+    Zeros,
+    /// `0xFF` fill, with or without a NUL terminator before it.
+    Fill,
+    /// A NUL terminator followed by other bytes, such as stale ones left
+    /// over from an earlier response.
+    Stale,
+    /// Neither terminator nor fill: the payload may have been cut off.
+    Unterminated,
+}
+
+/// Tells which kind of padding follows the payload (the bytes after the
+/// response code) in a response buffer.
+pub fn padding(payload: &[u8]) -> Padding {
+    let len = payload_len(payload);
+    let rest = match payload.get(len) {
+        None => return Padding::Unterminated,
+        Some(&0xff) => return Padding::Fill,
+        Some(_) => &payload[len + 1..],
+    };
+    if rest.iter().all(|&b| b == 0) {
+        Padding::Zeros
+    } else if rest.iter().all(|&b| b == 0xff) {
+        Padding::Fill
+    } else {
+        Padding::Stale
+    }
+}
+
+/// Extracts the ASCII text of a response payload (the bytes following the
+/// response code), clearing bit 7 on any byte that arrived with it set.
+pub fn string_from_response_data(payload: &[u8]) -> Result<String> {
+    let len = payload_len(payload);
+    let data: Vec<u8> = payload[..len].iter().map(|&b| b & 0x7f).collect();
+    String::from_utf8(data).chain_err(|| ErrorKind::ResponseParse)
+}
+
+/// The ASCII text of a response payload, borrowed from `payload` unless
+/// some byte arrived with bit 7 set and had to be corrected.
+pub fn str_from_response_data(payload: &[u8]) -> Result<Cow<'_, str>> {
+    let data = &payload[..payload_len(payload)];
+    if data.iter().any(|&b| b & 0x80 != 0) {
+        return string_from_response_data(payload).map(Cow::Owned);
+    }
+    str::from_utf8(data)
+        .map(Cow::Borrowed)
+        .chain_err(|| ErrorKind::ResponseParse)
+}
+
+/// The response code byte at the start of a raw buffer, as read. Unlike
+/// `code`, this keeps unusual values apart, for logging.
+pub fn raw_code(buf: &[u8]) -> Result<u8> {
+    buf.first().cloned().ok_or_else(|| ErrorKind::ResponseParse.into())
+}
+
+/// The response code at the start of a raw buffer.
+pub fn code(buf: &[u8]) -> Result<ResponseCode> {
+    match buf.first() {
+        Some(&byte) => Ok(response_code(byte)),
+        None => Err(ErrorKind::ResponseParse.into()),
+    }
+}
+
+/// The status strings in a payload, in order.
+fn tokens(payload: &str) -> impl Iterator<Item = StatusToken> + '_ {
+    payload.split('\r').filter_map(StatusToken::parse)
+}
+
+/// The status strings in a raw buffer, in order; empty if the payload is
+/// unreadable.
+pub fn status_tokens(buf: &[u8]) -> Vec<StatusToken> {
+    match buf.get(1..).map(str_from_response_data) {
+        Some(Ok(payload)) => tokens(&payload).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Takes the status strings out of a payload, leaving the data. Fails if
+/// the chip reported an error, or if more than one data line remains.
+fn without_status(payload: Cow<'_, str>) -> Result<Cow<'_, str>> {
+    if !payload.contains('*') {
+        return Ok(payload);
+    }
+    if tokens(&payload).any(|token| token == StatusToken::Error) {
+        return Err(ErrorKind::DeviceError.into());
+    }
+    let mut data = payload
+        .split('\r')
+        .filter(|line| !line.is_empty() && StatusToken::parse(line).is_none());
+    let line = match data.next() {
+        Some(line) => line,
+        None => return Ok(Cow::Borrowed("")),
+    };
+    if data.next().is_some() {
+        return Err(ErrorKind::ResponseParse.into());
+    }
+    let start = line.as_ptr() as usize - payload.as_ptr() as usize;
+    let end = start + line.len();
+    Ok(match payload {
+           Cow::Borrowed(text) => Cow::Borrowed(&text[start..end]),
+           Cow::Owned(text) => Cow::Owned(text[start..end].to_string()),
+       })
+}
+
+/// Returns `true` if `buf` holds the start of a successful response that was
+/// longer than the buffer: its payload has no terminator.
+pub fn is_truncated(buf: &[u8]) -> bool {
+    match code(buf) {
+        Ok(ResponseCode::Success) => padding(&buf[1..]) == Padding::Unterminated,
+        _ => false,
+    }
+}
+
+/// Fails unless the raw buffer holds a successful response: with
+/// `PendingResponse` if the chip was not done, with `DeviceError` if it
+/// answered with an error, and with `UnsuccessfulResponse` for any other
+/// code.
+pub fn check_code(buf: &[u8]) -> Result<()> {
+    match code(buf)? {
+        ResponseCode::Success => Ok(()),
+        ResponseCode::Pending => Err(ErrorKind::PendingResponse.into()),
+        ResponseCode::DeviceError => Err(ErrorKind::DeviceError.into()),
+        _ => Err(ErrorKind::UnsuccessfulResponse(buf[0]).into()),
+    }
+}
+
+/// The text payload of a raw buffer, which must hold a successful response,
+/// without status strings. Borrowed from `buf` where possible. Fails as
+/// `check_code` does for other codes, and with `TruncatedResponse` rather
+/// than parse a payload that was cut off.
+pub fn payload_str(buf: &[u8]) -> Result<Cow<'_, str>> {
+    check_code(buf)?;
+    if is_truncated(buf) {
+        return Err(ErrorKind::TruncatedResponse(buf.len()).into());
+    }
+    without_status(str_from_response_data(&buf[1..])?)
+}
+
+/// The text payload of a raw buffer, which must hold a successful response.
+pub fn payload(buf: &[u8]) -> Result<String> {
+    payload_str(buf).map(Cow::into_owned)
+}
+
+/// Parses the raw response to the `S,?` command.
+pub fn temperature_scale(buf: &[u8]) -> Result<TemperatureScale> {
+    TemperatureScale::parse(&payload_str(buf)?)
+}
+
+/// Parses the raw response to the `D,?` command.
+pub fn data_logger_interval(buf: &[u8]) -> Result<DataLoggerStorageIntervalSeconds> {
+    DataLoggerStorageIntervalSeconds::parse(&payload_str(buf)?)
+}
+
+/// Parses the raw response to the `Cal,?` command.
+pub fn calibration_state(buf: &[u8]) -> Result<CalibrationState> {
+    CalibrationState::parse(&payload_str(buf)?)
+}
+
+/// Parses the raw response to the `L,?` command.
+pub fn led_status(buf: &[u8]) -> Result<LedStatus> {
+    LedStatus::parse(&payload_str(buf)?)
+}
+
+/// Parses the raw response to the `Plock,?` command.
+pub fn protocol_lock_status(buf: &[u8]) -> Result<ProtocolLockStatus> {
+    ProtocolLockStatus::parse(&payload_str(buf)?)
+}
+
+/// Parses the raw response to the `R` command, taken in the given `scale`.
+pub fn temperature(buf: &[u8], scale: TemperatureScale) -> Result<Temperature> {
+    Temperature::parse(&payload_str(buf)?, scale)
+}
+
+/// Parses the raw response to the `Status` command.
+pub fn device_status(buf: &[u8]) -> Result<DeviceStatus> {
+    DeviceStatus::parse(&payload_str(buf)?)
+}
+
+/// Parses the raw response to the `Status` command, as formatted by
+/// firmware with the given response `format`.
+pub fn device_status_with_format(buf: &[u8], format: ResponseFormat) -> Result<DeviceStatus> {
+    DeviceStatus::parse_with_format(&payload_str(buf)?, format)
+}
+
+/// Parses the raw response to a command expecting `kind` of response;
+/// readings are taken in the given `scale`, and `Status` is read as
+/// formatted by firmware with the given response `format`. Fails with
+/// `ResponseParse` if the payload is not what `kind` calls for.
+pub fn response(buf: &[u8],
+                kind: CommandResponse,
+                scale: TemperatureScale,
+                format: ResponseFormat)
+                -> Result<Response> {
+    let payload = payload_str(buf)?;
+    Ok(match kind {
+           CommandResponse::Ack if payload.is_empty() => Response::Ack,
+           CommandResponse::Ack => return Err(ErrorKind::ResponseParse.into()),
+           CommandResponse::CalibrationState => {
+               Response::CalibrationState(CalibrationState::parse(&payload)?)
+           }
+           CommandResponse::DataloggerInterval => {
+               Response::DataloggerInterval(DataLoggerStorageIntervalSeconds::parse(&payload)?)
+           }
+           CommandResponse::DeviceInformation => {
+               Response::DeviceInformation(DeviceInfo::parse(&payload)?)
+           }
+           CommandResponse::ExportInfo => Response::ExportInfo(ExportInfo::parse(&payload)?),
+           CommandResponse::Export => Response::Export(payload.into_owned()),
+           CommandResponse::LedState => Response::LedState(LedStatus::parse(&payload)?),
+           CommandResponse::MemoryRecall => Response::MemoryRecall(MemoryEntry::parse(&payload)?),
+           CommandResponse::MemoryRecallLastLocation => {
+               Response::MemoryRecallLastLocation(MemoryLocation::parse(&payload)?)
+           }
+           CommandResponse::NameState => Response::NameState(DeviceName::parse(&payload)?),
+           CommandResponse::ProtocolLockState => {
+               Response::ProtocolLockState(ProtocolLockStatus::parse(&payload)?)
+           }
+           CommandResponse::Reading => Response::Reading(Temperature::parse(&payload, scale)?),
+           CommandResponse::ScaleState => {
+               Response::ScaleState(TemperatureScale::parse(&payload)?)
+           }
+           CommandResponse::Status => {
+               Response::Status(DeviceStatus::parse_with_format(&payload, format)?)
+           }
+       })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_strings_are_taken_out_of_the_payload() {
+        let buf = b"\x01?S,k\r*OK\0";
+        assert_eq!(temperature_scale(buf).unwrap(), TemperatureScale::Kelvin);
+        assert_eq!(status_tokens(buf), vec![StatusToken::Ok]);
+
+        let buf = b"\x01*WA\r-4.250\0";
+        assert_eq!(temperature(buf, TemperatureScale::Celsius).unwrap(),
+                   Temperature::Celsius(-4.25));
+        assert_eq!(status_tokens(buf), vec![StatusToken::Wake]);
+
+        assert_eq!(payload(b"\x01*OK\0").unwrap(), "");
+        match *payload(b"\x01*ER\0").unwrap_err().kind() {
+            ErrorKind::DeviceError => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert!(payload(b"\x01?L,1\r?L,0\r*OK\0").is_err());
+        assert!(status_tokens(b"\x01?L,1\0").is_empty());
+    }
+
+    #[test]
+    fn tolerates_padding_variations() {
+        assert_eq!(payload(b"\x01?L,1\0\0\0").unwrap(), "?L,1");
+        assert_eq!(padding(b"?L,1\0\0\0"), Padding::Zeros);
+
+        assert_eq!(payload(b"\x01?L,1\xff\xff\xff").unwrap(), "?L,1");
+        assert_eq!(padding(b"?L,1\xff\xff\xff"), Padding::Fill);
+        assert_eq!(padding(b"?L,1\0\xff\xff"), Padding::Fill);
+        assert!(!has_flipped_bits(b"?L,1\xff\xff\xff"));
+
+        assert_eq!(payload(b"\x01?L,1\0L,1\0").unwrap(), "?L,1");
+        assert_eq!(padding(b"?L,1\0L,1\0"), Padding::Stale);
+
+        assert_eq!(padding(b"?L,1"), Padding::Unterminated);
+        assert_eq!(padding(b""), Padding::Unterminated);
+    }
+
+    #[test]
+    fn refuses_truncated_responses() {
+        assert!(is_truncated(b"\x01-126.00"));
+        assert!(is_truncated(b"\x01"));
+        assert!(!is_truncated(b"\x01-126.00\0"));
+        assert!(!is_truncated(b"\x02"));
+        match *temperature(b"\x01-126.00", TemperatureScale::Celsius)
+                    .unwrap_err()
+                    .kind() {
+            ErrorKind::TruncatedResponse(8) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn extracts_payload_up_to_terminator() {
+        let payload = b"?L,1\0\0\0";
+        assert_eq!(string_from_response_data(payload).unwrap(), "?L,1");
+        assert!(!has_flipped_bits(payload));
+    }
+
+    #[test]
+    fn extracts_payload_without_terminator() {
+        let payload = b"25.104";
+        assert_eq!(string_from_response_data(payload).unwrap(), "25.104");
+    }
+
+    #[test]
+    fn corrects_and_reports_flipped_bytes() {
+        let payload = [b'2' | 0x80, b'5', b'.' | 0x80, b'1', 0x80, 0, 0];
+        assert_eq!(string_from_response_data(&payload).unwrap(), "25.1");
+        assert!(has_flipped_bits(&payload));
+    }
+
+    #[test]
+    fn flipped_bytes_after_terminator_are_not_reported() {
+        let payload = [b'2', b'5', 0, 0x80, 0x80];
+        assert_eq!(string_from_response_data(&payload).unwrap(), "25");
+        assert!(!has_flipped_bits(&payload));
+    }
+
+    #[test]
+    fn borrows_the_payload_unless_it_needs_correcting() {
+        match payload_str(b"\x0125.104\0\0").unwrap() {
+            Cow::Borrowed(text) => assert_eq!(text, "25.104"),
+            Cow::Owned(_) => panic!("a clean payload should be borrowed"),
+        }
+        match payload_str(&[1, b'2' | 0x80, b'5', 0]).unwrap() {
+            Cow::Owned(text) => assert_eq!(text, "25"),
+            Cow::Borrowed(_) => panic!("a corrected payload cannot be borrowed"),
+        }
+    }
+
+    #[test]
+    fn parses_raw_buffers() {
+        assert_eq!(payload(b"\x01?L,1\0\0").unwrap(), "?L,1");
+        assert_eq!(temperature_scale(b"\x01?S,k\0").unwrap(),
+                   TemperatureScale::Kelvin);
+        assert_eq!(data_logger_interval(b"\x01?D,10\0").unwrap(),
+                   DataLoggerStorageIntervalSeconds(10));
+        assert_eq!(temperature(b"\x0125.104\0", TemperatureScale::Celsius).unwrap(),
+                   Temperature::Celsius(25.104));
+        assert_eq!(device_status(b"\x01?Status,P,5.038\0").unwrap().vcc_voltage,
+                   5.038);
+    }
+
+    #[test]
+    fn parses_responses_by_command() {
+        let celsius = TemperatureScale::Celsius;
+        let current = ResponseFormat::Current;
+        assert_eq!(response(b"\x01\0", CommandResponse::Ack, celsius, current).unwrap(),
+                   Response::Ack);
+        assert_eq!(response(b"\x01?Cal,1\0", CommandResponse::CalibrationState, celsius, current)
+                       .unwrap(),
+                   Response::CalibrationState(CalibrationState::Calibrated));
+        assert_eq!(response(b"\x0125.104\0", CommandResponse::Reading, celsius, current).unwrap(),
+                   Response::Reading(Temperature::Celsius(25.104)));
+        let status = b"\x01?STATUS,P,5.038\0";
+        assert!(response(status, CommandResponse::Status, celsius, current).is_err());
+        match response(status, CommandResponse::Status, celsius, ResponseFormat::Legacy).unwrap() {
+            Response::Status(status) => assert_eq!(status.vcc_voltage, 5.038),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        match *response(b"\x01?L,1\0", CommandResponse::Reading, celsius, current)
+                    .unwrap_err()
+                    .kind() {
+            ErrorKind::ResponseParse => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        match *response(b"\x02\0", CommandResponse::LedState, celsius, current)
+                    .unwrap_err()
+                    .kind() {
+            ErrorKind::DeviceError => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unsuccessful_or_empty_buffers_yield_errors() {
+        assert!(code(b"").is_err());
+        assert!(payload(b"").is_err());
+        assert!(payload(b"\xfe\0").is_err());
+        assert!(payload(b"\xff").is_err());
+        assert!(temperature(b"\x02\0", TemperatureScale::Celsius).is_err());
+    }
+
+    #[test]
+    fn arbitrary_buffers_never_panic() {
+        let prefixes: [&[u8]; 5] = [b"", b"\x01", b"\x01?D,", b"\x01?S,", b"\x01?Status,"];
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        for prefix in prefixes.iter() {
+            for len in 0..24 {
+                for _ in 0..200 {
+                    let mut buf = prefix.to_vec();
+                    buf.extend((0..len).map(|_| {
+                                                state ^= state << 13;
+                                                state ^= state >> 7;
+                                                state ^= state << 17;
+                                                state as u8
+                                            }));
+                    let _ = has_flipped_bits(&buf);
+                    let _ = padding(&buf);
+                    let _ = is_truncated(&buf);
+                    let _ = status_tokens(&buf);
+                    let _ = string_from_response_data(&buf);
+                    let _ = code(&buf);
+                    let _ = raw_code(&buf);
+                    let _ = payload(&buf);
+                    let _ = payload_str(&buf);
+                    let _ = calibration_state(&buf);
+                    let _ = temperature_scale(&buf);
+                    let _ = data_logger_interval(&buf);
+                    let _ = temperature(&buf, TemperatureScale::Fahrenheit);
+                    let _ = device_status(&buf);
+                    let _ = response(&buf,
+                                     CommandResponse::MemoryRecall,
+                                     TemperatureScale::Kelvin,
+                                     ResponseFormat::Current);
+                }
+            }
+        }
+    }
+}
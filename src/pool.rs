@@ -0,0 +1,184 @@
+//! Lazily opened bus handles, shared by the sensors on each bus.
+//!
+//! Opening a `/dev/i2c-*` file for every sensor of a fleet wastes file
+//! descriptors. A `BusPool` opens one handle per bus, only when a sensor on
+//! that bus first talks to its chip, and closes the least recently used
+//! handle when more than its capacity of buses are open. Every
+//! `PooledDevice` selects its own address before each transaction.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use discovery::{Addressable, FIRST_ADDRESS};
+use errors::*;
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::LinuxI2CDevice;
+use sensor::RtdSensor;
+
+/// Opens the handle for a bus number.
+type Opener<D> = Box<dyn FnMut(u8) -> Result<D> + Send>;
+
+struct PoolState<D> {
+    capacity: usize,
+    opener: Opener<D>,
+    /// Open handles by bus number, least recently used first.
+    open: Vec<(u8, D)>,
+}
+
+impl<D: Addressable> PoolState<D> {
+    /// The handle for `bus`, opened if need be, and marked as the most
+    /// recently used.
+    fn handle(&mut self, bus: u8) -> Result<&mut D> {
+        let dev = match self.open.iter().position(|&(open, _)| open == bus) {
+            Some(index) => self.open.remove(index).1,
+            None => (self.opener)(bus)?,
+        };
+        if self.open.len() >= self.capacity {
+            self.open.remove(0);
+        }
+        self.open.push((bus, dev));
+        Ok(&mut self.open.last_mut().unwrap().1)
+    }
+}
+
+/// Bus handles shared by any number of `PooledDevice`s.
+///
+/// Cloning a `BusPool` yields another handle to the same pool.
+pub struct BusPool<D> {
+    state: Arc<Mutex<PoolState<D>>>,
+}
+
+impl<D> Clone for BusPool<D> {
+    fn clone(&self) -> BusPool<D> {
+        BusPool { state: self.state.clone() }
+    }
+}
+
+impl BusPool<LinuxI2CDevice> {
+    /// A pool of `/dev/i2c-<bus>` handles, at most `capacity` of them open
+    /// at once.
+    pub fn linux(capacity: usize) -> BusPool<LinuxI2CDevice> {
+        BusPool::new(capacity, |bus| {
+            let device_path = format!("/dev/i2c-{}", bus);
+            LinuxI2CDevice::new(&device_path, FIRST_ADDRESS)
+                .chain_err(|| format!("Could not open I2C device {}", device_path))
+        })
+    }
+}
+
+impl<D: Addressable> BusPool<D> {
+    /// A pool that opens bus handles with `opener`, keeping at most
+    /// `capacity` of them open at once; at least one is always kept.
+    pub fn new<F>(capacity: usize, opener: F) -> BusPool<D>
+        where F: FnMut(u8) -> Result<D> + Send + 'static
+    {
+        BusPool {
+            state: Arc::new(Mutex::new(PoolState {
+                                           capacity: capacity.max(1),
+                                           opener: Box::new(opener),
+                                           open: Vec::new(),
+                                       })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState<D>> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// A device for the chip at `address` on `bus`. Nothing is opened until
+    /// the first transaction.
+    pub fn device(&self, bus: u8, address: u16) -> PooledDevice<D> {
+        PooledDevice {
+            pool: self.clone(),
+            bus: bus,
+            address: address,
+        }
+    }
+
+    /// A sensor for the chip at `address` on `bus`, opened lazily.
+    pub fn sensor(&self, bus: u8, address: u16) -> RtdSensor<PooledDevice<D>> {
+        let mut sensor = RtdSensor::new(self.device(bus, address));
+        sensor.set_known_address(address);
+        sensor
+    }
+
+    /// The buses with an open handle, least recently used first.
+    pub fn open_buses(&self) -> Vec<u8> {
+        self.lock().open.iter().map(|&(bus, _)| bus).collect()
+    }
+}
+
+/// A chip on a bus whose handle is kept in a `BusPool`.
+pub struct PooledDevice<D> {
+    pool: BusPool<D>,
+    bus: u8,
+    address: u16,
+}
+
+impl<D: Addressable> PooledDevice<D> {
+    /// Runs `f` on the bus handle, pointed at this device's address.
+    fn with<T, F>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&mut D) -> ::std::result::Result<T, D::Error>
+    {
+        let mut state = self.pool.lock();
+        let dev = state.handle(self.bus)?;
+        dev.select_address(self.address)?;
+        f(dev).map_err(|e| e.to_string().into())
+    }
+}
+
+impl<D: Addressable> I2CDevice for PooledDevice<D> {
+    type Error = Error;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<()> {
+        self.with(|dev| dev.read(data))
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.with(|dev| dev.write(data))
+    }
+
+    fn smbus_write_quick(&mut self, bit: bool) -> Result<()> {
+        self.with(|dev| dev.smbus_write_quick(bit))
+    }
+
+    fn smbus_read_block_data(&mut self, register: u8) -> Result<Vec<u8>> {
+        self.with(|dev| dev.smbus_read_block_data(register))
+    }
+
+    fn smbus_write_block_data(&mut self, register: u8, values: &[u8]) -> Result<()> {
+        self.with(|dev| dev.smbus_write_block_data(register, values))
+    }
+
+    fn smbus_process_block(&mut self, register: u8, values: &[u8]) -> Result<()> {
+        self.with(|dev| dev.smbus_process_block(register, values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use response::Temperature;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn opens_buses_lazily_and_closes_the_least_recently_used() {
+        let buses = vec![VirtualBus::new(), VirtualBus::new()];
+        for (bus, celsius) in buses.iter().zip(&[10.0, 20.0]) {
+            let mut chip = SimulatedChip::new(0x66);
+            chip.set_temperature(*celsius);
+            bus.attach(chip);
+        }
+        let pool = BusPool::new(1, move |bus| Ok(buses[bus as usize].device(FIRST_ADDRESS)));
+
+        let mut first = pool.sensor(0, 0x66);
+        let mut second = pool.sensor(1, 0x66);
+        assert!(pool.open_buses().is_empty());
+
+        assert_eq!(first.read_temperature().unwrap(), Temperature::Celsius(10.0));
+        assert_eq!(pool.open_buses(), vec![0]);
+        assert_eq!(second.read_temperature().unwrap(), Temperature::Celsius(20.0));
+        assert_eq!(pool.open_buses(), vec![1]);
+        assert_eq!(first.read_temperature().unwrap(), Temperature::Celsius(10.0));
+        assert_eq!(pool.open_buses(), vec![0]);
+    }
+}
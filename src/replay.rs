@@ -0,0 +1,447 @@
+//! Recording the I2C transactions with a chip, and replaying them.
+//!
+//! A `RecordingDevice` wraps a device and keeps a `Transcript` of every
+//! address selection, quick write, write and read made through it, with
+//! the time each one started. A transcript saved in the field can then be
+//! played back by a `ReplayDevice`, which answers the same writes with the
+//! same reads, so that a bug seen on a real chip reproduces on the bench.
+//!
+//! Replaying is instantaneous by default. Bugs that depend on timing, such
+//! as reads made before the chip has finished processing, only reproduce
+//! when the transactions are spaced as they were: `ReplayTiming::Recorded`
+//! waits for each transaction's recorded offset, and `Scaled` stretches or
+//! compresses those offsets.
+//!
+//! Transcripts are saved as text, one transaction per line: the offset in
+//! microseconds, then `A` (address), `Q` (quick write), `W` (write) or `R`
+//! (read), followed by `!` if the transaction failed, and its bytes in hex.
+//!
+//! ```text
+//! 0 W 5200
+//! 601200 R 0132352e31303400
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use discovery::Addressable;
+use errors::*;
+use i2cdev::core::I2CDevice;
+
+/// What a recorded transaction did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// The device was pointed at this slave address.
+    Address(u16),
+    /// An SMBus quick write, as used to probe addresses.
+    Quick(bool),
+    /// These bytes were written.
+    Write(Vec<u8>),
+    /// These bytes were read.
+    Read(Vec<u8>),
+}
+
+/// One recorded transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    /// When the transaction started, from the start of the recording.
+    pub offset: Duration,
+    pub operation: Operation,
+    /// Whether the transaction succeeded.
+    pub ok: bool,
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let micros = self.offset.as_secs() * 1_000_000 + self.offset.subsec_micros() as u64;
+        let failed = if self.ok { "" } else { "!" };
+        match self.operation {
+            Operation::Address(address) => write!(f, "{} A{} {:02x}", micros, failed, address),
+            Operation::Quick(bit) => write!(f, "{} Q{} {}", micros, failed, bit as u8),
+            Operation::Write(ref bytes) => write!(f, "{} W{} {}", micros, failed, hex(bytes)),
+            Operation::Read(ref bytes) => write!(f, "{} R{} {}", micros, failed, hex(bytes)),
+        }
+    }
+}
+
+impl FromStr for Transaction {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Transaction> {
+        let mut fields = line.split_whitespace();
+        let micros: u64 = match fields.next().map(|micros| micros.parse()) {
+            Some(Ok(micros)) => micros,
+            _ => bail!("invalid transcript line `{}`", line),
+        };
+        let kind = fields.next().unwrap_or("");
+        let ok = !kind.ends_with('!');
+        let bytes = unhex(fields.next().unwrap_or(""))
+            .chain_err(|| format!("invalid transcript line `{}`", line))?;
+        let operation = match kind.trim_end_matches('!') {
+            "A" if bytes.len() == 1 => Operation::Address(bytes[0] as u16),
+            "Q" if bytes.len() == 1 => Operation::Quick(bytes[0] != 0),
+            "W" => Operation::Write(bytes),
+            "R" => Operation::Read(bytes),
+            _ => bail!("invalid transcript line `{}`", line),
+        };
+        Ok(Transaction {
+               offset: Duration::from_micros(micros),
+               operation: operation,
+               ok: ok,
+           })
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Result<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.is_ascii() {
+        bail!("odd number of hex digits");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+                 u8::from_str_radix(&text[i..i + 2], 16)
+                     .chain_err(|| format!("invalid hex byte `{}`", &text[i..i + 2]))
+             })
+        .collect()
+}
+
+/// A recording of transactions, in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transcript {
+    pub transactions: Vec<Transaction>,
+}
+
+impl Transcript {
+    /// Reads a transcript saved with `to_string`. Blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn parse(text: &str) -> Result<Transcript> {
+        let transactions = text.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse())
+            .collect::<Result<Vec<Transaction>>>()?;
+        Ok(Transcript { transactions: transactions })
+    }
+
+    /// Time from the first transaction to the last.
+    pub fn duration(&self) -> Duration {
+        match (self.transactions.first(), self.transactions.last()) {
+            (Some(first), Some(last)) => last.offset - first.offset,
+            _ => Duration::from_secs(0),
+        }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for transaction in &self.transactions {
+            writeln!(f, "{}", transaction)?;
+        }
+        Ok(())
+    }
+}
+
+/// A device that records the transactions made through it.
+pub struct RecordingDevice<D> {
+    inner: D,
+    started: Instant,
+    transcript: Transcript,
+}
+
+impl<D: I2CDevice> RecordingDevice<D> {
+    /// Records the transactions made on `inner`, from now on.
+    pub fn new(inner: D) -> RecordingDevice<D> {
+        RecordingDevice {
+            inner: inner,
+            started: Instant::now(),
+            transcript: Transcript::default(),
+        }
+    }
+
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Gives back the device and what was recorded.
+    pub fn into_parts(self) -> (D, Transcript) {
+        (self.inner, self.transcript)
+    }
+
+    fn record<T, E>(&mut self,
+                    offset: Duration,
+                    operation: Operation,
+                    result: ::std::result::Result<T, E>)
+                    -> ::std::result::Result<T, E> {
+        self.transcript
+            .transactions
+            .push(Transaction {
+                      offset: offset,
+                      operation: operation,
+                      ok: result.is_ok(),
+                  });
+        result
+    }
+}
+
+impl<D: Addressable> Addressable for RecordingDevice<D> {
+    fn select_address(&mut self, address: u16) -> Result<()> {
+        let offset = self.started.elapsed();
+        let result = self.inner.select_address(address);
+        self.record(offset, Operation::Address(address), result)
+    }
+}
+
+impl<D: I2CDevice> I2CDevice for RecordingDevice<D> {
+    type Error = D::Error;
+
+    fn read(&mut self, data: &mut [u8]) -> ::std::result::Result<(), D::Error> {
+        let offset = self.started.elapsed();
+        let result = self.inner.read(data);
+        let read = if result.is_ok() { data.to_vec() } else { Vec::new() };
+        self.record(offset, Operation::Read(read), result)
+    }
+
+    fn write(&mut self, data: &[u8]) -> ::std::result::Result<(), D::Error> {
+        let offset = self.started.elapsed();
+        let result = self.inner.write(data);
+        self.record(offset, Operation::Write(data.to_vec()), result)
+    }
+
+    fn smbus_write_quick(&mut self, bit: bool) -> ::std::result::Result<(), D::Error> {
+        let offset = self.started.elapsed();
+        let result = self.inner.smbus_write_quick(bit);
+        self.record(offset, Operation::Quick(bit), result)
+    }
+
+    /// Not recorded.
+    fn smbus_read_block_data(&mut self, register: u8) -> ::std::result::Result<Vec<u8>, D::Error> {
+        self.inner.smbus_read_block_data(register)
+    }
+
+    /// Not recorded.
+    fn smbus_write_block_data(&mut self,
+                              register: u8,
+                              values: &[u8])
+                              -> ::std::result::Result<(), D::Error> {
+        self.inner.smbus_write_block_data(register, values)
+    }
+
+    /// Not recorded.
+    fn smbus_process_block(&mut self,
+                           register: u8,
+                           values: &[u8])
+                           -> ::std::result::Result<(), D::Error> {
+        self.inner.smbus_process_block(register, values)
+    }
+}
+
+/// How a `ReplayDevice` spaces the transactions it replays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayTiming {
+    /// Each transaction is answered right away.
+    Immediate,
+    /// Each transaction waits until as long after the first one as it was
+    /// recorded.
+    Recorded,
+    /// Like `Recorded`, with the offsets multiplied by the factor: 2.0
+    /// replays at half speed.
+    Scaled(f64),
+}
+
+/// A device that plays a transcript back.
+///
+/// Each transaction has to be the one recorded next, with the same bytes
+/// written; a read gets the recorded bytes, and a transaction that failed
+/// fails again. Anything else is an error.
+pub struct ReplayDevice {
+    transcript: Transcript,
+    position: usize,
+    timing: ReplayTiming,
+    /// When the first transaction was replayed.
+    started: Option<Instant>,
+}
+
+impl ReplayDevice {
+    /// Replays `transcript` instantaneously.
+    pub fn new(transcript: Transcript) -> ReplayDevice {
+        ReplayDevice {
+            transcript: transcript,
+            position: 0,
+            timing: ReplayTiming::Immediate,
+            started: None,
+        }
+    }
+
+    pub fn set_timing(&mut self, timing: ReplayTiming) {
+        self.timing = timing;
+    }
+
+    /// Transactions not replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.transcript.transactions.len() - self.position
+    }
+
+    /// Takes the next recorded transaction, after waiting for its offset if
+    /// the timing is honored. Fails if it is not `expected`.
+    fn next<F>(&mut self, expected: &str, matches: F) -> Result<&Transaction>
+        where F: FnOnce(&Operation) -> bool
+    {
+        let index = self.position;
+        let (first, offset, ok) = match self.transcript.transactions.get(index) {
+            Some(transaction) => {
+                (self.transcript.transactions[0].offset,
+                 transaction.offset,
+                 matches(&transaction.operation))
+            }
+            None => bail!(ErrorKind::ReplayMismatch(index, "end of the transcript".to_string())),
+        };
+        if !ok {
+            let recorded = self.transcript.transactions[index].to_string();
+            bail!(ErrorKind::ReplayMismatch(index, format!("{} instead of {}", expected, recorded)));
+        }
+        self.position += 1;
+
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let factor = match self.timing {
+            ReplayTiming::Immediate => None,
+            ReplayTiming::Recorded => Some(1.0),
+            ReplayTiming::Scaled(factor) => Some(factor.max(0.0)),
+        };
+        if let Some(factor) = factor {
+            let due = started + (offset - first).mul_f64(factor);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        let transaction = &self.transcript.transactions[index];
+        if !transaction.ok {
+            bail!("the recorded {} failed", expected);
+        }
+        Ok(transaction)
+    }
+}
+
+impl Addressable for ReplayDevice {
+    fn select_address(&mut self, address: u16) -> Result<()> {
+        self.next(&format!("address {:#04x}", address),
+                  |operation| *operation == Operation::Address(address))
+            .map(|_| ())
+    }
+}
+
+impl I2CDevice for ReplayDevice {
+    type Error = Error;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<()> {
+        let transaction = self.next("a read", |operation| match *operation {
+            Operation::Read(_) => true,
+            _ => false,
+        })?;
+        if let Operation::Read(ref bytes) = transaction.operation {
+            for (dst, src) in data.iter_mut().zip(bytes.iter().chain(::std::iter::repeat(&0))) {
+                *dst = *src;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.next(&format!("a write of {}", hex(data)),
+                  |operation| *operation == Operation::Write(data.to_vec()))
+            .map(|_| ())
+    }
+
+    fn smbus_write_quick(&mut self, bit: bool) -> Result<()> {
+        self.next("a quick write", |operation| *operation == Operation::Quick(bit))
+            .map(|_| ())
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>> {
+        bail!("SMBus block reads are not recorded")
+    }
+
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> Result<()> {
+        bail!("SMBus block writes are not recorded")
+    }
+
+    fn smbus_process_block(&mut self, _register: u8, _values: &[u8]) -> Result<()> {
+        bail!("SMBus block calls are not recorded")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use response::Temperature;
+    use sensor::RtdSensor;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    fn recorded_session() -> Transcript {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(31.5);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(RecordingDevice::new(bus.device(0x66)));
+        assert_eq!(sensor.read_temperature().unwrap(), Temperature::Celsius(31.5));
+        sensor.into_inner().into_parts().1
+    }
+
+    #[test]
+    fn replays_a_recorded_session() {
+        let transcript = recorded_session();
+        let saved = transcript.to_string();
+        assert_eq!(Transcript::parse(&saved).unwrap(), transcript);
+
+        let mut sensor = RtdSensor::new(ReplayDevice::new(transcript));
+        assert_eq!(sensor.read_temperature().unwrap(), Temperature::Celsius(31.5));
+        assert_eq!(sensor.device().remaining(), 0);
+        assert!(sensor.read_temperature().is_err());
+    }
+
+    /// Makes the recorded transactions again, as fast as possible, and
+    /// returns how long the replay took.
+    fn replay(transcript: &Transcript, timing: ReplayTiming) -> Duration {
+        let mut dev = ReplayDevice::new(transcript.clone());
+        dev.set_timing(timing);
+        let started = Instant::now();
+        for transaction in &transcript.transactions {
+            match transaction.operation {
+                Operation::Address(address) => dev.select_address(address).unwrap(),
+                Operation::Quick(bit) => dev.smbus_write_quick(bit).unwrap(),
+                Operation::Write(ref bytes) => dev.write(bytes).unwrap(),
+                Operation::Read(ref bytes) => dev.read(&mut vec![0; bytes.len()]).unwrap(),
+            }
+        }
+        started.elapsed()
+    }
+
+    #[test]
+    fn honors_scaled_timings() {
+        let transcript = recorded_session();
+        let span = transcript.duration();
+        assert!(span >= Duration::from_millis(600));
+
+        assert!(replay(&transcript, ReplayTiming::Immediate) < span / 4);
+        assert!(replay(&transcript, ReplayTiming::Scaled(0.5)) >= span / 2);
+    }
+
+    #[test]
+    fn diverging_writes_are_errors() {
+        let transcript = Transcript::parse("# a reading\n0 W 5200\n600000 R 0132352e3000\n")
+            .unwrap();
+        let mut dev = ReplayDevice::new(transcript);
+        match *dev.write(b"I\0").unwrap_err().kind() {
+            ErrorKind::ReplayMismatch(0, _) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert!(Transcript::parse("0 X 00").is_err());
+    }
+}
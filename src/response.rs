@@ -6,6 +6,7 @@ use errors::*;
 
 /// Temperature scales supported by the EZO RTD sensor.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum TemperatureScale {
     Celsius,
     Kelvin,
@@ -13,6 +14,24 @@ pub enum TemperatureScale {
 }
 
 impl TemperatureScale {
+    /// Absolute zero in this scale.
+    pub fn absolute_zero(&self) -> f64 {
+        match *self {
+            TemperatureScale::Celsius => -273.15,
+            TemperatureScale::Kelvin => 0.0,
+            TemperatureScale::Fahrenheit => -459.67,
+        }
+    }
+
+    /// The unit symbol of the scale.
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            TemperatureScale::Celsius => "°C",
+            TemperatureScale::Kelvin => "K",
+            TemperatureScale::Fahrenheit => "°F",
+        }
+    }
+
     /// Parses the result of the "S,?" command to query temperature scale.
     pub fn parse(response: &str) -> Result<TemperatureScale> {
         match response {
@@ -24,8 +43,24 @@ impl TemperatureScale {
     }
 }
 
+/// Parses a scale as given in configuration: `C`, `K` or `F`, or
+/// `celsius`, `kelvin` or `fahrenheit`, in any case.
+impl FromStr for TemperatureScale {
+    type Err = Error;
+
+    fn from_str(scale: &str) -> Result<TemperatureScale> {
+        match scale.to_ascii_lowercase().as_str() {
+            "c" | "celsius" => Ok(TemperatureScale::Celsius),
+            "k" | "kelvin" => Ok(TemperatureScale::Kelvin),
+            "f" | "fahrenheit" => Ok(TemperatureScale::Fahrenheit),
+            _ => bail!("unknown scale `{}`; use C, K or F", scale),
+        }
+    }
+}
+
 /// Seconds between automatic logging of readings
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct DataLoggerStorageIntervalSeconds(pub u32);
 
 impl DataLoggerStorageIntervalSeconds {
@@ -43,14 +78,244 @@ impl DataLoggerStorageIntervalSeconds {
     }
 }
 
+/// Size of the calibration data, from the "Export,?" command
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExportInfo {
+    /// Strings sent by successive "Export" commands, before `*DONE`.
+    pub strings: u32,
+    /// Bytes in those strings, all together.
+    pub bytes: u32,
+}
+
+impl ExportInfo {
+    /// Parses the result of the "Export,?" command, `strings,bytes`.
+    pub fn parse(response: &str) -> Result<ExportInfo> {
+        let mut split = response.splitn(2, ',');
+        match (split.next().map(u32::from_str), split.next().map(u32::from_str)) {
+            (Some(Ok(strings)), Some(Ok(bytes))) => {
+                Ok(ExportInfo {
+                       strings: strings,
+                       bytes: bytes,
+                   })
+            }
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+/// Calibration data, as the strings sent by successive "Export" commands;
+/// it can be imported into the same chip, or another one of the same kind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct CalibrationString {
+    pub lines: Vec<String>,
+}
+
+impl CalibrationString {
+    pub fn new(lines: Vec<String>) -> CalibrationString {
+        CalibrationString { lines: lines }
+    }
+
+    /// Bytes of data, counted like the chip counts them for "Export,?".
+    pub fn len(&self) -> usize {
+        self.lines.iter().map(|line| line.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+impl From<Vec<String>> for CalibrationString {
+    fn from(lines: Vec<String>) -> CalibrationString {
+        CalibrationString::new(lines)
+    }
+}
+
+/// Location of the last reading stored by the data logger, from the "M,?"
+/// command; zero when nothing is stored
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MemoryLocation(pub u32);
+
+impl MemoryLocation {
+    /// Parses the result of the "M,?" command.
+    pub fn parse(response: &str) -> Result<MemoryLocation> {
+        if response.starts_with("?M,") {
+            let num_str = response.get(3..).unwrap();
+            let num = u32::from_str(num_str)
+                .chain_err(|| ErrorKind::ResponseParse)?;
+            Ok(MemoryLocation(num))
+        } else {
+            Err(ErrorKind::ResponseParse.into())
+        }
+    }
+}
+
+/// A reading stored by the data logger, from the "M" command
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MemoryEntry {
+    /// Where the reading is stored, counting from 1.
+    pub location: u32,
+    /// The reading, in the scale the chip was set to when it was stored.
+    pub value: f64,
+}
+
+impl MemoryEntry {
+    /// Parses the result of the "M" command, `location,value`.
+    pub fn parse(response: &str) -> Result<MemoryEntry> {
+        let mut split = response.splitn(2, ',');
+        match (split.next().map(u32::from_str), split.next().map(f64::from_str)) {
+            (Some(Ok(location)), Some(Ok(value))) => {
+                Ok(MemoryEntry {
+                       location: location,
+                       value: value,
+                   })
+            }
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+
+    /// The reading as a temperature in `scale`, which should be the scale
+    /// the chip was set to when it stored the reading.
+    pub fn temperature(&self, scale: TemperatureScale) -> Temperature {
+        Temperature::new(scale, self.value)
+    }
+}
+
+/// Seconds between readings in continuous mode, from the "C,?" command
+/// over UART; zero when continuous mode is off
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ContinuousReadingSeconds(pub u32);
+
+impl ContinuousReadingSeconds {
+    /// Parses the result of the "C,?" command.
+    pub fn parse(response: &str) -> Result<ContinuousReadingSeconds> {
+        if response.starts_with("?C,") {
+            let num_str = response.get(3..).unwrap();
+            let num = u32::from_str(num_str)
+                .chain_err(|| ErrorKind::ResponseParse)?;
+            Ok(ContinuousReadingSeconds(num))
+        } else {
+            Err(ErrorKind::ResponseParse.into())
+        }
+    }
+}
+
+/// Calibration state, from the "Cal,?" command
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CalibrationState {
+    Uncalibrated,
+    Calibrated,
+}
+
+impl CalibrationState {
+    /// Parses the result of the "Cal,?" command to query the calibration
+    /// state. Firmware revisions differ in the case of the "?Cal," prefix.
+    pub fn parse(response: &str) -> Result<CalibrationState> {
+        match response.to_lowercase().as_str() {
+            "?cal,0" => Ok(CalibrationState::Uncalibrated),
+            "?cal,1" => Ok(CalibrationState::Calibrated),
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+/// State of the LED, from the "L,?" command
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedStatus {
+    Off,
+    On,
+}
+
+impl LedStatus {
+    /// Parses the result of the "L,?" command to query the LED state.
+    pub fn parse(response: &str) -> Result<LedStatus> {
+        match response {
+            "?L,0" => Ok(LedStatus::Off),
+            "?L,1" => Ok(LedStatus::On),
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+/// State of the protocol lock, from the "Plock,?" command
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolLockStatus {
+    Unlocked,
+    Locked,
+}
+
+impl ProtocolLockStatus {
+    /// Parses the result of the "Plock,?" command to query the protocol
+    /// lock.
+    pub fn parse(response: &str) -> Result<ProtocolLockStatus> {
+        match response {
+            "?Plock,0" => Ok(ProtocolLockStatus::Unlocked),
+            "?Plock,1" => Ok(ProtocolLockStatus::Locked),
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+/// Status strings the chip sends in UART mode, which some firmware also
+/// leaves in I2C responses, separated from the data by a carriage return
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StatusToken {
+    /// "*OK": the command was understood
+    Ok,
+    /// "*ER": the command was not understood
+    Error,
+    /// "*OV": the supply voltage is too high
+    OverVoltage,
+    /// "*UV": the supply voltage is too low
+    UnderVoltage,
+    /// "*RS": the chip reset
+    Reset,
+    /// "*RE": the chip finished booting and is ready
+    Ready,
+    /// "*SL": the chip went to sleep
+    Sleep,
+    /// "*WA": the chip woke up
+    Wake,
+}
+
+impl StatusToken {
+    /// Parses a status string, or returns `None` if `response` is not one.
+    pub fn parse(response: &str) -> Option<StatusToken> {
+        match response {
+            "*OK" => Some(StatusToken::Ok),
+            "*ER" => Some(StatusToken::Error),
+            "*OV" => Some(StatusToken::OverVoltage),
+            "*UV" => Some(StatusToken::UnderVoltage),
+            "*RS" => Some(StatusToken::Reset),
+            "*RE" => Some(StatusToken::Ready),
+            "*SL" => Some(StatusToken::Sleep),
+            "*WA" => Some(StatusToken::Wake),
+            _ => None,
+        }
+    }
+}
+
 /// A temperature value from a temperature reading
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Temperature {
     Celsius(f64),
     Kelvin(f64),
     Fahrenheit(f64),
 }
 
+/// What the chip reads, in any scale, when no probe is connected.
+const NO_PROBE_READING: f64 = -1023.0;
+
 impl Temperature {
     /// Creates a new temperature value from a given temperature
     /// `scale`.  Note that this function simply copies the `value`
@@ -64,17 +329,189 @@ impl Temperature {
         }
     }
 
+    /// The scale this temperature is in.
+    pub fn scale(&self) -> TemperatureScale {
+        match *self {
+            Temperature::Celsius(_) => TemperatureScale::Celsius,
+            Temperature::Kelvin(_) => TemperatureScale::Kelvin,
+            Temperature::Fahrenheit(_) => TemperatureScale::Fahrenheit,
+        }
+    }
+
+    /// The value, in the temperature's own scale.
+    pub fn value(&self) -> f64 {
+        match *self {
+            Temperature::Celsius(value) |
+            Temperature::Kelvin(value) |
+            Temperature::Fahrenheit(value) => value,
+        }
+    }
+
+    /// Converts the temperature to another `scale`.
+    pub fn to_scale(&self, scale: TemperatureScale) -> Temperature {
+        let celsius = match *self {
+            Temperature::Celsius(value) => value,
+            Temperature::Kelvin(value) => value - 273.15,
+            Temperature::Fahrenheit(value) => (value - 32.0) * 5.0 / 9.0,
+        };
+        match scale {
+            TemperatureScale::Celsius => Temperature::Celsius(celsius),
+            TemperatureScale::Kelvin => Temperature::Kelvin(celsius + 273.15),
+            TemperatureScale::Fahrenheit => Temperature::Fahrenheit(celsius * 9.0 / 5.0 + 32.0),
+        }
+    }
+
     /// Parses the result of the "D" command to get a temperature reading.
     /// Note that this depends on knowing the temperature scale
-    /// which the device is configured to use.
+    /// which the device is configured to use: values below absolute zero
+    /// in that scale, such as a negative Kelvin reading, are refused. The
+    /// chip reads -1023 when no probe is connected, which yields
+    /// `ProbeDisconnected`.
     pub fn parse(response: &str, scale: TemperatureScale) -> Result<Temperature> {
         let val = f64::from_str(response).chain_err(|| ErrorKind::ResponseParse)?;
+        if val == NO_PROBE_READING {
+            return Err(ErrorKind::ProbeDisconnected.into());
+        }
+        if !val.is_finite() || val < scale.absolute_zero() {
+            return Err(ErrorKind::ResponseParse.into());
+        }
         Ok(Temperature::new(scale, val))
     }
 }
 
+#[cfg(feature = "uom")]
+mod quantities {
+    use uom::si::f64::ThermodynamicTemperature;
+    use uom::si::thermodynamic_temperature::{degree_celsius, degree_fahrenheit, kelvin};
+
+    use super::*;
+
+    impl Temperature {
+        /// The temperature `quantity`, in `scale`.
+        pub fn from_quantity(quantity: ThermodynamicTemperature,
+                             scale: TemperatureScale)
+                             -> Temperature {
+            Temperature::new(scale,
+                             match scale {
+                                 TemperatureScale::Celsius => quantity.get::<degree_celsius>(),
+                                 TemperatureScale::Kelvin => quantity.get::<kelvin>(),
+                                 TemperatureScale::Fahrenheit => {
+                                     quantity.get::<degree_fahrenheit>()
+                                 }
+                             })
+        }
+
+        /// The temperature as a `uom` quantity.
+        pub fn to_quantity(&self) -> ThermodynamicTemperature {
+            match *self {
+                Temperature::Celsius(value) => ThermodynamicTemperature::new::<degree_celsius>(value),
+                Temperature::Kelvin(value) => ThermodynamicTemperature::new::<kelvin>(value),
+                Temperature::Fahrenheit(value) => {
+                    ThermodynamicTemperature::new::<degree_fahrenheit>(value)
+                }
+            }
+        }
+    }
+
+    impl From<Temperature> for ThermodynamicTemperature {
+        fn from(temperature: Temperature) -> ThermodynamicTemperature {
+            temperature.to_quantity()
+        }
+    }
+
+    /// Quantities carry no scale of their own; they come out in Kelvin.
+    impl From<ThermodynamicTemperature> for Temperature {
+        fn from(quantity: ThermodynamicTemperature) -> Temperature {
+            Temperature::from_quantity(quantity, TemperatureScale::Kelvin)
+        }
+    }
+}
+
+/// How a firmware revision formats its responses
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResponseFormat {
+    /// Firmware 1.x, which labels the "Status" response "?STATUS,"
+    Legacy,
+    /// Firmware 2.0 and later, as described by the current datasheet
+    Current,
+}
+
+impl ResponseFormat {
+    /// The format used by the firmware in `info`. Unrecognized versions are
+    /// taken to be current.
+    pub fn for_firmware(info: &DeviceInfo) -> ResponseFormat {
+        match info.firmware_major() {
+            Some(major) if major < 2 => ResponseFormat::Legacy,
+            _ => ResponseFormat::Current,
+        }
+    }
+}
+
+/// Device type and firmware version, from the "I" command
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceInfo {
+    pub device_type: String,
+    pub firmware: String,
+}
+
+impl DeviceInfo {
+    /// The major version of the firmware, e.g. 2 for "2.01".
+    pub fn firmware_major(&self) -> Option<u32> {
+        self.firmware.split('.').next().and_then(|major| u32::from_str(major).ok())
+    }
+
+    /// Parses the result of the "I" command to get the device information.
+    pub fn parse(response: &str) -> Result<DeviceInfo> {
+        if response.starts_with("?I,") || response.starts_with("?i,") {
+            let rest = response.get(3..).unwrap();
+            let mut split = rest.split(',');
+
+            let device_type = match split.next() {
+                Some(device_type) if !device_type.is_empty() => device_type,
+                _ => return Err(ErrorKind::ResponseParse.into()),
+            };
+
+            let firmware = match split.next() {
+                Some(firmware) if !firmware.is_empty() => firmware,
+                _ => return Err(ErrorKind::ResponseParse.into()),
+            };
+
+            if let Some(_) = split.next() {
+                return Err(ErrorKind::ResponseParse.into());
+            }
+
+            Ok(DeviceInfo {
+                   device_type: device_type.to_string(),
+                   firmware: firmware.to_string(),
+               })
+        } else {
+            Err(ErrorKind::ResponseParse.into())
+        }
+    }
+}
+
+/// Name given to the device, from the "Name,?" command
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceName(pub String);
+
+impl DeviceName {
+    /// Parses the result of the "Name,?" command. An unnamed device yields
+    /// an empty name.
+    pub fn parse(response: &str) -> Result<DeviceName> {
+        if response.starts_with("?Name,") || response.starts_with("?NAME,") {
+            Ok(DeviceName(response.get(6..).unwrap().to_string()))
+        } else {
+            Err(ErrorKind::ResponseParse.into())
+        }
+    }
+}
+
 /// Reason for which the device restarted, data sheet pp. 58
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RestartReason {
     PoweredOff,
     SoftwareReset,
@@ -83,8 +520,22 @@ pub enum RestartReason {
     Unknown,
 }
 
+impl RestartReason {
+    /// A short description of the reason, for people.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            RestartReason::PoweredOff => "powered off",
+            RestartReason::SoftwareReset => "software reset",
+            RestartReason::BrownOut => "brown out",
+            RestartReason::Watchdog => "watchdog",
+            RestartReason::Unknown => "unknown",
+        }
+    }
+}
+
 /// Response from the "Status" command to get the device status
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct DeviceStatus {
     pub restart_reason: RestartReason,
     pub vcc_voltage: f64,
@@ -93,44 +544,98 @@ pub struct DeviceStatus {
 impl DeviceStatus {
     /// Parses the result of the "Status" command to get the device's status.
     pub fn parse(response: &str) -> Result<DeviceStatus> {
-        if response.starts_with("?Status,") {
-            let rest = response.get(8..).unwrap();
-            let mut split = rest.split(',');
-
-            let restart_reason = match split.next() {
-                Some("P") => RestartReason::PoweredOff,
-                Some("S") => RestartReason::SoftwareReset,
-                Some("B") => RestartReason::BrownOut,
-                Some("W") => RestartReason::Watchdog,
-                Some("U") => RestartReason::Unknown,
-                _ => return Err(ErrorKind::ResponseParse.into()),
-            };
-
-            let voltage = if let Some(voltage_str) = split.next() {
-                f64::from_str(voltage_str)
-                    .chain_err(|| ErrorKind::ResponseParse)?
-            } else {
-                return Err(ErrorKind::ResponseParse.into());
-            };
+        DeviceStatus::parse_with_format(response, ResponseFormat::Current)
+    }
 
-            if let Some(_) = split.next() {
-                return Err(ErrorKind::ResponseParse.into());
+    /// Parses the result of the "Status" command as formatted by firmware
+    /// with the given response `format`. Only the label differs; the restart
+    /// reason always comes before the supply voltage.
+    pub fn parse_with_format(response: &str, format: ResponseFormat) -> Result<DeviceStatus> {
+        let labelled = match format {
+            ResponseFormat::Current => response.starts_with("?Status,"),
+            ResponseFormat::Legacy => {
+                response
+                    .get(..8)
+                    .map_or(false, |label| label.eq_ignore_ascii_case("?Status,"))
             }
-
-            Ok(DeviceStatus {
-                   restart_reason: restart_reason,
-                   vcc_voltage: voltage,
-               })
+        };
+        if !labelled {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let rest = response.get(8..).unwrap();
+        let mut split = rest.split(',');
+
+        let restart_reason = match split.next() {
+            Some("P") => RestartReason::PoweredOff,
+            Some("S") => RestartReason::SoftwareReset,
+            Some("B") => RestartReason::BrownOut,
+            Some("W") => RestartReason::Watchdog,
+            Some("U") => RestartReason::Unknown,
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+
+        let voltage = if let Some(voltage_str) = split.next() {
+            f64::from_str(voltage_str)
+                .chain_err(|| ErrorKind::ResponseParse)?
         } else {
-            Err(ErrorKind::ResponseParse.into())
+            return Err(ErrorKind::ResponseParse.into());
+        };
+
+        if let Some(_) = split.next() {
+            return Err(ErrorKind::ResponseParse.into());
         }
+
+        Ok(DeviceStatus {
+               restart_reason: restart_reason,
+               vcc_voltage: voltage,
+           })
     }
 }
 
+/// The parsed response to a command, by the kind of response the command
+/// expects; see `parse::response`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Response {
+    Ack,
+    CalibrationState(CalibrationState),
+    DataloggerInterval(DataLoggerStorageIntervalSeconds),
+    DeviceInformation(DeviceInfo),
+    ExportInfo(ExportInfo),
+    /// One calibration string of `Export`, or `*DONE` after the last one.
+    Export(String),
+    LedState(LedStatus),
+    MemoryRecall(MemoryEntry),
+    MemoryRecallLastLocation(MemoryLocation),
+    NameState(DeviceName),
+    ProtocolLockState(ProtocolLockStatus),
+    Reading(Temperature),
+    ScaleState(TemperatureScale),
+    Status(DeviceStatus),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(feature = "uom")]
+    #[test]
+    fn converts_temperatures_to_and_from_quantities() {
+        use uom::si::f64::ThermodynamicTemperature;
+        use uom::si::thermodynamic_temperature::{degree_celsius, kelvin};
+
+        let quantity: ThermodynamicTemperature = Temperature::Fahrenheit(212.0).into();
+        assert!((quantity.get::<degree_celsius>() - 100.0).abs() < 1e-9);
+        assert!((quantity.get::<kelvin>() - 373.15).abs() < 1e-9);
+
+        let quantity = ThermodynamicTemperature::new::<degree_celsius>(-40.0);
+        match Temperature::from_quantity(quantity, TemperatureScale::Fahrenheit) {
+            Temperature::Fahrenheit(value) => assert!((value + 40.0).abs() < 1e-9),
+            other => panic!("unexpected temperature {:?}", other),
+        }
+        assert_eq!(Temperature::from(quantity).scale(), TemperatureScale::Kelvin);
+    }
+
     #[test]
     fn parses_temperature_scale() {
         let response = "?S,c";
@@ -155,6 +660,15 @@ mod tests {
         assert!(TemperatureScale::parse(&response).is_err());
     }
 
+    #[test]
+    fn parses_configured_scales() {
+        assert_eq!("c".parse::<TemperatureScale>().unwrap(), TemperatureScale::Celsius);
+        assert_eq!("Kelvin".parse::<TemperatureScale>().unwrap(), TemperatureScale::Kelvin);
+        assert_eq!("F".parse::<TemperatureScale>().unwrap(), TemperatureScale::Fahrenheit);
+        assert!("R".parse::<TemperatureScale>().is_err());
+        assert!("".parse::<TemperatureScale>().is_err());
+    }
+
     #[test]
     fn parses_data_logger_storage_interval() {
         let response = "?D,1";
@@ -166,6 +680,16 @@ mod tests {
                    DataLoggerStorageIntervalSeconds(42));
     }
 
+    #[test]
+    fn parses_continuous_reading_seconds() {
+        assert_eq!(ContinuousReadingSeconds::parse("?C,5").unwrap(),
+                   ContinuousReadingSeconds(5));
+        assert_eq!(ContinuousReadingSeconds::parse("?C,0").unwrap(),
+                   ContinuousReadingSeconds(0));
+        assert!(ContinuousReadingSeconds::parse("?C,").is_err());
+        assert!(ContinuousReadingSeconds::parse("?D,5").is_err());
+    }
+
     #[test]
     fn parsing_invalid_data_logger_storage_interval_yields_error() {
         let response = "?D,";
@@ -178,6 +702,50 @@ mod tests {
         assert!(DataLoggerStorageIntervalSeconds::parse(response).is_err());
     }
 
+    #[test]
+    fn parses_calibration_state() {
+        let response = "?Cal,0";
+        assert_eq!(CalibrationState::parse(response).unwrap(),
+                   CalibrationState::Uncalibrated);
+
+        let response = "?CAL,1";
+        assert_eq!(CalibrationState::parse(response).unwrap(),
+                   CalibrationState::Calibrated);
+    }
+
+    #[test]
+    fn parsing_invalid_calibration_state_yields_error() {
+        let response = "";
+        assert!(CalibrationState::parse(response).is_err());
+
+        let response = "?Cal,2";
+        assert!(CalibrationState::parse(response).is_err());
+    }
+
+    #[test]
+    fn parses_led_status() {
+        assert_eq!(LedStatus::parse("?L,0").unwrap(), LedStatus::Off);
+        assert_eq!(LedStatus::parse("?L,1").unwrap(), LedStatus::On);
+        assert!(LedStatus::parse("?L,").is_err());
+    }
+
+    #[test]
+    fn parses_protocol_lock_status() {
+        assert_eq!(ProtocolLockStatus::parse("?Plock,0").unwrap(),
+                   ProtocolLockStatus::Unlocked);
+        assert_eq!(ProtocolLockStatus::parse("?Plock,1").unwrap(),
+                   ProtocolLockStatus::Locked);
+        assert!(ProtocolLockStatus::parse("?Plock,").is_err());
+    }
+
+    #[test]
+    fn parses_status_tokens() {
+        assert_eq!(StatusToken::parse("*OK"), Some(StatusToken::Ok));
+        assert_eq!(StatusToken::parse("*WA"), Some(StatusToken::Wake));
+        assert_eq!(StatusToken::parse("*XX"), None);
+        assert_eq!(StatusToken::parse("?L,1"), None);
+    }
+
     #[test]
     fn parses_temperature() {
         let response = "0";
@@ -193,6 +761,58 @@ mod tests {
                    Temperature::Fahrenheit(-10.5));
     }
 
+    #[test]
+    fn parses_temperature_in_kelvin() {
+        let response = "310.150";
+        assert_eq!(Temperature::parse(response, TemperatureScale::Kelvin).unwrap(),
+                   Temperature::Kelvin(310.15));
+
+        let response = "0.000";
+        assert_eq!(Temperature::parse(response, TemperatureScale::Kelvin).unwrap(),
+                   Temperature::Kelvin(0.0));
+
+        let response = "-12.500";
+        assert!(Temperature::parse(response, TemperatureScale::Kelvin).is_err());
+        assert!(Temperature::parse(response, TemperatureScale::Celsius).is_ok());
+    }
+
+    #[test]
+    fn parsing_non_numeric_temperature_yields_error() {
+        for response in &["", "inf", "NaN", "-300", "?S,k"] {
+            assert!(Temperature::parse(response, TemperatureScale::Celsius).is_err(),
+                    "{:?} was accepted",
+                    response);
+        }
+    }
+
+    #[test]
+    fn missing_probe_is_told_apart_from_garbled_readings() {
+        for scale in &[TemperatureScale::Celsius, TemperatureScale::Kelvin] {
+            match *Temperature::parse("-1023.000", *scale).unwrap_err().kind() {
+                ErrorKind::ProbeDisconnected => {}
+                ref other => panic!("unexpected error {:?}", other),
+            }
+        }
+        match *Temperature::parse("-1023.5", TemperatureScale::Celsius)
+                   .unwrap_err()
+                   .kind() {
+            ErrorKind::ResponseParse => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn converts_temperature_between_scales() {
+        let boiling = Temperature::Celsius(100.0);
+        assert_eq!(boiling.to_scale(TemperatureScale::Fahrenheit),
+                   Temperature::Fahrenheit(212.0));
+        assert_eq!(boiling.to_scale(TemperatureScale::Kelvin),
+                   Temperature::Kelvin(373.15));
+        assert_eq!(Temperature::Fahrenheit(-40.0).to_scale(TemperatureScale::Celsius),
+                   Temperature::Celsius(-40.0));
+        assert_eq!(Temperature::Kelvin(0.0).scale(), TemperatureScale::Kelvin);
+    }
+
     #[test]
     fn parsing_invalid_temperature_yields_error() {
         let response = "";
@@ -202,6 +822,90 @@ mod tests {
         assert!(Temperature::parse(response, TemperatureScale::Celsius).is_err());
     }
 
+    #[test]
+    fn parses_device_info() {
+        let response = "?I,RTD,2.01";
+        assert_eq!(DeviceInfo::parse(response).unwrap(),
+                   DeviceInfo {
+                       device_type: "RTD".to_string(),
+                       firmware: "2.01".to_string(),
+                   });
+    }
+
+    #[test]
+    fn parses_memory_responses() {
+        assert_eq!(MemoryLocation::parse("?M,42").unwrap(), MemoryLocation(42));
+        assert!(MemoryLocation::parse("?M,").is_err());
+        assert_eq!(MemoryEntry::parse("7,25.104").unwrap(),
+                   MemoryEntry {
+                       location: 7,
+                       value: 25.104,
+                   });
+        assert!(MemoryEntry::parse("25.104").is_err());
+        assert!(MemoryEntry::parse("*DONE").is_err());
+    }
+
+    #[test]
+    fn parses_export_info() {
+        assert_eq!(ExportInfo::parse("10,120").unwrap(),
+                   ExportInfo {
+                       strings: 10,
+                       bytes: 120,
+                   });
+        assert!(ExportInfo::parse("10").is_err());
+        assert!(ExportInfo::parse("*DONE").is_err());
+
+        let calibration = CalibrationString::new(vec!["6E6F74".to_string(), "2063".to_string()]);
+        assert_eq!(calibration.len(), 10);
+    }
+
+    #[test]
+    fn parsing_invalid_device_info_yields_error() {
+        let response = "";
+        assert!(DeviceInfo::parse(response).is_err());
+
+        let response = "?I,RTD";
+        assert!(DeviceInfo::parse(response).is_err());
+
+        let response = "?I,RTD,2.01,";
+        assert!(DeviceInfo::parse(response).is_err());
+    }
+
+    #[test]
+    fn parses_device_name() {
+        let response = "?Name,sump";
+        assert_eq!(DeviceName::parse(response).unwrap(),
+                   DeviceName("sump".to_string()));
+
+        let response = "?Name,";
+        assert_eq!(DeviceName::parse(response).unwrap(),
+                   DeviceName(String::new()));
+    }
+
+    #[test]
+    fn parsing_invalid_device_name_yields_error() {
+        let response = "Name,sump";
+        assert!(DeviceName::parse(response).is_err());
+    }
+
+    #[test]
+    fn parses_legacy_responses() {
+        let info = DeviceInfo::parse("?I,RTD,1.02").unwrap();
+        assert_eq!(info.device_type, "RTD");
+        assert_eq!(ResponseFormat::for_firmware(&info), ResponseFormat::Legacy);
+
+        let response = "?STATUS,B,4.980";
+        assert!(DeviceStatus::parse(response).is_err());
+        assert_eq!(DeviceStatus::parse_with_format(response, ResponseFormat::Legacy).unwrap(),
+                   DeviceStatus {
+                       restart_reason: RestartReason::BrownOut,
+                       vcc_voltage: 4.98,
+                   });
+
+        let info = DeviceInfo::parse("?I,RTD,2.01").unwrap();
+        assert_eq!(ResponseFormat::for_firmware(&info), ResponseFormat::Current);
+    }
+
     #[test]
     fn parses_device_status() {
         let response = "?Status,P,1.5";
@@ -0,0 +1,153 @@
+//! Keeping track of the chip's restarts over time.
+//!
+//! `Status` only gives the reason for the chip's last restart. A sensor
+//! passes every status it reads to its `RestartHistory`, which keeps a
+//! record of each restart it notices, with the time it noticed it, so that
+//! a chronic power problem shows up as a count of brown-outs, rather than
+//! as a calibration that keeps drifting.
+//!
+//! A restart is noticed when the reason changes from one status to the
+//! next, or when the chip was seen rebooting in between: from a `*RS` or
+//! `*RE` status string, or after a calibration import. Two restarts for
+//! the same reason between statuses, without status strings enabled, count
+//! as one. The first status read only sets the baseline, since the restart
+//! it reports may be arbitrarily old.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+use response::RestartReason;
+
+/// Restarts kept; older ones are dropped.
+pub const MAX_RESTARTS: usize = 256;
+
+/// A restart, and when it was noticed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RestartRecord {
+    pub time: SystemTime,
+    pub reason: RestartReason,
+}
+
+/// The restarts of one chip.
+#[derive(Debug, Clone, Default)]
+pub struct RestartHistory {
+    records: VecDeque<RestartRecord>,
+    /// The reason given by the last status read.
+    last_reason: Option<RestartReason>,
+    /// Whether the chip was seen rebooting since then.
+    rebooted: bool,
+}
+
+impl RestartHistory {
+    pub fn new() -> RestartHistory {
+        RestartHistory::default()
+    }
+
+    /// Takes the restart reason of a status read at `time`. Returns the
+    /// restart noticed, if any.
+    pub fn observe(&mut self, reason: RestartReason, time: SystemTime) -> Option<RestartRecord> {
+        let restarted = match self.last_reason {
+            Some(last) => last != reason || self.rebooted,
+            None => false,
+        };
+        self.last_reason = Some(reason);
+        self.rebooted = false;
+        if !restarted {
+            return None;
+        }
+        let record = RestartRecord {
+            time: time,
+            reason: reason,
+        };
+        if self.records.len() == MAX_RESTARTS {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+        Some(record)
+    }
+
+    /// Notes that the chip rebooted, so that the next status counts as a
+    /// restart even if its reason is the same as the last one.
+    pub fn note_reboot(&mut self) {
+        if self.last_reason.is_some() {
+            self.rebooted = true;
+        }
+    }
+
+    /// The restarts noticed, oldest first.
+    pub fn records(&self) -> Vec<RestartRecord> {
+        self.records.iter().cloned().collect()
+    }
+
+    /// Restarts for `reason` noticed from `since` on.
+    pub fn count_since(&self, reason: RestartReason, since: SystemTime) -> usize {
+        self.records
+            .iter()
+            .filter(|record| record.reason == reason && record.time >= since)
+            .count()
+    }
+
+    /// Restarts for `reason` noticed within `window` of now.
+    pub fn count_within(&self, reason: RestartReason, window: Duration) -> usize {
+        match SystemTime::now().checked_sub(window) {
+            Some(since) => self.count_since(reason, since),
+            None => self.records.iter().filter(|record| record.reason == reason).count(),
+        }
+    }
+
+    /// Brown-outs noticed in the last 24 hours.
+    pub fn brownouts_last_24h(&self) -> usize {
+        self.count_within(RestartReason::BrownOut, Duration::from_secs(24 * 3600))
+    }
+
+    /// Watchdog restarts noticed in the last 24 hours.
+    pub fn watchdog_resets_last_24h(&self) -> usize {
+        self.count_within(RestartReason::Watchdog, Duration::from_secs(24 * 3600))
+    }
+
+    /// Forgets every restart, and the baseline.
+    pub fn clear(&mut self) {
+        *self = RestartHistory::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::RetryPolicy;
+    use sensor::RtdSensor;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn counts_restarts_between_statuses() {
+        let mut history = RestartHistory::new();
+        let now = SystemTime::now();
+        let day_ago = now - Duration::from_secs(25 * 3600);
+
+        assert_eq!(history.observe(RestartReason::PoweredOff, day_ago), None);
+        assert_eq!(history.observe(RestartReason::PoweredOff, day_ago), None);
+        assert!(history.observe(RestartReason::BrownOut, day_ago).is_some());
+        assert!(history.observe(RestartReason::PoweredOff, now).is_some());
+        history.note_reboot();
+        assert!(history.observe(RestartReason::PoweredOff, now).is_some());
+        assert!(history.observe(RestartReason::BrownOut, now).is_some());
+
+        assert_eq!(history.records().len(), 4);
+        assert_eq!(history.brownouts_last_24h(), 1);
+        assert_eq!(history.count_since(RestartReason::BrownOut, day_ago), 2);
+    }
+
+    #[test]
+    fn sensors_track_the_restarts_of_their_chip() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy::no_wait());
+
+        sensor.get_status().unwrap();
+        bus.with_chip(chip, |chip| { chip.set_restart_reason('B'); });
+        sensor.get_status().unwrap();
+        sensor.get_status().unwrap();
+        assert_eq!(sensor.restarts().brownouts_last_24h(), 1);
+    }
+}
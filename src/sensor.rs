@@ -0,0 +1,1645 @@
+//! A high-level handle that owns the I2C device and hides the command
+//! plumbing.
+
+use std::fmt::Write;
+use std::mem;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant, SystemTime};
+
+use buffer::MAX_RESPONSE;
+use common::{self, RetryPolicy};
+use correction::Correction;
+use debug::{DebugBundle, RecentCommands};
+use discovery::{self, Addressable};
+use errors::*;
+use events::{DeviceEvent, Subscribers};
+use ezo_common::{ResponseCode, response_code};
+use hooks::{self, CommandEvent, CommandHook};
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::LinuxI2CDevice;
+use parse;
+use restarts::RestartHistory;
+use session::Session;
+use stats::TransactionStats;
+use response::{CalibrationState, CalibrationString, DataLoggerStorageIntervalSeconds,
+               DeviceInfo, DeviceName, DeviceStatus, ExportInfo, LedStatus, ProtocolLockStatus,
+               ResponseFormat, StatusToken, Temperature, TemperatureScale};
+use stretch::ClockStretchProfile;
+use timing::{self, Delays};
+use uncertainty::{UncertainReading, UncertaintyModel};
+use {CommandBuilder, CommandOptions, I2cCommand, TemperatureCommand};
+
+/// Upper bound on the number of `Export` strings read for one calibration.
+const MAX_EXPORT_LINES: usize = 64;
+
+/// Longest payload the chip takes in one `Import,n` command.
+pub const MAX_IMPORT_CHUNK: usize = 12;
+
+/// Time the chip takes to reboot after the last `Import` command, in
+/// milliseconds.
+pub const IMPORT_REBOOT_DELAY: u64 = 1000;
+
+/// Time the chip takes to reboot at its new address after an `I2C,n`
+/// command, in milliseconds.
+pub const ADDRESS_REBOOT_DELAY: u64 = 1000;
+
+/// Times the chip is asked for its status after a reboot, `WAKE_SETTLE_MS`
+/// apart, before giving up on it coming back.
+const REBOOT_POLLS: u32 = 5;
+
+/// Splits calibration data, as given by `export_calibration`, into the
+/// payloads of successive `Import` commands: lines longer than
+/// `MAX_IMPORT_CHUNK` are cut into several. Fails on data the chip cannot
+/// take: anything but printable ASCII without spaces or commas.
+pub fn import_chunks<S: AsRef<str>>(lines: &[S]) -> Result<Vec<String>> {
+    let mut chunks = Vec::new();
+    for line in lines {
+        let line = line.as_ref();
+        if line.is_empty() ||
+           !line.bytes().all(|b| (b'!'..=b'~').contains(&b) && b != b',') {
+            return Err(ErrorKind::InvalidCommand("calibration data the chip cannot import")
+                           .into());
+        }
+        let bytes = line.as_bytes();
+        for chunk in bytes.chunks(MAX_IMPORT_CHUNK) {
+            chunks.push(String::from_utf8_lossy(chunk).into_owned());
+        }
+    }
+    Ok(chunks)
+}
+
+/// Lowest temperature the PT-1000 probe can be calibrated at, in °C.
+pub const MIN_CALIBRATION_CELSIUS: f64 = -126.0;
+
+/// Highest temperature the PT-1000 probe can be calibrated at, in °C.
+pub const MAX_CALIBRATION_CELSIUS: f64 = 1254.0;
+
+/// Oldest firmware major version that answers `Name,?`.
+const NAMING_FIRMWARE: u32 = 2;
+
+/// The chip's settings and state, from `RtdSensor::snapshot`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub info: DeviceInfo,
+    pub status: DeviceStatus,
+    pub scale: TemperatureScale,
+    pub calibration: CalibrationState,
+    pub datalogger_interval: DataLoggerStorageIntervalSeconds,
+    pub led: LedStatus,
+    /// `None` if the firmware does not support naming; empty if the chip
+    /// has no name.
+    pub name: Option<String>,
+}
+
+/// An RTD EZO chip on an I2C bus.
+pub struct RtdSensor<D: I2CDevice> {
+    dev: D,
+    /// Temperature scale last reported by or set on the chip.
+    scale: Option<TemperatureScale>,
+    /// Response format of the chip's firmware, once known.
+    format: Option<ResponseFormat>,
+    /// How writes are retried, and how waits are made.
+    retry: RetryPolicy,
+    read_retry: RetryPolicy,
+    delays: Delays,
+    /// Extra wait after each write.
+    settle: Duration,
+    /// Least time between the end of a command and the next write.
+    min_gap: Duration,
+    /// When the last command ended.
+    last_end: Option<Instant>,
+    /// When the operation run by `with_budget` has to be over, and its
+    /// budget.
+    deadline: Option<(Instant, Duration)>,
+    /// Response code byte of the last response.
+    last_code: Option<u8>,
+    /// Slave address of the chip, when the handle knows it.
+    address: Option<u16>,
+    /// The last temperature read, corrected.
+    last_reading: Option<Temperature>,
+    /// Correction applied to readings in software.
+    correction: Option<Correction>,
+    /// What uncertainties are estimated from, fed with every reading.
+    uncertainty: Option<UncertaintyModel>,
+    /// Whether settings are queried back after being changed.
+    verify: bool,
+    /// Whether the last command put the chip to sleep.
+    asleep: bool,
+    /// Responses are read into this buffer, which is reused for every
+    /// command.
+    buf: [u8; MAX_RESPONSE],
+    stats: TransactionStats,
+    /// The command written by `begin` and when, until `collect` reads its
+    /// response.
+    started: Option<(&'static str, Instant)>,
+    hooks: Vec<Box<dyn CommandHook + Send>>,
+    events: Subscribers,
+    /// The event for the command written by `begin`, emitted once the chip
+    /// has taken it.
+    pending_event: Option<DeviceEvent>,
+    /// Commands that failed since the last one that went through.
+    failures: u32,
+    recent: RecentCommands,
+    restarts: RestartHistory,
+}
+
+impl RtdSensor<LinuxI2CDevice> {
+    /// Opens the chip at `address` on `/dev/i2c-<bus>`.
+    pub fn open(bus: u8, address: u16) -> Result<RtdSensor<LinuxI2CDevice>> {
+        let device_path = format!("/dev/i2c-{}", bus);
+        let dev = LinuxI2CDevice::new(&device_path, address)
+            .chain_err(|| format!("Could not open I2C device {}", device_path))?;
+        let mut sensor = RtdSensor::new(dev);
+        sensor.address = Some(address);
+        Ok(sensor)
+    }
+}
+
+impl<D: I2CDevice> RtdSensor<D> {
+    /// Wraps an already opened I2C device.
+    pub fn new(dev: D) -> RtdSensor<D> {
+        RtdSensor {
+            dev: dev,
+            scale: None,
+            format: None,
+            retry: RetryPolicy::default(),
+            read_retry: RetryPolicy::default(),
+            delays: Delays::default(),
+            settle: Duration::from_millis(0),
+            min_gap: Duration::from_millis(0),
+            last_end: None,
+            deadline: None,
+            last_code: None,
+            address: None,
+            last_reading: None,
+            correction: None,
+            uncertainty: None,
+            verify: false,
+            asleep: false,
+            buf: [0; MAX_RESPONSE],
+            stats: TransactionStats::new(),
+            started: None,
+            hooks: Vec::new(),
+            events: Subscribers::new(),
+            pending_event: None,
+            failures: 0,
+            recent: RecentCommands::new(),
+            restarts: RestartHistory::new(),
+        }
+    }
+
+    /// Sets how failed writes and reads are retried for every command.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+        self.read_retry = retry;
+    }
+
+    /// How failed writes are retried. Its wait strategy also makes the
+    /// processing waits, and the waits between commands.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Sets how failed writes are retried, leaving reads alone. Writes fail
+    /// when the chip does not ACK, because it is asleep, at another
+    /// address, or the bus is disturbed.
+    pub fn set_write_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Sets how failed reads are retried, leaving writes alone. Reads fail
+    /// mostly when the chip stretches the clock while still processing.
+    pub fn set_read_retry_policy(&mut self, retry: RetryPolicy) {
+        self.read_retry = retry;
+    }
+
+    pub fn read_retry_policy(&self) -> RetryPolicy {
+        self.read_retry
+    }
+
+    /// Talks to the chip with the workarounds in `profile`: its retry
+    /// policy replaces the current ones, and each write is followed by its
+    /// settle time.
+    pub fn set_clock_stretch_profile(&mut self, profile: &ClockStretchProfile) {
+        self.set_retry_policy(profile.retry);
+        self.settle = profile.post_write_settle;
+    }
+
+    /// Keeps at least `gap` between the end of a command and the next
+    /// write to the chip, on top of the processing delays; for callers
+    /// that interleave queries with readings quickly enough to get garbage
+    /// back. Zero, the default, keeps no gap.
+    pub fn set_min_gap(&mut self, gap: Duration) {
+        self.min_gap = gap;
+    }
+
+    pub fn min_gap(&self) -> Duration {
+        self.min_gap
+    }
+
+    /// Runs `operation`, such as `snapshot` or `export_calibration`, within
+    /// `budget`: once a command could not be over before the budget runs
+    /// out, it is not written, and fails with `BudgetExhausted`. The retries
+    /// and pending polls of the commands that are written are cut short at
+    /// the end of the budget, and a command whose write or read is still
+    /// failing then fails with `BudgetExhausted` too. Budgets nest; the
+    /// inner one cannot outlast the outer one.
+    pub fn with_budget<T, F>(&mut self, budget: Duration, operation: F) -> Result<T>
+        where F: FnOnce(&mut RtdSensor<D>) -> Result<T>
+    {
+        let outer = self.deadline;
+        let deadline = Instant::now() + budget;
+        self.deadline = match outer {
+            Some((end, _)) if end < deadline => outer,
+            _ => Some((deadline, budget)),
+        };
+        let result = operation(self);
+        self.deadline = outer;
+        result
+    }
+
+    /// Fails with `BudgetExhausted` if waiting for `wait` would go past the
+    /// deadline.
+    fn check_budget(&self, wait: Duration) -> Result<()> {
+        match self.deadline {
+            Some((end, budget)) if Instant::now() + wait > end => {
+                Err(ErrorKind::BudgetExhausted(budget.as_millis() as u64).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Turns `e` into `BudgetExhausted` if the deadline has passed, as when
+    /// retrying was cut short by it.
+    fn budget_error(&self, e: Error) -> Error {
+        match self.deadline {
+            Some((end, budget)) if Instant::now() >= end => {
+                Error::with_chain(e, ErrorKind::BudgetExhausted(budget.as_millis() as u64))
+            }
+            _ => e,
+        }
+    }
+
+    /// Makes the setters query each setting back after changing it, and
+    /// fail with `VerificationFailed` if the chip did not apply it.
+    pub fn set_verify_settings(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// The slave address of the chip, if the handle was opened at a known
+    /// address, or told it with `set_known_address`.
+    pub fn address(&self) -> Option<u16> {
+        self.address
+    }
+
+    /// Records the slave address `dev` talks to, for reports.
+    pub fn set_known_address(&mut self, address: u16) {
+        self.address = Some(address);
+    }
+
+    /// Applies `correction` to every reading from now on, or no correction
+    /// for `None`. The chip's own calibration is left as it is.
+    pub fn set_correction(&mut self, correction: Option<Correction>) {
+        self.correction = correction;
+    }
+
+    /// The correction applied to readings, if any.
+    pub fn correction(&self) -> Option<Correction> {
+        self.correction
+    }
+
+    /// Applies the correction, if any, and remembers the reading.
+    fn corrected(&mut self, temperature: Temperature) -> Temperature {
+        let temperature = match self.correction {
+            Some(ref correction) => correction.apply(temperature),
+            None => temperature,
+        };
+        self.last_reading = Some(temperature);
+        if let Some(ref mut model) = self.uncertainty {
+            model.observe(temperature);
+        }
+        temperature
+    }
+
+    /// Estimates the uncertainty of readings with `model`, or stops for
+    /// `None`. Every reading from now on feeds the model's noise estimate.
+    pub fn set_uncertainty_model(&mut self, model: Option<UncertaintyModel>) {
+        self.uncertainty = model;
+    }
+
+    /// Takes a reading, like `read_temperature`, with its estimated
+    /// uncertainty. Fails if no uncertainty model is set.
+    pub fn read_with_uncertainty(&mut self) -> Result<UncertainReading> {
+        if self.uncertainty.is_none() {
+            bail!("no uncertainty model is set");
+        }
+        let temperature = self.read_temperature()?;
+        match self.uncertainty {
+            Some(ref model) => Ok(model.estimate(temperature, SystemTime::now())),
+            None => bail!("no uncertainty model is set"),
+        }
+    }
+
+    /// The last temperature read by this handle, corrected.
+    pub fn last_reading(&self) -> Option<Temperature> {
+        self.last_reading
+    }
+
+    /// The processing delays waited out after each command.
+    pub fn delays(&self) -> Delays {
+        self.delays
+    }
+
+    /// Replaces the datasheet processing delays, typically with the ones
+    /// suggested by `tune_delays`.
+    pub fn set_delays(&mut self, delays: Delays) {
+        self.delays = delays;
+    }
+
+    /// Measures how long this chip takes to answer, `samples` times per kind
+    /// of command, and suggests delays with `margin_percent` added. The
+    /// suggestion is not applied; see `set_delays`.
+    pub fn tune_delays(&mut self, samples: u32, margin_percent: u64) -> Result<Delays> {
+        let delays = timing::tune(&mut self.dev, samples, margin_percent)?;
+        self.asleep = false;
+        Ok(delays)
+    }
+
+    /// The response code byte of the last response, exactly as read;
+    /// `None` until a command with a response has been run.
+    pub fn last_response_code(&self) -> Option<u8> {
+        self.last_code
+    }
+
+    /// Whether the chip was put to sleep through this handle and has not
+    /// been sent a command since.
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    /// Response codes, retries and latencies of the transactions made
+    /// through this handle.
+    pub fn stats(&self) -> &TransactionStats {
+        &self.stats
+    }
+
+    /// Clears the transaction statistics.
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    /// The restarts of the chip noticed by this handle; see the `restarts`
+    /// module.
+    pub fn restarts(&self) -> &RestartHistory {
+        &self.restarts
+    }
+
+    /// Forgets the restarts noticed so far.
+    pub fn reset_restarts(&mut self) {
+        self.restarts.clear();
+    }
+
+    /// The last commands run through this handle, and the last failed
+    /// ones.
+    pub fn recent_commands(&self) -> &RecentCommands {
+        &self.recent
+    }
+
+    /// Runs `hook` around every command from now on, after the hooks
+    /// already added.
+    pub fn add_hook<H: CommandHook + Send + 'static>(&mut self, hook: H) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Removes every hook.
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    /// Passes every `DeviceEvent` to `callback` from now on.
+    pub fn subscribe<F: FnMut(&DeviceEvent) + Send + 'static>(&mut self, callback: F) {
+        self.events.subscribe(callback);
+    }
+
+    /// Sends every `DeviceEvent` to the returned channel from now on.
+    pub fn subscribe_channel(&mut self) -> Receiver<DeviceEvent> {
+        self.events.channel()
+    }
+
+    /// Removes every event subscriber.
+    pub fn unsubscribe_all(&mut self) {
+        self.events.clear();
+    }
+
+    /// The underlying I2C device.
+    pub fn device(&mut self) -> &mut D {
+        &mut self.dev
+    }
+
+    /// Gives back the underlying I2C device.
+    pub fn into_inner(self) -> D {
+        self.dev
+    }
+
+    /// Talks to the chip through `dev` from now on, as after reopening the
+    /// bus, and gives back the old device. What the handle knew about the
+    /// chip's state is forgotten; its settings are kept.
+    pub fn replace_device(&mut self, dev: D) -> D {
+        self.scale = None;
+        self.format = None;
+        self.asleep = false;
+        mem::replace(&mut self.dev, dev)
+    }
+
+    /// Wakes the chip up, whether or not it was put to sleep through this
+    /// handle.
+    pub fn wake(&mut self) {
+        common::wake(&mut self.dev, &self.retry.wait_strategy);
+        self.asleep = false;
+        self.events.emit(DeviceEvent::Woke);
+    }
+
+    /// Builds a command with this handle's retry policy and delays, and
+    /// writes it. Only a chip known to be asleep is woken up first.
+    fn begin(&mut self, command: &TemperatureCommand) -> Result<CommandOptions> {
+        let mut cmd = command.build();
+        cmd.retry = self.retry;
+        cmd.read_retry = Some(self.read_retry);
+        cmd.delay = self.delays.for_command(command);
+        cmd.deadline = self.deadline.map(|(end, _)| end);
+        hooks::check(&mut self.hooks, command_text(&cmd))?;
+        self.check_budget(self.min_gap + cmd.processing_time())?;
+        self.keep_gap();
+        if self.asleep {
+            common::wake(&mut self.dev, &self.retry.wait_strategy);
+            self.events.emit(DeviceEvent::Woke);
+        }
+        let started = Instant::now();
+        if let Err(e) = cmd.write(&mut self.dev) {
+            self.stats.record(command.name(), None, cmd.retries, true, started.elapsed());
+            self.failures += 1;
+            self.last_end = Some(Instant::now());
+            let event = CommandEvent {
+                command: command_text(&cmd),
+                response: None,
+                elapsed: started.elapsed(),
+                error: Some(&e),
+            };
+            hooks::report(&mut self.hooks, &event);
+            self.recent.record(&event);
+            return Err(self.budget_error(e));
+        }
+        self.started = Some((command.name(), started));
+        self.pending_event = DeviceEvent::for_command(command);
+        if self.settle > Duration::from_millis(0) {
+            self.retry.wait_strategy.wait(self.settle);
+        }
+        self.asleep = match *command {
+            TemperatureCommand::Sleep => true,
+            _ => false,
+        };
+        Ok(cmd)
+    }
+
+    /// Reads the response to a command written by `begin` into the handle's
+    /// buffer, once its processing delay has passed. Returns the raw
+    /// response, or `None` for commands without one; a response cut off by
+    /// the buffer fails to parse with `TruncatedResponse`.
+    fn collect(&mut self, cmd: &mut CommandOptions) -> Result<Option<&[u8]>> {
+        let started = self.started.take();
+        let event = self.pending_event.take();
+        let read = cmd.read_response_into(&mut self.dev, &mut self.buf);
+        if let Some((name, started)) = started {
+            for _ in 0..cmd.pending_reads {
+                self.stats.record_code(ResponseCode::Pending);
+            }
+            let code = if read.is_ok() { cmd.code.map(response_code) } else { None };
+            self.stats.record(name, code, cmd.retries, read.is_err(), started.elapsed());
+        }
+        self.last_end = Some(Instant::now());
+        let elapsed = started.map_or(Duration::from_millis(0), |(_, at)| at.elapsed());
+        if let Err(e) = read {
+            self.failures += 1;
+            let event = CommandEvent {
+                command: command_text(cmd),
+                response: None,
+                elapsed: elapsed,
+                error: Some(&e),
+            };
+            hooks::report(&mut self.hooks, &event);
+            self.recent.record(&event);
+            return Err(self.budget_error(e));
+        }
+        self.last_code = cmd.code;
+        match cmd.code.map(response_code) {
+            None | Some(ResponseCode::Success) => {
+                if self.failures > 0 {
+                    self.events.emit(DeviceEvent::RecoveredFromError(self.failures));
+                    self.failures = 0;
+                }
+                if let Some(event) = event {
+                    self.events.emit(event);
+                }
+            }
+            Some(ResponseCode::Pending) => {}
+            Some(_) => self.failures += 1,
+        }
+        let tokens = if cmd.response.is_some() && self.buf.contains(&b'*') {
+            parse::status_tokens(&self.buf)
+        } else {
+            Vec::new()
+        };
+        for token in tokens {
+            self.note_status(token);
+        }
+        let response = match cmd.response {
+            Some(_) => Some(&self.buf[..]),
+            None => None,
+        };
+        let event = CommandEvent {
+            command: command_text(cmd),
+            response: response,
+            elapsed: elapsed,
+            error: None,
+        };
+        hooks::report(&mut self.hooks, &event);
+        self.recent.record(&event);
+        Ok(response)
+    }
+
+    /// Keeps track of what a status string says about the chip.
+    fn note_status(&mut self, token: StatusToken) {
+        match token {
+            StatusToken::Reset | StatusToken::Ready => {
+                self.scale = None;
+                self.restarts.note_reboot();
+            }
+            StatusToken::Sleep => self.asleep = true,
+            StatusToken::Wake => self.asleep = false,
+            _ => {}
+        }
+    }
+
+    /// Waits until `min_gap` has passed since the last command ended.
+    fn keep_gap(&self) {
+        if let Some(end) = self.last_end {
+            let elapsed = end.elapsed();
+            if elapsed < self.min_gap {
+                self.retry.wait_strategy.wait(self.min_gap - elapsed);
+            }
+        }
+    }
+
+    /// Runs a command, reading the response into the handle's buffer.
+    /// Returns the raw response, or `None` for commands without one.
+    fn exchange(&mut self, command: TemperatureCommand) -> Result<Option<&[u8]>> {
+        let mut cmd = self.begin(&command)?;
+        cmd.delay()?;
+        self.collect(&mut cmd)
+    }
+
+    /// Runs a command that has a response, and returns the raw response.
+    fn query(&mut self, command: TemperatureCommand) -> Result<&[u8]> {
+        match self.exchange(command)? {
+            Some(data) => Ok(data),
+            None => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+
+    /// Runs a command, waiting for the chip to process it, and returns the
+    /// response payload. Commands without a response yield an empty string.
+    /// Fails unless the chip answered with a success code.
+    pub fn send(&mut self, command: TemperatureCommand) -> Result<String> {
+        match self.exchange(command)? {
+            Some(data) => parse::payload(data),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Starts a chain of commands, run by `Session::execute`.
+    pub fn session(&mut self) -> Session<'_, D> {
+        Session::new(self)
+    }
+
+    /// Takes a temperature reading, in the scale the chip is set to, with
+    /// the software correction applied.
+    pub fn read_temperature(&mut self) -> Result<Temperature> {
+        let temperature = self.read_uncorrected_temperature()?;
+        Ok(self.corrected(temperature))
+    }
+
+    /// Takes a temperature reading as the chip reports it, without the
+    /// software correction; for working out a correction.
+    pub fn read_uncorrected_temperature(&mut self) -> Result<Temperature> {
+        let scale = self.get_scale()?;
+        let data = self.query(TemperatureCommand::Reading)?;
+        parse::temperature(data, scale)
+    }
+
+    /// The temperature scale used for readings. The chip is only queried
+    /// the first time.
+    pub fn get_scale(&mut self) -> Result<TemperatureScale> {
+        if let Some(scale) = self.scale {
+            return Ok(scale);
+        }
+        let scale = parse::temperature_scale(self.query(TemperatureCommand::ScaleState)?)?;
+        self.scale = Some(scale);
+        Ok(scale)
+    }
+
+    /// Sets the temperature scale used for readings.
+    pub fn set_scale(&mut self, scale: TemperatureScale) -> Result<()> {
+        self.scale = None;
+        self.send(match scale {
+                      TemperatureScale::Celsius => TemperatureCommand::ScaleCelsius,
+                      TemperatureScale::Kelvin => TemperatureCommand::ScaleKelvin,
+                      TemperatureScale::Fahrenheit => TemperatureCommand::ScaleFahrenheit,
+                  })?;
+        self.verify_setting("temperature scale", scale, |sensor| {
+            parse::temperature_scale(sensor.query(TemperatureCommand::ScaleState)?)
+        })?;
+        self.scale = Some(scale);
+        Ok(())
+    }
+
+    /// If verification is on, fails unless `query` gives `expected`.
+    fn verify_setting<T, F>(&mut self, setting: &'static str, expected: T, query: F) -> Result<()>
+        where T: PartialEq,
+              F: FnOnce(&mut RtdSensor<D>) -> Result<T>
+    {
+        if self.verify && query(self)? != expected {
+            return Err(ErrorKind::VerificationFailed(setting).into());
+        }
+        Ok(())
+    }
+
+    /// Drops the cached temperature scale, so that the next reading queries
+    /// the chip again; useful after the chip has restarted.
+    pub fn forget_scale(&mut self) {
+        self.scale = None;
+    }
+
+    /// Names the chip; an empty name clears it. Names are at most 16
+    /// printable ASCII characters, without spaces.
+    pub fn set_name(&mut self, name: &str) -> Result<()> {
+        self.send(TemperatureCommand::Name(name.to_string()))?;
+        self.verify_setting("name", name.to_string(), |sensor| sensor.get_name())
+    }
+
+    /// Queries the device status: restart reason and supply voltage.
+    pub fn get_status(&mut self) -> Result<DeviceStatus> {
+        let format = self.response_format()?;
+        let status = {
+            let data = self.query(TemperatureCommand::Status)?;
+            parse::device_status_with_format(data, format)?
+        };
+        self.restarts.observe(status.restart_reason, SystemTime::now());
+        Ok(status)
+    }
+
+    /// Queries the device type and firmware version.
+    pub fn get_device_info(&mut self) -> Result<DeviceInfo> {
+        let response = self.send(TemperatureCommand::DeviceInformation)?;
+        let info = DeviceInfo::parse(&response)?;
+        self.format = Some(ResponseFormat::for_firmware(&info));
+        Ok(info)
+    }
+
+    /// How the chip's firmware formats its responses. The chip is only
+    /// queried the first time.
+    pub fn response_format(&mut self) -> Result<ResponseFormat> {
+        match self.format {
+            Some(format) => Ok(format),
+            None => self.get_device_info().map(|info| ResponseFormat::for_firmware(&info)),
+        }
+    }
+
+    /// The chip's name, or `None` if firmware `info` does not support
+    /// naming.
+    fn name_if_supported(&mut self, info: &DeviceInfo) -> Result<Option<String>> {
+        match info.firmware_major() {
+            Some(major) if major >= NAMING_FIRMWARE => self.get_name().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// The chip's name, or `None` if its firmware does not support naming.
+    pub fn get_name_if_supported(&mut self) -> Result<Option<String>> {
+        let info = self.get_device_info()?;
+        self.name_if_supported(&info)
+    }
+
+    /// The name given to the chip with `Name,n`; empty if it has none.
+    pub fn get_name(&mut self) -> Result<String> {
+        let response = self.send(TemperatureCommand::NameState)?;
+        let DeviceName(name) = DeviceName::parse(&response)?;
+        Ok(name)
+    }
+
+    /// Queries the chip's settings and state in as few transactions as
+    /// possible: each query is sent once, the scale comes from the cache
+    /// when known, `Name,?` is skipped on firmware that does not support
+    /// it, and the calibration data is not exported.
+    pub fn snapshot(&mut self) -> Result<Snapshot> {
+        let info = self.get_device_info()?;
+        let name = self.name_if_supported(&info)?;
+        Ok(Snapshot {
+               status: self.get_status()?,
+               scale: self.get_scale()?,
+               calibration: self.get_calibration_state()?,
+               datalogger_interval: self.get_datalogger_interval()?,
+               led: self.get_led_status()?,
+               info: info,
+               name: name,
+           })
+    }
+
+    /// A multi-line report on the chip, for people: model, firmware,
+    /// address, name, settings, supply voltage and the last reading, which
+    /// is taken now if the handle has not read the temperature yet.
+    pub fn describe(&mut self) -> Result<String> {
+        let snapshot = self.snapshot()?;
+        let reading = match self.last_reading {
+            Some(reading) => reading,
+            None => self.read_temperature()?,
+        };
+        let mut report = String::new();
+        let _ = writeln!(report, "model: EZO {}", snapshot.info.device_type);
+        let _ = writeln!(report, "firmware: {}", snapshot.info.firmware);
+        match self.address {
+            Some(address) => {
+                let _ = writeln!(report, "address: {:#04x} ({})", address, address);
+            }
+            None => {
+                let _ = writeln!(report, "address: unknown");
+            }
+        }
+        let _ = match snapshot.name {
+            Some(ref name) if !name.is_empty() => writeln!(report, "name: {}", name),
+            Some(_) => writeln!(report, "name: (none)"),
+            None => writeln!(report, "name: (not supported by the firmware)"),
+        };
+        let _ = writeln!(report, "scale: {}", snapshot.scale.symbol());
+        let _ = writeln!(report,
+                         "calibration: {}",
+                         match snapshot.calibration {
+                             CalibrationState::Calibrated => "calibrated",
+                             CalibrationState::Uncalibrated => "uncalibrated",
+                         });
+        let _ = writeln!(report, "vcc: {:.3} V", snapshot.status.vcc_voltage);
+        let _ = writeln!(report,
+                         "restart reason: {}",
+                         snapshot.status.restart_reason.description());
+        let _ = writeln!(report,
+                         "last reading: {:.3} {}",
+                         reading.value(),
+                         reading.scale().symbol());
+        Ok(report)
+    }
+
+    /// Gathers the recent commands and errors, a snapshot of the chip, the
+    /// transaction statistics and the handle's settings, for a bug report;
+    /// see the `debug` module.
+    pub fn debug_bundle(&mut self) -> DebugBundle {
+        let commands = self.recent.commands();
+        let errors = self.recent.errors();
+        let snapshot = self.snapshot().map_err(|e| e.to_string());
+        DebugBundle {
+            created: SystemTime::now(),
+            address: self.address,
+            snapshot: snapshot,
+            delays: self.delays,
+            retry: self.retry,
+            read_retry: self.read_retry,
+            stats: self.stats.clone(),
+            restarts: self.restarts.records(),
+            commands: commands,
+            errors: errors,
+        }
+    }
+
+    /// Calibrates the probe against a reference at `temperature`, in the
+    /// chip's current scale. Temperatures outside the probe's range are
+    /// refused with `CalibrationOutOfRange`, in the same scale.
+    pub fn calibrate(&mut self, temperature: f64) -> Result<()> {
+        let scale = self.get_scale()?;
+        let bound = |celsius| Temperature::Celsius(celsius).to_scale(scale).value();
+        let (min, max) = (bound(MIN_CALIBRATION_CELSIUS), bound(MAX_CALIBRATION_CELSIUS));
+        // Conversions may round the bounds themselves off a little.
+        if !(min - 1e-9..=max + 1e-9).contains(&temperature) {
+            return Err(ErrorKind::CalibrationOutOfRange(temperature, min, max).into());
+        }
+        self.send(TemperatureCommand::CalibrationTemperature(temperature))?;
+        Ok(())
+    }
+
+    /// Deletes the calibration data.
+    pub fn clear_calibration(&mut self) -> Result<()> {
+        self.send(TemperatureCommand::CalibrationClear)?;
+        Ok(())
+    }
+
+    /// Whether the chip has been calibrated.
+    pub fn get_calibration_state(&mut self) -> Result<CalibrationState> {
+        let data = self.query(TemperatureCommand::CalibrationState)?;
+        parse::calibration_state(data)
+    }
+
+    /// Seconds between readings stored by the data logger; 0 when it is
+    /// disabled.
+    pub fn get_datalogger_interval(&mut self) -> Result<DataLoggerStorageIntervalSeconds> {
+        let data = self.query(TemperatureCommand::DataloggerInterval)?;
+        parse::data_logger_interval(data)
+    }
+
+    /// Whether the LED is on.
+    pub fn get_led_status(&mut self) -> Result<LedStatus> {
+        let data = self.query(TemperatureCommand::LedState)?;
+        parse::led_status(data)
+    }
+
+    /// Turns the LED on.
+    pub fn led_on(&mut self) -> Result<()> {
+        self.send(TemperatureCommand::LedOn)?;
+        self.verify_setting("LED state", LedStatus::On, |sensor| sensor.get_led_status())
+    }
+
+    /// Turns the LED off.
+    pub fn led_off(&mut self) -> Result<()> {
+        self.send(TemperatureCommand::LedOff)?;
+        self.verify_setting("LED state", LedStatus::Off, |sensor| sensor.get_led_status())
+    }
+
+    /// Sets the seconds between readings stored by the data logger; 0
+    /// disables it.
+    pub fn set_datalogger_interval(&mut self, seconds: u16) -> Result<()> {
+        self.send(match seconds {
+                      0 => TemperatureCommand::DataloggerDisable,
+                      n => TemperatureCommand::DataloggerPeriod(n),
+                  })?;
+        self.verify_setting("data logger interval",
+                            DataLoggerStorageIntervalSeconds(u32::from(seconds)),
+                            |sensor| sensor.get_datalogger_interval())
+    }
+
+    /// Stops the data logger; the readings it stored are kept.
+    pub fn disable_datalogger(&mut self) -> Result<()> {
+        self.set_datalogger_interval(0)
+    }
+
+    /// Whether the protocol lock keeps the chip from being switched to
+    /// UART mode.
+    pub fn get_protocol_lock(&mut self) -> Result<ProtocolLockStatus> {
+        let data = self.query(TemperatureCommand::ProtocolLockState)?;
+        parse::protocol_lock_status(data)
+    }
+
+    /// Locks or unlocks the protocol.
+    pub fn set_protocol_lock(&mut self, lock: ProtocolLockStatus) -> Result<()> {
+        self.send(match lock {
+                      ProtocolLockStatus::Locked => TemperatureCommand::ProtocolLockEnable,
+                      ProtocolLockStatus::Unlocked => TemperatureCommand::ProtocolLockDisable,
+                  })?;
+        self.verify_setting("protocol lock", lock, |sensor| sensor.get_protocol_lock())
+    }
+
+    /// Puts the chip to sleep. The next command wakes it up, and waits for
+    /// it to settle.
+    pub fn sleep(&mut self) -> Result<()> {
+        self.send(TemperatureCommand::Sleep)?;
+        Ok(())
+    }
+
+    /// Downloads the calibration data as the strings returned by successive
+    /// `Export` commands, up to `*DONE`. Fails with `IncompleteExport` if
+    /// they are not as many, or as long, as `Export,?` said they would be.
+    pub fn export_calibration(&mut self) -> Result<CalibrationString> {
+        let info = ExportInfo::parse(&self.send(TemperatureCommand::ExportInfo)?)?;
+        let mut lines = Vec::new();
+        loop {
+            let line = self.send(TemperatureCommand::Export)?;
+            if line == "*DONE" {
+                break;
+            }
+            if lines.len() == MAX_EXPORT_LINES {
+                return Err(ErrorKind::ResponseParse.into());
+            }
+            lines.push(line);
+        }
+        let calibration = CalibrationString::new(lines);
+        if calibration.lines.len() != info.strings as usize ||
+           calibration.len() != info.bytes as usize {
+            return Err(ErrorKind::IncompleteExport(info.strings, info.bytes).into());
+        }
+        Ok(calibration)
+    }
+
+    /// Uploads calibration data previously downloaded with
+    /// `export_calibration`, split with `import_chunks`, one `Import`
+    /// command at a time. The chip reboots after the last one; this waits
+    /// until it answers again. Nothing is sent for empty data.
+    pub fn import_calibration(&mut self, calibration: &CalibrationString) -> Result<()> {
+        let chunks = import_chunks(&calibration.lines)?;
+        if chunks.is_empty() {
+            return Ok(());
+        }
+        for chunk in chunks {
+            self.send(TemperatureCommand::Import(chunk))?;
+        }
+        self.await_reboot(IMPORT_REBOOT_DELAY)
+            .chain_err(|| "the chip did not come back after the calibration import")
+    }
+
+    /// Waits `delay` milliseconds for the chip to reboot, forgetting what
+    /// the handle knew about its state, and polls its status until it
+    /// answers.
+    fn await_reboot(&mut self, delay: u64) -> Result<()> {
+        self.retry.wait_strategy.wait(Duration::from_millis(delay));
+        self.restarts.note_reboot();
+        self.scale = None;
+        self.format = None;
+        self.asleep = false;
+        let mut polls = 0;
+        loop {
+            match self.get_status() {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    polls += 1;
+                    if polls >= REBOOT_POLLS {
+                        return Err(e);
+                    }
+                    self.retry
+                        .wait_strategy
+                        .wait(Duration::from_millis(common::WAKE_SETTLE_MS));
+                }
+            }
+        }
+    }
+
+    /// Uploads calibration data and checks that the chip took it: the chip
+    /// must report being calibrated, and exporting must give back the same
+    /// data as `calibration`, however it is split into strings.
+    pub fn restore_calibration(&mut self, calibration: &CalibrationString) -> Result<()> {
+        self.import_calibration(calibration)?;
+        if self.get_calibration_state()? != CalibrationState::Calibrated ||
+           self.export_calibration()?.lines.concat() != calibration.lines.concat() {
+            return Err(ErrorKind::CalibrationMismatch.into());
+        }
+        Ok(())
+    }
+
+    /// Changes the chip's I2C address. The chip reboots and answers only at
+    /// the new address afterwards; this handle keeps pointing at the old one.
+    ///
+    /// Nothing is checked at the new address; see `change_address`.
+    pub fn set_address(&mut self, address: u16) -> Result<()> {
+        self.send(TemperatureCommand::DeviceAddress(address))?;
+        Ok(())
+    }
+}
+
+impl<D: Addressable> RtdSensor<D> {
+    /// Moves the chip from `from`, the address this handle points at, to
+    /// `address`. Fails with `AddressInUse` if something already
+    /// acknowledges `address`, since two chips at one address would both
+    /// become unreachable. The handle points at the new address afterwards;
+    /// the chip answers there once it has rebooted.
+    pub fn change_address(&mut self, from: u16, address: u16) -> Result<()> {
+        let taken = discovery::acknowledges(&mut self.dev, address);
+        self.dev.select_address(from)?;
+        if taken? {
+            return Err(ErrorKind::AddressInUse(address).into());
+        }
+        self.set_address(address)?;
+        self.dev.select_address(address)?;
+        self.address = Some(address);
+        Ok(())
+    }
+
+    /// Moves the chip to `address` from the one this handle points at,
+    /// which must be known, like `change_address`; then waits for the chip
+    /// to reboot, and checks that the same kind of chip, with the same
+    /// firmware, answers at `address`.
+    pub fn change_i2c_address(&mut self, address: u16) -> Result<()> {
+        let from = match self.address {
+            Some(from) => from,
+            None => bail!("the chip's address is unknown; see set_known_address"),
+        };
+        let info = self.get_device_info()?;
+        self.change_address(from, address)?;
+        self.await_reboot(ADDRESS_REBOOT_DELAY)
+            .chain_err(|| format!("the chip did not answer at {:#04x}", address))?;
+        if self.get_device_info()? != info {
+            return Err(ErrorKind::VerificationFailed("address").into());
+        }
+        Ok(())
+    }
+}
+
+/// The command string of `cmd`, without its terminating NUL.
+fn command_text(cmd: &CommandOptions) -> &str {
+    cmd.command.as_str().trim_end_matches('\0')
+}
+
+/// Takes a reading from every sensor, overlapping the processing time: the
+/// `R` command is written to all of them, and the responses are read after
+/// a single wait for the slowest one, made with the first sensor's wait
+/// strategy. The results are in the order of `sensors`.
+pub fn read_temperatures<D: I2CDevice>(sensors: &mut [RtdSensor<D>]) -> Vec<Result<Temperature>> {
+    let mut started = Vec::with_capacity(sensors.len());
+    let mut delay = 0;
+    for sensor in sensors.iter_mut() {
+        let start = sensor
+            .get_scale()
+            .and_then(|scale| {
+                          sensor
+                              .begin(&TemperatureCommand::Reading)
+                              .map(|cmd| (scale, cmd))
+                      });
+        if let Ok((_, ref cmd)) = start {
+            delay = delay.max(cmd.delay.unwrap_or(0));
+        }
+        started.push(start);
+    }
+    if let Some(sensor) = sensors.first() {
+        sensor.retry.wait_strategy.wait(Duration::from_millis(delay));
+    }
+    sensors
+        .iter_mut()
+        .zip(started)
+        .map(|(sensor, start)| {
+            let (scale, mut cmd) = start?;
+            let temperature = match sensor.collect(&mut cmd)? {
+                Some(data) => parse::temperature(data, scale)?,
+                None => return Err(ErrorKind::ResponseParse.into()),
+            };
+            Ok(sensor.corrected(temperature))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::{MockDevice, Reply};
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn reads_temperature_in_the_chip_scale() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(-12.5);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(-12.5));
+    }
+
+    #[test]
+    fn handles_freezer_temperatures() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(-18.53);
+        let id = bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(-18.53));
+        sensor.set_scale(TemperatureScale::Fahrenheit).unwrap();
+        match sensor.read_temperature().unwrap() {
+            Temperature::Fahrenheit(f) => assert!((f - -1.354).abs() < 0.001),
+            other => panic!("unexpected reading {:?}", other),
+        }
+        sensor.calibrate(-1.35).unwrap();
+        assert_eq!(bus.with_chip(id, |chip| chip.is_calibrated()), Some(true));
+    }
+
+    #[test]
+    fn calibration_outside_the_probe_range_is_refused() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        for &temperature in &[-126.5, 1254.5, ::std::f64::NAN] {
+            match *sensor.calibrate(temperature).unwrap_err().kind() {
+                ErrorKind::CalibrationOutOfRange(..) => {}
+                ref other => panic!("unexpected error {:?}", other),
+            }
+        }
+        assert_eq!(bus.with_chip(id, |chip| chip.is_calibrated()), Some(false));
+
+        sensor.set_scale(TemperatureScale::Kelvin).unwrap();
+        assert!(sensor.calibrate(100.0).is_err());
+        sensor.calibrate(147.15).unwrap();
+        assert_eq!(bus.with_chip(id, |chip| chip.is_calibrated()), Some(true));
+    }
+
+    #[test]
+    fn reads_temperature_in_kelvin() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(37.0);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        sensor.set_scale(TemperatureScale::Kelvin).unwrap();
+        match sensor.read_temperature().unwrap() {
+            Temperature::Kelvin(kelvin) => assert!((kelvin - 310.15).abs() < 0.001),
+            other => panic!("unexpected reading {:?}", other),
+        }
+    }
+
+    #[test]
+    fn calibration_round_trip() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert_eq!(sensor.get_calibration_state().unwrap(),
+                   CalibrationState::Uncalibrated);
+        sensor.calibrate(100.0).unwrap();
+        assert_eq!(sensor.get_calibration_state().unwrap(),
+                   CalibrationState::Calibrated);
+
+        let calibration = sensor.export_calibration().unwrap();
+        assert!(!calibration.is_empty());
+        sensor.clear_calibration().unwrap();
+        sensor.import_calibration(&calibration).unwrap();
+        assert_eq!(sensor.get_calibration_state().unwrap(),
+                   CalibrationState::Calibrated);
+        assert_eq!(bus.with_chip(id, |chip| chip.is_calibrated()), Some(true));
+    }
+
+    #[test]
+    fn calibration_data_is_imported_in_chunks() {
+        assert_eq!(import_chunks(&["0123456789abcdefgh", "short"]).unwrap(),
+                   vec!["0123456789ab", "cdefgh", "short"]);
+        assert!(import_chunks(&["with,comma"]).is_err());
+        assert!(import_chunks(&["with space"]).is_err());
+        assert!(import_chunks(&[""]).is_err());
+
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        let calibration = CalibrationString::new(vec!["6E6F742063616C6962".to_string()]);
+        sensor.restore_calibration(&calibration).unwrap();
+        assert_eq!(bus.with_chip(id, |chip| chip.is_calibrated()), Some(true));
+    }
+
+    #[test]
+    fn restored_calibration_is_verified() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let replacement = bus.attach(SimulatedChip::new(0x65));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        let mut new_sensor = RtdSensor::new(bus.device(0x65));
+
+        assert!(new_sensor.restore_calibration(&CalibrationString::default()).is_err());
+
+        sensor.calibrate(100.0).unwrap();
+        let calibration = sensor.export_calibration().unwrap();
+        new_sensor.restore_calibration(&calibration).unwrap();
+        assert_eq!(bus.with_chip(replacement, |chip| chip.is_calibrated()),
+                   Some(true));
+    }
+
+    #[test]
+    fn incomplete_exports_are_refused() {
+        let dev = MockDevice::new(0x66);
+        dev.script("Export,?", vec![Reply::success("2,10")])
+            .script("Export", vec![Reply::success("6E6F742063"), Reply::success("*DONE")]);
+        let mut sensor = RtdSensor::new(dev);
+        sensor.set_retry_policy(RetryPolicy::no_wait());
+        match *sensor.export_calibration().unwrap_err().kind() {
+            ErrorKind::IncompleteExport(2, 10) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_a_sleeping_chip_is_woken_up() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy {
+                                    attempts: 1,
+                                    min_wait: Duration::from_millis(0),
+                                    max_wait: Duration::from_millis(0),
+                                    ..RetryPolicy::default()
+                                });
+
+        assert!(!sensor.is_asleep());
+        sensor.sleep().unwrap();
+        assert!(sensor.is_asleep());
+        assert_eq!(bus.with_chip(id, |chip| chip.is_asleep()), Some(true));
+
+        sensor.read_temperature().unwrap();
+        assert!(!sensor.is_asleep());
+        assert_eq!(bus.with_chip(id, |chip| chip.is_asleep()), Some(false));
+    }
+
+    #[test]
+    fn tuned_delays_are_applied() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_processing_time(Duration::from_millis(10));
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        let delays = sensor.tune_delays(2, 50).unwrap();
+        assert!(delays.reading < Delays::default().reading);
+        sensor.set_delays(delays);
+        assert_eq!(sensor.delays(), delays);
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(25.0));
+    }
+
+    #[test]
+    fn readings_overlap_across_sensors() {
+        use std::time::Instant;
+
+        let bus = VirtualBus::new();
+        let mut sensors = Vec::new();
+        for address in 0x61..0x65 {
+            let mut chip = SimulatedChip::new(address);
+            chip.set_temperature(f64::from(address));
+            bus.attach(chip);
+            let mut sensor = RtdSensor::new(bus.device(address));
+            sensor.get_scale().unwrap();
+            sensor.set_delays(Delays {
+                                  reading: 100,
+                                  ..Delays::default()
+                              });
+            sensors.push(sensor);
+        }
+
+        let start = Instant::now();
+        let readings = read_temperatures(&mut sensors);
+        assert!(start.elapsed() < Duration::from_millis(300));
+        for (address, reading) in (0x61..0x65).zip(readings) {
+            assert_eq!(reading.unwrap(), Temperature::Celsius(f64::from(address)));
+        }
+    }
+
+    #[test]
+    fn snapshot_skips_unsupported_queries() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_name("tank");
+        bus.attach(chip);
+        let mut old = SimulatedChip::new(0x65);
+        old.set_identity("RTD", "1.02").set_name("tank");
+        bus.attach(old);
+
+        let snapshot = RtdSensor::new(bus.device(0x66)).snapshot().unwrap();
+        assert_eq!(snapshot.info.firmware, "2.01");
+        assert_eq!(snapshot.scale, TemperatureScale::Celsius);
+        assert_eq!(snapshot.calibration, CalibrationState::Uncalibrated);
+        assert_eq!(snapshot.datalogger_interval, DataLoggerStorageIntervalSeconds(0));
+        assert_eq!(snapshot.led, LedStatus::On);
+        assert_eq!(snapshot.name, Some("tank".to_string()));
+
+        let snapshot = RtdSensor::new(bus.device(0x65)).snapshot().unwrap();
+        assert_eq!(snapshot.name, None);
+        assert_eq!(snapshot.status.vcc_voltage, 5.038);
+    }
+
+    #[test]
+    fn corrections_apply_on_top_of_readings() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_correction(Some(Correction::offset(TemperatureScale::Celsius, -0.25)));
+
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(24.75));
+        assert_eq!(sensor.read_uncorrected_temperature().unwrap(),
+                   Temperature::Celsius(25.0));
+        assert_eq!(read_temperatures(::std::slice::from_mut(&mut sensor))[0]
+                       .as_ref()
+                       .unwrap(),
+                   &Temperature::Celsius(24.75));
+        assert_eq!(bus.with_chip(id, |chip| chip.is_calibrated()), Some(false));
+
+        sensor.set_correction(None);
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(25.0));
+    }
+
+    #[test]
+    fn readings_carry_their_uncertainty() {
+        use uncertainty::ProbeClass;
+
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        assert!(sensor.read_with_uncertainty().is_err());
+
+        sensor.set_uncertainty_model(Some(UncertaintyModel::new(ProbeClass::A, None)));
+        let reading = sensor.read_with_uncertainty().unwrap();
+        assert_eq!(reading.temperature, Temperature::Celsius(25.0));
+        assert!(reading.standard > 0.0 && reading.expanded() < 1.0);
+    }
+
+    #[test]
+    fn describes_the_chip() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_name("tank").set_temperature(21.5);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_known_address(0x66);
+
+        let report = sensor.describe().unwrap();
+        for line in &["model: EZO RTD",
+                      "firmware: 2.01",
+                      "address: 0x66 (102)",
+                      "name: tank",
+                      "scale: °C",
+                      "calibration: uncalibrated",
+                      "vcc: 5.038 V",
+                      "last reading: 21.500 °C"] {
+            assert!(report.lines().any(|l| l == *line),
+                    "{:?} not in\n{}",
+                    line,
+                    report);
+        }
+        assert_eq!(sensor.last_reading(), Some(Temperature::Celsius(21.5)));
+    }
+
+    #[test]
+    fn address_change_refuses_a_taken_address() {
+        let bus = VirtualBus::new();
+        let moved = bus.attach(SimulatedChip::new(0x66));
+        bus.attach(SimulatedChip::new(0x65));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        match *sensor.change_address(0x66, 0x65).unwrap_err().kind() {
+            ErrorKind::AddressInUse(0x65) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(bus.with_chip(moved, |chip| chip.address()), Some(0x66));
+
+        sensor.change_address(0x66, 0x70).unwrap();
+        assert_eq!(bus.with_chip(moved, |chip| chip.address()), Some(0x70));
+        assert!(sensor.get_device_info().is_ok());
+    }
+
+    #[test]
+    fn address_change_is_verified_at_the_new_address() {
+        let bus = VirtualBus::new();
+        let moved = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy::no_wait());
+        assert!(sensor.change_i2c_address(0x70).is_err());
+
+        sensor.set_known_address(0x66);
+        sensor.change_i2c_address(0x70).unwrap();
+        assert_eq!(bus.with_chip(moved, |chip| chip.address()), Some(0x70));
+        assert_eq!(sensor.address(), Some(0x70));
+    }
+
+    #[test]
+    fn settings_are_verified() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_verify_settings(true);
+
+        sensor.set_scale(TemperatureScale::Fahrenheit).unwrap();
+        assert_eq!(sensor.get_scale().unwrap(), TemperatureScale::Fahrenheit);
+        sensor.led_off().unwrap();
+        sensor.set_datalogger_interval(30).unwrap();
+        sensor.set_protocol_lock(ProtocolLockStatus::Locked).unwrap();
+        assert_eq!(bus.with_chip(id, |chip| {
+                                     (chip.scale(),
+                                      chip.led(),
+                                      chip.datalogger_interval(),
+                                      chip.protocol_lock())
+                                 }),
+                   Some((TemperatureScale::Fahrenheit, false, 30, true)));
+    }
+
+    #[test]
+    fn status_strings_do_not_break_parsing() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_status_tokens(true).set_temperature(-4.25);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(-4.25));
+        sensor.led_off().unwrap();
+        assert_eq!(sensor.get_led_status().unwrap(), LedStatus::Off);
+        assert!(sensor.send(TemperatureCommand::DataloggerPeriod(1)).is_err());
+    }
+
+    #[test]
+    fn full_length_names_fit_with_status_strings() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_status_tokens(true);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor
+            .send(TemperatureCommand::Name("thermowell-north".to_string()))
+            .unwrap();
+
+        // `?Name,thermowell-north\r*OK` still fits in the buffer.
+        assert_eq!(sensor.get_name().unwrap(), "thermowell-north");
+    }
+
+    #[test]
+    fn cut_off_responses_are_refused() {
+        let mut long = b"\x01?Name,".to_vec();
+        long.extend(vec![b'x'; MAX_RESPONSE]);
+        let dev = MockDevice::new(0x66);
+        dev.script("Name,?", vec![Reply::Raw(long)])
+            .script("S,?", vec![Reply::success("?S,c")])
+            .script("R", vec![Reply::success("25.000")]);
+        let mut sensor = RtdSensor::new(dev);
+
+        match *sensor.get_name().unwrap_err().kind() {
+            ErrorKind::TruncatedResponse(MAX_RESPONSE) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(25.0));
+    }
+
+    #[test]
+    fn unusual_response_codes_are_kept() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_response_code(Some(0x7a));
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        match *sensor.send(TemperatureCommand::Status).unwrap_err().kind() {
+            ErrorKind::UnsuccessfulResponse(0x7a) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(sensor.last_response_code(), Some(0x7a));
+    }
+
+    #[test]
+    fn unsuccessful_response_is_an_error() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        assert!(sensor.send(TemperatureCommand::DataloggerPeriod(1)).is_err());
+    }
+
+    #[test]
+    fn counts_transactions_by_command_and_code() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        sensor.read_temperature().unwrap();
+        sensor.read_temperature().unwrap();
+        bus.with_chip(chip, |chip| { chip.set_response_code(Some(2)); });
+        assert!(sensor.read_temperature().is_err());
+
+        let stats = sensor.stats();
+        assert_eq!(stats.commands()["R"].count, 3);
+        assert_eq!(stats.commands()["S,?"].count, 1);
+        assert_eq!(stats.successes(), 3);
+        assert_eq!(stats.device_errors(), 1);
+        assert!(stats.latency_percentile(50.0).is_some());
+        sensor.reset_stats();
+        assert_eq!(sensor.stats().transactions(), 0);
+    }
+
+    #[test]
+    fn keeps_the_minimum_gap_between_commands() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_processing_time(Duration::from_millis(0));
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_delays(Delays {
+                              reading: 0,
+                              calibration: 0,
+                              command: 0,
+                          });
+        sensor.set_min_gap(Duration::from_millis(100));
+
+        let started = Instant::now();
+        sensor.get_led_status().unwrap();
+        sensor.get_led_status().unwrap();
+        sensor.get_led_status().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn commands_that_would_outrun_the_budget_are_not_written() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        let started = Instant::now();
+        let result = sensor.with_budget(Duration::from_millis(100), |sensor| sensor.snapshot());
+        match *result.unwrap_err().kind() {
+            ErrorKind::BudgetExhausted(100) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(sensor.stats().transactions(), 0);
+
+        let snapshot = sensor.with_budget(Duration::from_secs(60), |sensor| {
+            sensor.with_budget(Duration::from_secs(3600), |sensor| sensor.snapshot())
+        });
+        assert!(snapshot.is_ok());
+    }
+
+    #[test]
+    fn retries_do_not_outrun_the_budget() {
+        let bus = VirtualBus::new();
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy {
+                                    attempts: 50,
+                                    min_wait: Duration::from_millis(100),
+                                    max_wait: Duration::from_millis(100),
+                                    ..RetryPolicy::default()
+                                });
+
+        let started = Instant::now();
+        let result = sensor.with_budget(Duration::from_millis(1000),
+                                        |sensor| sensor.get_led_status());
+        match *result.unwrap_err().kind() {
+            ErrorKind::BudgetExhausted(1000) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(1000));
+        assert!(started.elapsed() < Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn writes_and_reads_are_retried_separately() {
+        let bus = VirtualBus::new();
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        let retry = RetryPolicy {
+            attempts: 4,
+            ..RetryPolicy::no_wait()
+        };
+        sensor.set_retry_policy(retry);
+        assert!(sensor.get_led_status().is_err());
+        assert_eq!(sensor.stats().retries(), 3);
+
+        sensor.reset_stats();
+        sensor.set_write_retry_policy(RetryPolicy { attempts: 1, ..retry });
+        assert!(sensor.get_led_status().is_err());
+        assert_eq!(sensor.stats().retries(), 0);
+        assert_eq!(sensor.read_retry_policy(), retry);
+
+        bus.attach(SimulatedChip::new(0x66));
+        let cmd = sensor.begin(&TemperatureCommand::LedState).unwrap();
+        assert_eq!(cmd.retry.attempts, 1);
+        assert_eq!(cmd.read_retry_policy(), retry);
+    }
+
+    #[test]
+    fn polled_pending_codes_are_counted() {
+        let dev = MockDevice::new(0x66);
+        dev.script("S,?", vec![Reply::success("?S,c")])
+            .script("R", vec![Reply::Pending, Reply::Pending, Reply::success("21.500")]);
+        let mut sensor = RtdSensor::new(dev);
+        sensor.set_retry_policy(RetryPolicy {
+                                    pending_polls: 2,
+                                    ..RetryPolicy::no_wait()
+                                });
+
+        assert_eq!(sensor.read_temperature().unwrap(), Temperature::Celsius(21.5));
+        assert_eq!(sensor.stats().pending(), 2);
+        assert_eq!(sensor.stats().successes(), 2);
+        assert_eq!(sensor.stats().transactions(), 2);
+    }
+
+    #[test]
+    fn pending_responses_are_polled_if_the_policy_says_so() {
+        let dev = MockDevice::new(0x66);
+        dev.script("S,?", vec![Reply::success("?S,c")])
+            .script("R", vec![Reply::Pending, Reply::Pending, Reply::success("21.500")]);
+        let mut sensor = RtdSensor::new(dev.clone());
+        let retry = RetryPolicy::no_wait();
+        sensor.set_retry_policy(retry);
+        match *sensor.read_temperature().unwrap_err().kind() {
+            ErrorKind::PendingResponse => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+
+        sensor.set_read_retry_policy(RetryPolicy { pending_polls: 3, ..retry });
+        assert_eq!(sensor.read_temperature().unwrap(), Temperature::Celsius(21.5));
+        assert_eq!(dev.reads(), 4);
+
+        dev.script("R", vec![Reply::Code(2)]);
+        match *sensor.read_temperature().unwrap_err().kind() {
+            ErrorKind::DeviceError => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
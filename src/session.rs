@@ -0,0 +1,218 @@
+//! Several commands to a sensor, chained and then run in one go.
+//!
+//! ```no_run
+//! # use ezo_rtd::RtdSensor;
+//! # use ezo_rtd::response::TemperatureScale;
+//! # fn run() -> ezo_rtd::errors::Result<()> {
+//! let mut sensor = RtdSensor::open(1, 0x66)?;
+//! let results = sensor
+//!     .session()
+//!     .led_off()
+//!     .set_scale(TemperatureScale::Celsius)
+//!     .read()
+//!     .sleep()
+//!     .execute()?;
+//! println!("{:?}", results.readings());
+//! # Ok(())
+//! # }
+//! ```
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+use response::{CalibrationState, DeviceInfo, DeviceStatus, LedStatus, Temperature,
+               TemperatureScale};
+use sensor::RtdSensor;
+
+/// One command of a session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    LedOn,
+    LedOff,
+    LedStatus,
+    SetScale(TemperatureScale),
+    SetDataloggerInterval(u16),
+    Read,
+    Status,
+    DeviceInfo,
+    CalibrationState,
+    Sleep,
+}
+
+/// The result of one command of a session.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// A command that changes a setting, or has no response, went through.
+    Done,
+    Reading(Temperature),
+    Status(DeviceStatus),
+    DeviceInfo(DeviceInfo),
+    CalibrationState(CalibrationState),
+    LedStatus(LedStatus),
+}
+
+/// The results of a session, one per command, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionResults(pub Vec<Outcome>);
+
+impl SessionResults {
+    /// The readings taken, in order.
+    pub fn readings(&self) -> Vec<Temperature> {
+        self.0
+            .iter()
+            .filter_map(|outcome| match *outcome {
+                            Outcome::Reading(temperature) => Some(temperature),
+                            _ => None,
+                        })
+            .collect()
+    }
+
+    /// The last device status queried, if any.
+    pub fn status(&self) -> Option<DeviceStatus> {
+        self.0
+            .iter()
+            .filter_map(|outcome| match *outcome {
+                            Outcome::Status(status) => Some(status),
+                            _ => None,
+                        })
+            .last()
+    }
+
+    /// The last device information queried, if any.
+    pub fn device_info(&self) -> Option<&DeviceInfo> {
+        self.0
+            .iter()
+            .filter_map(|outcome| match *outcome {
+                            Outcome::DeviceInfo(ref info) => Some(info),
+                            _ => None,
+                        })
+            .last()
+    }
+}
+
+/// Commands chained on a sensor, run by `execute`; see
+/// `RtdSensor::session`.
+pub struct Session<'a, D: I2CDevice + 'a> {
+    sensor: &'a mut RtdSensor<D>,
+    steps: Vec<Step>,
+}
+
+impl<'a, D: I2CDevice> Session<'a, D> {
+    pub fn new(sensor: &'a mut RtdSensor<D>) -> Session<'a, D> {
+        Session {
+            sensor: sensor,
+            steps: Vec::new(),
+        }
+    }
+
+    fn then(mut self, step: Step) -> Session<'a, D> {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn led_on(self) -> Session<'a, D> {
+        self.then(Step::LedOn)
+    }
+
+    pub fn led_off(self) -> Session<'a, D> {
+        self.then(Step::LedOff)
+    }
+
+    pub fn led_status(self) -> Session<'a, D> {
+        self.then(Step::LedStatus)
+    }
+
+    pub fn set_scale(self, scale: TemperatureScale) -> Session<'a, D> {
+        self.then(Step::SetScale(scale))
+    }
+
+    pub fn set_datalogger_interval(self, seconds: u16) -> Session<'a, D> {
+        self.then(Step::SetDataloggerInterval(seconds))
+    }
+
+    /// Takes a reading.
+    pub fn read(self) -> Session<'a, D> {
+        self.then(Step::Read)
+    }
+
+    pub fn status(self) -> Session<'a, D> {
+        self.then(Step::Status)
+    }
+
+    pub fn device_info(self) -> Session<'a, D> {
+        self.then(Step::DeviceInfo)
+    }
+
+    pub fn calibration_state(self) -> Session<'a, D> {
+        self.then(Step::CalibrationState)
+    }
+
+    pub fn sleep(self) -> Session<'a, D> {
+        self.then(Step::Sleep)
+    }
+
+    /// Runs the commands in order. Stops at the first that fails, with an
+    /// error that tells which one it was.
+    pub fn execute(self) -> Result<SessionResults> {
+        let sensor = self.sensor;
+        let mut outcomes = Vec::with_capacity(self.steps.len());
+        for (index, step) in self.steps.iter().enumerate() {
+            let outcome = match *step {
+                Step::LedOn => sensor.led_on().map(|_| Outcome::Done),
+                Step::LedOff => sensor.led_off().map(|_| Outcome::Done),
+                Step::LedStatus => sensor.get_led_status().map(Outcome::LedStatus),
+                Step::SetScale(scale) => sensor.set_scale(scale).map(|_| Outcome::Done),
+                Step::SetDataloggerInterval(seconds) => {
+                    sensor
+                        .set_datalogger_interval(seconds)
+                        .map(|_| Outcome::Done)
+                }
+                Step::Read => sensor.read_temperature().map(Outcome::Reading),
+                Step::Status => sensor.get_status().map(Outcome::Status),
+                Step::DeviceInfo => sensor.get_device_info().map(Outcome::DeviceInfo),
+                Step::CalibrationState => {
+                    sensor
+                        .get_calibration_state()
+                        .map(Outcome::CalibrationState)
+                }
+                Step::Sleep => sensor.sleep().map(|_| Outcome::Done),
+            };
+            outcomes.push(outcome.chain_err(|| format!("step {} ({:?}) failed", index + 1, step))?);
+        }
+        Ok(SessionResults(outcomes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn runs_chained_commands() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        let results = sensor
+            .session()
+            .led_off()
+            .set_scale(TemperatureScale::Kelvin)
+            .read()
+            .status()
+            .sleep()
+            .execute()
+            .unwrap();
+        assert_eq!(results.0.len(), 5);
+        assert_eq!(results.readings(), vec![Temperature::Kelvin(298.15)]);
+        assert_eq!(results.status().unwrap().vcc_voltage, 5.038);
+        assert_eq!(bus.with_chip(id, |chip| chip.is_asleep()), Some(true));
+    }
+
+    #[test]
+    fn tells_which_step_failed() {
+        let bus = VirtualBus::new();
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        let error = sensor.session().led_on().read().execute().unwrap_err();
+        assert_eq!(error.to_string(), "step 1 (LedOn) failed");
+    }
+}
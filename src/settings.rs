@@ -0,0 +1,356 @@
+//! Settings applied as a whole, or rolled back.
+//!
+//! `RtdSensor::apply_settings` reads the current value of every setting it
+//! is about to change first. If one of the changes fails, say on a
+//! transient bus error, the ones already made are undone, so that the chip
+//! is not left half configured, and the report tells exactly what was
+//! changed, what was restored, and what could not be.
+//!
+//! A `Profile` holds the settings a chip should have. `diff` compares it
+//! with the chip's `SensorState`, to show what applying the profile would
+//! change before anything is touched.
+
+use std::fmt;
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+use response::{LedStatus, ProtocolLockStatus, TemperatureScale};
+use sensor::RtdSensor;
+
+/// A setting of the chip, with its value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Setting {
+    Scale(TemperatureScale),
+    Led(LedStatus),
+    /// Seconds between readings stored by the data logger; 0 disables it.
+    DataloggerInterval(u16),
+    ProtocolLock(ProtocolLockStatus),
+    Name(String),
+}
+
+impl Setting {
+    /// The name of the setting, without its value.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Setting::Scale(_) => "temperature scale",
+            Setting::Led(_) => "LED state",
+            Setting::DataloggerInterval(_) => "data logger interval",
+            Setting::ProtocolLock(_) => "protocol lock",
+            Setting::Name(_) => "name",
+        }
+    }
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Setting::Scale(scale) => write!(f, "{} {}", self.name(), scale.symbol()),
+            Setting::Led(led) => write!(f, "{} {:?}", self.name(), led),
+            Setting::DataloggerInterval(0) => write!(f, "{} off", self.name()),
+            Setting::DataloggerInterval(seconds) => write!(f, "{} {} s", self.name(), seconds),
+            Setting::ProtocolLock(lock) => write!(f, "{} {:?}", self.name(), lock),
+            Setting::Name(ref name) => write!(f, "{} {:?}", self.name(), name),
+        }
+    }
+}
+
+/// The chip's settings, from `RtdSensor::read_settings`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorState {
+    pub scale: TemperatureScale,
+    pub led: LedStatus,
+    pub datalogger_interval: u16,
+    pub protocol_lock: ProtocolLockStatus,
+    /// `None` if the firmware does not support naming.
+    pub name: Option<String>,
+}
+
+/// The settings a chip should have; `None` leaves a setting as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub scale: Option<TemperatureScale>,
+    pub led: Option<LedStatus>,
+    pub datalogger_interval: Option<u16>,
+    pub protocol_lock: Option<ProtocolLockStatus>,
+    pub name: Option<String>,
+}
+
+/// A setting that applying a profile would change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingChange {
+    /// The current value; `None` if the chip cannot report it.
+    pub from: Option<Setting>,
+    pub to: Setting,
+}
+
+impl fmt::Display for SettingChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.from {
+            Some(ref from) => write!(f, "{} -> {}", from, self.to),
+            None => write!(f, "(unknown) -> {}", self.to),
+        }
+    }
+}
+
+/// The settings that applying `profile` to a chip in `state` would change,
+/// in the order `apply_profile` applies them.
+pub fn diff(state: &SensorState, profile: &Profile) -> Vec<SettingChange> {
+    let wanted = [(profile.scale.map(Setting::Scale), Some(Setting::Scale(state.scale))),
+                  (profile.led.map(Setting::Led), Some(Setting::Led(state.led))),
+                  (profile.datalogger_interval.map(Setting::DataloggerInterval),
+                   Some(Setting::DataloggerInterval(state.datalogger_interval))),
+                  (profile.protocol_lock.map(Setting::ProtocolLock),
+                   Some(Setting::ProtocolLock(state.protocol_lock))),
+                  (profile.name.clone().map(Setting::Name), state.name.clone().map(Setting::Name))];
+    wanted
+        .iter()
+        .filter_map(|&(ref to, ref from)| match *to {
+                        Some(ref to) if from.as_ref() != Some(to) => {
+                            Some(SettingChange {
+                                     from: from.clone(),
+                                     to: to.clone(),
+                                 })
+                        }
+                        _ => None,
+                    })
+        .collect()
+}
+
+/// What `RtdSensor::apply_settings` did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SettingsReport {
+    /// The settings changed, in order, and left changed.
+    pub applied: Vec<Setting>,
+    /// The setting that could not be changed, and why.
+    pub failed: Option<(Setting, String)>,
+    /// The earlier values restored after the failure.
+    pub restored: Vec<Setting>,
+    /// The earlier values that could not be restored, and why; the chip
+    /// is left with the new values of these settings.
+    pub not_restored: Vec<(Setting, String)>,
+}
+
+impl SettingsReport {
+    /// Whether every setting was applied.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_none()
+    }
+
+    /// The settings applied, or an error that describes the failure and
+    /// the rollback.
+    pub fn into_result(self) -> Result<Vec<Setting>> {
+        let (setting, reason) = match self.failed {
+            None => return Ok(self.applied),
+            Some(failed) => failed,
+        };
+        let mut message = format!("could not set the {}: {}", setting.name(), reason);
+        if !self.restored.is_empty() {
+            let restored: Vec<String> = self.restored.iter().map(Setting::to_string).collect();
+            message.push_str(&format!("; restored {}", restored.join(", ")));
+        }
+        for &(ref setting, ref reason) in &self.not_restored {
+            message.push_str(&format!("; could not restore {}: {}", setting, reason));
+        }
+        bail!(message)
+    }
+}
+
+impl<D: I2CDevice> RtdSensor<D> {
+    /// The data logger interval, which the chip only takes up to
+    /// `u16::MAX` seconds.
+    fn datalogger_seconds(&mut self) -> Result<u16> {
+        let seconds = self.get_datalogger_interval()?.0;
+        if seconds > u32::from(u16::max_value()) {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        Ok(seconds as u16)
+    }
+
+    /// The current value of the setting `like` is about.
+    pub fn get_setting(&mut self, like: &Setting) -> Result<Setting> {
+        Ok(match *like {
+               Setting::Scale(_) => Setting::Scale(self.get_scale()?),
+               Setting::Led(_) => Setting::Led(self.get_led_status()?),
+               Setting::DataloggerInterval(_) => {
+                   Setting::DataloggerInterval(self.datalogger_seconds()?)
+               }
+               Setting::ProtocolLock(_) => Setting::ProtocolLock(self.get_protocol_lock()?),
+               Setting::Name(_) => Setting::Name(self.get_name()?),
+           })
+    }
+
+    /// Changes one setting.
+    pub fn set_setting(&mut self, setting: &Setting) -> Result<()> {
+        match *setting {
+            Setting::Scale(scale) => self.set_scale(scale),
+            Setting::Led(LedStatus::On) => self.led_on(),
+            Setting::Led(LedStatus::Off) => self.led_off(),
+            Setting::DataloggerInterval(seconds) => self.set_datalogger_interval(seconds),
+            Setting::ProtocolLock(lock) => self.set_protocol_lock(lock),
+            Setting::Name(ref name) => self.set_name(name),
+        }
+    }
+
+    /// Reads all the settings that a `Profile` covers.
+    pub fn read_settings(&mut self) -> Result<SensorState> {
+        Ok(SensorState {
+               scale: self.get_scale()?,
+               led: self.get_led_status()?,
+               datalogger_interval: self.datalogger_seconds()?,
+               protocol_lock: self.get_protocol_lock()?,
+               name: self.get_name_if_supported()?,
+           })
+    }
+
+    /// Applies the settings of `profile` that differ from the chip's, as
+    /// `apply_settings` does.
+    pub fn apply_profile(&mut self, profile: &Profile) -> Result<SettingsReport> {
+        let state = self.read_settings()?;
+        let settings: Vec<Setting> = diff(&state, profile)
+            .into_iter()
+            .map(|change| change.to)
+            .collect();
+        self.apply_settings(&settings)
+    }
+
+    /// Brings the chip to `profile`: queries its settings, changes only
+    /// those that differ, rolling back on failure, and queries them again
+    /// to check that they took. Returns the changes made, none if the chip
+    /// already matched, so that this can run at every boot.
+    pub fn reconcile(&mut self, profile: &Profile) -> Result<Vec<SettingChange>> {
+        let changes = diff(&self.read_settings()?, profile);
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        let settings: Vec<Setting> = changes.iter().map(|change| change.to.clone()).collect();
+        self.apply_settings(&settings)?.into_result()?;
+        if let Some(change) = diff(&self.read_settings()?, profile).first() {
+            return Err(ErrorKind::VerificationFailed(change.to.name()).into());
+        }
+        Ok(changes)
+    }
+
+    /// Applies `settings` in order, after reading their current values.
+    /// If a change fails, the changes already made are undone, last first.
+    /// Fails without changing anything if the current values cannot be
+    /// read; otherwise the report tells what happened.
+    pub fn apply_settings(&mut self, settings: &[Setting]) -> Result<SettingsReport> {
+        let mut previous = Vec::with_capacity(settings.len());
+        for setting in settings {
+            previous.push(self.get_setting(setting)
+                              .chain_err(|| format!("Could not read the {}", setting.name()))?);
+        }
+        let mut report = SettingsReport::default();
+        for setting in settings {
+            if let Err(e) = self.set_setting(setting) {
+                report.failed = Some((setting.clone(), e.to_string()));
+                break;
+            }
+            report.applied.push(setting.clone());
+        }
+        if report.failed.is_some() {
+            let changed = report.applied.split_off(0);
+            for (setting, before) in changed.into_iter().zip(previous).rev() {
+                match self.set_setting(&before) {
+                    Ok(()) => report.restored.push(before),
+                    Err(e) => {
+                        // What could not be restored stays changed.
+                        report.not_restored.push((before, e.to_string()));
+                        report.applied.insert(0, setting);
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn applies_all_settings() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        let report = sensor
+            .apply_settings(&[Setting::Scale(TemperatureScale::Fahrenheit),
+                              Setting::Led(LedStatus::Off),
+                              Setting::Name("tank".to_string())])
+            .unwrap();
+        assert!(report.is_complete());
+        assert_eq!(report.applied.len(), 3);
+        assert_eq!(bus.with_chip(id, |chip| (chip.scale(), chip.led())),
+                   Some((TemperatureScale::Fahrenheit, false)));
+        assert_eq!(sensor.get_name().unwrap(), "tank");
+    }
+
+    #[test]
+    fn shows_what_a_profile_would_change() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_name("tank");
+        let id = bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        let profile = Profile {
+            scale: Some(TemperatureScale::Celsius),
+            led: Some(LedStatus::Off),
+            name: Some("tank-2".to_string()),
+            ..Profile::default()
+        };
+
+        let state = sensor.read_settings().unwrap();
+        let changes = diff(&state, &profile);
+        let shown: Vec<String> = changes.iter().map(SettingChange::to_string).collect();
+        assert_eq!(shown,
+                   vec!["LED state On -> LED state Off",
+                        "name \"tank\" -> name \"tank-2\""]);
+        assert_eq!(bus.with_chip(id, |chip| chip.led()), Some(true));
+
+        assert!(sensor.apply_profile(&profile).unwrap().is_complete());
+        assert!(diff(&sensor.read_settings().unwrap(), &profile).is_empty());
+    }
+
+    #[test]
+    fn reconciling_is_idempotent() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        let profile = Profile {
+            scale: Some(TemperatureScale::Kelvin),
+            datalogger_interval: Some(60),
+            protocol_lock: Some(ProtocolLockStatus::Locked),
+            ..Profile::default()
+        };
+
+        assert_eq!(sensor.reconcile(&profile).unwrap().len(), 3);
+        assert_eq!(bus.with_chip(id, |chip| chip.datalogger_interval()), Some(60));
+        assert!(sensor.reconcile(&profile).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rolls_back_after_a_failure() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+
+        // A name that is not ASCII is refused before reaching the chip.
+        let report = sensor
+            .apply_settings(&[Setting::Scale(TemperatureScale::Kelvin),
+                              Setting::DataloggerInterval(30),
+                              Setting::Name("café".to_string())])
+            .unwrap();
+        assert!(!report.is_complete());
+        assert_eq!(report.failed.as_ref().unwrap().0, Setting::Name("café".to_string()));
+        assert!(report.applied.is_empty());
+        assert_eq!(report.restored,
+                   vec![Setting::DataloggerInterval(0),
+                        Setting::Scale(TemperatureScale::Celsius)]);
+        assert_eq!(bus.with_chip(id, |chip| (chip.scale(), chip.datalogger_interval())),
+                   Some((TemperatureScale::Celsius, 0)));
+        assert!(report.into_result().is_err());
+    }
+}
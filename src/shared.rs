@@ -0,0 +1,229 @@
+//! A sensor handle shared between components, with maintenance leases.
+//!
+//! Each call on a `SharedSensor` locks the sensor for its own commands,
+//! but a calibration, an import or an address change is a sequence of
+//! commands that must not be interleaved with anything else: a reading
+//! taken by a background poller halfway through a calibration is wrong,
+//! and may upset the chip. Such operations take the maintenance lease.
+//! While it is held, every other use of the sensor fails with
+//! `SensorLeased`, or, with `with_blocking`, waits for the lease to be
+//! released.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use discovery::Addressable;
+use errors::*;
+use i2cdev::core::I2CDevice;
+use response::CalibrationString;
+use sensor::RtdSensor;
+
+struct State<D: I2CDevice> {
+    sensor: RtdSensor<D>,
+    /// What the lease is held for, and the lease's number.
+    lease: Option<(&'static str, u64)>,
+    next_lease: u64,
+}
+
+struct Shared<D: I2CDevice> {
+    state: Mutex<State<D>>,
+    /// Signalled when the lease is released.
+    released: Condvar,
+}
+
+/// A sensor shared by any number of components.
+///
+/// Cloning a `SharedSensor` yields another handle to the same sensor.
+pub struct SharedSensor<D: I2CDevice> {
+    shared: Arc<Shared<D>>,
+}
+
+impl<D: I2CDevice> Clone for SharedSensor<D> {
+    fn clone(&self) -> SharedSensor<D> {
+        SharedSensor { shared: self.shared.clone() }
+    }
+}
+
+impl<D: I2CDevice> SharedSensor<D> {
+    pub fn new(sensor: RtdSensor<D>) -> SharedSensor<D> {
+        SharedSensor {
+            shared: Arc::new(Shared {
+                                 state: Mutex::new(State {
+                                                       sensor: sensor,
+                                                       lease: None,
+                                                       next_lease: 0,
+                                                   }),
+                                 released: Condvar::new(),
+                             }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<D>> {
+        self.shared.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs `f` on the sensor, unless the maintenance lease is held, in
+    /// which case it fails with `SensorLeased`.
+    pub fn with<T, F>(&self, f: F) -> Result<T>
+        where F: FnOnce(&mut RtdSensor<D>) -> Result<T>
+    {
+        let mut state = self.lock();
+        if let Some((purpose, _)) = state.lease {
+            return Err(ErrorKind::SensorLeased(purpose.to_string()).into());
+        }
+        f(&mut state.sensor)
+    }
+
+    /// Runs `f` on the sensor, waiting at most `timeout` for the
+    /// maintenance lease to be released; fails with `SensorLeased` if it
+    /// is still held by then.
+    pub fn with_blocking<T, F>(&self, timeout: Duration, f: F) -> Result<T>
+        where F: FnOnce(&mut RtdSensor<D>) -> Result<T>
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        while let Some((purpose, _)) = state.lease {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ErrorKind::SensorLeased(purpose.to_string()).into());
+            }
+            state = match self.shared.released.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+        f(&mut state.sensor)
+    }
+
+    /// Takes the maintenance lease, for `purpose`, such as "calibration";
+    /// fails with `SensorLeased` if it is already held. The lease is
+    /// released when the returned `Lease` is dropped.
+    pub fn lease(&self, purpose: &'static str) -> Result<Lease<D>> {
+        let mut state = self.lock();
+        if let Some((held, _)) = state.lease {
+            return Err(ErrorKind::SensorLeased(held.to_string()).into());
+        }
+        let number = state.next_lease;
+        state.next_lease += 1;
+        state.lease = Some((purpose, number));
+        Ok(Lease {
+               sensor: self.clone(),
+               number: number,
+           })
+    }
+
+    /// Whether the maintenance lease is held, and for what.
+    pub fn leased_for(&self) -> Option<&'static str> {
+        self.lock().lease.map(|(purpose, _)| purpose)
+    }
+
+    /// Runs `f` under the maintenance lease, taken for `purpose` and
+    /// released afterwards.
+    pub fn maintain<T, F>(&self, purpose: &'static str, f: F) -> Result<T>
+        where F: FnOnce(&mut RtdSensor<D>) -> Result<T>
+    {
+        self.lease(purpose)?.run(f)
+    }
+
+    /// Calibrates the probe, like `RtdSensor::calibrate`, under the
+    /// maintenance lease.
+    pub fn calibrate(&self, temperature: f64) -> Result<()> {
+        self.maintain("calibration", |sensor| sensor.calibrate(temperature))
+    }
+
+    /// Uploads calibration data, like `RtdSensor::import_calibration`,
+    /// under the maintenance lease.
+    pub fn import_calibration(&self, calibration: &CalibrationString) -> Result<()> {
+        self.maintain("calibration import", |sensor| sensor.import_calibration(calibration))
+    }
+}
+
+impl<D: Addressable> SharedSensor<D> {
+    /// Moves the chip, like `RtdSensor::change_address`, under the
+    /// maintenance lease.
+    pub fn change_address(&self, from: u16, address: u16) -> Result<()> {
+        self.maintain("address change", |sensor| sensor.change_address(from, address))
+    }
+
+    /// Moves the chip and checks it answers at its new address, like
+    /// `RtdSensor::change_i2c_address`, under the maintenance lease.
+    pub fn change_i2c_address(&self, address: u16) -> Result<()> {
+        self.maintain("address change", |sensor| sensor.change_i2c_address(address))
+    }
+}
+
+/// The maintenance lease of a `SharedSensor`, released when dropped.
+pub struct Lease<D: I2CDevice> {
+    sensor: SharedSensor<D>,
+    number: u64,
+}
+
+impl<D: I2CDevice> Lease<D> {
+    /// Runs `f` on the sensor, which only the lease holder can use.
+    pub fn run<T, F>(&self, f: F) -> Result<T>
+        where F: FnOnce(&mut RtdSensor<D>) -> Result<T>
+    {
+        f(&mut self.sensor.lock().sensor)
+    }
+}
+
+impl<D: I2CDevice> Drop for Lease<D> {
+    fn drop(&mut self) {
+        let mut state = self.sensor.lock();
+        if state.lease.map(|(_, number)| number) == Some(self.number) {
+            state.lease = None;
+        }
+        self.sensor.shared.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use simulator::{SimulatedChip, SimulatedDevice, VirtualBus};
+
+    fn shared(bus: &VirtualBus) -> SharedSensor<SimulatedDevice> {
+        bus.attach(SimulatedChip::new(0x66));
+        SharedSensor::new(RtdSensor::new(bus.device(0x66)))
+    }
+
+    #[test]
+    fn other_users_are_kept_out_during_maintenance() {
+        let bus = VirtualBus::new();
+        let sensor = shared(&bus);
+        let poller = sensor.clone();
+
+        let lease = sensor.lease("calibration").unwrap();
+        match *poller.with(|sensor| sensor.read_temperature()).unwrap_err().kind() {
+            ErrorKind::SensorLeased(ref purpose) => assert_eq!(purpose, "calibration"),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert!(sensor.calibrate(25.0).is_err());
+        lease.run(|sensor| sensor.calibrate(25.0)).unwrap();
+        drop(lease);
+
+        assert_eq!(poller.leased_for(), None);
+        assert!(poller.with(|sensor| sensor.read_temperature()).is_ok());
+        assert!(sensor.calibrate(25.0).is_ok());
+    }
+
+    #[test]
+    fn blocking_users_wait_for_the_lease() {
+        let bus = VirtualBus::new();
+        let sensor = shared(&bus);
+        let poller = sensor.clone();
+
+        let lease = sensor.lease("calibration").unwrap();
+        assert!(poller
+                    .with_blocking(Duration::from_millis(10), |_| Ok(()))
+                    .is_err());
+        let waiting = thread::spawn(move || {
+            poller.with_blocking(Duration::from_secs(60), |sensor| sensor.read_temperature())
+        });
+        thread::sleep(Duration::from_millis(50));
+        drop(lease);
+        assert!(waiting.join().unwrap().is_ok());
+    }
+}
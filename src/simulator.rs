@@ -0,0 +1,965 @@
+//! Simulated RTD EZO chips attached to a virtual I2C bus.
+//!
+//! A `VirtualBus` hosts any number of `SimulatedChip`s, either directly or
+//! behind TCA9548A-style multiplexers. Several chips may be attached at the
+//! same address; like on a real bus, their replies are wired-AND together
+//! and come out garbled. A `SimulatedDevice` is the handle for one slave
+//! address on the bus and implements `I2CDevice`, so it can be used anywhere
+//! a `LinuxI2CDevice` would be.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use discovery::Addressable;
+use ezo_common::ResponseCode;
+use i2cdev::core::I2CDevice;
+use response::TemperatureScale;
+
+/// Errors produced by the simulated bus.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimError {
+    /// Nothing acknowledged the given address.
+    Nack(u16),
+    /// The transaction is not supported by the simulator.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SimError::Nack(addr) => write!(f, "no device acknowledged address {:#04x}", addr),
+            SimError::Unsupported(what) => write!(f, "{} is not supported by the simulator", what),
+        }
+    }
+}
+
+impl Error for SimError {
+    fn description(&self) -> &str {
+        match *self {
+            SimError::Nack(_) => "no device acknowledged the address",
+            SimError::Unsupported(_) => "unsupported transaction",
+        }
+    }
+}
+
+/// Settings for randomly corrupting response bytes.
+#[derive(Clone, Debug)]
+struct Chaos {
+    probability: f64,
+    state: u64,
+    flipped: usize,
+}
+
+impl Chaos {
+    /// Next pseudo-random number in `[0, 1)`, from a xorshift64* generator.
+    fn next(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Sets bit 7 on each payload byte with the configured probability,
+    /// leaving the response code alone.
+    fn corrupt(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut().skip(1) {
+            if *byte & 0x80 == 0 && self.next() < self.probability {
+                *byte |= 0x80;
+                self.flipped += 1;
+            }
+        }
+    }
+}
+
+/// A response waiting to be read from a chip.
+#[derive(Clone, Debug)]
+struct PendingResponse {
+    ready_at: Instant,
+    bytes: Vec<u8>,
+}
+
+/// The simulated state of a single EZO chip.
+#[derive(Clone, Debug)]
+pub struct SimulatedChip {
+    address: u16,
+    device_type: String,
+    firmware: String,
+    name: String,
+    celsius: f64,
+    scale: TemperatureScale,
+    led: bool,
+    plock: bool,
+    calibrated: bool,
+    datalogger: u32,
+    vcc: f64,
+    restart_reason: char,
+    asleep: bool,
+    uart: bool,
+    /// Whether UART-style status strings are added to responses.
+    status_tokens: bool,
+    /// Response code byte sent instead of the real one, if any.
+    code_override: Option<u8>,
+    memory: Vec<f64>,
+    memory_cursor: usize,
+    calibration: Vec<String>,
+    export_cursor: Option<usize>,
+    import_buffer: Vec<String>,
+    processing_time: Duration,
+    response: Option<PendingResponse>,
+    chaos: Option<Chaos>,
+}
+
+impl SimulatedChip {
+    /// Creates an awake, uncalibrated RTD chip at `address`, reading 25 °C.
+    pub fn new(address: u16) -> SimulatedChip {
+        SimulatedChip {
+            address: address,
+            device_type: "RTD".to_string(),
+            firmware: "2.01".to_string(),
+            name: String::new(),
+            celsius: 25.0,
+            scale: TemperatureScale::Celsius,
+            led: true,
+            plock: false,
+            calibrated: false,
+            datalogger: 0,
+            vcc: 5.038,
+            restart_reason: 'P',
+            asleep: false,
+            uart: false,
+            status_tokens: false,
+            code_override: None,
+            memory: Vec::new(),
+            memory_cursor: 0,
+            calibration: vec!["6E6F742063616C6962".to_string(), "726174656400".to_string()],
+            export_cursor: None,
+            import_buffer: Vec::new(),
+            processing_time: Duration::from_millis(0),
+            response: None,
+            chaos: None,
+        }
+    }
+
+    /// Sets the device type and firmware reported by the `I` command, e.g.
+    /// to simulate a pH chip sharing the bus. Firmware 1.x labels its
+    /// `Status` response in upper case.
+    pub fn set_identity(&mut self, device_type: &str, firmware: &str) -> &mut SimulatedChip {
+        self.device_type = device_type.to_string();
+        self.firmware = firmware.to_string();
+        self
+    }
+
+    /// Sets the temperature sensed by the probe, in degrees Celsius.
+    pub fn set_temperature(&mut self, celsius: f64) -> &mut SimulatedChip {
+        self.celsius = celsius;
+        self
+    }
+
+    /// Sets the supply voltage reported by the `Status` command.
+    pub fn set_vcc(&mut self, vcc: f64) -> &mut SimulatedChip {
+        self.vcc = vcc;
+        self
+    }
+
+    /// Sets the restart reason reported by the `Status` command.
+    pub fn set_restart_reason(&mut self, reason: char) -> &mut SimulatedChip {
+        self.restart_reason = reason;
+        self
+    }
+
+    /// Makes the chip add `*OK` to its responses, and answer commands it
+    /// does not understand with a successful `*ER`, as some firmware does.
+    pub fn set_status_tokens(&mut self, status_tokens: bool) -> &mut SimulatedChip {
+        self.status_tokens = status_tokens;
+        self
+    }
+
+    /// Makes the chip send `code` as the response code of every response,
+    /// like a damaged or cloned chip might; `None` restores the real codes.
+    pub fn set_response_code(&mut self, code: Option<u8>) -> &mut SimulatedChip {
+        self.code_override = code;
+        self
+    }
+
+    /// Sets the name reported by `Name,?`.
+    pub fn set_name(&mut self, name: &str) -> &mut SimulatedChip {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Sets how long the chip takes to process a command. Reads issued
+    /// before this time has elapsed get a `Pending` response code.
+    pub fn set_processing_time(&mut self, time: Duration) -> &mut SimulatedChip {
+        self.processing_time = time;
+        self
+    }
+
+    /// Stores readings in the datalogger memory, as if they had been logged.
+    pub fn set_memory(&mut self, readings: &[f64]) -> &mut SimulatedChip {
+        self.memory = readings.to_vec();
+        self.memory_cursor = 0;
+        self
+    }
+
+    /// Enables chaos mode: every payload byte read from the chip gets bit 7
+    /// set with the given `probability`, like the glitchy hardware that
+    /// `CommandOptions::flipping` reports. The `seed` makes runs
+    /// reproducible. A probability of zero disables chaos mode.
+    pub fn set_bit_flip_chaos(&mut self, probability: f64, seed: u64) -> &mut SimulatedChip {
+        self.chaos = if probability > 0.0 {
+            Some(Chaos {
+                     probability: probability,
+                     // xorshift must not start from zero.
+                     state: seed | 1,
+                     flipped: 0,
+                 })
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Number of bytes corrupted by chaos mode so far.
+    pub fn flipped_bytes(&self) -> usize {
+        self.chaos.as_ref().map_or(0, |chaos| chaos.flipped)
+    }
+
+    /// Puts the chip to sleep, as the `Sleep` command would.
+    pub fn set_asleep(&mut self, asleep: bool) -> &mut SimulatedChip {
+        self.asleep = asleep;
+        self
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn scale(&self) -> TemperatureScale {
+        self.scale
+    }
+
+    pub fn led(&self) -> bool {
+        self.led
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    pub fn is_calibrated(&self) -> bool {
+        self.calibrated
+    }
+
+    pub fn datalogger_interval(&self) -> u32 {
+        self.datalogger
+    }
+
+    pub fn protocol_lock(&self) -> bool {
+        self.plock
+    }
+
+    /// Returns `true` once the chip has been switched to UART mode with the
+    /// `Baud` command, after which it no longer answers on the bus.
+    pub fn is_uart(&self) -> bool {
+        self.uart
+    }
+
+    /// Whether the chip answers at `address`.
+    fn answers_at(&self, address: u16) -> bool {
+        !self.uart && self.address == address
+    }
+
+    /// The current reading, converted to the chip's scale.
+    fn reading(&self) -> f64 {
+        match self.scale {
+            TemperatureScale::Celsius => self.celsius,
+            TemperatureScale::Kelvin => self.celsius + 273.15,
+            TemperatureScale::Fahrenheit => self.celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        let mut fresh = SimulatedChip::new(self.address);
+        fresh.device_type = self.device_type.clone();
+        fresh.firmware = self.firmware.clone();
+        fresh.celsius = self.celsius;
+        fresh.vcc = self.vcc;
+        fresh.processing_time = self.processing_time;
+        fresh.restart_reason = 'S';
+        *self = fresh;
+    }
+
+    /// Handles a write transaction. Returns `false` if the chip did not ACK.
+    fn write(&mut self, data: &[u8]) -> bool {
+        if self.asleep {
+            // Any transaction wakes the chip up, but this one is lost.
+            self.asleep = false;
+            return false;
+        }
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        let command = String::from_utf8_lossy(&data[..end]).into_owned();
+        let ready_at = Instant::now() + self.processing_time;
+        self.response = self.process(&command).map(|bytes| {
+                                                       PendingResponse {
+                                                           ready_at: ready_at,
+                                                           bytes: bytes,
+                                                       }
+                                                   });
+        true
+    }
+
+    /// Handles a read transaction of `len` bytes. What does not fit is
+    /// lost, like on the chip.
+    fn read(&mut self, len: usize) -> Vec<u8> {
+        let bytes = match self.response.take() {
+            Some(ref response) if Instant::now() < response.ready_at => {
+                self.response = Some(response.clone());
+                vec![ResponseCode::Pending as u8]
+            }
+            Some(response) => response.bytes,
+            None => vec![ResponseCode::NoDataExpected as u8],
+        };
+        let mut buf = vec![0u8; len];
+        for (dst, src) in buf.iter_mut().zip(bytes.iter()) {
+            *dst = *src;
+        }
+        if let Some(ref mut chaos) = self.chaos {
+            chaos.corrupt(&mut buf);
+        }
+        buf
+    }
+
+    /// Processes a command string, returning the response to be read, or
+    /// `None` for commands to which the chip does not reply.
+    fn process(&mut self, command: &str) -> Option<Vec<u8>> {
+        let lower = command.to_lowercase();
+        let mut parts = lower.splitn(2, ',');
+        let verb = parts.next().unwrap_or("");
+        let arg = parts.next();
+        let original_arg = command.splitn(2, ',').nth(1);
+
+        if verb != "import" && !self.import_buffer.is_empty() {
+            // The imported calibration takes effect once the upload is over.
+            self.calibration = self.import_buffer.split_off(0);
+            self.calibrated = true;
+        }
+
+        let reply = match (verb, arg) {
+            ("r", None) => Ok(Some(format!("{:.3}", self.reading()))),
+            ("s", Some("c")) => {
+                self.scale = TemperatureScale::Celsius;
+                Ok(None)
+            }
+            ("s", Some("k")) => {
+                self.scale = TemperatureScale::Kelvin;
+                Ok(None)
+            }
+            ("s", Some("f")) => {
+                self.scale = TemperatureScale::Fahrenheit;
+                Ok(None)
+            }
+            ("s", Some("?")) => {
+                let unit = match self.scale {
+                    TemperatureScale::Celsius => 'c',
+                    TemperatureScale::Kelvin => 'k',
+                    TemperatureScale::Fahrenheit => 'f',
+                };
+                Ok(Some(format!("?S,{}", unit)))
+            }
+            ("l", Some("1")) => {
+                self.led = true;
+                Ok(None)
+            }
+            ("l", Some("0")) => {
+                self.led = false;
+                Ok(None)
+            }
+            ("l", Some("?")) => Ok(Some(format!("?L,{}", self.led as u8))),
+            ("cal", Some("clear")) => {
+                self.calibrated = false;
+                Ok(None)
+            }
+            ("cal", Some("?")) => Ok(Some(format!("?Cal,{}", self.calibrated as u8))),
+            ("cal", Some(value)) => {
+                match f64::from_str(value) {
+                    Ok(_) => {
+                        self.calibrated = true;
+                        Ok(None)
+                    }
+                    Err(_) => Err(()),
+                }
+            }
+            ("d", Some("?")) => Ok(Some(format!("?D,{}", self.datalogger))),
+            ("d", Some(value)) => {
+                match u32::from_str(value) {
+                    Ok(n) if n == 0 || (10..=320_000).contains(&n) => {
+                        self.datalogger = n;
+                        Ok(None)
+                    }
+                    _ => Err(()),
+                }
+            }
+            ("plock", Some("1")) => {
+                self.plock = true;
+                Ok(None)
+            }
+            ("plock", Some("0")) => {
+                self.plock = false;
+                Ok(None)
+            }
+            ("plock", Some("?")) => Ok(Some(format!("?Plock,{}", self.plock as u8))),
+            ("i", None) => Ok(Some(format!("?I,{},{}", self.device_type, self.firmware))),
+            ("status", None) if self.firmware.starts_with("1.") => {
+                Ok(Some(format!("?STATUS,{},{:.3}", self.restart_reason, self.vcc)))
+            }
+            ("status", None) => {
+                Ok(Some(format!("?Status,{},{:.3}", self.restart_reason, self.vcc)))
+            }
+            ("name", Some("?")) => Ok(Some(format!("?Name,{}", self.name))),
+            ("name", Some(_)) => {
+                self.name = original_arg.unwrap_or("").to_string();
+                Ok(None)
+            }
+            ("f", None) | ("find", None) => Ok(None),
+            ("m", None) => {
+                match self.memory.get(self.memory_cursor) {
+                    Some(&value) => {
+                        self.memory_cursor += 1;
+                        Ok(Some(format!("{},{:.3}", self.memory_cursor, value)))
+                    }
+                    None => Ok(Some("*DONE".to_string())),
+                }
+            }
+            ("m", Some("?")) => Ok(Some(format!("?M,{}", self.memory.len()))),
+            ("m", Some("clear")) => {
+                self.memory.clear();
+                self.memory_cursor = 0;
+                Ok(None)
+            }
+            ("export", Some("?")) => {
+                let bytes: usize = self.calibration.iter().map(|line| line.len()).sum();
+                self.export_cursor = Some(0);
+                Ok(Some(format!("{},{}", self.calibration.len(), bytes)))
+            }
+            ("export", None) => {
+                let cursor = self.export_cursor.unwrap_or(0);
+                match self.calibration.get(cursor).cloned() {
+                    Some(line) => {
+                        self.export_cursor = Some(cursor + 1);
+                        Ok(Some(line))
+                    }
+                    None => {
+                        self.export_cursor = None;
+                        Ok(Some("*DONE".to_string()))
+                    }
+                }
+            }
+            ("import", Some(_)) => {
+                self.import_buffer.push(original_arg.unwrap_or("").to_string());
+                Ok(None)
+            }
+            ("i2c", Some(value)) if !self.plock => {
+                match u16::from_str(value) {
+                    Ok(n) if (1..=127).contains(&n) => {
+                        self.address = n;
+                        self.reset();
+                        return None;
+                    }
+                    _ => Err(()),
+                }
+            }
+            ("baud", Some(_)) if !self.plock => {
+                self.uart = true;
+                return None;
+            }
+            ("factory", None) => {
+                self.reset();
+                return None;
+            }
+            ("sleep", None) => {
+                self.asleep = true;
+                return None;
+            }
+            _ => Err(()),
+        };
+
+        let reply = match reply {
+            Ok(Some(payload)) if self.status_tokens => Ok(Some(payload + "\r*OK")),
+            Ok(None) if self.status_tokens => Ok(Some("*OK".to_string())),
+            Err(()) if self.status_tokens => Ok(Some("*ER".to_string())),
+            reply => reply,
+        };
+        let mut bytes = match reply {
+            Ok(Some(payload)) => {
+                let mut bytes = vec![ResponseCode::Success as u8];
+                bytes.extend_from_slice(payload.as_bytes());
+                bytes.push(0);
+                bytes
+            }
+            Ok(None) => vec![ResponseCode::Success as u8, 0],
+            Err(()) => vec![ResponseCode::DeviceError as u8, 0],
+        };
+        if let Some(code) = self.code_override {
+            bytes[0] = code;
+        }
+        Some(bytes)
+    }
+}
+
+/// Identifies a chip attached to a `VirtualBus`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChipId(usize);
+
+#[derive(Debug)]
+struct Slot {
+    id: ChipId,
+    /// Multiplexer address and channel the chip sits behind, if any.
+    channel: Option<(u16, u8)>,
+    chip: SimulatedChip,
+}
+
+#[derive(Debug)]
+struct Mux {
+    address: u16,
+    /// Bit mask of the enabled downstream channels.
+    selected: u8,
+}
+
+#[derive(Debug, Default)]
+struct BusState {
+    next_id: usize,
+    slots: Vec<Slot>,
+    muxes: Vec<Mux>,
+}
+
+impl BusState {
+    fn mux_mut(&mut self, address: u16) -> Option<&mut Mux> {
+        self.muxes.iter_mut().find(|mux| mux.address == address)
+    }
+
+    fn channel_enabled(&self, channel: Option<(u16, u8)>) -> bool {
+        match channel {
+            None => true,
+            Some((mux_address, channel)) => {
+                self.muxes
+                    .iter()
+                    .any(|mux| mux.address == mux_address && mux.selected & (1 << channel) != 0)
+            }
+        }
+    }
+
+    /// Indices of the slots whose chips currently answer at `address`.
+    fn visible(&self, address: u16) -> Vec<usize> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|&(_, slot)| {
+                        slot.chip.answers_at(address) && self.channel_enabled(slot.channel)
+                    })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    fn attach(&mut self, channel: Option<(u16, u8)>, chip: SimulatedChip) -> ChipId {
+        let id = ChipId(self.next_id);
+        self.next_id += 1;
+        self.slots.push(Slot {
+                            id: id,
+                            channel: channel,
+                            chip: chip,
+                        });
+        id
+    }
+}
+
+/// A virtual I2C bus shared by any number of `SimulatedDevice` handles.
+///
+/// Cloning a `VirtualBus` yields another handle to the same bus.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualBus {
+    state: Arc<Mutex<BusState>>,
+}
+
+impl VirtualBus {
+    /// Creates an empty bus.
+    pub fn new() -> VirtualBus {
+        VirtualBus::default()
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, BusState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Attaches a chip directly to the bus, at the chip's own address.
+    pub fn attach(&self, chip: SimulatedChip) -> ChipId {
+        self.lock().attach(None, chip)
+    }
+
+    /// Adds a TCA9548A-style multiplexer at `address`, with all of its
+    /// channels disabled.
+    pub fn add_mux(&self, address: u16) {
+        self.lock().muxes.push(Mux {
+                                   address: address,
+                                   selected: 0,
+                               });
+    }
+
+    /// Attaches a chip behind `channel` (0-7) of the multiplexer at
+    /// `mux_address`. The chip is only reachable while that channel is
+    /// selected.
+    pub fn attach_behind_mux(&self, mux_address: u16, channel: u8, chip: SimulatedChip) -> ChipId {
+        assert!(channel < 8, "multiplexer channels range from 0 to 7");
+        self.lock().attach(Some((mux_address, channel)), chip)
+    }
+
+    /// Removes a chip from the bus, returning its final state.
+    pub fn detach(&self, id: ChipId) -> Option<SimulatedChip> {
+        let mut state = self.lock();
+        state
+            .slots
+            .iter()
+            .position(|slot| slot.id == id)
+            .map(|index| state.slots.remove(index).chip)
+    }
+
+    /// Gives access to an attached chip, to inspect or alter its state.
+    pub fn with_chip<F, R>(&self, id: ChipId, f: F) -> Option<R>
+        where F: FnOnce(&mut SimulatedChip) -> R
+    {
+        let mut state = self.lock();
+        state
+            .slots
+            .iter_mut()
+            .find(|slot| slot.id == id)
+            .map(|slot| f(&mut slot.chip))
+    }
+
+    /// Returns `true` if a chip or multiplexer acknowledges `address` with
+    /// the current multiplexer selection.
+    pub fn acks(&self, address: u16) -> bool {
+        let state = self.lock();
+        state.muxes.iter().any(|mux| mux.address == address) || !state.visible(address).is_empty()
+    }
+
+    /// Addresses at which more than one chip is currently reachable.
+    pub fn collisions(&self) -> Vec<u16> {
+        let state = self.lock();
+        let mut addresses: Vec<u16> = state.slots.iter().map(|slot| slot.chip.address).collect();
+        addresses.sort();
+        addresses.dedup();
+        addresses
+            .into_iter()
+            .filter(|&address| state.visible(address).len() > 1)
+            .collect()
+    }
+
+    /// Opens a handle for the slave at `address`.
+    pub fn device(&self, address: u16) -> SimulatedDevice {
+        SimulatedDevice {
+            bus: self.clone(),
+            address: address,
+        }
+    }
+
+    fn write(&self, address: u16, data: &[u8]) -> ::std::result::Result<(), SimError> {
+        let mut state = self.lock();
+        if let Some(mux) = state.mux_mut(address) {
+            return match data.len() {
+                       1 => {
+                           mux.selected = data[0];
+                           Ok(())
+                       }
+                       _ => Err(SimError::Unsupported("multi-byte multiplexer write")),
+                   };
+        }
+        let visible = state.visible(address);
+        let mut acked = false;
+        for index in visible {
+            acked |= state.slots[index].chip.write(data);
+        }
+        if acked {
+            Ok(())
+        } else {
+            Err(SimError::Nack(address))
+        }
+    }
+
+    fn read(&self, address: u16, data: &mut [u8]) -> ::std::result::Result<(), SimError> {
+        let mut state = self.lock();
+        if let Some(mux) = state.mux_mut(address) {
+            for byte in data.iter_mut() {
+                *byte = mux.selected;
+            }
+            return Ok(());
+        }
+        let visible = state.visible(address);
+        if visible.is_empty() {
+            return Err(SimError::Nack(address));
+        }
+        // The bus is open-drain: a zero bit from any chip wins.
+        for byte in data.iter_mut() {
+            *byte = 0xff;
+        }
+        for index in visible {
+            let reply = state.slots[index].chip.read(data.len());
+            for (dst, src) in data.iter_mut().zip(reply.iter()) {
+                *dst &= *src;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A handle for one slave address on a `VirtualBus`.
+#[derive(Clone, Debug)]
+pub struct SimulatedDevice {
+    bus: VirtualBus,
+    address: u16,
+}
+
+impl SimulatedDevice {
+    /// The slave address this handle talks to.
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    /// Points the handle at another slave address, like
+    /// `LinuxI2CDevice::set_slave_address`.
+    pub fn set_slave_address(&mut self, address: u16) -> ::std::result::Result<(), SimError> {
+        self.address = address;
+        Ok(())
+    }
+
+    /// The bus this handle belongs to.
+    pub fn bus(&self) -> &VirtualBus {
+        &self.bus
+    }
+}
+
+impl Addressable for SimulatedDevice {
+    fn select_address(&mut self, address: u16) -> ::errors::Result<()> {
+        self.address = address;
+        Ok(())
+    }
+}
+
+impl I2CDevice for SimulatedDevice {
+    type Error = SimError;
+
+    fn read(&mut self, data: &mut [u8]) -> ::std::result::Result<(), SimError> {
+        self.bus.read(self.address, data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> ::std::result::Result<(), SimError> {
+        self.bus.write(self.address, data)
+    }
+
+    fn smbus_write_quick(&mut self, _bit: bool) -> ::std::result::Result<(), SimError> {
+        if self.bus.acks(self.address) {
+            Ok(())
+        } else {
+            Err(SimError::Nack(self.address))
+        }
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> ::std::result::Result<Vec<u8>, SimError> {
+        Err(SimError::Unsupported("SMBus block read"))
+    }
+
+    fn smbus_write_block_data(&mut self,
+                              _register: u8,
+                              _values: &[u8])
+                              -> ::std::result::Result<(), SimError> {
+        Err(SimError::Unsupported("SMBus block write"))
+    }
+
+    fn smbus_process_block(&mut self,
+                           _register: u8,
+                           _values: &[u8])
+                           -> ::std::result::Result<(), SimError> {
+        Err(SimError::Unsupported("SMBus block process call"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::QUERY_RESPONSE;
+    use {CommandBuilder, I2cCommand, TemperatureCommand};
+
+    fn query(dev: &mut SimulatedDevice, command: TemperatureCommand) -> String {
+        let mut cmd = command.build();
+        cmd.delay = None;
+        cmd.run(dev).unwrap();
+        cmd.parse_response().unwrap()
+    }
+
+    #[test]
+    fn chips_answer_at_their_own_addresses() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(21.5);
+        bus.attach(chip);
+        let mut other = SimulatedChip::new(0x63);
+        other.set_identity("pH", "1.96");
+        bus.attach(other);
+
+        let mut rtd = bus.device(0x66);
+        let mut ph = bus.device(0x63);
+        assert_eq!(query(&mut rtd, TemperatureCommand::Reading), "21.500");
+        assert_eq!(query(&mut rtd, TemperatureCommand::DeviceInformation),
+                   "?I,RTD,2.01");
+        assert_eq!(query(&mut ph, TemperatureCommand::DeviceInformation),
+                   "?I,pH,1.96");
+    }
+
+    #[test]
+    fn empty_address_is_not_acknowledged() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut dev = bus.device(0x10);
+        assert_eq!(dev.write(b"R\0"), Err(SimError::Nack(0x10)));
+        assert!(!bus.acks(0x10));
+        assert!(bus.acks(0x66));
+    }
+
+    #[test]
+    fn colliding_chips_garble_replies() {
+        let bus = VirtualBus::new();
+        let mut first = SimulatedChip::new(0x66);
+        first.set_temperature(25.0);
+        bus.attach(first);
+        let mut second = SimulatedChip::new(0x66);
+        second.set_temperature(-3.0);
+        bus.attach(second);
+
+        assert_eq!(bus.collisions(), vec![0x66]);
+        let mut dev = bus.device(0x66);
+        dev.write(b"R\0").unwrap();
+        let mut buf = [0u8; 16];
+        dev.read(&mut buf).unwrap();
+        assert_eq!(buf[0], ResponseCode::Success as u8);
+        assert!(&buf[1..7] != b"25.000");
+        assert!(&buf[1..7] != b"-3.000");
+    }
+
+    #[test]
+    fn chips_behind_a_mux_need_their_channel_selected() {
+        let bus = VirtualBus::new();
+        bus.add_mux(0x70);
+        bus.attach_behind_mux(0x70, 0, SimulatedChip::new(0x66));
+        bus.attach_behind_mux(0x70, 3, SimulatedChip::new(0x66));
+        assert!(!bus.acks(0x66));
+
+        let mut mux = bus.device(0x70);
+        mux.write(&[1 << 3]).unwrap();
+        assert!(bus.acks(0x66));
+        assert!(bus.collisions().is_empty());
+
+        mux.write(&[(1 << 3) | 1]).unwrap();
+        assert_eq!(bus.collisions(), vec![0x66]);
+    }
+
+    #[test]
+    fn settings_change_chip_state() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut dev = bus.device(0x66);
+
+        query(&mut dev, TemperatureCommand::ScaleKelvin);
+        query(&mut dev, TemperatureCommand::LedOff);
+        assert_eq!(query(&mut dev, TemperatureCommand::ScaleState), "?S,k");
+        assert_eq!(query(&mut dev, TemperatureCommand::Reading), "298.150");
+        assert_eq!(bus.with_chip(id, |chip| chip.led()), Some(false));
+    }
+
+    #[test]
+    fn sleeping_chip_drops_the_first_write() {
+        let bus = VirtualBus::new();
+        let id = bus.attach(SimulatedChip::new(0x66));
+        let mut dev = bus.device(0x66);
+
+        TemperatureCommand::Sleep.build().run(&mut dev).unwrap();
+        assert_eq!(bus.with_chip(id, |chip| chip.is_asleep()), Some(true));
+        assert_eq!(dev.write(b"R\0"), Err(SimError::Nack(0x66)));
+        assert_eq!(bus.with_chip(id, |chip| chip.is_asleep()), Some(false));
+    }
+
+    #[test]
+    fn address_change_moves_the_chip() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let mut dev = bus.device(0x66);
+
+        TemperatureCommand::DeviceAddress(0x67).build().write(&mut dev).unwrap();
+        assert!(!bus.acks(0x66));
+        assert!(bus.acks(0x67));
+    }
+
+    #[test]
+    fn chaos_mode_flips_are_corrected_and_reported() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(-12.53).set_bit_flip_chaos(1.0, 7);
+        let id = bus.attach(chip);
+        let mut dev = bus.device(0x66);
+
+        let mut cmd = TemperatureCommand::Reading.build();
+        cmd.delay = None;
+        cmd.run(&mut dev).unwrap();
+        assert!(cmd.response_data().unwrap()[1..].iter().all(|&b| b & 0x80 != 0));
+        assert!(cmd.flipping);
+        assert_eq!(cmd.parse_response().unwrap(), "-12.530");
+        assert_eq!(bus.with_chip(id, |chip| chip.flipped_bytes()),
+                   Some(QUERY_RESPONSE - 1));
+    }
+
+    #[test]
+    fn chaos_mode_under_stress() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(37.25).set_bit_flip_chaos(0.2, 0xdead_beef);
+        let id = bus.attach(chip);
+        let mut dev = bus.device(0x66);
+
+        let mut detected = 0;
+        for _ in 0..200 {
+            let before = bus.with_chip(id, |chip| chip.flipped_bytes()).unwrap();
+            let mut cmd = TemperatureCommand::Reading.build();
+            cmd.delay = None;
+            cmd.run(&mut dev).unwrap();
+            assert_eq!(cmd.parse_response().unwrap(), "37.250");
+            let data = cmd.data.as_ref().unwrap();
+            let in_payload = data[1..8].iter().filter(|&&b| b & 0x80 != 0).count();
+            assert_eq!(cmd.flipping, in_payload > 0);
+            let after = bus.with_chip(id, |chip| chip.flipped_bytes()).unwrap();
+            assert!(after - before >= in_payload);
+            if cmd.flipping {
+                detected += 1;
+            }
+        }
+        assert!(detected > 0);
+    }
+
+    #[test]
+    fn reads_before_processing_time_are_pending() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_processing_time(Duration::from_secs(60));
+        bus.attach(chip);
+        let mut dev = bus.device(0x66);
+
+        dev.write(b"R\0").unwrap();
+        let mut buf = [0u8; 16];
+        dev.read(&mut buf).unwrap();
+        assert_eq!(buf[0], ResponseCode::Pending as u8);
+    }
+}
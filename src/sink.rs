@@ -0,0 +1,152 @@
+//! Destinations for readings.
+//!
+//! A `ReadingSink` takes timestamped readings wherever they go: a file, a
+//! broker, a database. The sinks of `ezo-rtd daemon` are `ReadingSink`s,
+//! and `Sinks` hands each reading to any number of them, so applications
+//! polling sensors can store their readings in places of their own, next to
+//! the built-in ones, without changes to this crate.
+
+use std::time::SystemTime;
+
+use errors::*;
+use events::DeviceEvent;
+use response::Temperature;
+
+/// A reading, the sensor it was taken from, and when.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampedReading {
+    pub sensor: String,
+    pub time: SystemTime,
+    pub temperature: Temperature,
+}
+
+impl TimestampedReading {
+    /// A reading taken from `sensor` just now.
+    pub fn now(sensor: &str, temperature: Temperature) -> TimestampedReading {
+        TimestampedReading {
+            sensor: sensor.to_string(),
+            time: SystemTime::now(),
+            temperature: temperature,
+        }
+    }
+}
+
+/// A destination for readings.
+pub trait ReadingSink {
+    /// Stores or sends `reading`, or queues it for `flush`.
+    fn accept(&mut self, reading: &TimestampedReading) -> Result<()>;
+
+    /// Stores or sends something `sensor` did to its chip; most sinks only
+    /// take readings.
+    fn accept_event(&mut self,
+                    _sensor: &str,
+                    _time: SystemTime,
+                    _event: &DeviceEvent)
+                    -> Result<()> {
+        Ok(())
+    }
+
+    /// Sends what is queued. Called after each round of readings, for sinks
+    /// that batch them.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Any number of sinks, each given every reading.
+///
+/// A failing sink does not keep the others from getting the reading: the
+/// errors of all of them are handed back, for the caller to report.
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<Box<dyn ReadingSink>>,
+}
+
+impl Sinks {
+    pub fn new() -> Sinks {
+        Sinks::default()
+    }
+
+    pub fn add<S: ReadingSink + 'static>(&mut self, sink: S) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub fn add_boxed(&mut self, sink: Box<dyn ReadingSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Gives `reading` to every sink; returns their errors.
+    pub fn accept(&mut self, reading: &TimestampedReading) -> Vec<Error> {
+        self.sinks
+            .iter_mut()
+            .filter_map(|sink| sink.accept(reading).err())
+            .collect()
+    }
+
+    /// Gives `event` to every sink; returns their errors.
+    pub fn accept_event(&mut self,
+                        sensor: &str,
+                        time: SystemTime,
+                        event: &DeviceEvent)
+                        -> Vec<Error> {
+        self.sinks
+            .iter_mut()
+            .filter_map(|sink| sink.accept_event(sensor, time, event).err())
+            .collect()
+    }
+
+    /// Flushes every sink; returns their errors.
+    pub fn flush(&mut self) -> Vec<Error> {
+        self.sinks
+            .iter_mut()
+            .filter_map(|sink| sink.flush().err())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Recorder(Arc<Mutex<Vec<TimestampedReading>>>);
+
+    impl ReadingSink for Recorder {
+        fn accept(&mut self, reading: &TimestampedReading) -> Result<()> {
+            self.0.lock().unwrap().push(reading.clone());
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl ReadingSink for Broken {
+        fn accept(&mut self, _reading: &TimestampedReading) -> Result<()> {
+            bail!("the disk is full")
+        }
+    }
+
+    #[test]
+    fn every_sink_gets_the_reading_despite_failures() {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let mut sinks = Sinks::new();
+        sinks.add(Broken);
+        sinks.add(Recorder(recorded.clone()));
+
+        let reading = TimestampedReading::now("sump", Temperature::Celsius(21.5));
+        let errors = sinks.accept(&reading);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "the disk is full");
+        assert_eq!(*recorded.lock().unwrap(), vec![reading]);
+        assert!(sinks.flush().is_empty());
+    }
+}
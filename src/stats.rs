@@ -0,0 +1,194 @@
+//! Per-device transaction statistics.
+//!
+//! A chip that is wearing out, or a bus that is getting noisy, rarely fails
+//! all at once: more responses come back pending or with errors, more
+//! transactions need retries, and they take longer. `TransactionStats`
+//! counts all of that for one sensor, so that the trend can be watched.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use ezo_common::ResponseCode;
+
+/// Latencies kept for the percentiles; older ones are dropped.
+pub const LATENCY_SAMPLES: usize = 1024;
+
+/// Counts for one kind of command.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CommandStats {
+    /// Commands run.
+    pub count: u64,
+    /// Retries made by the writes and reads of those commands.
+    pub retries: u64,
+    /// Commands whose write or read failed even after retrying.
+    pub failures: u64,
+}
+
+/// What the transactions of one sensor looked like.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionStats {
+    /// Counts by response code, in the order they were first read.
+    codes: Vec<(ResponseCode, u64)>,
+    commands: BTreeMap<&'static str, CommandStats>,
+    latencies: VecDeque<Duration>,
+}
+
+impl TransactionStats {
+    pub fn new() -> TransactionStats {
+        TransactionStats::default()
+    }
+
+    /// Counts a transaction of the command named `command`, as given by
+    /// `TemperatureCommand::name`. `code` is the response code read, if
+    /// any, and `latency` the time from the write to the end of the read.
+    pub fn record(&mut self,
+                  command: &'static str,
+                  code: Option<ResponseCode>,
+                  retries: u32,
+                  failed: bool,
+                  latency: Duration) {
+        if let Some(code) = code {
+            self.record_code(code);
+        }
+        let stats = self.commands.entry(command).or_insert_with(CommandStats::default);
+        stats.count += 1;
+        stats.retries += retries as u64;
+        if failed {
+            stats.failures += 1;
+        }
+        if self.latencies.len() == LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+    }
+
+    /// Counts a response code read within a transaction that is counted
+    /// with another code, such as a `Pending` code that was polled over.
+    pub fn record_code(&mut self, code: ResponseCode) {
+        match self.codes.iter_mut().find(|entry| entry.0 == code) {
+            Some(entry) => entry.1 += 1,
+            None => self.codes.push((code, 1)),
+        }
+    }
+
+    /// Forgets everything counted so far.
+    pub fn reset(&mut self) {
+        *self = TransactionStats::default();
+    }
+
+    /// How many times each response code was read, in the order the codes
+    /// were first read.
+    pub fn codes(&self) -> &[(ResponseCode, u64)] {
+        &self.codes
+    }
+
+    /// How many times the response code `code` was read.
+    pub fn code_count(&self, code: ResponseCode) -> u64 {
+        self.codes
+            .iter()
+            .find(|&&(seen, _)| seen == code)
+            .map_or(0, |&(_, count)| count)
+    }
+
+    pub fn successes(&self) -> u64 {
+        self.code_count(ResponseCode::Success)
+    }
+
+    pub fn device_errors(&self) -> u64 {
+        self.code_count(ResponseCode::DeviceError)
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.code_count(ResponseCode::Pending)
+    }
+
+    pub fn no_data(&self) -> u64 {
+        self.code_count(ResponseCode::NoDataExpected)
+    }
+
+    /// The share of response codes read that were `code`, from 0 to 1;
+    /// `None` before any response was read.
+    pub fn code_rate(&self, code: ResponseCode) -> Option<f64> {
+        let total: u64 = self.codes.iter().map(|&(_, count)| count).sum();
+        if total == 0 {
+            None
+        } else {
+            Some(self.code_count(code) as f64 / total as f64)
+        }
+    }
+
+    /// Counts by kind of command, keyed by `TemperatureCommand::name`.
+    pub fn commands(&self) -> &BTreeMap<&'static str, CommandStats> {
+        &self.commands
+    }
+
+    /// Transactions counted.
+    pub fn transactions(&self) -> u64 {
+        self.commands.values().map(|stats| stats.count).sum()
+    }
+
+    /// Retries made, for every kind of command.
+    pub fn retries(&self) -> u64 {
+        self.commands.values().map(|stats| stats.retries).sum()
+    }
+
+    /// The `percentile` (0 to 100) of the latencies of the last
+    /// `LATENCY_SAMPLES` transactions, by the nearest-rank method; `None`
+    /// before any transaction.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.latencies.iter().cloned().collect();
+        sorted.sort();
+        let percentile = percentile.max(0.0).min(100.0);
+        let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.max(1) - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_codes_retries_and_latencies() {
+        let mut stats = TransactionStats::new();
+        assert_eq!(stats.code_rate(ResponseCode::Success), None);
+        assert_eq!(stats.latency_percentile(50.0), None);
+
+        for ms in 1..11 {
+            stats.record("R",
+                         Some(ResponseCode::Success),
+                         0,
+                         false,
+                         Duration::from_millis(ms * 10));
+        }
+        stats.record("R", Some(ResponseCode::Pending), 2, false, Duration::from_millis(500));
+        stats.record("Cal,?",
+                     Some(ResponseCode::DeviceError),
+                     0,
+                     false,
+                     Duration::from_millis(5));
+        stats.record("Cal,?", None, 3, true, Duration::from_millis(5));
+
+        assert_eq!(stats.successes(), 10);
+        assert_eq!(stats.pending(), 1);
+        assert_eq!(stats.device_errors(), 1);
+        assert_eq!(stats.code_rate(ResponseCode::Pending), Some(1.0 / 12.0));
+        assert_eq!(stats.transactions(), 13);
+        assert_eq!(stats.retries(), 5);
+        assert_eq!(stats.commands()["Cal,?"],
+                   CommandStats {
+                       count: 2,
+                       retries: 3,
+                       failures: 1,
+                   });
+        assert_eq!(stats.latency_percentile(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(stats.latency_percentile(100.0), Some(Duration::from_millis(500)));
+        assert_eq!(stats.latency_percentile(0.0), Some(Duration::from_millis(5)));
+
+        stats.reset();
+        assert_eq!(stats.transactions(), 0);
+    }
+}
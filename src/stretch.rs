@@ -0,0 +1,98 @@
+//! Workarounds for I2C controllers that mishandle clock stretching.
+//!
+//! EZO chips hold the clock low while they prepare a response. The I2C
+//! block of the Raspberry Pi does not wait for the clock to be released
+//! when the stretch happens at certain points of a transfer, so bytes get
+//! corrupted (typically the most significant bit) or transactions fail.
+//! The known remedies are partly on the host side, which this crate cannot
+//! change, and partly in how the chip is talked to; a `ClockStretchProfile`
+//! describes both, and `RtdSensor::set_clock_stretch_profile` applies the
+//! latter.
+
+use std::time::Duration;
+
+use common::RetryPolicy;
+
+/// How to talk to chips behind a controller with a clock-stretching bug.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockStretchProfile {
+    /// Highest bus speed, in Hz, at which the controller copes with the
+    /// chip's stretching; `None` for no limit. The speed is set on the
+    /// host, see `host_config`.
+    pub max_bus_speed_hz: Option<u32>,
+    /// Extra wait after each write, before the processing delay, so that
+    /// the chip has released the clock before the next transaction.
+    pub post_write_settle: Duration,
+    /// Retries for the failed or corrupted transactions that stretching
+    /// causes: more attempts, further apart.
+    pub retry: RetryPolicy,
+    /// Host configuration that avoids the problem, one suggestion per line.
+    pub host_config: &'static [&'static str],
+}
+
+impl Default for ClockStretchProfile {
+    /// No workaround, for controllers that handle stretching.
+    fn default() -> ClockStretchProfile {
+        ClockStretchProfile {
+            max_bus_speed_hz: None,
+            post_write_settle: Duration::from_millis(0),
+            retry: RetryPolicy::default(),
+            host_config: &[],
+        }
+    }
+}
+
+impl ClockStretchProfile {
+    /// The workarounds for the Raspberry Pi's hardware I2C block.
+    pub fn raspberry_pi() -> ClockStretchProfile {
+        ClockStretchProfile {
+            max_bus_speed_hz: Some(10_000),
+            post_write_settle: Duration::from_millis(20),
+            retry: RetryPolicy {
+                attempts: 5,
+                min_wait: Duration::from_millis(50),
+                max_wait: Duration::from_millis(2000),
+                ..RetryPolicy::default()
+            },
+            host_config: &["dtparam=i2c_arm_baudrate=10000 in /boot/config.txt lowers the \
+                            bus speed",
+                           "dtoverlay=i2c-gpio in /boot/config.txt replaces the I2C block \
+                            with a bit-banged bus that handles stretching"],
+        }
+    }
+
+    /// Whether the profile changes anything.
+    pub fn is_workaround(&self) -> bool {
+        *self != ClockStretchProfile::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use response::Temperature;
+    use sensor::RtdSensor;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn raspberry_pi_profile_is_a_workaround() {
+        assert!(!ClockStretchProfile::default().is_workaround());
+        let profile = ClockStretchProfile::raspberry_pi();
+        assert!(profile.is_workaround());
+        assert!(profile.retry.attempts > RetryPolicy::default().attempts);
+        assert!(!profile.host_config.is_empty());
+    }
+
+    #[test]
+    fn sensor_runs_with_the_profile() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_temperature(18.5);
+        bus.attach(chip);
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_clock_stretch_profile(&ClockStretchProfile::raspberry_pi());
+
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(18.5));
+    }
+}
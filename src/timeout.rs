@@ -0,0 +1,204 @@
+//! A hard timeout on I2C transactions.
+//!
+//! A wedged bus can block a read or write on `/dev/i2c-*` indefinitely.
+//! `TimeoutDevice` runs every transaction of the device it wraps on a
+//! worker thread, and gives up on it after a deadline, so the caller always
+//! gets control back and can recover the bus. A transaction that timed out
+//! keeps the worker busy until it returns; until then, every transaction
+//! fails at once with `I2CTimeout`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+
+/// A transaction to run on the worker thread.
+type Job<D> = Box<dyn FnOnce(&mut D) + Send>;
+
+/// An I2C device whose transactions fail with `I2CTimeout` instead of
+/// blocking for longer than the timeout.
+pub struct TimeoutDevice<D> {
+    jobs: Sender<Job<D>>,
+    /// Set while the worker runs a transaction.
+    busy: Arc<AtomicBool>,
+    timeout: Duration,
+}
+
+impl<D: I2CDevice + Send + 'static> TimeoutDevice<D> {
+    /// Moves `dev` to a worker thread; each transaction is given at most
+    /// `timeout` to complete.
+    pub fn new(mut dev: D, timeout: Duration) -> Result<TimeoutDevice<D>> {
+        let (jobs, queue) = mpsc::channel::<Job<D>>();
+        let busy = Arc::new(AtomicBool::new(false));
+        thread::Builder::new()
+            .name("ezo-i2c".to_string())
+            .spawn(move || for job in queue {
+                       job(&mut dev);
+                   })
+            .chain_err(|| "Could not start the I2C worker thread")?;
+        Ok(TimeoutDevice {
+               jobs: jobs,
+               busy: busy,
+               timeout: timeout,
+           })
+    }
+
+    /// Whether a transaction that timed out is still blocking the device.
+    pub fn is_wedged(&self) -> bool {
+        self.busy.load(Ordering::SeqCst)
+    }
+
+    /// Runs `f` on the worker thread and waits for its result, at most for
+    /// the timeout.
+    fn run<T, F>(&mut self, f: F) -> Result<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut D) -> ::std::result::Result<T, D::Error> + Send + 'static
+    {
+        let timed_out = || ErrorKind::I2CTimeout(self.timeout.as_millis() as u64).into();
+        if self.is_wedged() {
+            return Err(timed_out());
+        }
+        let (reply, result) = mpsc::channel();
+        self.busy.store(true, Ordering::SeqCst);
+        let busy = self.busy.clone();
+        let job: Job<D> = Box::new(move |dev: &mut D| {
+            let outcome = f(dev).map_err(|e| e.to_string());
+            // Cleared before replying: once the caller has the reply, it may
+            // already have set the flag for its next transaction.
+            busy.store(false, Ordering::SeqCst);
+            // The caller may have given up waiting.
+            let _ = reply.send(outcome);
+        });
+        if self.jobs.send(job).is_err() {
+            self.busy.store(false, Ordering::SeqCst);
+            bail!("the I2C worker thread is gone");
+        }
+        match result.recv_timeout(self.timeout) {
+            Ok(result) => result.map_err(Error::from),
+            Err(_) => Err(timed_out()),
+        }
+    }
+}
+
+impl<D: I2CDevice + Send + 'static> I2CDevice for TimeoutDevice<D> {
+    type Error = Error;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<()> {
+        let len = data.len();
+        let bytes = self.run(move |dev| {
+                                 let mut bytes = vec![0; len];
+                                 dev.read(&mut bytes).map(|_| bytes)
+                             })?;
+        data.copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let bytes = data.to_vec();
+        self.run(move |dev| dev.write(&bytes))
+    }
+
+    fn smbus_write_quick(&mut self, bit: bool) -> Result<()> {
+        self.run(move |dev| dev.smbus_write_quick(bit))
+    }
+
+    fn smbus_read_block_data(&mut self, register: u8) -> Result<Vec<u8>> {
+        self.run(move |dev| dev.smbus_read_block_data(register))
+    }
+
+    fn smbus_write_block_data(&mut self, register: u8, values: &[u8]) -> Result<()> {
+        let values = values.to_vec();
+        self.run(move |dev| dev.smbus_write_block_data(register, &values))
+    }
+
+    fn smbus_process_block(&mut self, register: u8, values: &[u8]) -> Result<()> {
+        let values = values.to_vec();
+        self.run(move |dev| dev.smbus_process_block(register, &values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use response::Temperature;
+    use sensor::RtdSensor;
+    use simulator::{SimError, SimulatedChip, SimulatedDevice, VirtualBus};
+    use std::time::Instant;
+
+    /// A device whose reads hang for a while, like on a wedged bus.
+    struct Hanging(SimulatedDevice, Duration);
+
+    impl I2CDevice for Hanging {
+        type Error = SimError;
+
+        fn read(&mut self, data: &mut [u8]) -> ::std::result::Result<(), SimError> {
+            thread::sleep(self.1);
+            self.0.read(data)
+        }
+
+        fn write(&mut self, data: &[u8]) -> ::std::result::Result<(), SimError> {
+            self.0.write(data)
+        }
+
+        fn smbus_write_quick(&mut self, bit: bool) -> ::std::result::Result<(), SimError> {
+            self.0.smbus_write_quick(bit)
+        }
+
+        fn smbus_read_block_data(&mut self,
+                                 register: u8)
+                                 -> ::std::result::Result<Vec<u8>, SimError> {
+            self.0.smbus_read_block_data(register)
+        }
+
+        fn smbus_write_block_data(&mut self,
+                                  register: u8,
+                                  values: &[u8])
+                                  -> ::std::result::Result<(), SimError> {
+            self.0.smbus_write_block_data(register, values)
+        }
+
+        fn smbus_process_block(&mut self,
+                               register: u8,
+                               values: &[u8])
+                               -> ::std::result::Result<(), SimError> {
+            self.0.smbus_process_block(register, values)
+        }
+    }
+
+    #[test]
+    fn transactions_within_the_timeout_go_through() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let dev = TimeoutDevice::new(bus.device(0x66), Duration::from_millis(500)).unwrap();
+        let mut sensor = RtdSensor::new(dev);
+
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(25.0));
+    }
+
+    #[test]
+    fn hanging_reads_time_out() {
+        let bus = VirtualBus::new();
+        bus.attach(SimulatedChip::new(0x66));
+        let hanging = Hanging(bus.device(0x66), Duration::from_millis(200));
+        let mut dev = TimeoutDevice::new(hanging, Duration::from_millis(20)).unwrap();
+
+        let start = Instant::now();
+        let mut buf = [0u8; 2];
+        match *dev.read(&mut buf).unwrap_err().kind() {
+            ErrorKind::I2CTimeout(20) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_millis(150));
+        assert!(dev.is_wedged());
+        assert!(dev.write(b"R\0").is_err());
+
+        thread::sleep(Duration::from_millis(300));
+        assert!(!dev.is_wedged());
+        assert!(dev.write(b"R\0").is_ok());
+    }
+}
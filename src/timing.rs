@@ -0,0 +1,148 @@
+//! Measuring how long the chip takes to process commands, and tuning the
+//! processing delays to match.
+//!
+//! The datasheet times are conservative. `measure` writes a command and
+//! polls until the chip stops answering `Pending`; `tune` does so for
+//! commands that leave the chip's state alone, and suggests `Delays` with a
+//! safety margin added, which `RtdSensor::set_delays` applies.
+
+use std::time::{Duration, Instant};
+
+use buffer::MAX_RESPONSE;
+use common::read_with_retry;
+use errors::*;
+use ezo_common::ResponseCode;
+use i2cdev::core::I2CDevice;
+use parse;
+use {CALIBRATION_DELAY, COMMAND_DELAY, CommandBuilder, I2cCommand, READING_DELAY,
+     TemperatureCommand};
+
+/// Time between polls of a chip that answers `Pending`, in milliseconds.
+const POLL_INTERVAL_MS: u64 = 5;
+
+/// Processing delays, in milliseconds, by kind of command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delays {
+    /// Delay after `R`.
+    pub reading: u64,
+    /// Delay after `Cal,t`.
+    pub calibration: u64,
+    /// Delay after any other command that answers.
+    pub command: u64,
+}
+
+impl Default for Delays {
+    /// The datasheet delays.
+    fn default() -> Delays {
+        Delays {
+            reading: READING_DELAY,
+            calibration: CALIBRATION_DELAY,
+            command: COMMAND_DELAY,
+        }
+    }
+}
+
+impl Delays {
+    /// The delay to wait after `command`; `None` for commands after which
+    /// the chip does not answer at all.
+    pub fn for_command(&self, command: &TemperatureCommand) -> Option<u64> {
+        use TemperatureCommand::*;
+        command
+            .processing_delay()
+            .map(|_| match *command {
+                     Reading => self.reading,
+                     CalibrationTemperature(_) => self.calibration,
+                     _ => self.command,
+                 })
+    }
+}
+
+/// Runs `command` on `dev` and returns how long the chip took until it
+/// answered with anything but `Pending`; `None` for commands after which
+/// the chip does not answer. The chip is given twice its datasheet
+/// processing time before `PendingResponse` is returned.
+pub fn measure<D: I2CDevice>(dev: &mut D,
+                             command: &TemperatureCommand)
+                             -> Result<Option<Duration>> {
+    let mut cmd = command.build();
+    if cmd.response.is_none() {
+        return Ok(None);
+    }
+    let limit = Duration::from_millis(2 * cmd.delay.unwrap_or(0));
+    let poll = Duration::from_millis(POLL_INTERVAL_MS);
+    cmd.write(dev)?;
+    let start = Instant::now();
+    let mut buf = [0u8; MAX_RESPONSE];
+    loop {
+        cmd.retry.wait_strategy.wait(poll);
+        read_with_retry(dev, &mut buf, &cmd.retry, poll)?;
+        let elapsed = start.elapsed();
+        match parse::code(&buf)? {
+            ResponseCode::Pending if elapsed < limit => continue,
+            ResponseCode::Pending => return Err(ErrorKind::PendingResponse.into()),
+            _ => return Ok(Some(elapsed)),
+        }
+    }
+}
+
+/// Measures `R` and `Status` `samples` times each, and suggests delays of
+/// the slowest sample plus `margin_percent`. Calibration is not measured,
+/// since it would change the chip's state; its datasheet delay is kept.
+pub fn tune<D: I2CDevice>(dev: &mut D, samples: u32, margin_percent: u64) -> Result<Delays> {
+    let mut delays = Delays::default();
+    delays.reading = slowest(dev, &TemperatureCommand::Reading, samples, margin_percent)?;
+    delays.command = slowest(dev, &TemperatureCommand::Status, samples, margin_percent)?;
+    Ok(delays)
+}
+
+/// The slowest of `samples` runs of `command`, plus `margin_percent`, in
+/// whole milliseconds.
+fn slowest<D: I2CDevice>(dev: &mut D,
+                         command: &TemperatureCommand,
+                         samples: u32,
+                         margin_percent: u64)
+                         -> Result<u64> {
+    let mut slowest = Duration::from_millis(0);
+    for _ in 0..samples.max(1) {
+        if let Some(elapsed) = measure(dev, command)? {
+            slowest = slowest.max(elapsed);
+        }
+    }
+    let micros = slowest.as_micros() as u64 * (100 + margin_percent) / 100;
+    Ok((micros + 999) / 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulator::{SimulatedChip, VirtualBus};
+
+    #[test]
+    fn delays_default_to_the_datasheet() {
+        let delays = Delays::default();
+        for command in &[TemperatureCommand::Reading,
+                         TemperatureCommand::CalibrationTemperature(100.0),
+                         TemperatureCommand::Status,
+                         TemperatureCommand::Sleep] {
+            assert_eq!(delays.for_command(command), command.processing_delay());
+        }
+    }
+
+    #[test]
+    fn tuned_delays_follow_the_chip() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_processing_time(Duration::from_millis(20));
+        bus.attach(chip);
+        let mut dev = bus.device(0x66);
+
+        let elapsed = measure(&mut dev, &TemperatureCommand::Reading).unwrap().unwrap();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert_eq!(measure(&mut dev, &TemperatureCommand::Sleep).unwrap(), None);
+
+        let delays = tune(&mut dev, 3, 25).unwrap();
+        assert!(delays.reading >= 25 && delays.reading < READING_DELAY);
+        assert!(delays.command >= 25 && delays.command < COMMAND_DELAY);
+        assert_eq!(delays.calibration, CALIBRATION_DELAY);
+    }
+}
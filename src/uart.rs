@@ -0,0 +1,260 @@
+//! The chip in UART mode, on a serial port.
+//!
+//! After a `Baud` command the chip stops answering on the I2C bus and talks
+//! over its serial pins instead: commands and responses are text lines
+//! ended by a carriage return, and every command is acknowledged with a
+//! status string such as `*OK`. In continuous mode, set with `C,n`, the
+//! chip also sends a reading every `n` seconds without being asked, which
+//! `UartSensor::readings` consumes as a stream.
+//!
+//! For other uses of the serial stream, `codec::LineCodec` splits it into
+//! typed lines.
+//!
+//! The serial port is any `Read + Write`; a tty must already be set to the
+//! chip's baud rate, 8N1, for example with `stty`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use codec::UartLine;
+use errors::*;
+use response::{ContinuousReadingSeconds, StatusToken, Temperature, TemperatureScale};
+
+/// Longest interval of continuous mode, in seconds.
+pub const MAX_CONTINUOUS_SECONDS: u32 = 99;
+
+/// An RTD EZO chip in UART mode.
+pub struct UartSensor<P: Read + Write> {
+    port: BufReader<P>,
+    /// Temperature scale last reported by or set on the chip.
+    scale: Option<TemperatureScale>,
+    /// Bytes of the line being read, reused for every line.
+    line: Vec<u8>,
+}
+
+impl UartSensor<File> {
+    /// Opens the serial port at `path`, such as `/dev/ttyUSB0`.
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<UartSensor<File>> {
+        let path = path.as_ref();
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .chain_err(|| format!("Could not open serial port {}", path.display()))?;
+        Ok(UartSensor::new(port))
+    }
+}
+
+impl<P: Read + Write> UartSensor<P> {
+    /// Wraps an already opened serial port.
+    pub fn new(port: P) -> UartSensor<P> {
+        UartSensor {
+            port: BufReader::new(port),
+            scale: None,
+            line: Vec::new(),
+        }
+    }
+
+    /// Reads the next non-empty line, without its carriage return. Fails
+    /// when the port has nothing more to read.
+    fn read_line(&mut self) -> Result<String> {
+        loop {
+            self.line.clear();
+            let len = self.port
+                .read_until(b'\r', &mut self.line)
+                .chain_err(|| "Could not read from the serial port")?;
+            if len == 0 {
+                bail!("the serial port closed");
+            }
+            let text = String::from_utf8_lossy(&self.line);
+            let text = text.trim_matches(|c: char| c == '\r' || c == '\n');
+            if !text.is_empty() {
+                return Ok(text.to_string());
+            }
+        }
+    }
+
+    /// Sends a command, without its terminator, and waits for the status
+    /// string that acknowledges it. Returns the last line of data received
+    /// before the acknowledgement, if any; in continuous mode, this may be
+    /// a reading sent on its own. Fails if the chip answers `*ER`.
+    pub fn send(&mut self, command: &str) -> Result<Option<String>> {
+        if command.is_empty() || !command.bytes().all(|b| (b' '..=b'~').contains(&b)) {
+            return Err(ErrorKind::InvalidCommand("not a printable ASCII command").into());
+        }
+        {
+            let port = self.port.get_mut();
+            port.write_all(command.as_bytes())
+                .and_then(|_| port.write_all(b"\r"))
+                .and_then(|_| port.flush())
+                .chain_err(|| "Could not write to the serial port")?;
+        }
+        let mut data = None;
+        loop {
+            let line = self.read_line()?;
+            match StatusToken::parse(&line) {
+                Some(StatusToken::Ok) => return Ok(data),
+                Some(StatusToken::Error) => return Err(ErrorKind::DeviceError.into()),
+                Some(_) => {}
+                None => data = Some(line),
+            }
+        }
+    }
+
+    /// Sends a command that has a response, and returns the response.
+    fn query(&mut self, command: &str) -> Result<String> {
+        match self.send(command)? {
+            Some(response) => Ok(response),
+            None => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+
+    /// The temperature scale used for readings. The chip is only queried
+    /// the first time.
+    pub fn get_scale(&mut self) -> Result<TemperatureScale> {
+        if let Some(scale) = self.scale {
+            return Ok(scale);
+        }
+        let scale = TemperatureScale::parse(&self.query("S,?")?)?;
+        self.scale = Some(scale);
+        Ok(scale)
+    }
+
+    /// Takes a single temperature reading.
+    pub fn read_temperature(&mut self) -> Result<Temperature> {
+        let scale = self.get_scale()?;
+        Temperature::parse(&self.query("R")?, scale)
+    }
+
+    /// Makes the chip send a reading every `seconds`, from 1 to
+    /// `MAX_CONTINUOUS_SECONDS`.
+    pub fn start_continuous(&mut self, seconds: u32) -> Result<()> {
+        if seconds == 0 || seconds > MAX_CONTINUOUS_SECONDS {
+            bail!("continuous readings must be 1 to {} seconds apart",
+                  MAX_CONTINUOUS_SECONDS);
+        }
+        self.send(&format!("C,{}", seconds)).map(|_| ())
+    }
+
+    /// Turns continuous mode off.
+    pub fn stop_continuous(&mut self) -> Result<()> {
+        self.send("C,0").map(|_| ())
+    }
+
+    /// Seconds between readings in continuous mode, or zero if it is off.
+    pub fn get_continuous(&mut self) -> Result<ContinuousReadingSeconds> {
+        ContinuousReadingSeconds::parse(&self.query("C,?")?)
+    }
+
+    /// The readings the chip sends in continuous mode, as they arrive.
+    /// Status strings in the stream are skipped. The iterator ends when the
+    /// port has nothing more to read.
+    pub fn readings(&mut self) -> Result<Readings<'_, P>> {
+        let scale = self.get_scale()?;
+        Ok(Readings {
+               sensor: self,
+               scale: scale,
+           })
+    }
+}
+
+/// The stream of readings from a chip in continuous mode; see
+/// `UartSensor::readings`.
+pub struct Readings<'a, P: Read + Write + 'a> {
+    sensor: &'a mut UartSensor<P>,
+    scale: TemperatureScale,
+}
+
+impl<'a, P: Read + Write> Iterator for Readings<'a, P> {
+    type Item = Result<Temperature>;
+
+    fn next(&mut self) -> Option<Result<Temperature>> {
+        loop {
+            let line = match self.sensor.read_line() {
+                Ok(line) => line,
+                Err(_) => return None,
+            };
+            match UartLine::parse(&line, self.scale) {
+                UartLine::Reading(temperature) => return Some(Ok(temperature)),
+                UartLine::Status(_) => continue,
+                UartLine::Response(_) => return Some(Err(ErrorKind::ResponseParse.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    /// A serial port that answers with canned output and records what is
+    /// written to it.
+    struct Port {
+        output: Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Port {
+        fn new(output: &str) -> Port {
+            Port {
+                output: Cursor::new(output.as_bytes().to_vec()),
+                written: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Port {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.output.read(buf)
+        }
+    }
+
+    impl Write for Port {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sends_commands_and_reads_acknowledgements() {
+        let mut sensor = UartSensor::new(Port::new("?S,c\r*OK\r25.104\r*OK\r*ER\r"));
+        assert_eq!(sensor.read_temperature().unwrap(),
+                   Temperature::Celsius(25.104));
+        assert!(sensor.send("X").is_err());
+        assert_eq!(sensor.port.get_ref().written, b"S,?\rR\rX\r".to_vec());
+        assert!(sensor.send("Name,a\rb").is_err());
+    }
+
+    #[test]
+    fn streams_continuous_readings() {
+        let mut sensor = UartSensor::new(Port::new("*OK\r?S,f\r*OK\r77.012\r77.030\r*WA\r77.048\r"));
+        assert!(sensor.start_continuous(0).is_err());
+        assert!(sensor.start_continuous(100).is_err());
+        sensor.start_continuous(2).unwrap();
+
+        let readings: Vec<_> = sensor
+            .readings()
+            .unwrap()
+            .map(|reading| reading.unwrap())
+            .collect();
+        assert_eq!(readings,
+                   vec![Temperature::Fahrenheit(77.012),
+                        Temperature::Fahrenheit(77.030),
+                        Temperature::Fahrenheit(77.048)]);
+        assert_eq!(sensor.port.get_ref().written, b"C,2\rS,?\r".to_vec());
+    }
+
+    #[test]
+    fn stopping_skips_readings_already_sent() {
+        let mut sensor = UartSensor::new(Port::new("25.104\r*OK\r?C,0\r*OK\r"));
+        sensor.stop_continuous().unwrap();
+        assert_eq!(sensor.get_continuous().unwrap(),
+                   ContinuousReadingSeconds(0));
+    }
+}
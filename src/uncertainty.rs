@@ -0,0 +1,190 @@
+//! Estimated uncertainty of readings.
+//!
+//! The estimate combines, as independent standard uncertainties, the
+//! tolerance of the probe's class, or what is left of it after calibration,
+//! the drift since the last calibration, and the noise of recent readings.
+//! The result is meant for recording alongside readings, as a measure of
+//! confidence; it is no substitute for a calibration certificate.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+use response::{Temperature, TemperatureScale};
+
+/// Readings kept to estimate the noise.
+pub const NOISE_WINDOW: usize = 30;
+
+/// Standard uncertainty of a single calibration point, in °C, from the
+/// reference bath and the chip's resolution.
+const CALIBRATION_POINT_CELSIUS: f64 = 0.05;
+
+/// Drift of a calibrated probe per year, in °C.
+const DRIFT_CELSIUS_PER_YEAR: f64 = 0.05;
+
+const SECONDS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0;
+
+/// Tolerance classes of platinum RTDs, from IEC 60751.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeClass {
+    AA,
+    A,
+    B,
+    C,
+}
+
+impl ProbeClass {
+    /// The tolerance of the class at `celsius`, in °C.
+    pub fn tolerance(&self, celsius: f64) -> f64 {
+        let (fixed, proportional) = match *self {
+            ProbeClass::AA => (0.1, 0.0017),
+            ProbeClass::A => (0.15, 0.002),
+            ProbeClass::B => (0.3, 0.005),
+            ProbeClass::C => (0.6, 0.01),
+        };
+        fixed + proportional * celsius.abs()
+    }
+}
+
+/// When, and against how many points, the probe was calibrated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationInfo {
+    pub points: u32,
+    pub calibrated_at: SystemTime,
+}
+
+/// A reading and its estimated uncertainty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UncertainReading {
+    pub temperature: Temperature,
+    /// Standard uncertainty, in the temperature's scale.
+    pub standard: f64,
+}
+
+impl UncertainReading {
+    /// The expanded uncertainty for a coverage factor of 2, about 95 %
+    /// confidence.
+    pub fn expanded(&self) -> f64 {
+        2.0 * self.standard
+    }
+}
+
+/// What is known about the probe, and the recent readings, from which
+/// uncertainties are estimated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UncertaintyModel {
+    pub class: ProbeClass,
+    pub calibration: Option<CalibrationInfo>,
+    /// Recent readings, in °C, oldest first.
+    recent: VecDeque<f64>,
+}
+
+impl UncertaintyModel {
+    pub fn new(class: ProbeClass, calibration: Option<CalibrationInfo>) -> UncertaintyModel {
+        UncertaintyModel {
+            class: class,
+            calibration: calibration,
+            recent: VecDeque::with_capacity(NOISE_WINDOW),
+        }
+    }
+
+    /// Adds a reading to those the noise is estimated from.
+    pub fn observe(&mut self, temperature: Temperature) {
+        if self.recent.len() == NOISE_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent
+            .push_back(temperature.to_scale(TemperatureScale::Celsius).value());
+    }
+
+    /// Sample standard deviation of the recent readings, in °C; zero with
+    /// fewer than two.
+    fn noise(&self) -> f64 {
+        let n = self.recent.len();
+        if n < 2 {
+            return 0.0;
+        }
+        let mean = self.recent.iter().sum::<f64>() / n as f64;
+        let sum = self.recent.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>();
+        (sum / (n - 1) as f64).sqrt()
+    }
+
+    /// Estimates the uncertainty of `temperature`, taken at `now`.
+    pub fn estimate(&self, temperature: Temperature, now: SystemTime) -> UncertainReading {
+        let celsius = temperature.to_scale(TemperatureScale::Celsius).value();
+        let probe = match self.calibration {
+            Some(ref calibration) if calibration.points > 0 => {
+                let age = now.duration_since(calibration.calibrated_at)
+                    .unwrap_or(Duration::from_secs(0));
+                let drift = DRIFT_CELSIUS_PER_YEAR * age.as_secs() as f64 / SECONDS_PER_YEAR;
+                let points = CALIBRATION_POINT_CELSIUS / (calibration.points as f64).sqrt();
+                // Calibration cannot make the probe worse than its class.
+                (points * points + drift * drift)
+                    .sqrt()
+                    .min(self.class.tolerance(celsius) / 3f64.sqrt())
+            }
+            // A tolerance is the half-width of a rectangular distribution.
+            _ => self.class.tolerance(celsius) / 3f64.sqrt(),
+        };
+        let noise = self.noise();
+        let standard_celsius = (probe * probe + noise * noise).sqrt();
+        let standard = match temperature.scale() {
+            TemperatureScale::Fahrenheit => standard_celsius * 9.0 / 5.0,
+            _ => standard_celsius,
+        };
+        UncertainReading {
+            temperature: temperature,
+            standard: standard,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uncalibrated_probes_get_their_class_tolerance() {
+        let model = UncertaintyModel::new(ProbeClass::A, None);
+        let reading = model.estimate(Temperature::Celsius(100.0), SystemTime::now());
+        assert!((reading.standard - 0.35 / 3f64.sqrt()).abs() < 1e-9);
+
+        let reading = model.estimate(Temperature::Fahrenheit(212.0), SystemTime::now());
+        assert!((reading.standard - 0.35 / 3f64.sqrt() * 1.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn calibration_helps_and_ages() {
+        let now = SystemTime::now();
+        let fresh = UncertaintyModel::new(ProbeClass::B,
+                                          Some(CalibrationInfo {
+                                                   points: 2,
+                                                   calibrated_at: now,
+                                               }));
+        let old = UncertaintyModel::new(ProbeClass::B,
+                                        Some(CalibrationInfo {
+                                                 points: 2,
+                                                 calibrated_at: now -
+                                                                Duration::from_secs(3 * 365 *
+                                                                                    24 *
+                                                                                    3600),
+                                             }));
+        let uncalibrated = UncertaintyModel::new(ProbeClass::B, None);
+        let at = |model: &UncertaintyModel| {
+            model.estimate(Temperature::Celsius(25.0), now).standard
+        };
+        assert!(at(&fresh) < at(&old));
+        assert!(at(&old) < at(&uncalibrated));
+    }
+
+    #[test]
+    fn noise_adds_up() {
+        let mut model = UncertaintyModel::new(ProbeClass::AA, None);
+        let quiet = model.estimate(Temperature::Celsius(25.0), SystemTime::now());
+        for value in &[24.8, 25.2, 24.9, 25.1] {
+            model.observe(Temperature::Celsius(*value));
+        }
+        let noisy = model.estimate(Temperature::Celsius(25.0), SystemTime::now());
+        assert!(noisy.standard > quiet.standard);
+        assert_eq!(noisy.expanded(), 2.0 * noisy.standard);
+    }
+}
@@ -0,0 +1,252 @@
+//! Recovering sensors that stop giving good readings.
+//!
+//! A `WatchedSensor` keeps track of when its sensor last gave a good
+//! reading. Once no good reading has come for longer than the watchdog's
+//! window, a failed reading starts the recovery: the reading is tried again,
+//! then after waking the chip, then after reopening the device. If none of
+//! that works, the sensor is reported as failed, and stays so, without
+//! further recovery, until it gives a good reading again.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use i2cdev::core::I2CDevice;
+use response::Temperature;
+use sensor::RtdSensor;
+
+/// The recovery steps, in the order they are tried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecoveryStep {
+    /// Reads again.
+    Repoll,
+    /// Wakes the chip up, in case it was put to sleep, and reads again.
+    Wake,
+    /// Reopens the device, and reads again.
+    Reopen,
+}
+
+impl fmt::Display for RecoveryStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let step = match *self {
+            RecoveryStep::Repoll => "re-poll",
+            RecoveryStep::Wake => "wake",
+            RecoveryStep::Reopen => "reopen",
+        };
+        write!(f, "{}", step)
+    }
+}
+
+const STEPS: [RecoveryStep; 3] = [RecoveryStep::Repoll, RecoveryStep::Wake, RecoveryStep::Reopen];
+
+/// What the watchdog did, in the order it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchdogEvent {
+    /// No good reading for this long; recovery starts.
+    Stale(Duration),
+    /// A recovery step is being tried.
+    Trying(RecoveryStep),
+    /// A recovery step did not bring a good reading.
+    StepFailed(RecoveryStep, String),
+    /// A recovery step brought a good reading.
+    Recovered(RecoveryStep),
+    /// Every step failed; the sensor is considered failed.
+    Failed,
+    /// A failed sensor gave a good reading again.
+    Resumed,
+}
+
+impl fmt::Display for WatchdogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WatchdogEvent::Stale(since) => {
+                write!(f, "no good reading for {} s", since.as_secs())
+            }
+            WatchdogEvent::Trying(step) => write!(f, "trying to {}", step),
+            WatchdogEvent::StepFailed(step, ref error) => write!(f, "{} failed: {}", step, error),
+            WatchdogEvent::Recovered(step) => write!(f, "recovered after {}", step),
+            WatchdogEvent::Failed => write!(f, "recovery failed"),
+            WatchdogEvent::Resumed => write!(f, "readings resumed"),
+        }
+    }
+}
+
+/// A sensor whose readings are watched, with the means to reopen its
+/// device.
+pub struct WatchedSensor<D: I2CDevice, F> {
+    sensor: RtdSensor<D>,
+    reopen: F,
+    window: Duration,
+    last_good: Instant,
+    failed: bool,
+}
+
+impl<D, F> WatchedSensor<D, F>
+    where D: I2CDevice,
+          F: FnMut() -> Result<D>
+{
+    /// Watches `sensor`, which has to give a good reading at least once
+    /// every `window`. `reopen` opens the device again. The window starts
+    /// now.
+    pub fn new(sensor: RtdSensor<D>, window: Duration, reopen: F) -> WatchedSensor<D, F> {
+        WatchedSensor {
+            sensor: sensor,
+            reopen: reopen,
+            window: window,
+            last_good: Instant::now(),
+            failed: false,
+        }
+    }
+
+    pub fn sensor(&mut self) -> &mut RtdSensor<D> {
+        &mut self.sensor
+    }
+
+    /// Returns `true` if every recovery step failed, and no good reading
+    /// came since.
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Time since the last good reading.
+    pub fn since_good(&self) -> Duration {
+        self.last_good.elapsed()
+    }
+
+    /// Takes a reading, going through the recovery steps if it fails and
+    /// the sensor is stale. Everything the watchdog does is passed to
+    /// `on_event`. Returns the good reading, or the last error.
+    pub fn read<E: FnMut(WatchdogEvent)>(&mut self, mut on_event: E) -> Result<Temperature> {
+        let error = match self.sensor.read_temperature() {
+            Ok(temperature) => {
+                if self.failed {
+                    on_event(WatchdogEvent::Resumed);
+                }
+                self.good();
+                return Ok(temperature);
+            }
+            Err(e) => e,
+        };
+        let since = self.since_good();
+        if self.failed || since < self.window {
+            return Err(error);
+        }
+
+        on_event(WatchdogEvent::Stale(since));
+        let mut error = error;
+        for &step in &STEPS {
+            on_event(WatchdogEvent::Trying(step));
+            match self.attempt(step) {
+                Ok(temperature) => {
+                    on_event(WatchdogEvent::Recovered(step));
+                    self.good();
+                    return Ok(temperature);
+                }
+                Err(e) => {
+                    on_event(WatchdogEvent::StepFailed(step, e.to_string()));
+                    error = e;
+                }
+            }
+        }
+        self.failed = true;
+        on_event(WatchdogEvent::Failed);
+        Err(error)
+    }
+
+    fn attempt(&mut self, step: RecoveryStep) -> Result<Temperature> {
+        match step {
+            RecoveryStep::Repoll => {}
+            RecoveryStep::Wake => self.sensor.wake(),
+            RecoveryStep::Reopen => {
+                let dev = (self.reopen)()?;
+                self.sensor.replace_device(dev);
+            }
+        }
+        self.sensor.read_temperature()
+    }
+
+    fn good(&mut self) {
+        self.last_good = Instant::now();
+        self.failed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::RetryPolicy;
+    use simulator::{SimulatedChip, SimulatedDevice, VirtualBus};
+
+    fn sensor(bus: &VirtualBus) -> RtdSensor<SimulatedDevice> {
+        let mut sensor = RtdSensor::new(bus.device(0x66));
+        sensor.set_retry_policy(RetryPolicy {
+                                    attempts: 1,
+                                    ..RetryPolicy::no_wait()
+                                });
+        sensor
+    }
+
+    #[test]
+    fn escalates_until_a_step_recovers() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let replacement = VirtualBus::new();
+        replacement.attach(SimulatedChip::new(0x66));
+        let mut watched = WatchedSensor::new(sensor(&bus), Duration::from_secs(0), || {
+            Ok(replacement.device(0x66))
+        });
+
+        let mut events = Vec::new();
+        assert!(watched.read(|event| events.push(event)).is_ok());
+        assert!(events.is_empty());
+
+        bus.with_chip(chip, |chip| { chip.set_response_code(Some(2)); });
+        assert_eq!(watched.read(|event| events.push(event)).unwrap(),
+                   Temperature::Celsius(25.0));
+        assert_eq!(events.len(), 7);
+        assert_eq!(events[1], WatchdogEvent::Trying(RecoveryStep::Repoll));
+        assert_eq!(events[3], WatchdogEvent::Trying(RecoveryStep::Wake));
+        assert_eq!(events[5], WatchdogEvent::Trying(RecoveryStep::Reopen));
+        assert_eq!(events[6], WatchdogEvent::Recovered(RecoveryStep::Reopen));
+    }
+
+    #[test]
+    fn reports_failure_once_until_readings_resume() {
+        let bus = VirtualBus::new();
+        let chip = bus.attach(SimulatedChip::new(0x66));
+        let reopened = bus.clone();
+        let mut watched = WatchedSensor::new(sensor(&bus), Duration::from_secs(0), || {
+            Ok(reopened.device(0x66))
+        });
+        bus.with_chip(chip, |chip| { chip.set_response_code(Some(2)); });
+
+        let mut events = Vec::new();
+        assert!(watched.read(|event| events.push(event)).is_err());
+        assert_eq!(events.last(), Some(&WatchdogEvent::Failed));
+        assert!(watched.is_failed());
+
+        events.clear();
+        assert!(watched.read(|event| events.push(event)).is_err());
+        assert!(events.is_empty());
+
+        bus.with_chip(chip, |chip| { chip.set_response_code(None); });
+        assert!(watched.read(|event| events.push(event)).is_ok());
+        assert_eq!(events, vec![WatchdogEvent::Resumed]);
+        assert!(!watched.is_failed());
+    }
+
+    #[test]
+    fn failures_within_the_window_are_left_alone() {
+        let bus = VirtualBus::new();
+        let mut chip = SimulatedChip::new(0x66);
+        chip.set_response_code(Some(2));
+        bus.attach(chip);
+        let mut watched = WatchedSensor::new(sensor(&bus), Duration::from_secs(3600), || {
+            Ok(bus.device(0x66))
+        });
+
+        let mut events = Vec::new();
+        assert!(watched.read(|event| events.push(event)).is_err());
+        assert!(events.is_empty());
+    }
+}